@@ -18,12 +18,23 @@ let package = Package(
     targets: [
         .target(
             name: "AppleCapture",
-            dependencies: [],
+            dependencies: ["RecorderCoreC"],
             path: "Sources/AppleCapture",
             swiftSettings: [
                 .unsafeFlags(["-enable-library-evolution"])
+            ],
+            linkerSettings: [
+                // recorder_core's exports (the frame pacer) come from the Rust binary at load time
+                .unsafeFlags(["-Xlinker", "-undefined", "-Xlinker", "dynamic_lookup"])
             ]
         ),
+        // Header-only C target declaring the functions recorder_core exports
+        .target(
+            name: "RecorderCoreC",
+            dependencies: [],
+            path: "Sources/RecorderCoreC",
+            publicHeadersPath: "."
+        ),
         // Header-only C target so Cargo can dlopen symbols
         .target(
             name: "AppleCaptureC",
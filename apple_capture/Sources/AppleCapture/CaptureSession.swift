@@ -1,5 +1,5 @@
 // ABOUTME: Main capture session manager using AVFoundation for screen recording
-// ABOUTME: Handles window targeting, frame capture, and session lifecycle
+// ABOUTME: Handles window targeting, frame and audio capture, and session lifecycle
 
 import AVFoundation
 import CoreGraphics
@@ -10,6 +10,25 @@ public final class CaptureSession: NSObject {
     private let queue = DispatchQueue(label: "apple_capture", qos: .userInitiated)
     private var encoder: Encoder?
     
+    /// Rate control applied to the next `start`; defaults to ABR at the start bitrate.
+    public var encoding: EncodingSettings?
+    
+    /// Output frame rate; with `constantFrameRate` frames are dropped or
+    /// repeated onto a fixed grid, otherwise capture timestamps are kept.
+    public var frameRate = 60
+    public var constantFrameRate = true
+    
+    /// Receives encoded frames for the instant-replay buffer when set.
+    public var replaySink: (context: UnsafeRawPointer?, callback: ReplayPacketCallback)?
+    
+    /// Splits the recording into several files when set; the output URL
+    /// passed to `start` is then the first segment.
+    public var segmenting: SegmentSettings?
+    
+    /// Audio tracks to record; nil records video only.
+    public var audio: AudioSettings?
+    private var systemAudio: AnyObject?
+    
     public override init() {
         super.init()
     }
@@ -36,13 +55,23 @@ public final class CaptureSession: NSObject {
         }
     }
     
+    /// Blocks until the output file(s) are finalized, so callers can
+    /// post-process them (e.g. add chapters) right away.
     public func stop() {
-        queue.async { [weak self] in
+        queue.sync { [weak self] in
+            if #available(macOS 13.0, *) {
+                (self?.systemAudio as? SystemAudioCapture)?.stop()
+            }
             self?.session.stopRunning()
             self?.encoder?.finalizeRecording()
         }
     }
     
+    /// Frame counts of the current (or last) recording.
+    public var frameStats: FrameStats {
+        queue.sync { encoder?.frameStats ?? FrameStats() }
+    }
+    
     // MARK: - Private helpers
     private func configure(windowTitle: String,
                            width: Int, 
@@ -76,7 +105,7 @@ public final class CaptureSession: NSObject {
         }
         
         // Configure input
-        input.minFrameDuration = CMTime(value: 1, timescale: 60) // 60 fps max
+        input.minFrameDuration = CMTime(value: 1, timescale: Int32(max(frameRate, 1)))
         input.capturesCursor = true
         input.capturesMouseClicks = true
         
@@ -97,9 +126,64 @@ public final class CaptureSession: NSObject {
         encoder = try Encoder(outputURL: outputURL,
                               width: width,
                               height: height,
-                              bitrate: bitrate)
+                              settings: encoding ?? EncodingSettings(bitrate: bitrate),
+                              pacer: FramePacer(fps: frameRate, constant: constantFrameRate),
+                              audio: audio)
         
+        encoder?.segmenting = segmenting
         try encoder?.attach(to: session)
+        
+        if let sink = replaySink {
+            encoder?.replayTap = ReplayTap(width: width,
+                                           height: height,
+                                           fps: frameRate,
+                                           settings: encoding ?? EncodingSettings(bitrate: bitrate),
+                                           context: sink.context,
+                                           callback: sink.callback)
+        }
+        
+        if let audio, audio.mic {
+            try addMicrophone(audio.micDevice)
+        }
+        if let audio, audio.game {
+            startSystemAudio(audio)
+        }
+    }
+    
+    private func addMicrophone(_ device: String?) throws {
+        let discovery = AVCaptureDevice.DiscoverySession(deviceTypes: [.builtInMicrophone, .externalUnknown],
+                                                         mediaType: .audio,
+                                                         position: .unspecified)
+        let match = device.flatMap { name in
+            discovery.devices.first { $0.uniqueID == name || $0.localizedName == name }
+        }
+        guard let mic = match ?? AVCaptureDevice.default(for: .audio) else {
+            throw CaptureError.microphoneUnavailable
+        }
+        
+        let input = try AVCaptureDeviceInput(device: mic)
+        guard session.canAddInput(input) else {
+            throw CaptureError.cannotAddInput
+        }
+        session.addInput(input)
+        try encoder?.attachMicrophone(to: session)
+    }
+    
+    private func startSystemAudio(_ audio: AudioSettings) {
+        guard #available(macOS 13.0, *) else {
+            print("⚠️  Game audio needs macOS 13 or later; recording without it")
+            return
+        }
+        let capture = SystemAudioCapture(sampleRate: audio.sampleRate,
+                                         channels: audio.channels) { [weak encoder] buffer in
+            encoder?.appendAudio(buffer, track: .game)
+        }
+        capture.start { error in
+            if let error {
+                print("⚠️  Game audio capture failed: \(error.localizedDescription)")
+            }
+        }
+        systemAudio = capture
     }
 }
 
@@ -109,6 +193,7 @@ public enum CaptureError: LocalizedError {
     case cannotCreateInput
     case cannotAddInput
     case encoderSetupFailed
+    case microphoneUnavailable
     
     public var errorDescription: String? {
         switch self {
@@ -122,6 +207,8 @@ public enum CaptureError: LocalizedError {
             return "Cannot add input to capture session"
         case .encoderSetupFailed:
             return "Failed to setup video encoder"
+        case .microphoneUnavailable:
+            return "No microphone available"
         }
     }
 }
\ No newline at end of file
@@ -1,35 +1,214 @@
 // ABOUTME: Hardware H.264 encoder using AVAssetWriter and VideoToolbox
-// ABOUTME: Handles real-time encoding with minimal CPU usage for TFT recording
+// ABOUTME: Writes video plus optional AAC/Opus game and mic tracks, optionally split into segments
 
 import AVFoundation
 import VideoToolbox
 import CoreMedia
+import RecorderCoreC
 
-final class Encoder: NSObject {
-    private let writer: AVAssetWriter
-    private let input: AVAssetWriterInput
-    private let adaptor: AVAssetWriterInputPixelBufferAdaptor
-    private let queue = DispatchQueue(label: "encoder", qos: .userInitiated)
-    private var isWriting = false
+/// Frame counts of one recording; same layout as recorder_core's `PacerStats`.
+public struct FrameStats {
+    public var framesIn: UInt64 = 0
+    public var framesOut: UInt64 = 0
+    public var dropped: UInt64 = 0
+    public var duplicated: UInt64 = 0
+}
+
+/// recorder_core's frame pacer, called over the FFI so VideoToolbox
+/// recordings are paced exactly like software ones. Times cross as
+/// nanoseconds.
+final class FramePacer {
+    private let pacer: OpaquePointer
+
+    init(fps: Int, constant: Bool) {
+        pacer = recorder_pacer_create(UInt32(clamping: max(fps, 1)), constant)
+    }
+
+    deinit {
+        recorder_pacer_destroy(pacer)
+    }
+
+    /// Capture time of the first frame; output time zero. Audio is retimed against it.
+    var origin: CMTime? {
+        var nanos: UInt64 = 0
+        guard recorder_pacer_origin_ns(pacer, &nanos) else { return nil }
+        return Self.time(nanos)
+    }
+
+    /// Nominal length of one frame.
+    var frameDuration: CMTime {
+        slotTime(1)
+    }
+
+    /// Output time of grid slot `slot`.
+    func slotTime(_ slot: Int64) -> CMTime {
+        Self.time(recorder_pacer_slot_ns(pacer, UInt64(max(slot, 0))))
+    }
+
+    var stats: FrameStats {
+        var stats = recorder_pacer_stats_t()
+        recorder_pacer_stats(pacer, &stats)
+        return FrameStats(framesIn: stats.frames_in,
+                          framesOut: stats.frames_out,
+                          dropped: stats.dropped,
+                          duplicated: stats.duplicated)
+    }
+
+    /// Returns how many times the previous frame must be repeated before this
+    /// one plus this frame's output slot and time, or nil if it should be dropped.
+    func push(_ pts: CMTime) -> (duplicates: Int64, slot: Int64, time: CMTime)? {
+        let nanos = CMTimeConvertScale(pts, timescale: 1_000_000_000, method: .roundHalfAwayFromZero).value
+        var decision = recorder_pace_decision()
+        guard recorder_pacer_push(pacer, UInt64(max(nanos, 0)), &decision) else { return nil }
+        return (Int64(decision.duplicates), Int64(decision.slot), Self.time(decision.pts_ns))
+    }
+
+    private static func time(_ nanos: UInt64) -> CMTime {
+        CMTime(value: CMTimeValue(nanos), timescale: 1_000_000_000)
+    }
+}
+
+/// Rate-control modes; raw values match `RateControl::ffi_mode` on the Rust side.
+public enum RateControlMode: UInt32 {
+    case cbr = 0
+    case abr = 1
+    case vbr = 2
+    case crf = 3
+}
+
+public struct EncodingSettings {
+    public var rateMode: RateControlMode
+    public var bitrate: Int
+    /// Peak bitrate in bits per second; 0 means unlimited.
+    public var maxBitrate: Int
+    /// 0.0–1.0, only used in `.crf` mode.
+    public var quality: Float
+    public var keyframeInterval: Int
+    
+    public init(rateMode: RateControlMode = .abr,
+                bitrate: Int,
+                maxBitrate: Int = 0,
+                quality: Float = 0,
+                keyframeInterval: Int = 60) {
+        self.rateMode = rateMode
+        self.bitrate = bitrate
+        self.maxBitrate = maxBitrate
+        self.quality = quality
+        self.keyframeInterval = keyframeInterval
+    }
     
-    init(outputURL: URL, width: Int, height: Int, bitrate: Int) throws {
+    /// VideoToolbox compression properties for this rate-control mode.
+    var compressionProperties: [String: Any] {
+        var props: [String: Any] = [
+            AVVideoMaxKeyFrameIntervalKey: keyframeInterval,
+            AVVideoProfileLevelKey: AVVideoProfileLevelH264HighAutoLevel,
+            AVVideoH264EntropyModeKey: AVVideoH264EntropyModeCABAC,
+            // No B-frames, so finished recordings can be trimmed and concatenated
+            // by the Rust muxer, which writes decode order as presentation order
+            AVVideoAllowFrameReorderingKey: false
+        ]
+        
+        switch rateMode {
+        case .cbr, .abr, .vbr:
+            props[AVVideoAverageBitRateKey] = bitrate
+        case .crf:
+            props[AVVideoQualityKey] = quality
+        }
+        
+        // DataRateLimits is [bytes, seconds]; one-second window keeps peaks bounded
+        let peak = rateMode == .cbr ? bitrate : maxBitrate
+        if peak > 0 {
+            props[kVTCompressionPropertyKey_DataRateLimits as String] = [peak / 8, 1] as [Int]
+        }
+        
+        return props
+    }
+}
+
+/// Audio codecs; raw values match `AudioCodec::ffi_codec` on the Rust side.
+public enum AudioCodecKind: UInt32 {
+    case aac = 0
+    case opus = 1
+}
+
+/// Audio tracks to record. Each enabled source is written as its own track.
+public struct AudioSettings {
+    public var game: Bool
+    public var mic: Bool
+    /// Unique ID or name of the microphone; nil uses the default input.
+    public var micDevice: String?
+    public var codec: AudioCodecKind
+    public var bitrate: Int
+    public var sampleRate: Int
+    public var channels: Int
+    
+    public init(game: Bool = true,
+                mic: Bool = false,
+                micDevice: String? = nil,
+                codec: AudioCodecKind = .aac,
+                bitrate: Int = 160_000,
+                sampleRate: Int = 48_000,
+                channels: Int = 2) {
+        self.game = game
+        self.mic = mic
+        self.micDevice = micDevice
+        self.codec = codec
+        self.bitrate = bitrate
+        self.sampleRate = sampleRate
+        self.channels = channels
+    }
+    
+    var outputSettings: [String: Any] {
+        [
+            AVFormatIDKey: codec == .opus ? kAudioFormatOpus : kAudioFormatMPEG4AAC,
+            AVSampleRateKey: sampleRate,
+            AVNumberOfChannelsKey: channels,
+            AVEncoderBitRateKey: bitrate
+        ]
+    }
+}
+
+/// Which input an audio track records.
+enum AudioTrack {
+    case game
+    case mic
+}
+
+/// One AVAssetWriter with its inputs. A segmented recording has one per file.
+final class OutputFile {
+    let url: URL
+    let index: Int
+    /// Session time of the first frame in this file.
+    let start: CMTime
+    let writer: AVAssetWriter
+    let input: AVAssetWriterInput
+    let adaptor: AVAssetWriterInputPixelBufferAdaptor
+    private(set) var audioInputs: [AudioTrack: AVAssetWriterInput] = [:]
+    private(set) var isFinished = false
+    
+    init(url: URL,
+         index: Int,
+         start: CMTime,
+         width: Int,
+         height: Int,
+         settings: EncodingSettings,
+         audio: AudioSettings?) throws {
+        self.url = url
+        self.index = index
+        self.start = start
+        
         // Remove existing file if present
-        try? FileManager.default.removeItem(at: outputURL)
+        try? FileManager.default.removeItem(at: url)
         
         // Create writer
-        writer = try AVAssetWriter(outputURL: outputURL, fileType: .mp4)
+        writer = try AVAssetWriter(outputURL: url, fileType: .mp4)
         
         // Configure H.264 settings
         let settings: [String: Any] = [
             AVVideoCodecKey: AVVideoCodecType.h264,
             AVVideoWidthKey: width,
             AVVideoHeightKey: height,
-            AVVideoCompressionPropertiesKey: [
-                AVVideoAverageBitRateKey: bitrate,
-                AVVideoMaxKeyFrameIntervalKey: 60, // Keyframe every second at 60fps
-                AVVideoProfileLevelKey: AVVideoProfileLevelH264HighAutoLevel,
-                AVVideoH264EntropyModeKey: AVVideoH264EntropyModeCABAC
-            ] as [String: Any]
+            AVVideoCompressionPropertiesKey: settings.compressionProperties
         ]
         
         // Create input
@@ -56,9 +235,123 @@ final class Encoder: NSObject {
             throw CaptureError.encoderSetupFailed
         }
         
+        // Game first, then mic, matching the Rust track order
+        var tracks: [AudioTrack] = []
+        if audio?.game == true { tracks.append(.game) }
+        if audio?.mic == true { tracks.append(.mic) }
+        for track in tracks {
+            guard let audio else { break }
+            let audioInput = AVAssetWriterInput(mediaType: .audio, outputSettings: audio.outputSettings)
+            audioInput.expectsMediaDataInRealTime = true
+            guard writer.canAdd(audioInput) else {
+                throw CaptureError.encoderSetupFailed
+            }
+            writer.add(audioInput)
+            audioInputs[track] = audioInput
+        }
+    }
+    
+    /// Starts the file at `start`; a new writer always opens with a keyframe.
+    func begin() {
+        writer.startWriting()
+        writer.startSession(atSourceTime: start)
+    }
+    
+    var size: Int64 {
+        let attributes = try? FileManager.default.attributesOfItem(atPath: url.path)
+        return (attributes?[.size] as? NSNumber)?.int64Value ?? 0
+    }
+    
+    func finish(completion: @escaping () -> Void) {
+        guard !isFinished else { return }
+        isFinished = true
+        input.markAsFinished()
+        audioInputs.values.forEach { $0.markAsFinished() }
+        writer.finishWriting(completionHandler: completion)
+    }
+}
+
+final class Encoder: NSObject {
+    private var output: OutputFile
+    /// The previous segment, kept open briefly so late audio still lands in it.
+    private var closing: OutputFile?
+    private let queue = DispatchQueue(label: "encoder", qos: .userInitiated)
+    private var isWriting = false
+    private let pacer: FramePacer
+    private var previousFrame: CVPixelBuffer?
+    private var lastFrameTime = CMTime.zero
+    private var lastSizeCheck = CMTime.zero
+    private let width: Int
+    private let height: Int
+    private let settings: EncodingSettings
+    private let audio: AudioSettings?
+    /// Optional second encoder feeding the instant-replay buffer.
+    var replayTap: ReplayTap?
+    /// Rolls over to a new file when set.
+    var segmenting: SegmentSettings?
+    
+    init(outputURL: URL,
+         width: Int,
+         height: Int,
+         settings: EncodingSettings,
+         pacer: FramePacer,
+         audio: AudioSettings? = nil) throws {
+        self.pacer = pacer
+        self.width = width
+        self.height = height
+        self.settings = settings
+        self.audio = audio
+        output = try OutputFile(url: outputURL,
+                                index: 1,
+                                start: .zero,
+                                width: width,
+                                height: height,
+                                settings: settings,
+                                audio: audio)
+        
         super.init()
     }
     
+    /// Appends captured PCM to `track`, retimed so it lines up with the video,
+    /// whose first frame is time zero. Audio from before that frame is dropped.
+    func appendAudio(_ sampleBuffer: CMSampleBuffer, track: AudioTrack) {
+        queue.async { [weak self] in
+            guard let self, self.isWriting, let origin = self.pacer.origin else { return }
+            
+            let pts = CMSampleBufferGetPresentationTimeStamp(sampleBuffer)
+            let time = CMTimeSubtract(pts, origin)
+            guard time >= .zero else { return }
+            
+            // Audio from before the latest cut belongs to the previous segment
+            let file = time < self.output.start ? self.closing : self.output
+            guard let file, !file.isFinished, file.writer.status == .writing,
+                  let input = file.audioInputs[track], input.isReadyForMoreMediaData else { return }
+            
+            var timing = CMSampleTimingInfo(duration: CMSampleBufferGetDuration(sampleBuffer),
+                                            presentationTimeStamp: time,
+                                            decodeTimeStamp: .invalid)
+            var retimed: CMSampleBuffer?
+            CMSampleBufferCreateCopyWithNewTiming(allocator: kCFAllocatorDefault,
+                                                  sampleBuffer: sampleBuffer,
+                                                  sampleTimingEntryCount: 1,
+                                                  sampleTimingArray: &timing,
+                                                  sampleBufferOut: &retimed)
+            if let retimed {
+                input.append(retimed)
+            }
+        }
+    }
+    
+    /// Routes microphone buffers from `session` into the mic track.
+    func attachMicrophone(to session: AVCaptureSession) throws {
+        let output = AVCaptureAudioDataOutput()
+        output.setSampleBufferDelegate(self, queue: queue)
+        guard session.canAddOutput(output) else {
+            throw CaptureError.encoderSetupFailed
+        }
+        session.addOutput(output)
+    }
+    
     func attach(to session: AVCaptureSession) throws {
         let output = AVCaptureVideoDataOutput()
         output.setSampleBufferDelegate(self, queue: queue)
@@ -80,53 +373,139 @@ final class Encoder: NSObject {
     
     /// Finishes writing synchronously (max 10 s) so the resulting file is
     /// immediately playable in QuickTime.
+    /// Frame counts so far; final once `finalizeRecording` has returned.
+    var frameStats: FrameStats {
+        queue.sync { pacer.stats }
+    }
+    
     func finalizeRecording() {
         queue.sync { [weak self] in
             guard let self = self, self.isWriting else { return }
             self.isWriting = false
 
-            self.input.markAsFinished()
-
-            let sema = DispatchSemaphore(value: 0)
-            self.writer.finishWriting {
-                sema.signal()
+            self.replayTap?.finish()
+            
+            let group = DispatchGroup()
+            if let closing = self.closing {
+                self.close(closing, end: self.output.start, group: group)
             }
+            let end = CMTimeAdd(self.lastFrameTime, self.pacer.frameDuration)
+            self.close(self.output, end: end, group: group)
 
             // Wait up to 10 s for the moov atom to be written
-            if sema.wait(timeout: .now() + 10) == .timedOut {
+            if group.wait(timeout: .now() + 10) == .timedOut {
                 print("⚠️  Encoder: finishWriting timed out – file may be corrupt")
             }
         }
     }
+    
+    /// Starts the next segment at `time`, leaving the current one open for
+    /// another half second of audio.
+    private func rollOver(at time: CMTime) {
+        guard let segmenting else { return }
+        if let stale = closing {
+            close(stale, end: output.start)
+        }
+        
+        let index = output.index + 1
+        do {
+            let next = try OutputFile(url: segmenting.url(for: index),
+                                      index: index,
+                                      start: time,
+                                      width: width,
+                                      height: height,
+                                      settings: settings,
+                                      audio: audio)
+            next.begin()
+            closing = output
+            output = next
+        } catch {
+            print("⚠️  Encoder: could not start segment \(index): \(error.localizedDescription)")
+            return
+        }
+        
+        queue.asyncAfter(deadline: .now() + 0.5) { [weak self] in
+            guard let self, let closing = self.closing, closing.index == index - 1 else { return }
+            self.close(closing, end: time)
+        }
+    }
+    
+    private func close(_ file: OutputFile, end: CMTime, group: DispatchGroup? = nil) {
+        if closing === file {
+            closing = nil
+        }
+        guard !file.isFinished else { return }
+        group?.enter()
+        let segmenting = segmenting
+        file.finish {
+            segmenting?.report(file, duration: CMTimeSubtract(end, file.start))
+            group?.leave()
+        }
+    }
 }
 
 // MARK: - AVCaptureVideoDataOutputSampleBufferDelegate
-extension Encoder: AVCaptureVideoDataOutputSampleBufferDelegate {
+extension Encoder: AVCaptureVideoDataOutputSampleBufferDelegate, AVCaptureAudioDataOutputSampleBufferDelegate {
     func captureOutput(_ output: AVCaptureOutput,
                        didOutput sampleBuffer: CMSampleBuffer,
                        from connection: AVCaptureConnection) {
         
         guard CMSampleBufferDataIsReady(sampleBuffer) else { return }
         
+        // Microphone buffers arrive through the same delegate protocol
+        if output is AVCaptureAudioDataOutput {
+            appendAudio(sampleBuffer, track: .mic)
+            return
+        }
+        
         let presentationTime = CMSampleBufferGetPresentationTimeStamp(sampleBuffer)
         
-        // Start writing on first frame
+        // Start writing on first frame; the pacer rebases time to zero
         if !isWriting {
-            guard writer.status == .unknown else { return }
+            guard output.writer.status == .unknown else { return }
             
-            writer.startWriting()
-            writer.startSession(atSourceTime: presentationTime)
+            output.begin()
             isWriting = true
         }
         
-        // Write frame
-        guard writer.status == .writing,
-              input.isReadyForMoreMediaData,
-              let imageBuffer = CMSampleBufferGetImageBuffer(sampleBuffer) else {
+        guard output.writer.status == .writing,
+              let imageBuffer = CMSampleBufferGetImageBuffer(sampleBuffer),
+              let decision = pacer.push(presentationTime) else {
             return
         }
         
-        adaptor.append(imageBuffer, withPresentationTime: presentationTime)
+        // Fill capture gaps (static scenes) by repeating the previous frame
+        if let previous = previousFrame, decision.duplicates > 0 {
+            for slot in (decision.slot - decision.duplicates)..<decision.slot
+            where output.input.isReadyForMoreMediaData {
+                output.adaptor.append(previous, withPresentationTime: pacer.slotTime(slot))
+                replayTap?.encode(previous, time: pacer.slotTime(slot))
+            }
+        }
+        
+        if let segmenting, shouldRollOver(segmenting, at: decision.time) {
+            rollOver(at: decision.time)
+        }
+        
+        if output.input.isReadyForMoreMediaData {
+            output.adaptor.append(imageBuffer, withPresentationTime: decision.time)
+        }
+        replayTap?.encode(imageBuffer, time: decision.time)
+        previousFrame = imageBuffer
+        lastFrameTime = decision.time
+    }
+    
+    private func shouldRollOver(_ segmenting: SegmentSettings, at time: CMTime) -> Bool {
+        let elapsed = CMTimeSubtract(time, output.start)
+        if segmenting.maxSeconds > 0, elapsed.seconds >= Double(segmenting.maxSeconds) {
+            return true
+        }
+        // Stat the file about once a second rather than on every frame
+        guard segmenting.maxBytes > 0, CMTimeSubtract(time, lastSizeCheck).seconds >= 1 else {
+            return false
+        }
+        lastSizeCheck = time
+        return output.size >= segmenting.maxBytes
     }
     
     func captureOutput(_ output: AVCaptureOutput,
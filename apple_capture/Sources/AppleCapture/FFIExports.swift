@@ -47,11 +47,86 @@ public func swift_capture_start(_ ptr: UnsafeMutableRawPointer?,
     return started
 }
 
+@_cdecl("swift_capture_set_encoding")
+public func swift_capture_set_encoding(_ ptr: UnsafeMutableRawPointer?,
+                                       _ rateMode: UInt32,
+                                       _ bitrate: UInt32,
+                                       _ maxBitrate: UInt32,
+                                       _ quality: Float,
+                                       _ keyframeInterval: UInt32) {
+    guard let ptr else { return }
+    let mode = RateControlMode(rawValue: rateMode) ?? .abr
+    fromOpaque(ptr).encoding = EncodingSettings(rateMode: mode,
+                                                bitrate: Int(bitrate),
+                                                maxBitrate: Int(maxBitrate),
+                                                quality: quality,
+                                                keyframeInterval: Int(keyframeInterval))
+}
+
+@_cdecl("swift_capture_set_frame_rate")
+public func swift_capture_set_frame_rate(_ ptr: UnsafeMutableRawPointer?,
+                                         _ fps: UInt32,
+                                         _ constant: Bool) {
+    guard let ptr else { return }
+    let session = fromOpaque(ptr)
+    session.frameRate = Int(fps)
+    session.constantFrameRate = constant
+}
+
+@_cdecl("swift_capture_set_audio")
+public func swift_capture_set_audio(_ ptr: UnsafeMutableRawPointer?,
+                                    _ game: Bool,
+                                    _ mic: Bool,
+                                    _ micDevice: UnsafePointer<CChar>?,
+                                    _ codec: UInt32,
+                                    _ bitrate: UInt32,
+                                    _ sampleRate: UInt32,
+                                    _ channels: UInt32) {
+    guard let ptr else { return }
+    fromOpaque(ptr).audio = AudioSettings(game: game,
+                                          mic: mic,
+                                          micDevice: micDevice.map { String(cString: $0) },
+                                          codec: AudioCodecKind(rawValue: codec) ?? .aac,
+                                          bitrate: Int(bitrate),
+                                          sampleRate: Int(sampleRate),
+                                          channels: Int(channels))
+}
+
+@_cdecl("swift_capture_set_replay_sink")
+public func swift_capture_set_replay_sink(_ ptr: UnsafeMutableRawPointer?,
+                                          _ context: UnsafeRawPointer?,
+                                          _ callback: ReplayPacketCallback) {
+    guard let ptr else { return }
+    fromOpaque(ptr).replaySink = (context, callback)
+}
+
+@_cdecl("swift_capture_set_segmenting")
+public func swift_capture_set_segmenting(_ ptr: UnsafeMutableRawPointer?,
+                                         _ maxSeconds: UInt32,
+                                         _ maxBytes: UInt64,
+                                         _ pathPattern: UnsafePointer<CChar>?,
+                                         _ context: UnsafeRawPointer?,
+                                         _ callback: SegmentCallback) {
+    guard let ptr, let pathPattern else { return }
+    fromOpaque(ptr).segmenting = SegmentSettings(maxSeconds: Int(maxSeconds),
+                                                 maxBytes: Int64(maxBytes),
+                                                 pathPattern: String(cString: pathPattern),
+                                                 context: context,
+                                                 callback: callback)
+}
+
 @_cdecl("swift_capture_stop")
 public func swift_capture_stop(_ ptr: UnsafeMutableRawPointer?) {
     ptr.map { fromOpaque($0).stop() }
 }
 
+@_cdecl("swift_capture_get_stats")
+public func swift_capture_get_stats(_ ptr: UnsafeMutableRawPointer?,
+                                    _ out: UnsafeMutablePointer<FrameStats>?) {
+    guard let ptr, let out else { return }
+    out.pointee = fromOpaque(ptr).frameStats
+}
+
 @_cdecl("swift_capture_destroy")
 public func swift_capture_destroy(_ ptr: UnsafeMutableRawPointer?) {
     ptr.map { Unmanaged<CaptureSession>.fromOpaque($0).release() }
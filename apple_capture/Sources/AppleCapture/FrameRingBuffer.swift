@@ -1,20 +1,26 @@
 // ABOUTME: Ring buffer for storing recent frames to enable rewind/replay features
-// ABOUTME: Currently a placeholder for future instant-replay functionality
+// ABOUTME: Raw frames are large; instant replay uses recorder_core's encoded ReplayBuffer instead
 
 import Foundation
 import CoreVideo
 
 public final class FrameRingBuffer {
     private let capacity: Int
+    private let fps: Int
     private var buffer: [CVPixelBuffer?]
     private var writeIndex = 0
     private let lock = NSLock()
     
-    public init(capacity: Int = 300) { // 5 seconds at 60fps
+    public init(capacity: Int = 300, fps: Int = 60) { // 5 seconds at 60fps
         self.capacity = capacity
+        self.fps = max(fps, 1)
         self.buffer = Array(repeating: nil, count: capacity)
     }
     
+    public convenience init(seconds: TimeInterval, fps: Int) {
+        self.init(capacity: max(Int(seconds * Double(fps)), 1), fps: fps)
+    }
+    
     public func append(_ pixelBuffer: CVPixelBuffer) {
         lock.lock()
         defer { lock.unlock() }
@@ -31,7 +37,7 @@ public final class FrameRingBuffer {
         lock.lock()
         defer { lock.unlock() }
         
-        let frameCount = min(Int(seconds * 60), capacity)
+        let frameCount = min(Int(seconds * Double(fps)), capacity)
         var frames: [CVPixelBuffer] = []
         
         for i in 0..<frameCount {
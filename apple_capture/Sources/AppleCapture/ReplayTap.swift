@@ -0,0 +1,110 @@
+// ABOUTME: Second VideoToolbox session that hands encoded H.264 frames to the Rust replay buffer
+// ABOUTME: AVAssetWriter keeps its samples private, so replay encodes the paced frames itself
+
+import CoreMedia
+import VideoToolbox
+
+/// Matches `ReplayPacketCallback` in recorder_core/src/ffi.rs.
+public typealias ReplayPacketCallback = @convention(c) (
+    UnsafeRawPointer?,          // context
+    UnsafePointer<UInt8>?, Int, // AVCC data
+    Int64,                      // pts in microseconds
+    Bool,                       // keyframe
+    UnsafePointer<UInt8>?, Int, // SPS (keyframes only)
+    UnsafePointer<UInt8>?, Int  // PPS (keyframes only)
+) -> Void
+
+final class ReplayTap {
+    private var session: VTCompressionSession?
+    private let context: UnsafeRawPointer?
+    private let callback: ReplayPacketCallback
+
+    init?(width: Int,
+          height: Int,
+          fps: Int,
+          settings: EncodingSettings,
+          context: UnsafeRawPointer?,
+          callback: @escaping ReplayPacketCallback) {
+        self.context = context
+        self.callback = callback
+
+        var session: VTCompressionSession?
+        let status = VTCompressionSessionCreate(allocator: nil,
+                                                width: Int32(width),
+                                                height: Int32(height),
+                                                codecType: kCMVideoCodecType_H264,
+                                                encoderSpecification: nil,
+                                                imageBufferAttributes: nil,
+                                                compressedDataAllocator: nil,
+                                                outputCallback: nil,
+                                                refcon: nil,
+                                                compressionSessionOut: &session)
+        guard status == noErr, let session else { return nil }
+
+        // AVVideo* compression keys are the VideoToolbox property names, so the
+        // writer's settings apply unchanged
+        VTSessionSetProperties(session, propertyDictionary: settings.compressionProperties as CFDictionary)
+        VTSessionSetProperty(session, key: kVTCompressionPropertyKey_RealTime, value: kCFBooleanTrue)
+        // No B-frames: the replay muxer writes decode order as presentation order
+        VTSessionSetProperty(session, key: kVTCompressionPropertyKey_AllowFrameReordering, value: kCFBooleanFalse)
+        VTSessionSetProperty(session, key: kVTCompressionPropertyKey_ExpectedFrameRate, value: fps as CFNumber)
+        VTCompressionSessionPrepareToEncodeFrames(session)
+        self.session = session
+    }
+
+    func encode(_ pixelBuffer: CVPixelBuffer, time: CMTime) {
+        guard let session else { return }
+        VTCompressionSessionEncodeFrame(session,
+                                        imageBuffer: pixelBuffer,
+                                        presentationTimeStamp: time,
+                                        duration: .invalid,
+                                        frameProperties: nil,
+                                        infoFlagsOut: nil) { [weak self] status, _, sample in
+            guard status == noErr, let sample else { return }
+            self?.deliver(sample)
+        }
+    }
+
+    func finish() {
+        guard let session else { return }
+        VTCompressionSessionCompleteFrames(session, untilPresentationTimeStamp: .invalid)
+        VTCompressionSessionInvalidate(session)
+        self.session = nil
+    }
+
+    private func deliver(_ sample: CMSampleBuffer) {
+        guard let block = CMSampleBufferGetDataBuffer(sample) else { return }
+        let length = CMBlockBufferGetDataLength(block)
+        var data = [UInt8](repeating: 0, count: length)
+        guard CMBlockBufferCopyDataBytes(block, atOffset: 0, dataLength: length, destination: &data) == noErr else {
+            return
+        }
+
+        let attachments = CMSampleBufferGetSampleAttachmentsArray(sample, createIfNecessary: false) as? [[CFString: Any]]
+        let notSync = attachments?.first?[kCMSampleAttachmentKey_NotSync] as? Bool ?? false
+        let keyframe = !notSync
+        let pts = CMSampleBufferGetPresentationTimeStamp(sample)
+        let micros = Int64(pts.seconds * 1_000_000)
+
+        var sps: UnsafePointer<UInt8>?
+        var spsLength = 0
+        var pps: UnsafePointer<UInt8>?
+        var ppsLength = 0
+        if keyframe, let format = CMSampleBufferGetFormatDescription(sample) {
+            CMVideoFormatDescriptionGetH264ParameterSetAtIndex(format, parameterSetIndex: 0,
+                                                               parameterSetPointerOut: &sps,
+                                                               parameterSetSizeOut: &spsLength,
+                                                               parameterSetCountOut: nil,
+                                                               nalUnitHeaderLengthOut: nil)
+            CMVideoFormatDescriptionGetH264ParameterSetAtIndex(format, parameterSetIndex: 1,
+                                                               parameterSetPointerOut: &pps,
+                                                               parameterSetSizeOut: &ppsLength,
+                                                               parameterSetCountOut: nil,
+                                                               nalUnitHeaderLengthOut: nil)
+        }
+
+        data.withUnsafeBufferPointer { bytes in
+            callback(context, bytes.baseAddress, bytes.count, micros, keyframe, sps, spsLength, pps, ppsLength)
+        }
+    }
+}
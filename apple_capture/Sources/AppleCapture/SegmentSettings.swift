@@ -0,0 +1,53 @@
+// ABOUTME: Segment limits and naming for splitting a recording into several files
+// ABOUTME: Reports each closed segment back to the Rust SegmentSession through a C callback
+
+import CoreMedia
+import Foundation
+
+/// Matches `SegmentCallback` in recorder_core/src/ffi.rs.
+public typealias SegmentCallback = @convention(c) (
+    UnsafeRawPointer?,          // context
+    UnsafePointer<CChar>?,      // path
+    UInt32,                     // 1-based index
+    Int64,                      // start in microseconds
+    Int64,                      // duration in microseconds
+    UInt64                      // file size in bytes
+) -> Void
+
+public struct SegmentSettings {
+    /// 0 disables the duration limit.
+    public var maxSeconds: Int
+    /// 0 disables the size limit.
+    public var maxBytes: Int64
+    /// Absolute path containing `{n}` for the segment number.
+    public var pathPattern: String
+    public var context: UnsafeRawPointer?
+    public var callback: SegmentCallback
+    
+    public init(maxSeconds: Int,
+                maxBytes: Int64,
+                pathPattern: String,
+                context: UnsafeRawPointer?,
+                callback: SegmentCallback) {
+        self.maxSeconds = maxSeconds
+        self.maxBytes = maxBytes
+        self.pathPattern = pathPattern
+        self.context = context
+        self.callback = callback
+    }
+    
+    func url(for index: Int) -> URL {
+        URL(fileURLWithPath: pathPattern.replacingOccurrences(of: "{n}", with: String(index)))
+    }
+    
+    func report(_ file: OutputFile, duration: CMTime) {
+        file.url.path.withCString { path in
+            callback(context,
+                     path,
+                     UInt32(file.index),
+                     Int64(file.start.seconds * 1_000_000),
+                     Int64(duration.seconds * 1_000_000),
+                     UInt64(max(file.size, 0)))
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// ABOUTME: Captures system (game) audio through ScreenCaptureKit on macOS 13+
+// ABOUTME: Feeds PCM sample buffers to the encoder's game-audio track
+
+import CoreMedia
+import ScreenCaptureKit
+
+@available(macOS 13.0, *)
+final class SystemAudioCapture: NSObject, SCStreamOutput {
+    private let queue = DispatchQueue(label: "system_audio", qos: .userInitiated)
+    private var stream: SCStream?
+    private let sampleRate: Int
+    private let channels: Int
+    private let onAudio: (CMSampleBuffer) -> Void
+
+    init(sampleRate: Int, channels: Int, onAudio: @escaping (CMSampleBuffer) -> Void) {
+        self.sampleRate = sampleRate
+        self.channels = channels
+        self.onAudio = onAudio
+        super.init()
+    }
+
+    func start(completion: @escaping (Error?) -> Void) {
+        SCShareableContent.getExcludingDesktopWindows(false, onScreenWindowsOnly: true) { [weak self] content, error in
+            guard let self else { return }
+            guard let display = content?.displays.first else {
+                completion(error ?? CaptureError.cannotCreateInput)
+                return
+            }
+
+            // SCStream always carries video; keep it tiny since only audio is used
+            let config = SCStreamConfiguration()
+            config.capturesAudio = true
+            config.excludesCurrentProcessAudio = true
+            config.sampleRate = self.sampleRate
+            config.channelCount = self.channels
+            config.width = 2
+            config.height = 2
+            config.minimumFrameInterval = CMTime(value: 1, timescale: 1)
+
+            let filter = SCContentFilter(display: display, excludingWindows: [])
+            let stream = SCStream(filter: filter, configuration: config, delegate: nil)
+            do {
+                try stream.addStreamOutput(self, type: .audio, sampleHandlerQueue: self.queue)
+            } catch {
+                completion(error)
+                return
+            }
+            self.stream = stream
+            stream.startCapture(completionHandler: completion)
+        }
+    }
+
+    func stop() {
+        let sema = DispatchSemaphore(value: 0)
+        stream?.stopCapture { _ in sema.signal() }
+        _ = sema.wait(timeout: .now() + 2)
+        stream = nil
+    }
+
+    func stream(_ stream: SCStream, didOutputSampleBuffer sampleBuffer: CMSampleBuffer, of type: SCStreamOutputType) {
+        guard type == .audio, CMSampleBufferDataIsReady(sampleBuffer) else { return }
+        onAudio(sampleBuffer)
+    }
+}
@@ -2,12 +2,37 @@
 #define APPLE_CAPTURE_BRIDGE_H
 
 #include <stdbool.h>
+#include <stddef.h>
 #include <stdint.h>
 
 #ifdef __cplusplus
 extern "C" {
 #endif
 
+/* One AVCC-framed H.264 access unit; sps/pps are non-NULL on keyframes. */
+typedef void (*replay_packet_callback)(const void* ctx,
+                                       const uint8_t* data, size_t len,
+                                       int64_t pts_us,
+                                       bool keyframe,
+                                       const uint8_t* sps, size_t sps_len,
+                                       const uint8_t* pps, size_t pps_len);
+
+/* A closed segment file; path is NUL-terminated UTF-8, times in microseconds. */
+typedef void (*segment_callback)(const void* ctx,
+                                 const char* path,
+                                 uint32_t index,
+                                 int64_t start_us,
+                                 int64_t duration_us,
+                                 uint64_t bytes);
+
+/* Frame counts of a recording; mirrors recorder_core::pacer::PacerStats. */
+typedef struct {
+    uint64_t frames_in;
+    uint64_t frames_out;
+    uint64_t dropped;
+    uint64_t duplicated;
+} capture_frame_stats;
+
 void* swift_capture_create(void);
 bool swift_capture_start(void* cap,
                         const char* window_title,
@@ -15,7 +40,30 @@ bool swift_capture_start(void* cap,
                         uint32_t height,
                         uint32_t bitrate,
                         const char* output_path);
+void swift_capture_set_encoding(void* cap,
+                                uint32_t rate_mode,
+                                uint32_t bitrate,
+                                uint32_t max_bitrate,
+                                float quality,
+                                uint32_t keyframe_interval);
+void swift_capture_set_frame_rate(void* cap, uint32_t fps, bool constant);
+void swift_capture_set_audio(void* cap,
+                             bool game,
+                             bool mic,
+                             const char* mic_device,
+                             uint32_t codec,
+                             uint32_t bitrate,
+                             uint32_t sample_rate,
+                             uint32_t channels);
+void swift_capture_set_replay_sink(void* cap, const void* ctx, replay_packet_callback callback);
+void swift_capture_set_segmenting(void* cap,
+                                  uint32_t max_seconds,
+                                  uint64_t max_bytes,
+                                  const char* path_pattern,
+                                  const void* ctx,
+                                  segment_callback callback);
 void swift_capture_stop(void* cap);
+void swift_capture_get_stats(void* cap, capture_frame_stats* out);
 void swift_capture_destroy(void* cap);
 
 #ifdef __cplusplus
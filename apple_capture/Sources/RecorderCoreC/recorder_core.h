@@ -0,0 +1,46 @@
+#ifndef RECORDER_CORE_H
+#define RECORDER_CORE_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Functions exported by recorder_core (ffi.rs). They are resolved when the
+ * Rust binary loads AppleCapture, so this target has no implementation. */
+
+/* recorder_core::pacer::FramePacer, owned by the caller. */
+typedef struct recorder_pacer recorder_pacer;
+
+/* What to do with one frame: repeat the previous frame on the `duplicates`
+ * slots before `slot`, then write this one at `pts_ns`. */
+typedef struct {
+    uint32_t duplicates;
+    uint64_t slot;
+    uint64_t pts_ns;
+} recorder_pace_decision;
+
+/* Mirrors recorder_core::pacer::PacerStats. */
+typedef struct {
+    uint64_t frames_in;
+    uint64_t frames_out;
+    uint64_t dropped;
+    uint64_t duplicated;
+} recorder_pacer_stats_t;
+
+recorder_pacer* recorder_pacer_create(uint32_t fps, bool constant);
+/* Returns false if the frame should be dropped. */
+bool recorder_pacer_push(recorder_pacer* pacer, uint64_t capture_ns, recorder_pace_decision* out);
+uint64_t recorder_pacer_slot_ns(const recorder_pacer* pacer, uint64_t slot);
+/* Returns false until the first frame has been pushed. */
+bool recorder_pacer_origin_ns(const recorder_pacer* pacer, uint64_t* out);
+void recorder_pacer_stats(const recorder_pacer* pacer, recorder_pacer_stats_t* out);
+void recorder_pacer_destroy(recorder_pacer* pacer);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RECORDER_CORE_H */
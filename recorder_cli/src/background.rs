@@ -0,0 +1,59 @@
+// ABOUTME: Fire-and-forget jobs (hooks, webhook deliveries) run on threads of their own
+// ABOUTME: Counts the ones still running so a process can wait for them before exiting
+
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Cloneable handle; clones share the count of running jobs.
+#[derive(Clone, Default)]
+pub struct BackgroundJobs {
+    running: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl BackgroundJobs {
+    /// Runs `job` on a new thread without waiting for it.
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        let running = self.running.clone();
+        *running.0.lock().unwrap() += 1;
+        std::thread::spawn(move || {
+            let _done = Finished(running);
+            job();
+        });
+    }
+
+    /// Jobs started and not finished yet.
+    pub fn running(&self) -> usize {
+        *self.running.0.lock().unwrap()
+    }
+
+    /// Blocks until every running job has finished.
+    pub fn wait(&self) {
+        let (count, finished) = &*self.running;
+        let _idle = finished.wait_while(count.lock().unwrap(), |running| *running > 0).unwrap();
+    }
+}
+
+/// Counts a job out when its thread ends, even by a panic.
+struct Finished(Arc<(Mutex<usize>, Condvar)>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        let (count, finished) = &*self.0;
+        // A poisoned count is still a count
+        *count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        finished.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_survives_a_panicking_job() {
+        let jobs = BackgroundJobs::default();
+        jobs.spawn(|| panic!("hook blew up"));
+        jobs.spawn(|| std::thread::sleep(std::time::Duration::from_millis(50)));
+        jobs.wait();
+        assert_eq!(jobs.running(), 0);
+    }
+}
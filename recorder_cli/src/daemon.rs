@@ -0,0 +1,401 @@
+// ABOUTME: Background recorder daemon serving the ctl protocol on a Unix socket
+// ABOUTME: Owns a single Recorder, applies ctl requests to it, runs hooks, webhooks and uploads, marks and streams matches and prunes the library
+
+use crate::background::BackgroundJobs;
+use crate::game;
+use crate::gui;
+use crate::hooks::HookRunner;
+use crate::ipc::{Request, Response};
+use crate::settings::Settings;
+use crate::uploads::{self, UploadQueuer};
+use crate::webhooks::Webhooks;
+use anyhow::Result;
+use recorder_core::Recorder;
+use recorder_game::{GameConfig, GameEvent, GameWatcher};
+use recorder_library::{Library, RetentionPolicy};
+use serde_json::json;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinSet;
+
+pub fn run(socket: &str, retention: &RetentionPolicy, prune_every: Duration, settings: Settings) -> Result<()> {
+    println!("Starting recorder daemon on socket: {}", socket);
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        // A socket left behind by a crashed daemon would make bind fail
+        if Path::new(socket).exists() {
+            std::fs::remove_file(socket)?;
+        }
+        let listener = UnixListener::bind(socket)?;
+        println!("Daemon started. Listening for commands...");
+        report_leftovers(&gui::recordings_dir());
+
+        let recorder = Arc::new(Mutex::new(Recorder::new()));
+        let marks_games = settings.game.as_ref().is_some_and(|game| game.markers);
+        let (game, mut game_events) = follow_game(settings.game.clone());
+        let jobs = BackgroundJobs::default();
+        // Interrupted uploads resume from the queue on the next start
+        let stop_uploads = Arc::new(AtomicBool::new(false));
+        let notifier = notify(&recorder.lock().unwrap(), settings, jobs.clone(), &stop_uploads);
+        // Each ctl connection is served by a task of its own, so a client that
+        // stays connected doesn't hold up the others or this loop
+        let mut connections = JoinSet::new();
+        // The first tick is immediate, so the rules apply at startup too
+        let mut prune_timer = tokio::time::interval(prune_every.max(Duration::from_secs(1)));
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    let (recorder, game) = (recorder.clone(), game.clone());
+                    connections.spawn(async move {
+                        if let Err(e) = serve(stream, recorder, game).await {
+                            eprintln!("ctl connection failed: {:#}", e);
+                        }
+                    });
+                }
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                _ = prune_timer.tick(), if retention.is_active() => {
+                    if let Err(e) = prune(&gui::recordings_dir(), retention) {
+                        eprintln!("Failed to prune recordings: {:#}", e);
+                    }
+                }
+                Some(event) = game_events.recv() => {
+                    if marks_games {
+                        if game::marker_label(&event).is_some() {
+                            println!("{}", game::describe(&event));
+                        }
+                        game::mark(&recorder.lock().unwrap(), &event);
+                    }
+                }
+                _ = tokio::signal::ctrl_c() => break,
+            }
+        }
+
+        println!("Daemon shutting down...");
+        connections.shutdown().await;
+        {
+            let mut recorder = recorder.lock().unwrap();
+            if recorder.is_recording() {
+                recorder.stop();
+            }
+        }
+        // Dropping the recorder ends the listener once it has seen the last
+        // events; the hooks and deliveries they started still get to finish.
+        // A request still being handled holds it until it is done.
+        drop(recorder);
+        notifier.join().ok();
+        stop_uploads.store(true, Ordering::SeqCst);
+        if jobs.running() > 0 {
+            println!("Waiting for {} hook(s) and webhook(s) to finish...", jobs.running());
+            jobs.wait();
+        }
+        std::fs::remove_file(socket).ok();
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    Ok(())
+}
+
+/// Runs the hooks and webhooks of `settings` for the recorder's events and
+/// queues finalized recordings for the upload worker, on a thread that ends
+/// once the recorder is dropped.
+fn notify(recorder: &Recorder, settings: Settings, jobs: BackgroundJobs, stop_uploads: &Arc<AtomicBool>) -> std::thread::JoinHandle<()> {
+    let events = recorder.subscribe();
+    let hooks = HookRunner::new(settings.hooks, jobs.clone());
+    let webhooks = Webhooks::new(settings.webhooks, jobs);
+    let uploads = settings.upload.and_then(|config| match uploads::spawn_worker(config.clone(), stop_uploads.clone()) {
+        Ok(wake) => Some(UploadQueuer::new(config, Some(wake))),
+        Err(e) => {
+            eprintln!("Uploads are off: {:#}", e);
+            None
+        }
+    });
+    std::thread::spawn(move || {
+        for event in events {
+            hooks.handle(&event);
+            webhooks.handle(&event);
+            if let Some(uploads) = &uploads {
+                uploads.handle(&event);
+            }
+        }
+    })
+}
+
+/// Polls the game API of `config`, if any, handing its events to the
+/// daemon's loop; polling stops when the last handle on the returned watcher
+/// is dropped. ctl subscribers get events of their own from the watcher.
+fn follow_game(config: Option<GameConfig>) -> (Option<Arc<GameWatcher>>, UnboundedReceiver<GameEvent>) {
+    let Some((watcher, game_events)) = config.and_then(|config| game::start_watcher(&config)) else {
+        return (None, tokio::sync::mpsc::unbounded_channel().1);
+    };
+    (Some(Arc::new(watcher)), forward(game_events))
+}
+
+/// Hands a watcher subscription to async code. The forwarding thread ends
+/// with the watcher, or at the first event after the receiver is gone.
+fn forward(game_events: std::sync::mpsc::Receiver<GameEvent>) -> UnboundedReceiver<GameEvent> {
+    let (sender, events) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for event in game_events {
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    events
+}
+
+/// Points out recordings a crash left unfinished.
+fn report_leftovers(dir: &Path) {
+    let leftovers = recorder_core::partial::find_leftovers(dir).unwrap_or_default();
+    if !leftovers.is_empty() {
+        println!("Found {} unfinished recording(s) in {}; run `recorder library repair` to recover them", leftovers.len(), dir.display());
+    }
+}
+
+/// Applies the retention rules to the recordings in `dir`, logging what went.
+fn prune(dir: &Path, retention: &RetentionPolicy) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    let mut library = Library::open(dir)?;
+    library.scan()?;
+    let report = library.prune(retention, false)?;
+    for pruned in &report.deleted {
+        println!("Pruned {} ({:?})", pruned.entry.path.display(), pruned.reason);
+    }
+    if !report.deleted.is_empty() {
+        println!("Freed {:.1} MB; {:.1} MB of recordings left", report.freed_bytes as f64 / 1048576.0, report.remaining_bytes as f64 / 1048576.0);
+    }
+    Ok(())
+}
+
+/// Answers the requests of one ctl connection until the client hangs up.
+async fn serve(stream: UnixStream, recorder: Arc<Mutex<Recorder>>, game: Option<Arc<GameWatcher>>) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            // The connection becomes the subscription
+            Ok(Request::GameEvents) => {
+                let Some(game) = &game else {
+                    write_line(&mut write, &Response::failure(&anyhow::anyhow!("Game events are off: the settings have no [game] section"))).await?;
+                    continue;
+                };
+                let mut events = forward(game.subscribe());
+                write_line(&mut write, &Response::success(json!({ "streaming": "game_events" }))).await?;
+                loop {
+                    tokio::select! {
+                        event = events.recv() => {
+                            let Some(event) = event else { return Ok(()) };
+                            write_line(&mut write, &event).await?;
+                        }
+                        // Anything the client sends now is ignored; hanging up ends the stream
+                        line = lines.next_line() => {
+                            if line?.is_none() {
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+            }
+            // Starting and stopping wait on the capture; keep that off the async workers
+            Ok(request) => {
+                let recorder = recorder.clone();
+                tokio::task::spawn_blocking(move || handle(&mut recorder.lock().unwrap(), request)).await?
+            }
+            Err(e) => Response::failure(&anyhow::anyhow!("Invalid request: {}", e)),
+        };
+        write_line(&mut write, &response).await?;
+    }
+    Ok(())
+}
+
+async fn write_line(write: &mut OwnedWriteHalf, value: &impl serde::Serialize) -> Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    write.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+pub fn handle(recorder: &mut Recorder, request: Request) -> Response {
+    let result = match request {
+        Request::Mark { label, at_secs } => at_secs
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(anyhow::Error::from)
+            .and_then(|at| recorder.add_marker(&label, at))
+            .map(|marker| json!({ "label": marker.label, "time_secs": marker.time.as_secs_f64() })),
+        Request::Game { game } => recorder.set_game_info(game).and_then(|game| Ok(serde_json::to_value(game)?)),
+        Request::Start { config, output } => {
+            if let Some(parent) = Path::new(&output).parent() {
+                std::fs::create_dir_all(parent).ok();
+            }
+            recorder.start_with_config(&config, &output).map(|()| json!({ "output": output }))
+        }
+        Request::Stop => {
+            let was_recording = recorder.is_recording();
+            recorder.stop();
+            Ok(json!({ "was_recording": was_recording }))
+        }
+        Request::Status => Ok(json!({ "recording": recorder.is_recording() })),
+        Request::GameEvents => Err(anyhow::anyhow!("Game events are streamed over a ctl connection")),
+        Request::SaveReplay { seconds, output } => {
+            let output = output.unwrap_or_else(gui::next_replay_file_name);
+            if let Some(parent) = Path::new(&output).parent() {
+                std::fs::create_dir_all(parent).ok();
+            }
+            recorder.save_replay(seconds, &output).map(|clip| {
+                json!({
+                    "output": output,
+                    "duration_secs": clip.duration.as_secs_f64(),
+                    "video_frames": clip.video_frames,
+                    "bytes": clip.bytes,
+                })
+            })
+        }
+    };
+
+    match result {
+        Ok(value) => Response::success(value),
+        Err(e) => Response::failure(&e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use recorder_core::GameInfo;
+
+    #[test]
+    fn test_status_and_stop() {
+        let mut recorder = Recorder::new();
+        let status = handle(&mut recorder, Request::Status);
+        assert!(status.ok);
+        assert_eq!(status.result, json!({ "recording": false }));
+
+        let stop = handle(&mut recorder, Request::Stop);
+        assert_eq!(stop.result, json!({ "was_recording": false }));
+    }
+
+    #[test]
+    fn test_mark_needs_a_recording() {
+        let mut recorder = Recorder::new();
+        let response = handle(&mut recorder, Request::Mark { label: "Stage 2-1".into(), at_secs: Some(61.5) });
+        assert!(!response.ok);
+        assert_eq!(response.error.as_deref(), Some("Not recording"));
+
+        let negative = handle(&mut recorder, Request::Mark { label: "x".into(), at_secs: Some(-1.0) });
+        assert!(!negative.ok);
+
+        let game = GameInfo { placement: Some(1), ..Default::default() };
+        assert!(!handle(&mut recorder, Request::Game { game }).ok);
+    }
+
+    #[tokio::test]
+    async fn test_an_idle_connection_does_not_block_others() {
+        let socket = std::env::temp_dir().join(format!("tft_daemon_{}.sock", std::process::id()));
+        std::fs::remove_file(&socket).ok();
+        let listener = UnixListener::bind(&socket).unwrap();
+        let recorder = Arc::new(Mutex::new(Recorder::new()));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, recorder.clone(), None));
+            }
+        });
+
+        // Connected and silent, like `nc -U` left open
+        let _idle = UnixStream::connect(&socket).await.unwrap();
+        let path = socket.clone();
+        let status = tokio::task::spawn_blocking(move || crate::ipc::send(&path, &Request::Status));
+        let status = tokio::time::timeout(Duration::from_secs(5), status).await.unwrap().unwrap().unwrap();
+        assert_eq!(status, json!({ "recording": false }));
+        std::fs::remove_file(socket).ok();
+    }
+
+    #[tokio::test]
+    async fn test_game_events_stream_to_subscribers() {
+        use std::io::{BufRead, Write};
+
+        // The game API: loading until `started` is set, then a match
+        let api = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", api.local_addr().unwrap());
+        let started = Arc::new(AtomicBool::new(false));
+        let in_game = started.clone();
+        std::thread::spawn(move || {
+            for mut stream in api.incoming().flatten() {
+                let (mut request, mut line) = (std::io::BufReader::new(stream.try_clone().unwrap()), String::new());
+                while request.read_line(&mut line).unwrap_or(0) > 2 {
+                    line.clear();
+                }
+                let (status, body) = match in_game.load(Ordering::SeqCst) {
+                    true => (200, r#"{"gameData": {"gameMode": "TFT", "mapName": "Map22", "gameTime": 3.0}}"#),
+                    false => (404, "{}"),
+                };
+                write!(stream, "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body).ok();
+            }
+        });
+        let client = recorder_game::LiveClient::new(&url, Duration::from_secs(2)).unwrap();
+        let watcher = Arc::new(GameWatcher::start(client, Duration::from_millis(20)));
+
+        let socket = std::env::temp_dir().join(format!("tft_daemon_game_{}.sock", std::process::id()));
+        std::fs::remove_file(&socket).ok();
+        let listener = UnixListener::bind(&socket).unwrap();
+        let recorder = Arc::new(Mutex::new(Recorder::new()));
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, recorder.clone(), Some(watcher)).await.unwrap();
+        });
+
+        let path = socket.clone();
+        let lines = tokio::task::spawn_blocking(move || {
+            let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+            stream.write_all(b"{\"cmd\":\"game_events\"}\n").unwrap();
+            let mut reader = std::io::BufReader::new(stream);
+            let (mut ready, mut event) = (String::new(), String::new());
+            reader.read_line(&mut ready).unwrap();
+            started.store(true, Ordering::SeqCst);
+            reader.read_line(&mut event).unwrap();
+            (ready, event)
+        });
+        let (ready, event) = tokio::time::timeout(Duration::from_secs(10), lines).await.unwrap().unwrap();
+        assert_eq!(serde_json::from_str::<Response>(&ready).unwrap(), Response::success(json!({ "streaming": "game_events" })));
+        let event: serde_json::Value = serde_json::from_str(&event).unwrap();
+        assert_eq!((event["event"].as_str(), event["mode"].as_str()), (Some("game_started"), Some("TFT")));
+        std::fs::remove_file(socket).ok();
+    }
+
+    #[tokio::test]
+    async fn test_game_events_need_a_game_section() {
+        let (client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(serve(server, Arc::new(Mutex::new(Recorder::new())), None));
+        let (read, mut write) = client.into_split();
+        write.write_all(b"{\"cmd\":\"game_events\"}\n").await.unwrap();
+        let reply = BufReader::new(read).lines().next_line().await.unwrap().unwrap();
+        let response: Response = serde_json::from_str(&reply).unwrap();
+        assert!(!response.ok && response.error.unwrap().contains("[game]"));
+    }
+
+    #[test]
+    fn test_save_replay_without_buffer_is_an_error_response() {
+        let mut recorder = Recorder::new();
+        let output = std::env::temp_dir().join("daemon_replay_test.mp4");
+        let response = handle(
+            &mut recorder,
+            Request::SaveReplay { seconds: Some(10), output: Some(output.display().to_string()) },
+        );
+        assert!(!response.ok);
+        assert!(response.error.unwrap().contains("not enabled"));
+        assert!(!output.exists());
+    }
+}
@@ -0,0 +1,95 @@
+// ABOUTME: Glue between the recorder and recorder_game: marks recordings where matches start and end
+// ABOUTME: Also formats game events for `recorder game watch` and the daemon's log
+
+use recorder_core::Recorder;
+use recorder_game::{GameConfig, GameEvent, GameWatcher};
+use std::sync::mpsc::Receiver;
+
+/// Starts polling the game API of `config`, or explains why it can't.
+pub fn start_watcher(config: &GameConfig) -> Option<(GameWatcher, Receiver<GameEvent>)> {
+    match config.client() {
+        Ok(client) => {
+            let watcher = GameWatcher::start(client, config.interval());
+            let events = watcher.subscribe();
+            Some((watcher, events))
+        }
+        Err(e) => {
+            eprintln!("Game events are off: {:#}", e);
+            None
+        }
+    }
+}
+
+/// The marker a match's start or end leaves in the recording.
+pub fn marker_label(event: &GameEvent) -> Option<String> {
+    match event {
+        GameEvent::GameStarted { .. } => Some("Game start".to_string()),
+        GameEvent::GameEnded { result: Some(result), .. } => Some(format!("Game end ({})", result)),
+        GameEvent::GameEnded { result: None, .. } => Some("Game end".to_string()),
+        GameEvent::PlayersUpdated { .. } | GameEvent::MatchEvent { .. } => None,
+    }
+}
+
+/// Marks the recording in progress, if any, with the start or end of a match.
+pub fn mark(recorder: &Recorder, event: &GameEvent) {
+    let Some(label) = marker_label(event).filter(|_| recorder.is_recording()) else { return };
+    match recorder.add_marker(&label, None) {
+        Ok(marker) => println!("Marked {} at {:.1}s", label, marker.time.as_secs_f64()),
+        Err(e) => eprintln!("Failed to mark {}: {:#}", label, e),
+    }
+}
+
+/// One line of `recorder game watch`.
+pub fn describe(event: &GameEvent) -> String {
+    let clock = |secs: f64| format!("{:02}:{:02}", secs as u64 / 60, secs as u64 % 60);
+    match event {
+        GameEvent::GameStarted { mode, map, game_time, active_player, players } => format!(
+            "[{}] {} match started on {} with {} player(s){}",
+            clock(*game_time),
+            mode,
+            map,
+            players.len(),
+            active_player.as_ref().map(|me| format!(", playing as {}", me)).unwrap_or_default()
+        ),
+        GameEvent::PlayersUpdated { game_time, players } => {
+            let out = players.iter().filter(|player| player.is_dead).count();
+            format!("[{}] Players updated: {} in, {} out", clock(*game_time), players.len() - out, out)
+        }
+        GameEvent::MatchEvent { name, game_time, .. } => format!("[{}] {}", clock(*game_time), name),
+        GameEvent::GameEnded { game_time, result } => {
+            format!("[{}] Match ended{}", clock(*game_time), result.as_ref().map(|result| format!(": {}", result)).unwrap_or_default())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use recorder_game::Player;
+
+    #[test]
+    fn test_labels_and_lines() {
+        let started = GameEvent::GameStarted {
+            mode: "TFT".into(),
+            map: "Map22".into(),
+            game_time: 3.2,
+            active_player: Some("Tactician#EUW".into()),
+            players: vec![Player::default(), Player { is_dead: true, ..Player::default() }],
+        };
+        assert_eq!(marker_label(&started).as_deref(), Some("Game start"));
+        assert_eq!(describe(&started), "[00:03] TFT match started on Map22 with 2 player(s), playing as Tactician#EUW");
+
+        let ended = GameEvent::GameEnded { game_time: 1802.0, result: Some("Win".into()) };
+        assert_eq!(marker_label(&ended).as_deref(), Some("Game end (Win)"));
+        assert_eq!(describe(&ended), "[30:02] Match ended: Win");
+        let gone = GameEvent::GameEnded { game_time: 61.0, result: None };
+        assert_eq!((marker_label(&gone).as_deref(), describe(&gone).as_str()), (Some("Game end"), "[01:01] Match ended"));
+
+        let updated = GameEvent::PlayersUpdated { game_time: 600.0, players: vec![Player::default(), Player { is_dead: true, ..Player::default() }] };
+        assert_eq!(marker_label(&updated), None);
+        assert_eq!(describe(&updated), "[10:00] Players updated: 1 in, 1 out");
+
+        // Nothing to mark outside a recording
+        mark(&Recorder::new(), &started);
+    }
+}
@@ -2,14 +2,30 @@
 // ABOUTME: Provides a minimalist interface for recording and managing recordings
 
 use eframe::{egui, NativeOptions};
-use recorder_core::Recorder;
+use recorder_core::thumbnail::{self, RgbFrame};
+use recorder_core::mp4::Mp4Reader;
+use recorder_core::vertical::{self, CROP_PRESETS};
+use recorder_core::naming::{self, NameInfo, NamingProfile};
+use recorder_core::partial::{self, LeftoverPartial, PartialState};
+use recorder_core::events::RecorderEvent;
+use recorder_core::{AudioConfig, Recorder, RecordingConfig, VerticalOptions};
+use recorder_library::{Library, LibraryEntry, LibraryWatcher};
+use recorder_upload::{UploadItem, UploadQueue, UploadState};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use chrono::{DateTime, Local};
 
 const RECORDINGS_DIR: &str = "~/Movies/TFT Recorder";
 
+/// Size of the thumbnails in the recordings list.
+const THUMBNAIL_SIZE: egui::Vec2 = egui::vec2(80.0, 45.0);
+
+/// How often the upload queue is re-read while the window is open.
+const UPLOADS_REFRESH: std::time::Duration = std::time::Duration::from_secs(2);
+
 pub fn launch() -> anyhow::Result<()> {
     // Create the recordings directory if it doesn't exist
     let recordings_path = expand_home(RECORDINGS_DIR);
@@ -32,9 +48,287 @@ pub fn launch() -> anyhow::Result<()> {
 #[derive(Default)]
 struct RecorderApp {
     recorder: Arc<Mutex<Recorder>>,
+    /// The recorder's events, subscribed on the first frame.
+    events: Option<Receiver<RecorderEvent>>,
     is_recording: bool,
     started_at: Option<DateTime<Local>>,
     error_message: Option<String>,
+    audio: AudioConfig,
+    /// Naming profile index into `naming::PROFILES`.
+    profile: usize,
+    recordings: RecordingsList,
+    /// Partials a crash left behind, found once at startup.
+    leftovers: Option<Vec<LeftoverPartial>>,
+    thumbnails: Thumbnails,
+    clip_export: Option<ClipExport>,
+    uploads: UploadsList,
+}
+
+/// The recordings list, read from the library index and reloaded when its
+/// watcher reports a change instead of rescanning the directory every frame.
+#[derive(Default)]
+struct RecordingsList {
+    library: Option<Library>,
+    watcher: Option<LibraryWatcher>,
+    entries: Vec<LibraryEntry>,
+    error: Option<String>,
+}
+
+impl RecordingsList {
+    fn update(&mut self, ctx: &egui::Context) {
+        if self.library.is_none() && self.error.is_none() {
+            if let Err(e) = self.open(ctx) {
+                self.error = Some(format!("Can't index recordings: {:#}", e));
+            }
+        }
+        let changed = self.watcher.as_ref().is_some_and(|w| !w.changes().is_empty());
+        if let (true, Some(library)) = (changed, &self.library) {
+            match library.recordings() {
+                Ok(entries) => self.entries = entries,
+                Err(e) => self.error = Some(format!("Can't read the library: {:#}", e)),
+            }
+        }
+    }
+
+    fn open(&mut self, ctx: &egui::Context) -> anyhow::Result<()> {
+        let root = recordings_dir();
+        let mut library = Library::open(&root)?;
+        library.scan()?;
+        let ctx = ctx.clone();
+        self.watcher = Some(LibraryWatcher::start(&root, move || ctx.request_repaint())?);
+        self.entries = library.recordings()?;
+        self.library = Some(library);
+        Ok(())
+    }
+}
+
+/// The upload queue, re-read from its file every [`UPLOADS_REFRESH`] since
+/// the daemon or `recorder uploads run` do the uploading.
+#[derive(Default)]
+struct UploadsList {
+    items: Vec<UploadItem>,
+    loaded_at: Option<std::time::Instant>,
+    error: Option<String>,
+}
+
+impl UploadsList {
+    fn update(&mut self, ctx: &egui::Context) {
+        if self.loaded_at.is_some_and(|at| at.elapsed() < UPLOADS_REFRESH) {
+            return;
+        }
+        self.loaded_at = Some(std::time::Instant::now());
+        match UploadQueue::load(&expand_home(recorder_upload::DEFAULT_QUEUE)) {
+            Ok(queue) => {
+                self.items = queue.items().to_vec();
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("Can't read the upload queue: {:#}", e)),
+        }
+        if self.items.iter().any(|item| item.state.is_pending()) {
+            ctx.request_repaint_after(UPLOADS_REFRESH);
+        }
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        if self.items.is_empty() && self.error.is_none() {
+            return;
+        }
+        ui.add_space(20.0);
+        ui.heading("Uploads");
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::LIGHT_RED, error);
+        }
+        for item in &self.items {
+            ui.horizontal(|ui| {
+                ui.label(&item.key);
+                match item.state {
+                    UploadState::Queued => {
+                        ui.small("queued");
+                    }
+                    UploadState::Uploading => {
+                        ui.add(egui::ProgressBar::new(item.progress()).desired_width(160.0).show_percentage());
+                    }
+                    UploadState::Done => {
+                        ui.small("uploaded");
+                    }
+                    UploadState::Failed => {
+                        ui.colored_label(egui::Color32::LIGHT_RED, "failed").on_hover_text(item.error.as_deref().unwrap_or_default());
+                    }
+                }
+            });
+        }
+        let queue_file = expand_home(recorder_upload::DEFAULT_QUEUE);
+        ui.horizontal(|ui| {
+            let mut changed = None;
+            if self.items.iter().any(|item| item.state == UploadState::Failed) && ui.button("Retry failed").clicked() {
+                changed = Some(UploadQueue::update(&queue_file, |queue| queue.retry_failed()));
+            }
+            if self.items.iter().any(|item| item.state == UploadState::Done) && ui.button("Clear finished").clicked() {
+                changed = Some(UploadQueue::update(&queue_file, |queue| queue.clear_done()));
+            }
+            match changed {
+                Some(Ok(_)) => self.loaded_at = None,
+                Some(Err(e)) => self.error = Some(format!("{:#}", e)),
+                None => {}
+            }
+        });
+    }
+}
+
+/// The "Export clip" window: a vertical clip of one recording.
+struct ClipExport {
+    recording: PathBuf,
+    length: f64,
+    from: f64,
+    to: f64,
+    preset: usize,
+    status: Arc<Mutex<ExportStatus>>,
+}
+
+#[derive(Default)]
+enum ExportStatus {
+    #[default]
+    Idle,
+    Running,
+    Done(PathBuf),
+    Failed(String),
+}
+
+impl ClipExport {
+    fn open(recording: PathBuf) -> anyhow::Result<Self> {
+        let length = Mp4Reader::open(&recording)?.duration.as_secs_f64();
+        Ok(Self {
+            recording,
+            length,
+            from: 0.0,
+            to: length.min(60.0),
+            preset: 0,
+            status: Arc::default(),
+        })
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        let file_name = self.recording.file_name().and_then(|n| n.to_str()).unwrap_or("Unknown");
+        ui.label(file_name);
+        let clock = |secs: f64, _: std::ops::RangeInclusive<usize>| format!("{}:{:04.1}", secs as u64 / 60, secs % 60.0);
+        egui::Grid::new("clip_export_grid").num_columns(2).show(ui, |ui| {
+            ui.label("From");
+            ui.add(egui::DragValue::new(&mut self.from).clamp_range(0.0..=self.length).custom_formatter(clock));
+            ui.end_row();
+
+            ui.label("To");
+            ui.add(egui::DragValue::new(&mut self.to).clamp_range(self.from..=self.length).custom_formatter(clock));
+            ui.end_row();
+
+            ui.label("Crop");
+            egui::ComboBox::from_id_source("clip_export_preset")
+                .selected_text(CROP_PRESETS[self.preset].name)
+                .show_ui(ui, |ui| {
+                    for (i, preset) in CROP_PRESETS.iter().enumerate() {
+                        ui.selectable_value(&mut self.preset, i, preset.name).on_hover_text(preset.description);
+                    }
+                });
+            ui.end_row();
+        });
+
+        let running = matches!(*self.status.lock().unwrap(), ExportStatus::Running);
+        if ui.add_enabled(!running && self.from < self.to, egui::Button::new("Export 1080x1920")).clicked() {
+            self.start(ui.ctx().clone());
+        }
+        match &*self.status.lock().unwrap() {
+            ExportStatus::Idle => {}
+            ExportStatus::Running => {
+                ui.spinner();
+            }
+            ExportStatus::Done(path) => {
+                ui.label(format!("Saved {}", path.display()));
+            }
+            ExportStatus::Failed(e) => {
+                ui.colored_label(egui::Color32::LIGHT_RED, e);
+            }
+        }
+    }
+
+    /// Exports on a background thread; re-encoding takes a while.
+    fn start(&self, ctx: egui::Context) {
+        let options = VerticalOptions {
+            from: std::time::Duration::from_secs_f64(self.from),
+            to: Some(std::time::Duration::from_secs_f64(self.to)),
+            preset: CROP_PRESETS[self.preset],
+            ..Default::default()
+        };
+        let (input, output) = (self.recording.clone(), vertical::vertical_path(&self.recording));
+        let status = Arc::clone(&self.status);
+        *status.lock().unwrap() = ExportStatus::Running;
+        std::thread::spawn(move || {
+            let result = recorder_core::export_vertical(&input, &output, &options);
+            *status.lock().unwrap() = match result {
+                Ok(_) => ExportStatus::Done(output),
+                Err(e) => ExportStatus::Failed(format!("Export failed: {:#}", e)),
+            };
+            ctx.request_repaint();
+        });
+    }
+}
+
+/// Recordings-list thumbnails, made and decoded on a worker thread so a long
+/// list doesn't stall the UI.
+#[derive(Default)]
+struct Thumbnails {
+    textures: HashMap<PathBuf, egui::TextureHandle>,
+    requested: HashSet<PathBuf>,
+    worker: Option<ThumbnailWorker>,
+}
+
+/// Requests to, and finished frames from, the thumbnail worker thread.
+type ThumbnailWorker = (Sender<PathBuf>, Receiver<(PathBuf, RgbFrame)>);
+
+impl Thumbnails {
+    /// The thumbnail of `recording`, queuing it if it hasn't been asked for yet.
+    fn get(&mut self, ctx: &egui::Context, recording: &Path) -> Option<&egui::TextureHandle> {
+        if self.requested.insert(recording.to_path_buf()) {
+            let (requests, _) = self.worker.get_or_insert_with(|| spawn_thumbnail_worker(ctx.clone()));
+            let _ = requests.send(recording.to_path_buf());
+        }
+        self.textures.get(recording)
+    }
+
+    /// Uploads the thumbnails the worker has finished.
+    fn receive(&mut self, ctx: &egui::Context) {
+        let Some((_, results)) = &self.worker else {
+            return;
+        };
+        for (path, frame) in results.try_iter() {
+            let image = egui::ColorImage::from_rgb([frame.width as usize, frame.height as usize], &frame.data);
+            let texture = ctx.load_texture(path.display().to_string(), image, egui::TextureOptions::LINEAR);
+            self.textures.insert(path, texture);
+        }
+    }
+
+    /// Tries the recordings without a thumbnail again, e.g. the one that
+    /// was still being written when the list was first drawn.
+    fn retry_missing(&mut self) {
+        let textures = &self.textures;
+        self.requested.retain(|path| textures.contains_key(path));
+    }
+}
+
+fn spawn_thumbnail_worker(ctx: egui::Context) -> ThumbnailWorker {
+    let (request_tx, request_rx) = mpsc::channel::<PathBuf>();
+    let (result_tx, result_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for recording in request_rx {
+            // Unfinished recordings have no moov yet and simply get no thumbnail
+            let frame = thumbnail::thumbnail(&recording, None).and_then(|path| RgbFrame::open(&path));
+            if let Ok(frame) = frame {
+                if result_tx.send((recording, frame)).is_err() {
+                    break;
+                }
+                ctx.request_repaint();
+            }
+        }
+    });
+    (request_tx, result_rx)
 }
 
 impl eframe::App for RecorderApp {
@@ -45,6 +339,27 @@ impl eframe::App for RecorderApp {
             ..Default::default()
         });
 
+        if self.leftovers.is_none() {
+            self.leftovers = Some(partial::find_leftovers(&recordings_dir()).unwrap_or_default());
+        }
+
+        let events = self.events.get_or_insert_with(|| self.recorder.lock().unwrap().subscribe());
+        for event in events.try_iter() {
+            match event {
+                RecorderEvent::StoppedForDiskSpace { available_bytes } => {
+                    self.error_message = Some(format!("Recording stopped: the disk is almost full ({} MB free)", available_bytes / (1024 * 1024)));
+                }
+                RecorderEvent::RecordingFailed { message, .. } => self.error_message = Some(message),
+                _ => {}
+            }
+        }
+        // The recording may have ended without the Stop button, e.g. for disk space
+        if self.is_recording && self.recorder.lock().is_ok_and(|rec| !rec.is_recording()) {
+            self.is_recording = false;
+            self.started_at = None;
+            self.thumbnails.retry_missing();
+        }
+
         // ---------- top toolbar ----------
         egui::TopBottomPanel::top("toolbar").show(ctx, |ui| {
             ui.horizontal_centered(|ui| {
@@ -61,6 +376,7 @@ impl eframe::App for RecorderApp {
                         self.is_recording = false;
                         self.started_at = None;
                         self.error_message = None;
+                        self.thumbnails.retry_missing();
                     }
 
                     // Live timer
@@ -76,41 +392,90 @@ impl eframe::App for RecorderApp {
                     if ui.add(rec_button).clicked() {
                         self.start_recording();
                     }
+
+                    ui.add_space(20.0);
+                    ui.checkbox(&mut self.audio.game, "Game audio");
+                    ui.checkbox(&mut self.audio.mic, "Microphone");
+                    ui.add_space(20.0);
+                    egui::ComboBox::from_id_source("naming_profile")
+                        .selected_text(naming::PROFILES[self.profile].name)
+                        .show_ui(ui, |ui| {
+                            for (i, preset) in naming::PROFILES.iter().enumerate() {
+                                ui.selectable_value(&mut self.profile, i, preset.name).on_hover_text(preset.description);
+                            }
+                        });
                 }
             });
         });
 
         // ---------- left panel ----------
+        self.recordings.update(ctx);
+        self.thumbnails.receive(ctx);
+        let thumbnails = &mut self.thumbnails;
+        let recordings = &self.recordings;
+        let mut export_requested = None;
         egui::SidePanel::left("recordings_panel")
-            .default_width(250.0)
+            .default_width(330.0)
             .show(ctx, |ui| {
                 ui.heading("Recordings");
                 ui.separator();
                 
                 egui::ScrollArea::vertical().show(ui, |ui| {
-                    let recordings = list_recordings();
-                    
-                    if recordings.is_empty() {
+                    if let Some(error) = &recordings.error {
+                        ui.colored_label(egui::Color32::LIGHT_RED, error);
+                    }
+                    if recordings.entries.is_empty() {
                         ui.label("No recordings yet");
                     } else {
-                        for recording in recordings {
-                            let file_name = recording
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("Unknown");
-                            
-                            if ui.button(file_name).clicked() {
-                                // Reveal in Finder
-                                let _ = std::process::Command::new("open")
-                                    .arg("-R")
-                                    .arg(&recording)
-                                    .spawn();
-                            }
+                        for entry in &recordings.entries {
+                            let recording = &entry.path;
+                            ui.horizontal(|ui| {
+                                match thumbnails.get(ctx, recording) {
+                                    Some(texture) => {
+                                        ui.image((texture.id(), THUMBNAIL_SIZE));
+                                    }
+                                    None => {
+                                        ui.allocate_space(THUMBNAIL_SIZE);
+                                    }
+                                }
+
+                                let button = ui.button(entry.file_name()).on_hover_text(describe(entry));
+                                if button.clicked() {
+                                    // Reveal in Finder
+                                    let _ = std::process::Command::new("open")
+                                        .arg("-R")
+                                        .arg(recording)
+                                        .spawn();
+                                }
+                                if ui.small_button("Export clip").clicked() {
+                                    export_requested = Some(recording.clone());
+                                }
+                            });
                         }
                     }
                 });
             });
 
+        if let Some(recording) = export_requested {
+            match ClipExport::open(recording) {
+                Ok(export) => self.clip_export = Some(export),
+                Err(e) => self.error_message = Some(format!("Can't export clip: {:#}", e)),
+            }
+        }
+        if let Some(export) = &mut self.clip_export {
+            let mut open = true;
+            egui::Window::new("Export clip")
+                .open(&mut open)
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| export.ui(ui));
+            if !open {
+                self.clip_export = None;
+            }
+        }
+
+        self.uploads.update(ctx);
+
         // ---------- central panel (info / errors) ----------
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical_centered(|ui| {
@@ -120,6 +485,40 @@ impl eframe::App for RecorderApp {
                     ui.small("Files land in ~/Movies/TFT Recorder");
                 }
 
+                let leftovers = self.leftovers.as_deref().unwrap_or_default();
+                if !leftovers.is_empty() {
+                    ui.add_space(20.0);
+                    ui.label(format!("{} recording(s) weren't finished properly:", leftovers.len()));
+                    let mut handled = None;
+                    for (i, leftover) in leftovers.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.label(leftover.output.file_name().unwrap_or_default().to_string_lossy());
+                            match leftover.state {
+                                PartialState::Finished => {
+                                    if ui.button("Repair").on_hover_text("The file is complete; move it into place").clicked() {
+                                        handled = Some((i, true));
+                                    }
+                                }
+                                PartialState::Truncated => {
+                                    ui.small("cut off, can't be played");
+                                }
+                            }
+                            if ui.button("Delete").clicked() {
+                                handled = Some((i, false));
+                            }
+                        });
+                    }
+                    if let (Some((i, repair)), Some(leftovers)) = (handled, self.leftovers.as_mut()) {
+                        let leftover = leftovers.remove(i);
+                        let result = if repair { leftover.repair().map(drop) } else { leftover.delete() };
+                        if let Err(e) = result {
+                            self.error_message = Some(format!("{:#}", e));
+                        }
+                    }
+                }
+
+                self.uploads.ui(ui);
+
                 if let Some(err) = &self.error_message {
                     ui.add_space(20.0);
                     ui.colored_label(egui::Color32::LIGHT_RED, err);
@@ -140,11 +539,27 @@ impl eframe::App for RecorderApp {
 
 impl RecorderApp {
     fn start_recording(&mut self) {
-        let output_path = next_file_name();
+        let config = |window: &str| {
+            RecordingConfig::new(window, 1920, 1080)
+                .with_rate_control(recorder_core::RateControl::Abr { bitrate: 6_000_000 })
+                .with_audio(self.audio.clone())
+        };
+        let profile = NamingProfile::from(naming::PROFILES[self.profile]);
+        let output_path = match next_output_path(&config("Teamfight Tactics"), &profile, None) {
+            Ok(path) => path,
+            Err(e) => {
+                self.error_message = Some(format!("Can't name the recording: {:#}", e));
+                return;
+            }
+        };
+        // Layouts such as {year}/{month} may need new folders
+        if let Some(parent) = Path::new(&output_path).parent() {
+            fs::create_dir_all(parent).ok();
+        }
         
         if let Ok(mut recorder) = self.recorder.lock() {
             // Try to record with empty window name for full screen capture
-            match recorder.start("", 1920, 1080, 6_000_000, &output_path) {
+            match recorder.start_with_config(&config(""), &output_path) {
                 Ok(_) => {
                     self.is_recording = true;
                     self.started_at = Some(Local::now());
@@ -152,7 +567,7 @@ impl RecorderApp {
                 }
                 Err(e) => {
                     // Try again with "Teamfight Tactics" as fallback
-                    match recorder.start("Teamfight Tactics", 1920, 1080, 6_000_000, &output_path) {
+                    match recorder.start_with_config(&config("Teamfight Tactics"), &output_path) {
                         Ok(_) => {
                             self.is_recording = true;
                             self.started_at = Some(Local::now());
@@ -174,41 +589,60 @@ pub fn expand_home(path: &str) -> PathBuf {
     PathBuf::from(shellexpand::tilde(path).as_ref())
 }
 
-fn list_recordings() -> Vec<PathBuf> {
-    let dir = expand_home(RECORDINGS_DIR);
-    
-    if let Ok(entries) = fs::read_dir(&dir) {
-        let mut recordings: Vec<PathBuf> = entries
-            .filter_map(|entry| {
-                let entry = entry.ok()?;
-                let path = entry.path();
-                
-                // Only include .mp4 files
-                if path.extension()?.to_str()? == "mp4" {
-                    Some(path)
-                } else {
-                    None
-                }
-            })
-            .collect();
-        
-        // Sort by modification time (newest first)
-        recordings.sort_by(|a, b| {
-            let a_time = fs::metadata(a).and_then(|m| m.modified()).ok();
-            let b_time = fs::metadata(b).and_then(|m| m.modified()).ok();
-            b_time.cmp(&a_time)
-        });
-        
-        recordings
-    } else {
-        Vec::new()
+/// Where recordings are saved and indexed.
+pub fn recordings_dir() -> PathBuf {
+    expand_home(RECORDINGS_DIR)
+}
+
+/// One-line summary of a recording for the recordings list.
+fn describe(entry: &LibraryEntry) -> String {
+    let total = entry.duration_secs.unwrap_or_default() as u64;
+    let mut text = format!("{} · {}:{:02}", entry.date().format("%Y-%m-%d %H:%M"), total / 60, total % 60);
+    if let (Some(width), Some(height)) = (entry.width, entry.height) {
+        text.push_str(&format!(" · {}x{}", width, height));
+    }
+    if let Some(fps) = entry.fps {
+        text.push_str(&format!(" @ {} fps", fps));
+    }
+    if !entry.complete {
+        text.push_str(" · incomplete");
     }
+    if !entry.tags.is_empty() {
+        text.push_str(&format!(" · {}", entry.tags.join(", ")));
+    }
+    text
+}
+
+/// Path for a new recording of `config`. `out` may be a file path or a path
+/// template such as `~/clips/{patch}/{date}-{seq}.mp4`, or a directory to lay
+/// `profile` out in; without it the profile's layout in the recordings
+/// directory is used. Missing directories are created by the caller.
+pub fn next_output_path(config: &RecordingConfig, profile: &NamingProfile, out: Option<&str>) -> anyhow::Result<String> {
+    let info = NameInfo::new(config, Local::now());
+    let path = match out {
+        None => profile.path(&recordings_dir(), &info)?,
+        Some(out) => {
+            let expanded = expand_home(out);
+            if expanded.is_dir() || out.ends_with('/') {
+                profile.path(&expanded, &info)?
+            } else {
+                naming::available_path(&expanded.to_string_lossy(), &profile.name, &info)?
+            }
+        }
+    };
+    Ok(path.to_string_lossy().into_owned())
 }
 
+/// Path for a new recording with the default settings and naming profile.
 pub fn next_file_name() -> String {
+    next_output_path(&RecordingConfig::default(), &NamingProfile::default(), None).expect("the default naming profile is valid")
+}
+
+/// Default destination for `ctl save-replay`, next to the recordings.
+pub fn next_replay_file_name() -> String {
     let timestamp = chrono::Local::now().format("%Y-%m-%d-%H%M%S");
     let dir = expand_home(RECORDINGS_DIR);
-    format!("{}/TFT-{}.mp4", dir.display(), timestamp)
+    format!("{}/Replay-{}.mp4", dir.display(), timestamp)
 }
 
 // Re-export for use in main.rs
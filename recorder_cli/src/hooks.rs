@@ -0,0 +1,317 @@
+// ABOUTME: User commands run on recording events (on_start, on_stop, on_finalized, on_error)
+// ABOUTME: Each hook runs on a thread of its own with TFT_* environment variables, a timeout and a log
+
+use crate::background::BackgroundJobs;
+use anyhow::{Context, Result};
+use recorder_core::events::RecorderEvent;
+use recorder_core::markers::MarkerList;
+use recorder_core::RecordingMetadata;
+use serde::Deserialize;
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_LOG: &str = "~/.tft-recorder/hooks.log";
+
+/// How often a running hook is checked against its timeout.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The `[hooks]` section of the settings file. Each hook is a shell command
+/// run with `sh -c`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HookConfig {
+    /// Capture started.
+    pub on_start: Option<String>,
+    /// Capture stopped, before the file is finalized.
+    pub on_stop: Option<String>,
+    /// A recording, or a segment of one, is finished and in place.
+    pub on_finalized: Option<String>,
+    /// A recording failed to start or to be finalized.
+    pub on_error: Option<String>,
+    /// A hook still running after this long is killed.
+    pub timeout_secs: u64,
+    /// Where hook output and exit statuses are appended.
+    pub log: String,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        Self { on_start: None, on_stop: None, on_finalized: None, on_error: None, timeout_secs: 300, log: DEFAULT_LOG.to_string() }
+    }
+}
+
+impl HookConfig {
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(self.timeout_secs > 0, "hooks.timeout_secs must be at least 1");
+        for hook in Hook::ALL {
+            if let Some(command) = self.command(hook) {
+                anyhow::ensure!(!command.trim().is_empty(), "hooks.{} is empty", hook.name());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn command(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::Start => self.on_start.as_deref(),
+            Hook::Stop => self.on_stop.as_deref(),
+            Hook::Finalized => self.on_finalized.as_deref(),
+            Hook::Error => self.on_error.as_deref(),
+        }
+    }
+
+    /// The hooks that have a command.
+    pub fn configured(&self) -> Vec<Hook> {
+        Hook::ALL.into_iter().filter(|&hook| self.command(hook).is_some()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    Start,
+    Stop,
+    Finalized,
+    Error,
+}
+
+impl Hook {
+    pub const ALL: [Hook; 4] = [Hook::Start, Hook::Stop, Hook::Finalized, Hook::Error];
+
+    /// The key in the settings file, also passed to the hook as `TFT_HOOK`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::Start => "on_start",
+            Hook::Stop => "on_stop",
+            Hook::Finalized => "on_finalized",
+            Hook::Error => "on_error",
+        }
+    }
+}
+
+/// How a hook run ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookOutcome {
+    Exited(Option<i32>),
+    TimedOut,
+}
+
+/// Runs the configured hooks for the events it is handed.
+pub struct HookRunner {
+    config: HookConfig,
+    log: PathBuf,
+    /// Output of the recording in progress, whose sidecars segment hooks get.
+    session: Mutex<Option<PathBuf>>,
+    jobs: BackgroundJobs,
+}
+
+impl HookRunner {
+    /// Runs the hooks in `config` as `jobs`.
+    pub fn new(config: HookConfig, jobs: BackgroundJobs) -> Self {
+        let log = PathBuf::from(shellexpand::tilde(&config.log).as_ref());
+        Self { config, log, session: Mutex::default(), jobs }
+    }
+
+    /// Starts the hook `event` calls for, if one is configured, without
+    /// waiting for it.
+    pub fn handle(&self, event: &RecorderEvent) {
+        if let RecorderEvent::RecordingStarted { path } = event {
+            *self.session.lock().unwrap() = Some(path.clone());
+        }
+        let session = self.session.lock().unwrap().clone();
+        let Some((hook, env)) = invocation(event, session.as_deref()) else {
+            return;
+        };
+        let Some(command) = self.config.command(hook).map(str::to_string) else {
+            return;
+        };
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let log = self.log.clone();
+        self.jobs.spawn(move || {
+            if let Err(e) = run(hook, &command, &env, timeout, &log) {
+                eprintln!("Hook {} failed: {:#}", hook.name(), e);
+            }
+        });
+    }
+}
+
+/// The hook `event` triggers and the environment describing it. `session`
+/// is the output of the recording the event belongs to, if known.
+pub fn invocation(event: &RecorderEvent, session: Option<&Path>) -> Option<(Hook, Vec<(&'static str, String)>)> {
+    let (hook, path, mut env) = match event {
+        RecorderEvent::RecordingStarted { path } => (Hook::Start, path, vec![]),
+        RecorderEvent::RecordingStopped { path, duration_secs } => (Hook::Stop, path, vec![("TFT_DURATION_SECS", format!("{:.3}", duration_secs))]),
+        RecorderEvent::RecordingFinalized { path, duration_secs, bytes } => {
+            (Hook::Finalized, path, vec![("TFT_DURATION_SECS", format!("{:.3}", duration_secs)), ("TFT_BYTES", bytes.to_string())])
+        }
+        RecorderEvent::SegmentFinalized { index, path, start_secs, duration_secs, bytes } => (
+            Hook::Finalized,
+            path,
+            vec![
+                ("TFT_DURATION_SECS", format!("{:.3}", duration_secs)),
+                ("TFT_BYTES", bytes.to_string()),
+                ("TFT_SEGMENT_INDEX", index.to_string()),
+                ("TFT_SEGMENT_START_SECS", format!("{:.3}", start_secs)),
+            ],
+        ),
+        RecorderEvent::RecordingFailed { path, message } => (Hook::Error, path, vec![("TFT_ERROR", message.clone())]),
+        RecorderEvent::DiskSpaceLow { .. } | RecorderEvent::StoppedForDiskSpace { .. } => return None,
+    };
+    env.insert(0, ("TFT_HOOK", hook.name().to_string()));
+    env.insert(1, ("TFT_RECORDING_PATH", path.display().to_string()));
+    // Sidecars belong to the whole recording, not to a single segment
+    let output = match event {
+        RecorderEvent::SegmentFinalized { .. } => session,
+        _ => Some(path.as_path()),
+    };
+    if let Some(output) = output {
+        let metadata = RecordingMetadata::sidecar_path(output);
+        if metadata.exists() {
+            env.push(("TFT_METADATA_FILE", metadata.display().to_string()));
+        }
+        let markers = MarkerList::sidecar_path(output);
+        if markers.exists() {
+            env.push(("TFT_MARKERS_FILE", markers.display().to_string()));
+        }
+    }
+    Some((hook, env))
+}
+
+/// Runs `command` with `env`, appending its output and how it ended to
+/// `log`. A hook outliving `timeout` is killed along with everything it
+/// started.
+pub fn run(hook: Hook, command: &str, env: &[(&str, String)], timeout: Duration, log: &Path) -> Result<HookOutcome> {
+    if let Some(parent) = log.parent() {
+        std::fs::create_dir_all(parent).ok();
+    }
+    let mut log_file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .with_context(|| format!("Failed to open hook log {}", log.display()))?;
+    let stamp = || chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+    writeln!(log_file, "[{}] {}: {}", stamp(), hook.name(), command)?;
+
+    let started = Instant::now();
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file.try_clone()?)
+        // A group of its own, so a timeout reaches what the shell started too
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("Failed to run {} hook", hook.name()))?;
+    let outcome = loop {
+        if let Some(status) = child.try_wait()? {
+            break HookOutcome::Exited(status.code());
+        }
+        if started.elapsed() >= timeout {
+            // The group's ID is the shell's PID
+            unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) };
+            child.wait().ok();
+            break HookOutcome::TimedOut;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    let elapsed = started.elapsed().as_secs_f64();
+    match outcome {
+        HookOutcome::Exited(Some(0)) => writeln!(log_file, "[{}] {} finished in {:.1} s", stamp(), hook.name(), elapsed)?,
+        HookOutcome::Exited(code) => {
+            let status = code.map_or("a signal".to_string(), |code| format!("status {}", code));
+            writeln!(log_file, "[{}] {} exited with {} after {:.1} s", stamp(), hook.name(), status, elapsed)?;
+            eprintln!("Hook {} exited with {}; see {}", hook.name(), status, log.display());
+        }
+        HookOutcome::TimedOut => {
+            writeln!(log_file, "[{}] {} timed out after {} s and was killed", stamp(), hook.name(), timeout.as_secs_f64())?;
+            eprintln!("Hook {} timed out after {} s and was killed", hook.name(), timeout.as_secs_f64());
+        }
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tft_hooks_{}_{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_finalized_hook_gets_recording_env() {
+        let dir = temp_dir("env");
+        let output = dir.join("TFT-1.mp4");
+        std::fs::write(&output, b"").unwrap();
+        std::fs::write(MarkerList::sidecar_path(&output), b"[]").unwrap();
+        let env_file = dir.join("env.txt");
+        let config = HookConfig {
+            on_finalized: Some(format!("env | grep ^TFT_ | sort > '{}'", env_file.display())),
+            log: dir.join("hooks.log").display().to_string(),
+            ..Default::default()
+        };
+        let jobs = BackgroundJobs::default();
+        let hooks = HookRunner::new(config, jobs.clone());
+
+        // Not configured: nothing runs
+        hooks.handle(&RecorderEvent::RecordingStarted { path: output.clone() });
+        assert_eq!(jobs.running(), 0);
+
+        hooks.handle(&RecorderEvent::RecordingFinalized { path: output.clone(), duration_secs: 12.5, bytes: 2048 });
+        jobs.wait();
+        let env = std::fs::read_to_string(&env_file).unwrap();
+        assert!(env.contains("TFT_HOOK=on_finalized\n"), "{}", env);
+        assert!(env.contains(&format!("TFT_RECORDING_PATH={}\n", output.display())));
+        assert!(env.contains("TFT_DURATION_SECS=12.500\n") && env.contains("TFT_BYTES=2048\n"));
+        assert!(env.contains(&format!("TFT_MARKERS_FILE={}\n", MarkerList::sidecar_path(&output).display())));
+        assert!(!env.contains("TFT_METADATA_FILE"));
+        let log = std::fs::read_to_string(dir.join("hooks.log")).unwrap();
+        assert!(log.contains("on_finalized finished"), "{}", log);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_segment_hooks_get_the_session_sidecars() {
+        let session = PathBuf::from("/rec/TFT-1.mp4");
+        let segment = RecorderEvent::SegmentFinalized { index: 2, path: PathBuf::from("/rec/TFT-1-002.mp4"), start_secs: 600.0, duration_secs: 600.0, bytes: 1 };
+        let (hook, env) = invocation(&segment, Some(&session)).unwrap();
+        assert_eq!(hook, Hook::Finalized);
+        assert!(env.contains(&("TFT_RECORDING_PATH", "/rec/TFT-1-002.mp4".to_string())));
+        assert!(env.contains(&("TFT_SEGMENT_INDEX", "2".to_string())));
+
+        let failed = RecorderEvent::RecordingFailed { path: session.clone(), message: "No window".into() };
+        assert_eq!(invocation(&failed, None).unwrap().1[2], ("TFT_ERROR", "No window".to_string()));
+        assert!(invocation(&RecorderEvent::DiskSpaceLow { available_bytes: 0, remaining_secs: 0.0 }, None).is_none());
+    }
+
+    #[test]
+    fn test_hook_is_killed_after_timeout() {
+        let dir = temp_dir("timeout");
+        let log = dir.join("hooks.log");
+        let started = Instant::now();
+        let outcome = run(Hook::Stop, "echo waiting; sleep 10", &[], Duration::from_millis(200), &log).unwrap();
+        assert_eq!(outcome, HookOutcome::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let text = std::fs::read_to_string(&log).unwrap();
+        assert!(text.contains("waiting\n") && text.contains("on_stop timed out"), "{}", text);
+
+        assert_eq!(run(Hook::Error, "exit 3", &[], Duration::from_secs(5), &log).unwrap(), HookOutcome::Exited(Some(3)));
+
+        // Whatever the shell started goes down with it
+        let survivor = dir.join("survived");
+        let command = format!("(sleep 1; touch '{}') & wait", survivor.display());
+        assert_eq!(run(Hook::Stop, &command, &[], Duration::from_millis(200), &log).unwrap(), HookOutcome::TimedOut);
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!survivor.exists());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,118 @@
+// ABOUTME: JSON-lines protocol spoken between `recorder ctl` and `recorder daemon`
+// ABOUTME: One request object per line in, one response object per line out, over a Unix socket; subscriptions then stream events
+
+use anyhow::{bail, Context, Result};
+use recorder_core::{GameInfo, RecordingConfig};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+pub const DEFAULT_SOCKET: &str = "/tmp/tft-recorder.sock";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    Start { config: Box<RecordingConfig>, output: String },
+    Stop,
+    Status,
+    /// `output` defaults to a timestamped file in the recordings directory.
+    SaveReplay { seconds: Option<u32>, output: Option<String> },
+    /// `at_secs` is measured from the start of the recording; defaults to now.
+    Mark { label: String, at_secs: Option<f64> },
+    /// Fields left out keep their previous value.
+    Game { game: GameInfo },
+    /// After the response, every game event as a JSON line until the client
+    /// hangs up.
+    GameEvents,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub result: serde_json::Value,
+}
+
+impl Response {
+    pub fn success(result: serde_json::Value) -> Self {
+        Self { ok: true, error: None, result }
+    }
+
+    pub fn failure(error: &anyhow::Error) -> Self {
+        Self { ok: false, error: Some(format!("{:#}", error)), result: serde_json::Value::Null }
+    }
+}
+
+/// Sends one request to the daemon and returns its result, turning an error
+/// response into an `Err`.
+pub fn send(socket: &Path, request: &Request) -> Result<serde_json::Value> {
+    let (_, result) = request_on(socket, request)?;
+    Ok(result)
+}
+
+/// Sends a subscription request such as [`Request::GameEvents`] and hands
+/// each streamed event to `on_event` until the daemon closes the connection.
+pub fn subscribe(socket: &Path, request: &Request, mut on_event: impl FnMut(serde_json::Value) -> Result<()>) -> Result<()> {
+    let (mut reader, _) = request_on(socket, request)?;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        if !line.trim().is_empty() {
+            on_event(serde_json::from_str(&line).context("Malformed event from the daemon")?)?;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Sends `request` and reads its response, leaving the connection open.
+fn request_on(socket: &Path, request: &Request) -> Result<(BufReader<UnixStream>, serde_json::Value)> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("Failed to connect to daemon at {}", socket.display()))?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut reply = String::new();
+    reader.read_line(&mut reply)?;
+    if reply.is_empty() {
+        bail!("Daemon closed the connection without replying");
+    }
+    let response: Response = serde_json::from_str(&reply).context("Malformed daemon response")?;
+    if !response.ok {
+        bail!("{}", response.error.unwrap_or_else(|| "Daemon request failed".into()));
+    }
+    Ok((reader, response.result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_wire_format() {
+        let request = Request::SaveReplay { seconds: Some(15), output: None };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(json, r#"{"cmd":"save_replay","seconds":15,"output":null}"#);
+
+        let parsed: Request = serde_json::from_str(r#"{"cmd":"status"}"#).unwrap();
+        assert_eq!(parsed, Request::Status);
+
+        assert_eq!(serde_json::from_str::<Request>(r#"{"cmd":"game_events"}"#).unwrap(), Request::GameEvents);
+        let mark: Request = serde_json::from_str(r#"{"cmd":"mark","label":"Stage 2-1"}"#).unwrap();
+        assert_eq!(mark, Request::Mark { label: "Stage 2-1".into(), at_secs: None });
+
+        let start = Request::Start { config: Box::new(RecordingConfig::new("TFT", 1280, 720)), output: "/tmp/a.mp4".into() };
+        let round_trip: Request = serde_json::from_str(&serde_json::to_string(&start).unwrap()).unwrap();
+        assert_eq!(round_trip, start);
+    }
+
+    #[test]
+    fn test_failure_response_omits_result() {
+        let response = Response::failure(&anyhow::anyhow!("nope"));
+        assert_eq!(serde_json::to_string(&response).unwrap(), r#"{"ok":false,"error":"nope"}"#);
+    }
+}
@@ -2,12 +2,23 @@
 // ABOUTME: Provides user-friendly interface for screen capture and plugin management
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use recorder_core::Recorder;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use recorder_core::{AnimationOptions, AudioCodec, AudioConfig, CropPreset, DiskConfig, FrameRateMode, GameInfo, HighlightOptions, NamingProfile, RateControl, Recorder, RecordingConfig, RecordingMetadata, ReplayConfig, SegmentConfig, TimelapseOptions, TimelapseSampling, TrimOptions, VerticalOptions};
+use recorder_core::events::RecorderEvent;
+use recorder_core::partial::PartialState;
+use recorder_core::mp4::{Mp4Reader, TrackInfo};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+mod background;
+mod daemon;
+mod game;
 pub mod gui;
+mod hooks;
+mod ipc;
+mod settings;
+mod uploads;
+mod webhooks;
 
 #[derive(Parser)]
 #[command(name = "recorder")]
@@ -16,29 +27,20 @@ pub mod gui;
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Settings file: [hooks], [[webhooks]], [upload] and [game]
+    #[arg(long, global = true, value_name = "FILE", default_value = settings::DEFAULT_SETTINGS)]
+    settings: String,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Record TFT gameplay
     Record {
-        /// Window title to capture
-        #[arg(long, default_value = "Teamfight Tactics")]
-        window: String,
-        
-        /// Video width in pixels
-        #[arg(long, default_value = "1280")]
-        width: u32,
-        
-        /// Video height in pixels
-        #[arg(long, default_value = "720")]
-        height: u32,
+        #[command(flatten)]
+        args: RecordArgs,
         
-        /// Video bitrate in bits per second
-        #[arg(long, default_value = "4000000")]
-        bitrate: u32,
-        
-        /// Output file path (defaults to ~/Movies/TFT Recorder/TFT-timestamp.mp4)
+        /// Output file, path template or directory (defaults to the naming profile's layout in ~/Movies/TFT Recorder)
         #[arg(long)]
         out: Option<String>,
         
@@ -57,131 +59,1714 @@ enum Commands {
     /// Run as daemon for background recording
     Daemon {
         /// Unix socket path for IPC
-        #[arg(long, default_value = "/tmp/tft-recorder.sock")]
+        #[arg(long, default_value = ipc::DEFAULT_SOCKET)]
+        socket: String,
+        
+        #[command(flatten)]
+        retention: RetentionArgs,
+        
+        /// How often to apply the retention rules (HH:MM:SS, MM:SS or seconds)
+        #[arg(long, value_parser = parse_timestamp, default_value = "1:00:00")]
+        prune_every: std::time::Duration,
+    },
+    
+    /// Show the tracks, chapters and embedded metadata of a recording
+    Probe {
+        /// MP4 file to inspect
+        file: std::path::PathBuf,
+        
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    
+    /// Cut a time range out of a recording without re-encoding it
+    Trim {
+        /// Recording to cut
+        input: std::path::PathBuf,
+        
+        /// Start of the range (HH:MM:SS, MM:SS or seconds)
+        #[arg(long, value_parser = parse_timestamp, default_value = "0")]
+        from: std::time::Duration,
+        
+        /// End of the range (defaults to the end of the recording)
+        #[arg(long, value_parser = parse_timestamp)]
+        to: Option<std::time::Duration>,
+        
+        /// Output file path (defaults to <input>-trim.mp4)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+        
+        /// Start exactly at --from by re-encoding up to the next keyframe,
+        /// instead of moving the cut back to the previous one
+        #[arg(long)]
+        exact: bool,
+    },
+    
+    /// Save a frame of a recording as a PNG or JPEG thumbnail
+    Thumbnail {
+        /// Recording to take the frame from
+        file: std::path::PathBuf,
+        
+        /// Time of the frame (HH:MM:SS, MM:SS or seconds); defaults to 5:00,
+        /// or half-way through shorter recordings
+        #[arg(long, value_parser = parse_timestamp)]
+        at: Option<std::time::Duration>,
+        
+        /// Image to write (.png, .jpg); defaults to the cached thumbnail next to the recording
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+        
+        /// Maximum width in pixels (0 keeps the recording's size)
+        #[arg(long, default_value_t = recorder_core::thumbnail::THUMBNAIL_WIDTH, requires = "out")]
+        width: u32,
+        
+        /// Decode the exact frame at --at instead of the keyframe before it
+        #[arg(long, requires = "out")]
+        exact: bool,
+    },
+    
+    /// Export a short range of a recording as an animated GIF or WebP
+    ExportGif {
+        /// Recording to take the clip from
+        input: std::path::PathBuf,
+        
+        /// Start of the range (HH:MM:SS, MM:SS or seconds)
+        #[arg(long, value_parser = parse_timestamp, default_value = "0")]
+        from: std::time::Duration,
+        
+        /// End of the range (defaults to the end of the recording)
+        #[arg(long, value_parser = parse_timestamp)]
+        to: Option<std::time::Duration>,
+        
+        /// Maximum width in pixels
+        #[arg(long, default_value = "480")]
+        width: u32,
+        
+        /// Frames per second
+        #[arg(long, default_value = "15")]
+        fps: u32,
+        
+        /// Lower fps and width until the file fits in this many megabytes
+        #[arg(long)]
+        max_mb: Option<f64>,
+        
+        /// Output file, .gif or .webp (defaults to <input>-clip.gif)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+    
+    /// Export a range of a recording as a vertical 9:16 clip
+    ExportClip {
+        /// Recording to take the clip from
+        input: std::path::PathBuf,
+        
+        /// Start of the range (HH:MM:SS, MM:SS or seconds)
+        #[arg(long, value_parser = parse_timestamp, default_value = "0")]
+        from: std::time::Duration,
+        
+        /// End of the range (defaults to the end of the recording)
+        #[arg(long, value_parser = parse_timestamp)]
+        to: Option<std::time::Duration>,
+        
+        /// Region to show: board, bench-shop or augments
+        #[arg(long, default_value = "board")]
+        preset: String,
+        
+        /// Output file path (defaults to <input>-vertical.mp4)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+    
+    /// Condense a recording into a short timelapse
+    Timelapse {
+        /// Recording to condense
+        input: std::path::PathBuf,
+        
+        /// Speed-up factor, e.g. 20x
+        #[arg(long, value_parser = parse_speed, default_value = "20x", conflicts_with_all = ["every", "at_markers"])]
+        speed: f64,
+        
+        /// Take one frame per interval instead (HH:MM:SS, MM:SS or seconds)
+        #[arg(long, value_parser = parse_timestamp, conflicts_with = "at_markers")]
+        every: Option<std::time::Duration>,
+        
+        /// Take one frame per marker instead, each shown for a second
+        #[arg(long)]
+        at_markers: bool,
+        
+        /// Frames per second of the timelapse
+        #[arg(long, default_value = "30")]
+        fps: u32,
+        
+        /// Output file path (defaults to <input>-timelapse.mp4)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+    
+    /// Cut a window around each marker into one highlight reel
+    Highlights {
+        /// Recordings, or directories of recordings, to take highlights from
+        #[arg(required = true)]
+        inputs: Vec<std::path::PathBuf>,
+        
+        /// Time kept before each marker
+        #[arg(long, value_parser = parse_timestamp, default_value = "5s")]
+        pre: std::time::Duration,
+        
+        /// Time kept after each marker
+        #[arg(long, value_parser = parse_timestamp, default_value = "10s")]
+        post: std::time::Duration,
+        
+        /// Only use markers whose label contains this (repeatable)
+        #[arg(long = "label")]
+        labels: Vec<String>,
+        
+        /// Output file path (defaults to <input>-highlights.mp4, or
+        /// highlights.mp4 inside a directory)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+    
+    /// Join recordings or segments into one file without re-encoding
+    Concat {
+        /// Recordings to join, in order; an .m3u8 playlist stands for its segments
+        #[arg(required = true)]
+        inputs: Vec<std::path::PathBuf>,
+        
+        /// Output file path
+        #[arg(long)]
+        out: std::path::PathBuf,
+    },
+    
+    /// Index and list the recordings directory
+    Library {
+        /// Recordings directory (defaults to ~/Movies/TFT Recorder)
+        #[arg(long, global = true)]
+        dir: Option<std::path::PathBuf>,
+        
+        #[command(subcommand)]
+        command: LibraryCommand,
+    },
+    
+    /// Control a running daemon
+    Ctl {
+        /// Unix socket path of the daemon
+        #[arg(long, default_value = ipc::DEFAULT_SOCKET)]
         socket: String,
+        
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+
+    /// Check the webhooks in the settings file
+    Webhooks {
+        #[command(subcommand)]
+        command: WebhookCommand,
+    },
+
+    /// Show and work through the queue of uploads to the [upload] target of the settings file
+    Uploads {
+        #[command(subcommand)]
+        command: Option<UploadCommand>,
+    },
+
+    /// Follow matches through the game's Live Client Data API
+    Game {
+        #[command(subcommand)]
+        command: GameCommand,
     },
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Subcommand, Debug)]
+enum GameCommand {
+    /// Print match events as they happen until Ctrl+C
+    Watch {
+        /// API base URL, instead of game.url (e.g. a local mock)
+        #[arg(long)]
+        url: Option<String>,
+        /// Print each event as a JSON line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the running match's mode, time and players once
+    Status {
+        /// API base URL, instead of game.url (e.g. a local mock)
+        #[arg(long)]
+        url: Option<String>,
+        /// Print the game's full data as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum UploadCommand {
+    /// List queued, running, failed and finished uploads (the default)
+    List {
+        /// Print the queue as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Queue recordings, with their metadata and marker sidecars
+    Add {
+        #[arg(required = true)]
+        files: Vec<std::path::PathBuf>,
+    },
+    /// Upload everything queued, in the foreground; Ctrl+C stops after the current part
+    Run {
+        /// Bandwidth cap in megabits per second, instead of upload.max_mbps (0 for none)
+        #[arg(long)]
+        max_mbps: Option<f64>,
+    },
+    /// Queue failed uploads again
+    Retry,
+    /// Forget finished uploads
+    Clear,
+    /// Remove an upload from the queue
+    Rm {
+        id: u64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum WebhookCommand {
+    /// Post a sample recording_finalized payload marked "test": true to every webhook
+    Test {
+        /// Only post to this URL (it needn't be in the settings file)
+        #[arg(long)]
+        url: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum LibraryCommand {
+    /// Bring the index up to date with the directory
+    Scan,
+    /// List recordings, newest first unless --sort says otherwise
+    List {
+        #[command(flatten)]
+        filter: LibraryFilterArgs,
+        
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// List recordings whose name, tags or marker labels contain some text
+    Search {
+        /// Text to look for (case-insensitive)
+        text: String,
+        
+        #[command(flatten)]
+        filter: LibraryFilterArgs,
+        
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show everything the index knows about a recording
+    Info {
+        /// Recording, as a path or a file name in the library
+        recording: std::path::PathBuf,
+        
+        /// Print JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add tags to a recording's metadata
+    Tag {
+        /// Recording, as a path or a file name in the library
+        recording: std::path::PathBuf,
+        
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from a recording's metadata
+    Untag {
+        /// Recording, as a path or a file name in the library
+        recording: std::path::PathBuf,
+        
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Delete recordings along with their sidecars and thumbnails
+    Rm {
+        /// Recordings, as paths or file names in the library
+        #[arg(required = true)]
+        recordings: Vec<std::path::PathBuf>,
+    },
+    /// Delete recordings the retention rules don't keep, oldest first
+    Prune {
+        #[command(flatten)]
+        retention: RetentionArgs,
+        
+        /// Only report what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+        
+        /// Print JSON instead of a report
+        #[arg(long)]
+        json: bool,
+    },
+    /// Move recordings that were finished but never renamed from their
+    /// `.partial` name into place, and list (or delete) ones cut off mid-recording
+    Repair {
+        /// Delete partials that were cut off and can't be played
+        #[arg(long)]
+        delete_truncated: bool,
+        
+        /// Only report what would be done
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Rename a recording, or move it to another directory, along with its
+    /// sidecars and thumbnails
+    Mv {
+        /// Recording, as a path or a file name in the library
+        recording: std::path::PathBuf,
+        
+        /// New name (kept in the library) or path
+        to: std::path::PathBuf,
+    },
+}
+
+/// Retention rules shared by `library prune` and `daemon`.
+#[derive(Args, Debug)]
+struct RetentionArgs {
+    /// Delete recordings older than this many days
+    #[arg(long)]
+    keep_days: Option<u32>,
     
-    match cli.command {
-        Some(Commands::Record { window, width, height, bitrate, out, duration }) => {
-            let output_path = out.unwrap_or_else(|| gui::get_default_output_path());
-            record_command(window, width, height, bitrate, output_path, duration)
+    /// Delete the oldest recordings while the library is over this many GB
+    #[arg(long)]
+    max_gb: Option<f64>,
+    
+    /// Let tagged (e.g. starred) recordings be deleted too
+    #[arg(long)]
+    prune_tagged: bool,
+}
+
+impl RetentionArgs {
+    fn to_policy(&self) -> recorder_library::RetentionPolicy {
+        recorder_library::RetentionPolicy {
+            keep_days: self.keep_days,
+            max_bytes: self.max_gb.map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as u64),
+            keep_tagged: !self.prune_tagged,
         }
-        Some(Commands::Host { port }) => {
-            host_command(port)
+    }
+}
+
+/// Filters and sorting shared by `library list` and `library search`.
+#[derive(Args, Debug)]
+struct LibraryFilterArgs {
+    /// Recorded on or after this day (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    since: Option<chrono::NaiveDate>,
+    
+    /// Recorded on or before this day (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    until: Option<chrono::NaiveDate>,
+    
+    /// At least this long (HH:MM:SS, MM:SS or seconds)
+    #[arg(long, value_parser = parse_timestamp)]
+    min_duration: Option<std::time::Duration>,
+    
+    /// At most this long (HH:MM:SS, MM:SS or seconds)
+    #[arg(long, value_parser = parse_timestamp)]
+    max_duration: Option<std::time::Duration>,
+    
+    /// Only recordings with this tag (repeatable; all must match)
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    
+    /// Game patch, e.g. 14.20
+    #[arg(long)]
+    patch: Option<String>,
+    
+    /// Placement, or a range such as 1-4
+    #[arg(long, value_parser = parse_placement)]
+    placement: Option<(u8, u8)>,
+    
+    /// Column to sort by
+    #[arg(long, value_enum, default_value = "date")]
+    sort: SortArg,
+    
+    /// Reverse the sort order
+    #[arg(long)]
+    reverse: bool,
+    
+    /// Show at most this many recordings
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+impl LibraryFilterArgs {
+    fn to_query(&self, text: Option<String>) -> recorder_library::LibraryQuery {
+        use chrono::TimeZone;
+        let midnight = |day: chrono::NaiveDate| chrono::Local.from_local_datetime(&day.and_time(chrono::NaiveTime::MIN)).earliest();
+        recorder_library::LibraryQuery {
+            since: self.since.and_then(midnight),
+            // Up to the end of the day
+            until: self.until.and_then(|day| day.succ_opt()).and_then(midnight),
+            min_duration: self.min_duration,
+            max_duration: self.max_duration,
+            tags: self.tags.clone(),
+            patch: self.patch.clone(),
+            placement: self.placement,
+            text,
+            sort: self.sort.into(),
+            reverse: self.reverse,
+            limit: self.limit,
         }
-        Some(Commands::Daemon { socket }) => {
-            daemon_command(socket)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SortArg {
+    /// Newest first
+    Date,
+    /// Longest first
+    Duration,
+    /// Largest first
+    Size,
+    /// Best placement first
+    Placement,
+    /// By file name
+    Name,
+}
+
+impl From<SortArg> for recorder_library::SortKey {
+    fn from(arg: SortArg) -> Self {
+        match arg {
+            SortArg::Date => Self::Date,
+            SortArg::Duration => Self::Duration,
+            SortArg::Size => Self::Size,
+            SortArg::Placement => Self::Placement,
+            SortArg::Name => Self::Name,
         }
-        None => {
-            // Launched from Finder - show GUI
-            gui::launch()
+    }
+}
+
+#[derive(Subcommand)]
+enum CtlCommand {
+    /// Start recording in the daemon
+    Start {
+        #[command(flatten)]
+        args: Box<RecordArgs>,
+        
+        /// Output file, path template or directory (defaults to the naming profile's layout in ~/Movies/TFT Recorder)
+        #[arg(long)]
+        out: Option<String>,
+    },
+    /// Stop the daemon's recording
+    Stop,
+    /// Show whether the daemon is recording
+    Status,
+    /// Write the replay buffer to a file without interrupting the recording
+    SaveReplay {
+        /// Seconds to save (defaults to the whole buffer)
+        #[arg(long)]
+        seconds: Option<u32>,
+        
+        /// Output file path (defaults to ~/Movies/TFT Recorder/Replay-timestamp.mp4)
+        #[arg(long)]
+        out: Option<String>,
+    },
+    /// Mark a moment in the recording; markers become chapters in the file
+    Mark {
+        /// Chapter title, e.g. "Stage 2-1"
+        label: String,
+        
+        /// Seconds from the start of the recording (defaults to now)
+        #[arg(long)]
+        at: Option<f64>,
+    },
+    /// Update the game details embedded in the recording
+    Game {
+        #[command(flatten)]
+        game: GameArgs,
+        
+        /// Final placement (1-8)
+        #[arg(long)]
+        placement: Option<u8>,
+    },
+    /// Print the daemon's game events as JSON lines until interrupted
+    GameEvents,
+}
+
+#[derive(Args, Debug)]
+struct GameArgs {
+    /// Game patch, e.g. 14.20
+    #[arg(long)]
+    patch: Option<String>,
+    
+    /// Ranked tier, e.g. "Diamond II"
+    #[arg(long)]
+    rank: Option<String>,
+}
+
+/// Where a recording goes and what it's called, for `record` and `ctl start`.
+#[derive(Args, Debug)]
+struct NamingArgs {
+    /// Naming profile: default, monthly ({year}/{month}/), daily ({year}/{month}/{day}/) or patch ({patch}/)
+    #[arg(long, default_value = "default")]
+    profile: String,
+    
+    /// Folder layout below the recordings directory, overriding the profile's, e.g. "{year}/{month}"
+    #[arg(long)]
+    layout: Option<String>,
+    
+    /// File name template, overriding the profile's; {date}, {time}, {year}, {month}, {day}, {profile}, {window}, {patch}, {placement} and {seq} are replaced
+    #[arg(long)]
+    name_template: Option<String>,
+}
+
+impl NamingArgs {
+    fn to_profile(&self) -> Result<NamingProfile> {
+        let mut profile = NamingProfile::named(&self.profile)?;
+        if let Some(layout) = &self.layout {
+            profile = profile.with_directory(layout);
         }
+        if let Some(template) = &self.name_template {
+            profile = profile.with_file_name(template);
+        }
+        profile.validate()?;
+        Ok(profile)
     }
 }
 
-fn record_command(
+/// Capture and encoding options shared by `record` and `ctl start`.
+#[derive(Args, Debug)]
+struct RecordArgs {
+    /// Window title to capture
+    #[arg(long, default_value = "Teamfight Tactics")]
     window: String,
+    
+    /// Video width in pixels
+    #[arg(long, default_value = "1280")]
     width: u32,
+    
+    /// Video height in pixels
+    #[arg(long, default_value = "720")]
     height: u32,
+    
+    /// Video bitrate in bits per second
+    #[arg(long, default_value = "4000000")]
     bitrate: u32,
-    out: String,
-    duration: u32,
-) -> Result<()> {
-    // Ensure the output directory exists
-    if let Some(parent) = std::path::Path::new(&out).parent() {
-        std::fs::create_dir_all(parent)?;
-    }
-    println!("Starting recording...");
-    println!("Window: {}", window);
-    println!("Resolution: {}x{}", width, height);
-    println!("Bitrate: {} bps", bitrate);
-    println!("Output: {}", out);
     
-    let mut recorder = Recorder::new();
+    /// Rate-control mode
+    #[arg(long, value_enum, default_value = "abr")]
+    rate_control: RateControlArg,
     
-    // Set up graceful shutdown
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
+    /// Peak bitrate in bits per second (VBR, capped CRF)
+    #[arg(long)]
+    max_bitrate: Option<u32>,
     
-    ctrlc::set_handler(move || {
-        println!("\nStopping recording...");
-        r.store(false, Ordering::SeqCst);
-    })?;
+    /// Constant rate factor for CRF mode (0 best – 51 worst)
+    #[arg(long, default_value = "23")]
+    crf: u8,
     
-    // Start recording
-    if let Err(e) = recorder.start(&window, width, height, bitrate, &out) {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
-    }
-    println!("Recording started. Press Ctrl+C to stop.");
+    /// Maximum frames between keyframes
+    #[arg(long, default_value = "60")]
+    keyframe_interval: u32,
     
-    // Wait for duration or interrupt
-    if duration > 0 {
-        println!("Recording for {} seconds...", duration);
-        let start = std::time::Instant::now();
-        
-        while running.load(Ordering::SeqCst) && start.elapsed().as_secs() < duration as u64 {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-    } else {
-        // Wait for Ctrl+C
-        while running.load(Ordering::SeqCst) {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-    }
+    /// Output frame rate
+    #[arg(long, default_value = "60")]
+    fps: u32,
     
-    // Stop recording
-    recorder.stop();
-    println!("Recording saved to: {}", out);
+    /// Keep capture timestamps (variable frame rate) instead of pacing to --fps
+    #[arg(long)]
+    vfr: bool,
     
-    Ok(())
-}
-
-fn host_command(port: u16) -> Result<()> {
-    println!("Starting extension host on port {}...", port);
+    /// Don't record system (game) audio
+    #[arg(long)]
+    no_game_audio: bool,
     
-    // In a real implementation, this would launch the Node.js process
-    // For now, we'll just spawn it as a subprocess
-    let status = std::process::Command::new("node")
-        .arg("extension-host/dist/index.js")
-        .arg("--port")
-        .arg(port.to_string())
-        .status()?;
+    /// Record the microphone as a separate track
+    #[arg(long)]
+    mic: bool,
     
-    if !status.success() {
-        anyhow::bail!("Extension host failed to start");
-    }
+    /// Microphone device (defaults to the system input)
+    #[arg(long, requires = "mic")]
+    mic_device: Option<String>,
     
-    Ok(())
-}
-
-fn daemon_command(socket: String) -> Result<()> {
-    println!("Starting recorder daemon on socket: {}", socket);
+    /// Audio codec
+    #[arg(long, value_enum, default_value = "aac")]
+    audio_codec: AudioCodecArg,
     
-    // Set up the async runtime for gRPC
-    let runtime = tokio::runtime::Runtime::new()?;
+    /// Audio bitrate per track in bits per second
+    #[arg(long, default_value = "160000")]
+    audio_bitrate: u32,
     
-    runtime.block_on(async {
-        // In a real implementation, this would start the gRPC server
-        println!("Daemon started. Listening for commands...");
-        
-        // Keep running until interrupted
-        tokio::signal::ctrl_c().await?;
-        println!("Daemon shutting down...");
-        
-        Ok::<(), anyhow::Error>(())
-    })?;
+    /// Keep the last N seconds in memory for `ctl save-replay` (0 disables)
+    #[arg(long, default_value = "0")]
+    replay_seconds: u32,
     
-    Ok(())
+    /// Memory cap for the replay buffer in megabytes
+    #[arg(long, default_value = "256")]
+    replay_max_mb: u32,
+    
+    /// Start a new file every N minutes (0 disables)
+    #[arg(long, default_value = "0")]
+    segment_minutes: u32,
+    
+    /// Start a new file every N megabytes (0 disables)
+    #[arg(long, default_value = "0")]
+    segment_mb: u32,
+    
+    /// Segment file name; {date}, {n} and {stem} are replaced
+    #[arg(long, default_value = recorder_core::config::DEFAULT_SEGMENT_TEMPLATE)]
+    segment_template: String,
+    
+    /// Free space to always leave on the volume in megabytes; recording stops cleanly when it's reached
+    #[arg(long, default_value = "1024")]
+    min_free_mb: u32,
+    
+    /// Minutes of recording there must be room for to start
+    #[arg(long, default_value = "45")]
+    expected_minutes: u32,
+    
+    /// Tag saved in the recording's metadata (repeatable)
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    
+    /// Title embedded in the file (defaults to "TFT <date>")
+    #[arg(long)]
+    title: Option<String>,
+    
+    /// Comment embedded in the file
+    #[arg(long)]
+    comment: Option<String>,
+    
+    #[command(flatten)]
+    game: GameArgs,
+    
+    #[command(flatten)]
+    naming: NamingArgs,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl RecordArgs {
+    fn to_config(&self) -> Result<RecordingConfig> {
+        let frame_rate_mode = if self.vfr { FrameRateMode::Variable } else { FrameRateMode::Constant };
+        let audio = AudioConfig {
+            game: !self.no_game_audio,
+            mic: self.mic,
+            mic_device: self.mic_device.clone(),
+            codec: self.audio_codec.into(),
+            bitrate: self.audio_bitrate,
+            ..Default::default()
+        };
+        let replay = ReplayConfig {
+            enabled: self.replay_seconds > 0,
+            seconds: self.replay_seconds,
+            max_megabytes: self.replay_max_mb,
+        };
+        let segment = SegmentConfig {
+            max_seconds: self.segment_minutes.saturating_mul(60),
+            max_megabytes: self.segment_mb,
+            name_template: self.segment_template.clone(),
+        };
+        let disk = DiskConfig { min_free_megabytes: self.min_free_mb, expected_minutes: self.expected_minutes, ..Default::default() };
+        let rate_control = rate_control_from_args(self.rate_control, self.bitrate, self.max_bitrate, self.crf)?;
+        let config = RecordingConfig::new(&self.window, self.width, self.height)
+            .with_rate_control(rate_control)
+            .with_keyframe_interval(self.keyframe_interval)
+            .with_frame_rate(self.fps, frame_rate_mode)
+            .with_audio(audio)
+            .with_replay(replay)
+            .with_segments(segment)
+            .with_disk(disk)
+            .with_tags(self.tags.clone())
+            .with_game(GameInfo { patch: self.game.patch.clone(), rank: self.game.rank.clone(), placement: None });
+        let config = match &self.title {
+            Some(title) => config.with_title(title),
+            None => config,
+        };
+        let config = match &self.comment {
+            Some(comment) => config.with_comment(comment),
+            None => config,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Where to record `config`: `--out` or the naming profile's next path.
+    fn output_path(&self, config: &RecordingConfig, out: Option<&str>) -> Result<String> {
+        gui::next_output_path(config, &self.naming.to_profile()?, out)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum RateControlArg {
+    /// Constant bitrate
+    Cbr,
+    /// Average bitrate
+    Abr,
+    /// Variable bitrate bounded by --max-bitrate
+    Vbr,
+    /// Constant quality, optionally capped by --max-bitrate
+    Crf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum AudioCodecArg {
+    Aac,
+    Opus,
+}
+
+impl From<AudioCodecArg> for AudioCodec {
+    fn from(arg: AudioCodecArg) -> Self {
+        match arg {
+            AudioCodecArg::Aac => AudioCodec::Aac,
+            AudioCodecArg::Opus => AudioCodec::Opus,
+        }
+    }
+}
+
+fn rate_control_from_args(
+    mode: RateControlArg,
+    bitrate: u32,
+    max_bitrate: Option<u32>,
+    crf: u8,
+) -> Result<RateControl> {
+    let rate_control = match mode {
+        RateControlArg::Cbr => RateControl::Cbr { bitrate },
+        RateControlArg::Abr => RateControl::Abr { bitrate },
+        RateControlArg::Vbr => RateControl::Vbr {
+            bitrate,
+            max_bitrate: max_bitrate
+                .ok_or_else(|| anyhow::anyhow!("--rate-control vbr requires --max-bitrate"))?,
+        },
+        RateControlArg::Crf => RateControl::Crf { crf, max_bitrate },
+    };
+    rate_control.validate()?;
+    Ok(rate_control)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    
+    match cli.command {
+        Some(Commands::Record { args, out, duration }) => {
+            let config = args.to_config()?;
+            let output_path = args.output_path(&config, out.as_deref())?;
+            let settings = settings::Settings::load(&gui::expand_home(&cli.settings))?;
+            record_command(config, output_path, duration, settings)
+        }
+        Some(Commands::Host { port }) => {
+            host_command(port)
+        }
+        Some(Commands::Daemon { socket, retention, prune_every }) => {
+            let settings = settings::Settings::load(&gui::expand_home(&cli.settings))?;
+            daemon::run(&socket, &retention.to_policy(), prune_every, settings)
+        }
+        Some(Commands::Probe { file, json }) => {
+            probe_command(&file, json)
+        }
+        Some(Commands::Trim { input, from, to, out, exact }) => {
+            let out = out.unwrap_or_else(|| trim_output_path(&input));
+            trim_command(&input, &out, TrimOptions { from, to, exact })
+        }
+        Some(Commands::Thumbnail { file, at, out, width, exact }) => {
+            thumbnail_command(&file, at, out.as_deref(), width, exact)
+        }
+        Some(Commands::ExportGif { input, from, to, width, fps, max_mb, out }) => {
+            let out = out.unwrap_or_else(|| clip_output_path(&input));
+            let max_bytes = max_mb.map(|mb| (mb * 1024.0 * 1024.0) as u64);
+            export_gif_command(&input, &out, AnimationOptions { from, to, width, fps, max_bytes })
+        }
+        Some(Commands::ExportClip { input, from, to, preset, out }) => {
+            let out = out.unwrap_or_else(|| recorder_core::vertical::vertical_path(&input));
+            let options = VerticalOptions { from, to, preset: CropPreset::named(&preset)?, ..Default::default() };
+            export_clip_command(&input, &out, options)
+        }
+        Some(Commands::Timelapse { input, speed, every, at_markers, fps, out }) => {
+            let out = out.unwrap_or_else(|| timelapse_output_path(&input));
+            let sampling = match (every, at_markers) {
+                (_, true) => TimelapseSampling::Markers,
+                (Some(interval), false) => TimelapseSampling::Interval(interval),
+                (None, false) => TimelapseSampling::Speed(speed),
+            };
+            timelapse_command(&input, &out, &TimelapseOptions { sampling, fps })
+        }
+        Some(Commands::Highlights { inputs, pre, post, labels, out }) => {
+            let out = out.unwrap_or_else(|| highlights_output_path(&inputs[0]));
+            let options = HighlightOptions { pre, post, labels, ..Default::default() };
+            highlights_command(&inputs, &out, &options)
+        }
+        Some(Commands::Concat { inputs, out }) => {
+            concat_command(&inputs, &out)
+        }
+        Some(Commands::Library { dir, command }) => {
+            library_command(&dir.unwrap_or_else(gui::recordings_dir), command)
+        }
+        Some(Commands::Ctl { socket, command }) => {
+            ctl_command(&socket, command)
+        }
+        Some(Commands::Webhooks { command }) => {
+            let settings = settings::Settings::load(&gui::expand_home(&cli.settings))?;
+            webhooks_command(&settings, command)
+        }
+        Some(Commands::Game { command }) => {
+            let settings = settings::Settings::load(&gui::expand_home(&cli.settings))?;
+            game_command(settings.game.unwrap_or_default(), command)
+        }
+        Some(Commands::Uploads { command }) => {
+            let settings = settings::Settings::load(&gui::expand_home(&cli.settings))?;
+            uploads_command(settings.upload, command.unwrap_or(UploadCommand::List { json: false }))
+        }
+        None => {
+            // Launched from Finder - show GUI
+            gui::launch()
+        }
+    }
+}
+
+fn record_command(config: RecordingConfig, out: String, duration: u32, settings: settings::Settings) -> Result<()> {
+    // Ensure the output directory exists
+    if let Some(parent) = std::path::Path::new(&out).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    println!("Starting recording...");
+    println!("Window: {}", config.window_title);
+    println!("Resolution: {}x{}", config.width, config.height);
+    println!("Rate control: {:?}", config.rate_control);
+    println!("Keyframe interval: {} frames", config.keyframe_interval);
+    println!("Frame rate: {} fps ({:?})", config.fps, config.frame_rate_mode);
+    println!(
+        "Audio: game {}, mic {} ({:?}, {} bps)",
+        if config.audio.game { "on" } else { "off" },
+        if config.audio.mic { "on" } else { "off" },
+        config.audio.codec,
+        config.audio.bitrate
+    );
+    if config.replay.enabled {
+        println!("Replay buffer: {} s (max {} MB)", config.replay.seconds, config.replay.max_megabytes);
+    }
+    if config.segment.enabled() {
+        println!(
+            "Segments: every {} s / {} MB as {}",
+            config.segment.max_seconds, config.segment.max_megabytes, config.segment.name_template
+        );
+    }
+    println!(
+        "Disk: keep {} MB free, need room for {} min (~{:.0} MB/min)",
+        config.disk.min_free_megabytes,
+        config.disk.expected_minutes,
+        recorder_core::disk::estimated_byte_rate(&config) as f64 * 60.0 / (1024.0 * 1024.0)
+    );
+    println!("Output: {}", out);
+    let configured = settings.hooks.configured();
+    if !configured.is_empty() {
+        let names: Vec<&str> = configured.iter().map(|hook| hook.name()).collect();
+        println!("Hooks: {} (log: {})", names.join(", "), settings.hooks.log);
+    }
+    if !settings.webhooks.is_empty() {
+        println!("Webhooks: {}", settings.webhooks.len());
+    }
+    if let Some(upload) = settings.upload.as_ref().filter(|upload| upload.auto) {
+        println!("Upload: queued for {}/{}", upload.endpoint, upload.bucket);
+    }
+    if let Some(game) = settings.game.as_ref().filter(|game| game.markers) {
+        println!("Game: marking match start and end from {}", game.url);
+    }
+    
+    let mut recorder = Recorder::new();
+    let events = recorder.subscribe();
+    // Hooks and webhook deliveries run in the background; the process waits
+    // for them before exiting
+    let jobs = background::BackgroundJobs::default();
+    let hooks = hooks::HookRunner::new(settings.hooks, jobs.clone());
+    let webhooks = webhooks::Webhooks::new(settings.webhooks, jobs.clone());
+    let uploads = settings.upload.map(|upload| uploads::UploadQueuer::new(upload, None));
+    let queued = std::cell::Cell::new(0);
+    let dispatch = |event: RecorderEvent| {
+        hooks.handle(&event);
+        webhooks.handle(&event);
+        if let Some(uploads) = &uploads {
+            queued.set(queued.get() + uploads.handle(&event));
+        }
+        print_event(event);
+    };
+    let game = settings.game.filter(|game| game.markers).and_then(|config| game::start_watcher(&config));
+    let follow_game = |recorder: &Recorder| {
+        if let Some((_, game_events)) = &game {
+            game_events.try_iter().for_each(|event| game::mark(recorder, &event));
+        }
+    };
+    
+    // Set up graceful shutdown
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    
+    ctrlc::set_handler(move || {
+        println!("\nStopping recording...");
+        r.store(false, Ordering::SeqCst);
+    })?;
+    
+    // Start recording
+    if let Err(e) = recorder.start_with_config(&config, &out) {
+        eprintln!("Error: {}", e);
+        // Let on_error run before exiting
+        events.try_iter().for_each(dispatch);
+        jobs.wait();
+        std::process::exit(1);
+    }
+    println!("Recording started. Press Ctrl+C to stop.");
+    
+    // Wait for duration or interrupt
+    if duration > 0 {
+        println!("Recording for {} seconds...", duration);
+        let start = std::time::Instant::now();
+        
+        while running.load(Ordering::SeqCst) && recorder.is_recording() && start.elapsed().as_secs() < duration as u64 {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            events.try_iter().for_each(dispatch);
+            follow_game(&recorder);
+        }
+    } else {
+        // Wait for Ctrl+C (or the disk guard stopping the recording)
+        while running.load(Ordering::SeqCst) && recorder.is_recording() {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            events.try_iter().for_each(dispatch);
+            follow_game(&recorder);
+        }
+    }
+    
+    // Stop recording
+    recorder.stop();
+    events.try_iter().for_each(dispatch);
+    if config.segment.enabled() {
+        println!("Playlist saved to: {}", std::path::Path::new(&out).with_extension("m3u8").display());
+    } else {
+        println!("Recording saved to: {}", out);
+    }
+    println!("Metadata saved to: {}", RecordingMetadata::sidecar_path(std::path::Path::new(&out)).display());
+    if queued.get() > 0 {
+        println!("Queued {} file(s) for upload; `recorder daemon` or `recorder uploads run` sends them", queued.get());
+    }
+    if jobs.running() > 0 {
+        println!("Waiting for {} hook(s) and webhook(s) to finish...", jobs.running());
+        jobs.wait();
+    }
+    
+    Ok(())
+}
+
+fn host_command(port: u16) -> Result<()> {
+    println!("Starting extension host on port {}...", port);
+    
+    // In a real implementation, this would launch the Node.js process
+    // For now, we'll just spawn it as a subprocess
+    let status = std::process::Command::new("node")
+        .arg("extension-host/dist/index.js")
+        .arg("--port")
+        .arg(port.to_string())
+        .status()?;
+    
+    if !status.success() {
+        anyhow::bail!("Extension host failed to start");
+    }
+    
+    Ok(())
+}
+
+fn print_event(event: RecorderEvent) {
+    match event {
+        RecorderEvent::SegmentFinalized { index, path, duration_secs, .. } => {
+            println!("Segment {} saved ({:.1} s): {}", index, duration_secs, path.display());
+        }
+        RecorderEvent::DiskSpaceLow { available_bytes, remaining_secs } => {
+            eprintln!("Warning: low disk space, {} MB free (~{:.0} min of recording left)", available_bytes / (1024 * 1024), remaining_secs / 60.0);
+        }
+        RecorderEvent::StoppedForDiskSpace { available_bytes } => {
+            eprintln!("Disk almost full ({} MB free): recording stopped and saved", available_bytes / (1024 * 1024));
+        }
+        // record_command reports these itself, and the recorder logs its failures
+        RecorderEvent::RecordingStarted { .. }
+        | RecorderEvent::RecordingStopped { .. }
+        | RecorderEvent::RecordingFinalized { .. }
+        | RecorderEvent::RecordingFailed { .. } => {}
+    }
+}
+
+fn library_command(dir: &std::path::Path, command: LibraryCommand) -> Result<()> {
+    let mut library = recorder_library::Library::open(dir)?;
+    let stats = library.scan()?;
+    match command {
+        LibraryCommand::Scan => {
+            println!(
+                "Indexed {} ({} added, {} updated, {} removed, {} unchanged)",
+                dir.display(),
+                stats.added,
+                stats.updated,
+                stats.removed,
+                stats.unchanged
+            );
+        }
+        LibraryCommand::List { filter, json } => {
+            print_library_entries(&library.find(&filter.to_query(None))?, json)?;
+        }
+        LibraryCommand::Search { text, filter, json } => {
+            print_library_entries(&library.find(&filter.to_query(Some(text)))?, json)?;
+        }
+        LibraryCommand::Info { recording, json } => {
+            let recording = library_path(dir, &recording);
+            let entry = library.get(&recording)?.ok_or_else(|| anyhow::anyhow!("{} is not in the library at {}", recording.display(), dir.display()))?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entry)?);
+            } else {
+                print_library_entry(&entry);
+            }
+        }
+        LibraryCommand::Tag { recording, tags } => {
+            let tags = library.retag(&library_path(dir, &recording), &tags, &[])?;
+            println!("Tags: {}", tags.join(", "));
+        }
+        LibraryCommand::Untag { recording, tags } => {
+            let tags = library.retag(&library_path(dir, &recording), &[], &tags)?;
+            println!("Tags: {}", if tags.is_empty() { "(none)".to_string() } else { tags.join(", ") });
+        }
+        LibraryCommand::Rm { recordings } => {
+            for recording in recordings {
+                for file in library.delete(&library_path(dir, &recording))? {
+                    println!("Deleted {}", file.display());
+                }
+            }
+        }
+        LibraryCommand::Prune { retention, dry_run, json } => {
+            let policy = retention.to_policy();
+            anyhow::ensure!(policy.is_active(), "No retention rule given; pass --keep-days and/or --max-gb");
+            let report = library.prune(&policy, dry_run)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_prune_report(&report);
+            }
+        }
+        LibraryCommand::Repair { delete_truncated, dry_run } => {
+            let leftovers = recorder_core::partial::find_leftovers(dir)?;
+            if leftovers.is_empty() {
+                println!("No unfinished recordings in {}", dir.display());
+            }
+            for leftover in &leftovers {
+                let size = format!("{:.1} MB", leftover.bytes as f64 / (1024.0 * 1024.0));
+                match (leftover.state, delete_truncated, dry_run) {
+                    (PartialState::Finished, _, true) => println!("Would repair {} ({})", leftover.output.display(), size),
+                    (PartialState::Finished, _, false) => {
+                        let output = leftover.repair()?;
+                        library.refresh(&output)?;
+                        println!("Repaired {} ({})", output.display(), size);
+                    }
+                    (PartialState::Truncated, false, _) => {
+                        println!("{} ({}) was cut off and can't be played; pass --delete-truncated to remove it", leftover.path.display(), size)
+                    }
+                    (PartialState::Truncated, true, true) => println!("Would delete {} ({})", leftover.path.display(), size),
+                    (PartialState::Truncated, true, false) => {
+                        leftover.delete()?;
+                        println!("Deleted {} ({})", leftover.path.display(), size);
+                    }
+                }
+            }
+        }
+        LibraryCommand::Mv { recording, to } => {
+            // A bare name stays in the library directory
+            let to = if to.parent().is_some_and(|p| p.as_os_str().is_empty()) { dir.join(to) } else { to };
+            let moved = library.rename(&library_path(dir, &recording), &to)?;
+            println!("Moved to {}", moved.display());
+        }
+    }
+    Ok(())
+}
+
+/// `recording` as given if it exists, otherwise the file of that name in the library.
+fn library_path(dir: &std::path::Path, recording: &std::path::Path) -> std::path::PathBuf {
+    if recording.exists() {
+        recording.to_path_buf()
+    } else {
+        dir.join(recording)
+    }
+}
+
+fn print_library_entries(entries: &[recorder_library::LibraryEntry], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(entries)?);
+        return Ok(());
+    }
+    if entries.is_empty() {
+        println!("No matching recordings");
+        return Ok(());
+    }
+    println!("{:<16}  {:>8}  {:>9}  {:>5}  {:<6}  NAME", "DATE", "DURATION", "SIZE", "PLACE", "PATCH");
+    for entry in entries {
+        let secs = entry.duration_secs.unwrap_or_default() as u64;
+        println!(
+            "{:<16}  {:>5}:{:02}  {:>6.1} MB  {:>5}  {:<6}  {}{}",
+            entry.date().format("%Y-%m-%d %H:%M"),
+            secs / 60,
+            secs % 60,
+            entry.size as f64 / (1024.0 * 1024.0),
+            entry.game.placement.map(|p| format!("#{}", p)).unwrap_or_default(),
+            entry.game.patch.as_deref().unwrap_or_default(),
+            entry.file_name(),
+            if entry.tags.is_empty() { String::new() } else { format!("  [{}]", entry.tags.join(", ")) }
+        );
+    }
+    Ok(())
+}
+
+fn print_prune_report(report: &recorder_library::PruneReport) {
+    let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+    for pruned in &report.deleted {
+        let reason = match pruned.reason {
+            recorder_library::retention::PruneReason::Age => "too old",
+            recorder_library::retention::PruneReason::Size => "over size limit",
+        };
+        println!(
+            "{} {}  {}  {:.1} MB  ({})",
+            if report.dry_run { "Would delete" } else { "Deleted" },
+            pruned.entry.file_name(),
+            pruned.entry.date().format("%Y-%m-%d %H:%M"),
+            mb(pruned.entry.size),
+            reason
+        );
+    }
+    println!(
+        "{} {} recordings, {:.1} MB of {:.1} MB; {:.1} MB left",
+        if report.dry_run { "Would delete" } else { "Deleted" },
+        report.deleted.len(),
+        mb(report.freed_bytes),
+        mb(report.total_bytes),
+        mb(report.remaining_bytes)
+    );
+    if report.protected > 0 {
+        println!("Kept {} tagged or in-progress recordings the rules would otherwise allow deleting", report.protected);
+    }
+}
+
+fn print_library_entry(entry: &recorder_library::LibraryEntry) {
+    println!("File: {}", entry.path.display());
+    println!("Date: {}", entry.date().format("%Y-%m-%d %H:%M:%S"));
+    match entry.duration_secs {
+        Some(secs) => println!("Duration: {:.3} s", secs),
+        None => println!("Duration: unknown"),
+    }
+    println!("Size: {} bytes", entry.size);
+    if let (Some(width), Some(height)) = (entry.width, entry.height) {
+        println!("Video: {} {}x{}{}", entry.video_codec.as_deref().unwrap_or("?"), width, height, entry.fps.map(|f| format!(" @ {} fps", f)).unwrap_or_default());
+    }
+    if !entry.audio_codecs.is_empty() {
+        println!("Audio: {}", entry.audio_codecs.join(", "));
+    }
+    if !entry.complete {
+        println!("Incomplete: the recorder did not stop cleanly (or is still recording)");
+    }
+    for (name, value) in [("Patch", &entry.game.patch), ("Rank", &entry.game.rank)] {
+        if let Some(value) = value {
+            println!("{}: {}", name, value);
+        }
+    }
+    if let Some(placement) = entry.game.placement {
+        println!("Placement: {}", placement);
+    }
+    if !entry.tags.is_empty() {
+        println!("Tags: {}", entry.tags.join(", "));
+    }
+    if !entry.markers.is_empty() {
+        println!("Markers:");
+        for marker in &entry.markers {
+            println!("  {:>9.3} s  {}", marker.time.as_secs_f64(), marker.label);
+        }
+    }
+}
+
+fn uploads_command(config: Option<recorder_upload::UploadConfig>, command: UploadCommand) -> Result<()> {
+    use recorder_upload::UploadQueue;
+    let queue_file = uploads::queue_file();
+    let config = || config.clone().ok_or_else(|| anyhow::anyhow!("No upload target; add an [upload] section to the settings file"));
+    match command {
+        UploadCommand::List { json } => {
+            let queue = UploadQueue::load(&queue_file)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(queue.items())?);
+            } else if queue.items().is_empty() {
+                println!("The upload queue is empty");
+            } else {
+                for item in queue.items() {
+                    println!("{}", uploads::describe(item));
+                }
+            }
+        }
+        UploadCommand::Add { files } => {
+            let config = config()?;
+            let root = gui::recordings_dir();
+            let mut queued = 0;
+            for file in &files {
+                queued += recorder_upload::enqueue_recording(&queue_file, &config, &root, file)?.len();
+            }
+            println!("Queued {} file(s)", queued);
+        }
+        UploadCommand::Run { max_mbps } => {
+            let mut config = config()?;
+            if let Some(max_mbps) = max_mbps {
+                config.max_mbps = max_mbps;
+                config.validate()?;
+            }
+            let uploader = recorder_upload::Uploader::new(config, &queue_file)?;
+            let stop = Arc::new(AtomicBool::new(false));
+            let s = stop.clone();
+            ctrlc::set_handler(move || {
+                println!("\nStopping after the current part...");
+                s.store(true, Ordering::SeqCst);
+            })?;
+            let stats = uploader.run_pending(&stop, &|item| println!("{}", uploads::describe(item)))?;
+            println!("{} uploaded, {} failed{}", stats.uploaded, stats.failed, if stats.interrupted { "; the rest resumes on the next run" } else { "" });
+        }
+        UploadCommand::Retry => println!("Queued {} failed upload(s) again", UploadQueue::update(&queue_file, |queue| queue.retry_failed())?),
+        UploadCommand::Clear => println!("Forgot {} finished upload(s)", UploadQueue::update(&queue_file, |queue| queue.clear_done())?),
+        UploadCommand::Rm { id } => {
+            let removed = UploadQueue::update(&queue_file, |queue| queue.remove(id))?;
+            let item = removed.ok_or_else(|| anyhow::anyhow!("No upload {} in the queue", id))?;
+            println!("Removed {}", item.key);
+        }
+    }
+    Ok(())
+}
+
+fn game_command(mut config: recorder_game::GameConfig, command: GameCommand) -> Result<()> {
+    match command {
+        GameCommand::Watch { url, json } => {
+            config.url = url.unwrap_or(config.url);
+            config.validate()?;
+            let watcher = recorder_game::GameWatcher::start(config.client()?, config.interval());
+            let events = watcher.subscribe();
+            let running = Arc::new(AtomicBool::new(true));
+            let r = running.clone();
+            ctrlc::set_handler(move || r.store(false, Ordering::SeqCst))?;
+            eprintln!("Watching {} for matches; press Ctrl+C to stop.", config.url);
+            while running.load(Ordering::SeqCst) {
+                let Ok(event) = events.recv_timeout(std::time::Duration::from_millis(200)) else { continue };
+                if json {
+                    println!("{}", serde_json::to_string(&event)?);
+                } else {
+                    println!("{}", game::describe(&event));
+                }
+            }
+        }
+        GameCommand::Status { url, json } => {
+            config.url = url.unwrap_or(config.url);
+            config.validate()?;
+            match config.client()?.poll()? {
+                recorder_game::Snapshot::NoGame => println!("No game running at {}", config.url),
+                recorder_game::Snapshot::Loading => println!("A match is loading"),
+                recorder_game::Snapshot::InGame(data) if json => println!("{}", serde_json::to_string_pretty(&data)?),
+                recorder_game::Snapshot::InGame(data) => {
+                    let time = data.game_data.game_time as u64;
+                    println!("{} on {}, {:02}:{:02} in", data.game_data.game_mode, data.game_data.map_name, time / 60, time % 60);
+                    let me = data.active_player.as_ref().map(|me| me.name());
+                    for player in &data.all_players {
+                        let you = if Some(player.name()) == me { " (you)" } else { "" };
+                        let out = if player.is_dead { ", out" } else { "" };
+                        println!("  {}{}: level {}{}", player.name(), you, player.level, out);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn webhooks_command(settings: &settings::Settings, command: WebhookCommand) -> Result<()> {
+    match command {
+        WebhookCommand::Test { url } => {
+            let targets = webhooks::test_targets(&settings.webhooks, url.as_deref());
+            anyhow::ensure!(!targets.is_empty(), "No webhooks configured; add a [[webhooks]] entry to the settings file or pass --url");
+            let mut failed = 0;
+            for webhook in &targets {
+                match webhooks::send_test(webhook, &gui::recordings_dir()) {
+                    Ok(delivery) => println!("{}: {} after {} attempt(s)", webhook.url, delivery.status, delivery.attempts),
+                    Err(e) => {
+                        eprintln!("{}: {:#}", webhook.url, e);
+                        failed += 1;
+                    }
+                }
+            }
+            anyhow::ensure!(failed == 0, "{} of {} webhook(s) failed", failed, targets.len());
+            Ok(())
+        }
+    }
+}
+
+fn ctl_command(socket: &str, command: CtlCommand) -> Result<()> {
+    let request = match command {
+        CtlCommand::Start { args, out } => {
+            let config = args.to_config()?;
+            let output = args.output_path(&config, out.as_deref())?;
+            ipc::Request::Start { config: Box::new(config), output }
+        }
+        CtlCommand::Stop => ipc::Request::Stop,
+        CtlCommand::Status => ipc::Request::Status,
+        CtlCommand::SaveReplay { seconds, out } => ipc::Request::SaveReplay {
+            seconds,
+            output: Some(out.unwrap_or_else(gui::next_replay_file_name)),
+        },
+        CtlCommand::Mark { label, at } => ipc::Request::Mark { label, at_secs: at },
+        CtlCommand::Game { game, placement } => ipc::Request::Game {
+            game: GameInfo { patch: game.patch, rank: game.rank, placement },
+        },
+        CtlCommand::GameEvents => {
+            return ipc::subscribe(std::path::Path::new(socket), &ipc::Request::GameEvents, |event| {
+                println!("{}", event);
+                Ok(())
+            });
+        }
+    };
+    let result = ipc::send(std::path::Path::new(socket), &request)?;
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+/// Parses `HH:MM:SS`, `MM:SS` or plain seconds (optionally written `5s`),
+/// each with optional fractions.
+fn parse_timestamp(value: &str) -> Result<std::time::Duration, String> {
+    let text = value.trim();
+    let text = text.strip_suffix('s').filter(|t| !t.contains(':')).unwrap_or(text);
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() > 3 {
+        return Err(format!("'{}' is not HH:MM:SS, MM:SS or seconds", value));
+    }
+    let mut secs = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let number: f64 = part.parse().map_err(|_| format!("'{}' is not HH:MM:SS, MM:SS or seconds", value))?;
+        let last = i == parts.len() - 1;
+        if number < 0.0 || (!last && number.fract() != 0.0) || (i > 0 && number >= 60.0) {
+            return Err(format!("'{}' is not a valid time", value));
+        }
+        secs = secs * 60.0 + number;
+    }
+    std::time::Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+}
+
+/// `TFT-x.mp4` is trimmed to `TFT-x-trim.mp4` next to it.
+fn trim_output_path(input: &std::path::Path) -> std::path::PathBuf {
+    let stem = input.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    input.with_file_name(format!("{}-trim.mp4", stem))
+}
+
+fn trim_command(input: &std::path::Path, out: &std::path::Path, options: TrimOptions) -> Result<()> {
+    println!("Trimming {}...", input.display());
+    let result = recorder_core::trim(input, out, &options)?;
+    println!(
+        "Wrote {} ({:.3} s from {:.3} s, {} frames, {} bytes)",
+        out.display(),
+        result.duration().as_secs_f64(),
+        result.start.as_secs_f64(),
+        result.video_frames,
+        result.bytes
+    );
+    if result.reencoded_frames > 0 {
+        println!("Re-encoded {} frames up to the first keyframe", result.reencoded_frames);
+    } else if result.start < options.from {
+        println!("Starts {:.3} s early, on the previous keyframe (use --exact to cut precisely)", (options.from - result.start).as_secs_f64());
+    }
+    if result.markers > 0 {
+        println!("Carried over {} markers", result.markers);
+    }
+    Ok(())
+}
+
+fn thumbnail_command(
+    file: &std::path::Path,
+    at: Option<std::time::Duration>,
+    out: Option<&std::path::Path>,
+    width: u32,
+    exact: bool,
+) -> Result<()> {
+    use recorder_core::thumbnail;
+
+    let Some(out) = out else {
+        let path = thumbnail::thumbnail(file, at)?;
+        println!("{}", path.display());
+        return Ok(());
+    };
+    let at = match at {
+        Some(at) => at,
+        None => thumbnail::DEFAULT_THUMBNAIL_AT.min(Mp4Reader::open(file)?.duration / 2),
+    };
+    let frame = thumbnail::extract_frame(file, at, exact)?;
+    frame.scaled_to_width(width).save(out)?;
+    println!("{} (frame at {:.3} s)", out.display(), frame.time.as_secs_f64());
+    Ok(())
+}
+
+/// `TFT-x.mp4` is exported to `TFT-x-clip.gif` next to it.
+fn clip_output_path(input: &std::path::Path) -> std::path::PathBuf {
+    let stem = input.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    input.with_file_name(format!("{}-clip.gif", stem))
+}
+
+fn export_gif_command(input: &std::path::Path, out: &std::path::Path, options: AnimationOptions) -> Result<()> {
+    println!("Exporting {}...", input.display());
+    let result = recorder_core::export_animation(input, out, &options)?;
+    println!(
+        "Wrote {} ({} frames, {}x{} at {} fps, {:.1} s, {} bytes)",
+        out.display(),
+        result.frames,
+        result.width,
+        result.height,
+        result.fps,
+        result.duration.as_secs_f64(),
+        result.bytes
+    );
+    if result.reduced {
+        println!("Lowered to {} fps and {} px wide to fit the size budget", result.fps, result.width);
+    }
+    Ok(())
+}
+
+fn export_clip_command(input: &std::path::Path, out: &std::path::Path, options: VerticalOptions) -> Result<()> {
+    println!("Exporting {} ({})...", input.display(), options.preset.description);
+    let result = recorder_core::export_vertical(input, out, &options)?;
+    println!(
+        "Wrote {} ({}x{}, {:.3} s, {} frames, {} bytes)",
+        out.display(),
+        options.width,
+        options.height,
+        result.duration().as_secs_f64(),
+        result.video_frames,
+        result.bytes
+    );
+    Ok(())
+}
+
+/// Parses a day written `YYYY-MM-DD`.
+fn parse_date(value: &str) -> Result<chrono::NaiveDate, String> {
+    chrono::NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").map_err(|_| format!("'{}' is not a date like 2024-09-30", value))
+}
+
+/// Parses a placement (`3`) or an inclusive range of them (`1-4`).
+fn parse_placement(value: &str) -> Result<(u8, u8), String> {
+    let invalid = || format!("'{}' is not a placement between 1 and 8 or a range like 1-4", value);
+    let parse = |text: &str| text.trim().parse::<u8>().ok().filter(|p| (1..=8).contains(p)).ok_or_else(invalid);
+    let (best, worst) = match value.split_once('-') {
+        Some((best, worst)) => (parse(best)?, parse(worst)?),
+        None => (parse(value)?, parse(value)?),
+    };
+    if best > worst {
+        return Err(invalid());
+    }
+    Ok((best, worst))
+}
+
+/// Parses a speed-up factor such as `20x` or `2.5`.
+fn parse_speed(value: &str) -> Result<f64, String> {
+    let text = value.trim();
+    let speed: f64 = text.strip_suffix(['x', 'X']).unwrap_or(text).parse().map_err(|_| format!("'{}' is not a speed like 20x", value))?;
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(format!("'{}' is not a valid speed", value));
+    }
+    Ok(speed)
+}
+
+/// `TFT-x.mp4` is condensed to `TFT-x-timelapse.mp4` next to it.
+fn timelapse_output_path(input: &std::path::Path) -> std::path::PathBuf {
+    let stem = input.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    input.with_file_name(format!("{}-timelapse.mp4", stem))
+}
+
+fn timelapse_command(input: &std::path::Path, out: &std::path::Path, options: &TimelapseOptions) -> Result<()> {
+    println!("Condensing {}...", input.display());
+    let result = recorder_core::timelapse(input, out, options)?;
+    println!("Wrote {} ({} frames, {:.1} s, {} bytes)", out.display(), result.frames, result.duration.as_secs_f64(), result.bytes);
+    if result.markers > 0 {
+        println!("Carried over {} markers", result.markers);
+    }
+    Ok(())
+}
+
+/// `TFT-x.mp4` gets `TFT-x-highlights.mp4` next to it; a directory gets
+/// `highlights.mp4` inside it.
+fn highlights_output_path(input: &std::path::Path) -> std::path::PathBuf {
+    if input.is_dir() {
+        return input.join("highlights.mp4");
+    }
+    let stem = input.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    input.with_file_name(format!("{}-highlights.mp4", stem))
+}
+
+/// Replaces each directory in `inputs` with the recordings in it, oldest
+/// first. Only files with a metadata sidecar count, so trims, clips and
+/// earlier reels are left out.
+fn expand_recording_dirs(inputs: &[std::path::PathBuf]) -> Result<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+        let mut recordings: Vec<std::path::PathBuf> = std::fs::read_dir(input)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|e| e == "mp4"))
+            .filter(|path| RecordingMetadata::sidecar_path(path).exists())
+            .collect();
+        // Names start with the recording's timestamp
+        recordings.sort();
+        files.extend(recordings);
+    }
+    Ok(files)
+}
+
+fn highlights_command(inputs: &[std::path::PathBuf], out: &std::path::Path, options: &HighlightOptions) -> Result<()> {
+    let recordings = expand_recording_dirs(inputs)?;
+    anyhow::ensure!(!recordings.is_empty(), "No recordings found in {}", inputs[0].display());
+    let clips = recorder_core::highlights::find_highlights(&recordings, options)?;
+    for (i, clip) in clips.iter().enumerate() {
+        let labels: Vec<&str> = clip.markers.iter().map(|m| m.label.as_str()).collect();
+        println!(
+            "{:>3}. {} {:.1}-{:.1} s  {}",
+            i + 1,
+            clip.recording.file_name().map(|n| n.to_string_lossy()).unwrap_or_default(),
+            clip.start.as_secs_f64(),
+            clip.end.as_secs_f64(),
+            labels.join(" / ")
+        );
+    }
+    let result = recorder_core::highlights(&recordings, out, options)?;
+    println!(
+        "Wrote {} ({} clips, {:.1} s, {} frames, {} bytes)",
+        out.display(),
+        result.clips,
+        result.duration.as_secs_f64(),
+        result.video_frames,
+        result.bytes
+    );
+    Ok(())
+}
+
+/// Replaces each playlist in `inputs` with the segments it lists.
+fn expand_playlists(inputs: &[std::path::PathBuf]) -> Result<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.extension().is_some_and(|e| e.eq_ignore_ascii_case("m3u8") || e.eq_ignore_ascii_case("m3u")) {
+            files.extend(recorder_core::segment::read_playlist(input)?);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn concat_command(inputs: &[std::path::PathBuf], out: &std::path::Path) -> Result<()> {
+    let files = expand_playlists(inputs)?;
+    println!("Joining {} recordings...", files.len());
+    let result = recorder_core::concat(&files, out)?;
+    println!(
+        "Wrote {} ({:.3} s, {} frames, {} bytes)",
+        out.display(),
+        result.duration.as_secs_f64(),
+        result.video_frames,
+        result.bytes
+    );
+    if result.markers > 0 {
+        println!("Carried over {} markers", result.markers);
+    }
+    Ok(())
+}
+
+fn probe_command(file: &std::path::Path, json: bool) -> Result<()> {
+    let reader = Mp4Reader::open(file)?;
+    let tracks: Vec<serde_json::Value> = reader
+        .tracks
+        .iter()
+        .map(|track| match &track.info {
+            TrackInfo::Video(v) => serde_json::json!({
+                "id": track.id,
+                "type": "video",
+                "codec": "h264",
+                "width": v.width,
+                "height": v.height,
+                "samples": track.samples.len(),
+                "duration_secs": track.duration().as_secs_f64(),
+            }),
+            TrackInfo::Audio(a) => serde_json::json!({
+                "id": track.id,
+                "type": "audio",
+                "name": track.name,
+                "codec": format!("{:?}", a.codec).to_lowercase(),
+                "sample_rate": a.sample_rate,
+                "channels": a.channels,
+                "samples": track.samples.len(),
+                "duration_secs": track.duration().as_secs_f64(),
+            }),
+        })
+        .collect();
+
+    if json {
+        let chapters: Vec<_> = reader
+            .chapters
+            .iter()
+            .map(|c| serde_json::json!({ "start_secs": c.start.as_secs_f64(), "title": c.title }))
+            .collect();
+        let summary = serde_json::json!({
+            "file": file,
+            "duration_secs": reader.duration.as_secs_f64(),
+            "tracks": tracks,
+            "chapters": chapters,
+            "metadata": reader.tags,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+
+    println!("File: {}", file.display());
+    println!("Duration: {:.3} s", reader.duration.as_secs_f64());
+    println!("Tracks:");
+    for track in &reader.tracks {
+        match &track.info {
+            TrackInfo::Video(v) => println!(
+                "  #{} video  h264 {}x{}, {} frames",
+                track.id,
+                v.width,
+                v.height,
+                track.samples.len()
+            ),
+            TrackInfo::Audio(a) => println!(
+                "  #{} audio  {:?} {} Hz, {} ch ({})",
+                track.id, a.codec, a.sample_rate, a.channels, track.name
+            ),
+        }
+    }
+    if !reader.chapters.is_empty() {
+        println!("Chapters:");
+        for chapter in &reader.chapters {
+            let secs = chapter.start.as_secs();
+            println!("  {:02}:{:02}:{:02}  {}", secs / 3600, secs / 60 % 60, secs % 60, chapter.title);
+        }
+    }
+    let tags = &reader.tags;
+    if !tags.is_empty() {
+        println!("Metadata:");
+        let fields = [
+            ("Title", tags.title.clone()),
+            ("Date", tags.date.clone()),
+            ("Encoder", tags.encoder.clone()),
+            ("Comment", tags.comment.clone()),
+            ("Patch", tags.patch.clone()),
+            ("Rank", tags.rank.clone()),
+            ("Placement", tags.placement.map(|p| p.to_string())),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                println!("  {:<10} {}", format!("{}:", name), value);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     use clap::CommandFactory;
 
     #[test]
@@ -193,15 +1778,391 @@ mod tests {
     fn test_default_args() {
         let cli = Cli::parse_from(vec!["recorder", "record"]);
         match cli.command {
-            Some(Commands::Record { window, width, height, bitrate, out, duration }) => {
-                assert_eq!(window, "Teamfight Tactics");
-                assert_eq!(width, 1280);
-                assert_eq!(height, 720);
-                assert_eq!(bitrate, 4000000);
+            Some(Commands::Record { args, out, duration }) => {
+                assert_eq!(args.window, "Teamfight Tactics");
+                assert_eq!(args.width, 1280);
+                assert_eq!(args.height, 720);
+                assert_eq!(args.bitrate, 4000000);
+                assert_eq!(args.rate_control, RateControlArg::Abr);
+                assert!(args.max_bitrate.is_none());
+                assert_eq!(args.crf, 23);
+                assert_eq!(args.keyframe_interval, 60);
+                assert_eq!(args.fps, 60);
+                assert!(!args.vfr);
+                assert!(!args.no_game_audio);
+                assert!(!args.mic);
+                assert!(args.mic_device.is_none());
+                assert_eq!(args.audio_codec, AudioCodecArg::Aac);
+                assert_eq!(args.audio_bitrate, 160000);
+                assert_eq!(args.replay_seconds, 0);
+                assert_eq!(args.segment_minutes, 0);
+                assert_eq!(args.segment_mb, 0);
+                assert_eq!(args.segment_template, "TFT-{date}-part{n}.mp4");
+                assert!(args.tags.is_empty());
                 assert!(out.is_none());
                 assert_eq!(duration, 0);
+
+                let config = args.to_config().unwrap();
+                assert!(config.audio.game);
+                assert_eq!(config.disk, DiskConfig::default());
+                assert!(!config.replay.enabled);
+                assert!(!config.segment.enabled());
             }
             _ => panic!("Expected Record command"),
         }
     }
+
+    #[test]
+    fn test_naming_args() {
+        let dir = std::env::temp_dir().join(format!("tft_cli_naming_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cli = Cli::parse_from(["recorder", "record", "--window", "TFT: Set 12", "--profile", "monthly", "--name-template", "{window}-{seq}.mp4"]);
+        let Some(Commands::Record { args, .. }) = cli.command else { panic!("Expected Record command") };
+        let config = args.to_config().unwrap();
+        let out = format!("{}/", dir.display());
+        let path = std::path::PathBuf::from(args.output_path(&config, Some(&out)).unwrap());
+        let month = chrono::Local::now().format("%Y/%m").to_string();
+        assert_eq!(path, dir.join(month).join("TFT_ Set 12-1.mp4"));
+
+        let template = dir.join("{patch}-{seq}.mp4");
+        assert_eq!(args.output_path(&config, template.to_str()).unwrap(), dir.join("unknown-1.mp4").to_string_lossy());
+
+        let cli = Cli::parse_from(["recorder", "record", "--profile", "weekly"]);
+        let Some(Commands::Record { args, .. }) = cli.command else { panic!("Expected Record command") };
+        assert!(args.output_path(&RecordingConfig::default(), None).is_err());
+        let cli = Cli::parse_from(["recorder", "record", "--layout", "{season}"]);
+        let Some(Commands::Record { args, .. }) = cli.command else { panic!("Expected Record command") };
+        assert!(args.output_path(&RecordingConfig::default(), None).is_err());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_subcommand_descriptions() {
+        let cli = <Cli as clap::CommandFactory>::command();
+        let about = |name: &str| cli.find_subcommand(name).and_then(|command| command.get_about()).map(|about| about.to_string());
+        assert_eq!(about("ctl").as_deref(), Some("Control a running daemon"));
+        assert_eq!(about("library").as_deref(), Some("Index and list the recordings directory"));
+        assert!(cli.get_subcommands().all(|command| command.get_about().is_some()), "Every subcommand needs a description");
+    }
+
+    #[test]
+    fn test_game_args() {
+        let cli = Cli::parse_from(["recorder", "game", "watch", "--url", "http://127.0.0.1:8999", "--json"]);
+        let Some(Commands::Game { command: GameCommand::Watch { url, json } }) = cli.command else { panic!("Expected game watch") };
+        assert_eq!((url.as_deref(), json), (Some("http://127.0.0.1:8999"), true));
+        assert!(matches!(Cli::parse_from(["recorder", "game", "status"]).command, Some(Commands::Game { command: GameCommand::Status { url: None, json: false } })));
+        assert!(Cli::try_parse_from(["recorder", "game"]).is_err());
+    }
+
+    #[test]
+    fn test_uploads_args() {
+        assert!(matches!(Cli::parse_from(["recorder", "uploads"]).command, Some(Commands::Uploads { command: None })));
+        let cli = Cli::parse_from(["recorder", "uploads", "run", "--max-mbps", "25"]);
+        let Some(Commands::Uploads { command: Some(UploadCommand::Run { max_mbps }) }) = cli.command else { panic!("Expected uploads run") };
+        assert_eq!(max_mbps, Some(25.0));
+        let cli = Cli::parse_from(["recorder", "uploads", "add", "a.mp4", "b.mp4"]);
+        let Some(Commands::Uploads { command: Some(UploadCommand::Add { files }) }) = cli.command else { panic!("Expected uploads add") };
+        assert_eq!(files.len(), 2);
+        assert!(Cli::try_parse_from(["recorder", "uploads", "add"]).is_err());
+    }
+
+    #[test]
+    fn test_webhooks_args() {
+        let cli = Cli::parse_from(["recorder", "webhooks", "test", "--url", "http://localhost:8080/hook", "--settings", "/tmp/tft.toml"]);
+        assert_eq!(cli.settings, "/tmp/tft.toml");
+        let Some(Commands::Webhooks { command: WebhookCommand::Test { url } }) = cli.command else { panic!("Expected webhooks test") };
+        assert_eq!(url.as_deref(), Some("http://localhost:8080/hook"));
+        assert_eq!(Cli::parse_from(["recorder", "daemon"]).settings, settings::DEFAULT_SETTINGS);
+    }
+
+    #[test]
+    fn test_disk_args() {
+        let cli = Cli::parse_from(["recorder", "record", "--min-free-mb", "4096", "--expected-minutes", "60"]);
+        let Some(Commands::Record { args, .. }) = cli.command else { panic!("Expected Record command") };
+        let disk = args.to_config().unwrap().disk;
+        assert_eq!((disk.min_free_megabytes, disk.expected_minutes), (4096, 60));
+        let cli = Cli::parse_from(["recorder", "record", "--min-free-mb", "0"]);
+        let Some(Commands::Record { args, .. }) = cli.command else { panic!("Expected Record command") };
+        assert!(args.to_config().is_err());
+    }
+
+    #[test]
+    fn test_segment_args() {
+        let cli = Cli::parse_from(["recorder", "record", "--segment-minutes", "15", "--segment-mb", "2048"]);
+        let Some(Commands::Record { args, .. }) = cli.command else {
+            panic!("Expected Record command");
+        };
+        let config = args.to_config().unwrap();
+        assert_eq!(config.segment.max_seconds, 900);
+        assert_eq!(config.segment.max_megabytes, 2048);
+
+        let bad = Cli::parse_from(["recorder", "record", "--segment-minutes", "5", "--segment-template", "fixed.mp4"]);
+        let Some(Commands::Record { args, .. }) = bad.command else {
+            panic!("Expected Record command");
+        };
+        assert!(args.to_config().is_err());
+    }
+
+    #[test]
+    fn test_tag_args() {
+        let cli = Cli::parse_from(["recorder", "record", "--tag", "ranked", "--tag", "hyper roll"]);
+        let Some(Commands::Record { args, .. }) = cli.command else {
+            panic!("Expected Record command");
+        };
+        assert_eq!(args.to_config().unwrap().tags, ["ranked", "hyper roll"]);
+    }
+
+    #[test]
+    fn test_embedded_metadata_args() {
+        let cli = Cli::parse_from([
+            "recorder", "record", "--title", "Road to Master", "--patch", "14.20", "--rank", "Diamond II",
+        ]);
+        let Some(Commands::Record { args, .. }) = cli.command else {
+            panic!("Expected Record command");
+        };
+        let config = args.to_config().unwrap();
+        assert_eq!(config.title.as_deref(), Some("Road to Master"));
+        assert!(config.comment.is_none());
+        assert_eq!(config.game.patch.as_deref(), Some("14.20"));
+        assert_eq!(config.game.rank.as_deref(), Some("Diamond II"));
+
+        let probe = Cli::parse_from(["recorder", "probe", "game.mp4", "--json"]);
+        assert!(matches!(probe.command, Some(Commands::Probe { json: true, .. })));
+
+        let ctl = Cli::parse_from(["recorder", "ctl", "game", "--placement", "2"]);
+        match ctl.command {
+            Some(Commands::Ctl { command: CtlCommand::Game { game, placement }, .. }) => {
+                assert!(game.patch.is_none());
+                assert_eq!(placement, Some(2));
+            }
+            _ => panic!("Expected ctl game"),
+        }
+    }
+
+    #[test]
+    fn test_trim_args() {
+        let cli = Cli::parse_from(["recorder", "trim", "/rec/TFT-1.mp4", "--from", "12:30", "--to", "1:01:00.5", "--exact"]);
+        match cli.command {
+            Some(Commands::Trim { input, from, to, out, exact }) => {
+                assert_eq!(from, std::time::Duration::from_secs(750));
+                assert_eq!(to, Some(std::time::Duration::from_secs_f64(3660.5)));
+                assert!(out.is_none() && exact);
+                assert_eq!(trim_output_path(&input), std::path::Path::new("/rec/TFT-1-trim.mp4"));
+            }
+            _ => panic!("Expected trim"),
+        }
+        assert!(Cli::try_parse_from(["recorder", "trim", "a.mp4", "--from", "1:75"]).is_err());
+    }
+
+    #[test]
+    fn test_concat_args_expand_playlists() {
+        let dir = std::env::temp_dir().join(format!("concat_args_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let playlist = dir.join("TFT.m3u8");
+        std::fs::write(&playlist, "#EXTM3U\n#EXTINF:900.000,Part 1\npart1.mp4\n#EXTINF:12.500,Part 2\npart2.mp4\n").unwrap();
+
+        let cli = Cli::parse_from(["recorder", "concat", "intro.mp4", playlist.to_str().unwrap(), "--out", "match.mp4"]);
+        let Some(Commands::Concat { inputs, out }) = cli.command else {
+            panic!("Expected concat");
+        };
+        assert_eq!(out, std::path::Path::new("match.mp4"));
+        let files = expand_playlists(&inputs).unwrap();
+        assert_eq!(files, [std::path::PathBuf::from("intro.mp4"), dir.join("part1.mp4"), dir.join("part2.mp4")]);
+        assert!(Cli::try_parse_from(["recorder", "concat", "--out", "match.mp4"]).is_err());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_export_gif_args() {
+        let cli = Cli::parse_from(["recorder", "export-gif", "game.mp4", "--from", "12:30", "--to", "12:38", "--max-mb", "8"]);
+        match cli.command {
+            Some(Commands::ExportGif { input, from, to, width, fps, max_mb, out }) => {
+                assert_eq!((from, to), (std::time::Duration::from_secs(750), Some(std::time::Duration::from_secs(758))));
+                assert_eq!((width, fps, max_mb), (480, 15, Some(8.0)));
+                assert!(out.is_none());
+                assert_eq!(clip_output_path(&input), std::path::Path::new("game-clip.gif"));
+            }
+            _ => panic!("Expected export-gif"),
+        }
+    }
+
+    #[test]
+    fn test_export_clip_args() {
+        let cli = Cli::parse_from(["recorder", "export-clip", "game.mp4", "--from", "31:02", "--to", "31:20", "--preset", "augments"]);
+        match cli.command {
+            Some(Commands::ExportClip { from, to, preset, out, .. }) => {
+                assert_eq!((from, to), (std::time::Duration::from_secs(1862), Some(std::time::Duration::from_secs(1880))));
+                assert_eq!(CropPreset::named(&preset).unwrap().name, "augments");
+                assert!(out.is_none());
+            }
+            _ => panic!("Expected export-clip"),
+        }
+        let cli = Cli::parse_from(["recorder", "export-clip", "game.mp4"]);
+        assert!(matches!(cli.command, Some(Commands::ExportClip { preset, .. }) if preset == "board"));
+    }
+
+    #[test]
+    fn test_library_args() {
+        let cli = Cli::parse_from(["recorder", "library", "scan", "--dir", "/tmp/recordings"]);
+        assert!(matches!(cli.command, Some(Commands::Library { dir: Some(_), command: LibraryCommand::Scan })));
+
+        let cli = Cli::parse_from([
+            "recorder", "library", "list", "--since", "2024-09-01", "--until", "2024-09-30", "--min-duration", "20:00", "--tag", "ranked",
+            "--placement", "1-4", "--sort", "placement", "--json",
+        ]);
+        let Some(Commands::Library { dir: None, command: LibraryCommand::List { filter, json: true } }) = cli.command else {
+            panic!("Expected library list");
+        };
+        let query = filter.to_query(None);
+        assert_eq!(query.since.unwrap().date_naive(), chrono::NaiveDate::from_ymd_opt(2024, 9, 1).unwrap());
+        // --until includes the whole day
+        assert_eq!(query.until.unwrap().date_naive(), chrono::NaiveDate::from_ymd_opt(2024, 10, 1).unwrap());
+        assert_eq!(query.min_duration, Some(std::time::Duration::from_secs(1200)));
+        assert_eq!((query.tags, query.placement, query.sort), (vec!["ranked".to_string()], Some((1, 4)), recorder_library::SortKey::Placement));
+
+        let cli = Cli::parse_from(["recorder", "library", "search", "kobuko", "--limit", "5"]);
+        let Some(Commands::Library { command: LibraryCommand::Search { text, filter, json: false }, .. }) = cli.command else {
+            panic!("Expected library search");
+        };
+        assert_eq!(filter.to_query(Some(text)).text.as_deref(), Some("kobuko"));
+        assert!(Cli::try_parse_from(["recorder", "library", "tag", "TFT-1.mp4"]).is_err());
+        assert!(Cli::try_parse_from(["recorder", "library", "mv", "TFT-1.mp4", "Win.mp4"]).is_ok());
+        let cli = Cli::parse_from(["recorder", "library", "repair", "--delete-truncated"]);
+        assert!(matches!(cli.command, Some(Commands::Library { command: LibraryCommand::Repair { delete_truncated: true, dry_run: false }, .. })));
+
+        let cli = Cli::parse_from(["recorder", "library", "prune", "--keep-days", "30", "--max-gb", "0.5", "--dry-run"]);
+        let Some(Commands::Library { command: LibraryCommand::Prune { retention, dry_run: true, json: false }, .. }) = cli.command else {
+            panic!("Expected library prune");
+        };
+        let policy = retention.to_policy();
+        assert_eq!((policy.keep_days, policy.max_bytes, policy.keep_tagged), (Some(30), Some(512 * 1024 * 1024), true));
+        let cli = Cli::parse_from(["recorder", "daemon", "--keep-days", "14", "--prune-tagged"]);
+        let Some(Commands::Daemon { retention, prune_every, .. }) = cli.command else {
+            panic!("Expected daemon");
+        };
+        assert!(!retention.to_policy().keep_tagged);
+        assert_eq!(prune_every, std::time::Duration::from_secs(3600));
+
+        assert_eq!(parse_placement("3"), Ok((3, 3)));
+        assert_eq!(parse_placement("1-4"), Ok((1, 4)));
+        assert!(parse_placement("4-1").is_err() && parse_placement("9").is_err());
+        assert!(parse_date("30/09/2024").is_err());
+        let dir = std::path::Path::new("/nonexistent/library");
+        assert_eq!(library_path(dir, std::path::Path::new("TFT-1.mp4")), dir.join("TFT-1.mp4"));
+    }
+
+    #[test]
+    fn test_timelapse_args() {
+        let cli = Cli::parse_from(["recorder", "timelapse", "game.mp4", "--speed", "40x"]);
+        let Some(Commands::Timelapse { input, speed, every, at_markers, fps, out }) = cli.command else {
+            panic!("Expected timelapse");
+        };
+        assert_eq!((speed, every, at_markers, fps), (40.0, None, false, 30));
+        assert!(out.is_none());
+        assert_eq!(timelapse_output_path(&input), std::path::Path::new("game-timelapse.mp4"));
+
+        let cli = Cli::parse_from(["recorder", "timelapse", "game.mp4", "--every", "10s"]);
+        assert!(matches!(cli.command, Some(Commands::Timelapse { every: Some(every), speed, .. }) if every == std::time::Duration::from_secs(10) && speed == 20.0));
+        assert!(Cli::try_parse_from(["recorder", "timelapse", "game.mp4", "--at-markers", "--every", "5"]).is_err());
+        assert!(Cli::try_parse_from(["recorder", "timelapse", "game.mp4", "--speed", "0x"]).is_err());
+        assert_eq!(parse_speed("2.5"), Ok(2.5));
+        assert!(parse_speed("fast").is_err());
+    }
+
+    #[test]
+    fn test_highlights_args_and_directories() {
+        let dir = std::env::temp_dir().join(format!("highlights_args_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["TFT-2.mp4", "TFT-1.mp4", "TFT-1-trim.mp4"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        for name in ["TFT-2.mp4", "TFT-1.mp4"] {
+            std::fs::write(RecordingMetadata::sidecar_path(&dir.join(name)), b"{}").unwrap();
+        }
+
+        let cli = Cli::parse_from(["recorder", "highlights", dir.to_str().unwrap(), "--pre", "3s", "--label", "augment", "--label", "3-star"]);
+        let Some(Commands::Highlights { inputs, pre, post, labels, out }) = cli.command else {
+            panic!("Expected highlights");
+        };
+        assert_eq!((pre, post), (std::time::Duration::from_secs(3), std::time::Duration::from_secs(10)));
+        assert_eq!(labels, ["augment", "3-star"]);
+        assert!(out.is_none());
+        assert_eq!(highlights_output_path(&inputs[0]), dir.join("highlights.mp4"));
+        assert_eq!(highlights_output_path(std::path::Path::new("TFT-1.mp4")), std::path::Path::new("TFT-1-highlights.mp4"));
+        assert_eq!(expand_recording_dirs(&inputs).unwrap(), [dir.join("TFT-1.mp4"), dir.join("TFT-2.mp4")]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_thumbnail_args() {
+        let cli = Cli::parse_from(["recorder", "thumbnail", "game.mp4", "--at", "5:00"]);
+        match cli.command {
+            Some(Commands::Thumbnail { at, out, width, exact, .. }) => {
+                assert_eq!(at, Some(std::time::Duration::from_secs(300)));
+                assert!(out.is_none() && !exact);
+                assert_eq!(width, recorder_core::thumbnail::THUMBNAIL_WIDTH);
+            }
+            _ => panic!("Expected thumbnail"),
+        }
+        // Custom frames go to an explicit file so the cache only holds standard thumbnails
+        assert!(Cli::try_parse_from(["recorder", "thumbnail", "game.mp4", "--exact"]).is_err());
+        assert!(Cli::try_parse_from(["recorder", "thumbnail", "game.mp4", "--exact", "--width", "0", "--out", "f.png"]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("90"), Ok(std::time::Duration::from_secs(90)));
+        assert_eq!(parse_timestamp("1:30.25"), Ok(std::time::Duration::from_secs_f64(90.25)));
+        assert_eq!(parse_timestamp("01:00:00"), Ok(std::time::Duration::from_secs(3600)));
+        assert_eq!(parse_timestamp("2.5s"), Ok(std::time::Duration::from_millis(2500)));
+        assert!(parse_timestamp("1:00s").is_err());
+        assert!(parse_timestamp("1.5:00").is_err());
+        assert!(parse_timestamp("-3").is_err());
+        assert!(parse_timestamp("1:2:3:4").is_err());
+        assert!(parse_timestamp("soon").is_err());
+    }
+
+    #[test]
+    fn test_ctl_mark_args() {
+        let cli = Cli::parse_from(["recorder", "ctl", "mark", "First 3-star", "--at", "412.5"]);
+        match cli.command {
+            Some(Commands::Ctl { command: CtlCommand::Mark { label, at }, .. }) => {
+                assert_eq!(label, "First 3-star");
+                assert_eq!(at, Some(412.5));
+            }
+            _ => panic!("Expected ctl mark"),
+        }
+    }
+
+    #[test]
+    fn test_ctl_save_replay_args() {
+        let cli = Cli::parse_from(["recorder", "ctl", "save-replay", "--seconds", "20"]);
+        match cli.command {
+            Some(Commands::Ctl { socket, command: CtlCommand::SaveReplay { seconds, out } }) => {
+                assert_eq!(socket, ipc::DEFAULT_SOCKET);
+                assert_eq!(seconds, Some(20));
+                assert!(out.is_none());
+            }
+            _ => panic!("Expected ctl save-replay"),
+        }
+    }
+
+    #[test]
+    fn test_mic_device_requires_mic() {
+        assert!(Cli::try_parse_from(["recorder", "record", "--mic-device", "USB"]).is_err());
+        assert!(Cli::try_parse_from(["recorder", "record", "--mic", "--mic-device", "USB"]).is_ok());
+    }
+
+    #[test]
+    fn test_rate_control_args() {
+        assert_eq!(
+            rate_control_from_args(RateControlArg::Cbr, 6_000_000, None, 23).unwrap(),
+            RateControl::Cbr { bitrate: 6_000_000 }
+        );
+        assert_eq!(
+            rate_control_from_args(RateControlArg::Crf, 6_000_000, Some(10_000_000), 20).unwrap(),
+            RateControl::Crf { crf: 20, max_bitrate: Some(10_000_000) }
+        );
+        assert!(rate_control_from_args(RateControlArg::Vbr, 6_000_000, None, 23).is_err());
+    }
 }
\ No newline at end of file
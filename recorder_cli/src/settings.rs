@@ -0,0 +1,133 @@
+// ABOUTME: Settings file (~/.tft-recorder/config.toml) read by the record command and the daemon
+// ABOUTME: Holds what doesn't fit on a command line: hook commands, webhooks, the upload target and the game API
+
+use crate::hooks::HookConfig;
+use crate::webhooks::WebhookConfig;
+use anyhow::{Context, Result};
+use recorder_game::GameConfig;
+use recorder_upload::UploadConfig;
+use serde::Deserialize;
+use std::path::Path;
+
+pub const DEFAULT_SETTINGS: &str = "~/.tft-recorder/config.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub hooks: HookConfig,
+    /// `[[webhooks]]` entries.
+    pub webhooks: Vec<WebhookConfig>,
+    /// Where finished recordings are uploaded, if anywhere.
+    pub upload: Option<UploadConfig>,
+    /// Where to follow matches from, if at all.
+    pub game: Option<GameConfig>,
+}
+
+impl Settings {
+    /// Reads the settings at `path`; a missing file means the defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid settings in {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let settings: Settings = toml::from_str(text)?;
+        settings.hooks.validate()?;
+        for webhook in &settings.webhooks {
+            webhook.validate()?;
+        }
+        if let Some(upload) = &settings.upload {
+            upload.validate()?;
+        }
+        if let Some(game) = &settings.game {
+            game.validate()?;
+        }
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hooks() {
+        let settings = Settings::parse(
+            r#"
+            [hooks]
+            on_finalized = "~/bin/analyze \"$TFT_RECORDING_PATH\""
+            on_error = "notify-send 'Recording failed' \"$TFT_ERROR\""
+            timeout_secs = 60
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.hooks.on_finalized.as_deref(), Some(r#"~/bin/analyze "$TFT_RECORDING_PATH""#));
+        assert_eq!(settings.hooks.on_start, None);
+        assert_eq!(settings.hooks.timeout_secs, 60);
+        assert_eq!(settings.hooks.log, crate::hooks::DEFAULT_LOG);
+
+        assert_eq!(Settings::parse("").unwrap(), Settings::default());
+        assert_eq!(settings.hooks.configured(), [crate::hooks::Hook::Finalized, crate::hooks::Hook::Error]);
+        assert!(Settings::default().hooks.configured().is_empty());
+        assert!(Settings::parse("[hooks]\non_stopp = \"x\"").is_err());
+        assert!(Settings::parse("[hooks]\ntimeout_secs = 0").is_err());
+        assert!(Settings::parse("[hooks]\non_start = \" \"").is_err());
+        assert_eq!(Settings::load(Path::new("/nonexistent/config.toml")).unwrap(), Settings::default());
+    }
+
+    #[test]
+    fn test_parse_upload() {
+        let settings = Settings::parse(
+            r#"
+            [upload]
+            endpoint = "http://minio.local:9000"
+            bucket = "scrims"
+            prefix = "team-a"
+            max_mbps = 20
+            "#,
+        )
+        .unwrap();
+        let upload = settings.upload.unwrap();
+        assert_eq!((upload.bucket.as_str(), upload.prefix.as_str(), upload.max_mbps), ("scrims", "team-a", 20.0));
+        assert_eq!((upload.part_size_mb, upload.auto), (16, true));
+        assert!(Settings::parse("[upload]\nendpoint = \"http://minio.local:9000\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_game() {
+        let settings = Settings::parse("[game]\nurl = \"http://127.0.0.1:8999\"\nmarkers = false").unwrap();
+        let game = settings.game.unwrap();
+        assert_eq!((game.url.as_str(), game.poll_ms, game.markers), ("http://127.0.0.1:8999", 1000, false));
+        assert_eq!(Settings::parse("[game]").unwrap().game.unwrap().url, recorder_game::DEFAULT_URL);
+        assert!(Settings::parse("[game]\npoll_ms = 5").is_err());
+        assert!(Settings::parse("").unwrap().game.is_none());
+    }
+
+    #[test]
+    fn test_parse_webhooks() {
+        let settings = Settings::parse(
+            r#"
+            [[webhooks]]
+            url = "https://bot.example/tft"
+            events = ["recording_finalized"]
+            secret = "s3cret"
+
+            [[webhooks]]
+            url = "http://localhost:8080/events"
+            retries = 0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.webhooks.len(), 2);
+        assert_eq!(settings.webhooks[0].events, ["recording_finalized"]);
+        assert_eq!(settings.webhooks[0].secret.as_deref(), Some("s3cret"));
+        assert_eq!(settings.webhooks[1].events.len(), 4);
+        assert_eq!((settings.webhooks[1].retries, settings.webhooks[1].timeout_secs), (0, 10));
+        assert!(Settings::parse("[[webhooks]]\nevents = []").is_err());
+        assert!(settings.upload.is_none());
+        assert!(Settings::parse("[[webhooks]]\nurl = \"https://x\"\nevents = [\"game_over\"]").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+// ABOUTME: Glue between the recorder and recorder_upload: queues finalized recordings for upload
+// ABOUTME: Runs the daemon's upload worker and formats the queue for `recorder uploads`
+
+use crate::gui;
+use anyhow::Result;
+use recorder_core::events::RecorderEvent;
+use recorder_upload::{UploadConfig, UploadItem, UploadState, Uploader};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long the daemon's worker waits before trying failed uploads again
+/// when nothing new is queued.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn queue_file() -> PathBuf {
+    gui::expand_home(recorder_upload::DEFAULT_QUEUE)
+}
+
+/// Queues recordings for upload as they are finalized, if `auto` is on.
+pub struct UploadQueuer {
+    config: UploadConfig,
+    /// Wakes the daemon's worker; `record` leaves the uploading to it or to
+    /// `recorder uploads run`.
+    wake: Option<Sender<()>>,
+}
+
+impl UploadQueuer {
+    pub fn new(config: UploadConfig, wake: Option<Sender<()>>) -> Self {
+        Self { config, wake }
+    }
+
+    /// Queues the file `event` finalized with its sidecars, returning how
+    /// many files were queued.
+    pub fn handle(&self, event: &RecorderEvent) -> usize {
+        let path = match event {
+            RecorderEvent::RecordingFinalized { path, .. } | RecorderEvent::SegmentFinalized { path, .. } if self.config.auto => path,
+            _ => return 0,
+        };
+        match recorder_upload::enqueue_recording(&queue_file(), &self.config, &gui::recordings_dir(), path) {
+            Ok(queued) => {
+                if let Some(wake) = &self.wake {
+                    wake.send(()).ok();
+                }
+                queued.len()
+            }
+            Err(e) => {
+                eprintln!("Failed to queue {} for upload: {:#}", path.display(), e);
+                0
+            }
+        }
+    }
+}
+
+/// Starts the daemon's upload worker, which works through the queue when
+/// woken and retries failures every [`RETRY_INTERVAL`]. Setting `stop` ends
+/// it after the part being sent; the rest resumes on the next start.
+pub fn spawn_worker(config: UploadConfig, stop: Arc<AtomicBool>) -> Result<Sender<()>> {
+    let uploader = Uploader::new(config, &queue_file())?;
+    let (wake, woken) = mpsc::channel();
+    std::thread::spawn(move || loop {
+        if let Err(e) = uploader.run_pending(&stop, &log_outcome) {
+            eprintln!("Upload queue failed: {:#}", e);
+        }
+        if stop.load(std::sync::atomic::Ordering::SeqCst) {
+            break;
+        }
+        match woken.recv_timeout(RETRY_INTERVAL) {
+            Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    });
+    Ok(wake)
+}
+
+fn log_outcome(item: &UploadItem) {
+    match item.state {
+        UploadState::Done => println!("Uploaded {}", item.key),
+        UploadState::Failed => eprintln!("Upload of {} failed: {}", item.key, item.error.as_deref().unwrap_or_default()),
+        UploadState::Queued | UploadState::Uploading => {
+            if let Some(error) = &item.error {
+                eprintln!("Upload of {} failed (attempt {}), will retry: {}", item.key, item.attempts, error);
+            }
+        }
+    }
+}
+
+/// One line of `recorder uploads`.
+pub fn describe(item: &UploadItem) -> String {
+    let size = format!("{:.1} MB", item.bytes as f64 / 1048576.0);
+    let status = match item.state {
+        UploadState::Queued => "queued".to_string(),
+        UploadState::Uploading => format!("uploading {:.0}%", item.progress() * 100.0),
+        UploadState::Done => "done".to_string(),
+        UploadState::Failed => format!("failed: {}", item.error.as_deref().unwrap_or_default()),
+    };
+    format!("{:>4}  {:<50}  {:>9}  {}", item.id, item.key, size, status)
+}
@@ -0,0 +1,366 @@
+// ABOUTME: HTTP webhooks posting a JSON payload for recording events, e.g. to a team chat bot
+// ABOUTME: Deliveries run in the background, signed with HMAC-SHA256 and retried with exponential backoff
+
+use crate::background::BackgroundJobs;
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use recorder_core::events::RecorderEvent;
+use recorder_core::RecordingMetadata;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `sha256=<hex HMAC-SHA256 of the body>`, sent when the webhook has a secret.
+pub const SIGNATURE_HEADER: &str = "X-TFT-Signature";
+/// The event name, as in the payload's `event` field.
+pub const EVENT_HEADER: &str = "X-TFT-Event";
+
+/// Longest wait between two attempts; the doubling stops there.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// The events a webhook can subscribe to, by their wire names.
+pub const EVENTS: [&str; 7] = [
+    "recording_started",
+    "recording_stopped",
+    "recording_finalized",
+    "recording_failed",
+    "segment_finalized",
+    "disk_space_low",
+    "stopped_for_disk_space",
+];
+
+/// One `[[webhooks]]` entry of the settings file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Events to post, by wire name; defaults to the four `recording_*` events.
+    pub events: Vec<String>,
+    /// Key for the [`SIGNATURE_HEADER`]; unsigned without one.
+    pub secret: Option<String>,
+    /// Further attempts after a failed one; client errors (4xx) aren't retried.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after up to
+    /// [`MAX_RETRY_DELAY`].
+    pub retry_delay_ms: u64,
+    /// Limit for each attempt, connecting included.
+    pub timeout_secs: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            events: EVENTS[..4].iter().map(|event| event.to_string()).collect(),
+            secret: None,
+            retries: 3,
+            retry_delay_ms: 1000,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl WebhookConfig {
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            self.url.starts_with("http://") || self.url.starts_with("https://"),
+            "Webhook url must be an http:// or https:// URL, got {:?}",
+            self.url
+        );
+        for event in &self.events {
+            anyhow::ensure!(EVENTS.contains(&event.as_str()), "Unknown webhook event {:?}; expected one of {}", event, EVENTS.join(", "));
+        }
+        anyhow::ensure!(self.timeout_secs > 0, "Webhook timeout_secs must be at least 1");
+        anyhow::ensure!(
+            u128::from(self.retry_delay_ms) <= MAX_RETRY_DELAY.as_millis(),
+            "Webhook retry_delay_ms must be at most {} ({} minutes)",
+            MAX_RETRY_DELAY.as_millis(),
+            MAX_RETRY_DELAY.as_secs() / 60
+        );
+        Ok(())
+    }
+
+    fn wants(&self, event: &str) -> bool {
+        self.events.iter().any(|wanted| wanted == event)
+    }
+}
+
+/// The outcome of a successful delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delivery {
+    pub status: u16,
+    pub attempts: u32,
+}
+
+/// Posts the events it is handed to the webhooks subscribed to them.
+pub struct Webhooks {
+    webhooks: Vec<Arc<WebhookConfig>>,
+    jobs: BackgroundJobs,
+}
+
+impl Webhooks {
+    /// Delivers to `webhooks` as `jobs`.
+    pub fn new(webhooks: Vec<WebhookConfig>, jobs: BackgroundJobs) -> Self {
+        Self { webhooks: webhooks.into_iter().map(Arc::new).collect(), jobs }
+    }
+
+    /// Starts delivering `event` to each subscribed webhook without waiting
+    /// for the responses; failures are logged once retries run out.
+    pub fn handle(&self, event: &RecorderEvent) {
+        let body = Arc::new(payload(event, false));
+        let name = body["event"].as_str().unwrap_or_default().to_string();
+        for webhook in self.webhooks.iter().filter(|webhook| webhook.wants(&name)) {
+            let (webhook, body, name) = (webhook.clone(), body.clone(), name.clone());
+            self.jobs.spawn(move || {
+                if let Err(e) = deliver(&webhook, &name, &body.to_string()) {
+                    eprintln!("Webhook {} failed for {}: {:#}", webhook.url, name, e);
+                }
+            });
+        }
+    }
+}
+
+/// The JSON body posted for `event`: the event's own fields, when it was
+/// sent and the recording's metadata sidecar if it has one. Test deliveries
+/// carry `"test": true`.
+pub fn payload(event: &RecorderEvent, test: bool) -> Value {
+    let mut payload = serde_json::to_value(event).unwrap_or_default();
+    payload["sent_at"] = json!(chrono::Local::now().to_rfc3339());
+    if let Some(metadata) = event_path(event).and_then(|path| RecordingMetadata::for_recording(path).ok().flatten()) {
+        payload["metadata"] = serde_json::to_value(metadata).unwrap_or_default();
+    }
+    if test {
+        payload["test"] = json!(true);
+    }
+    payload
+}
+
+fn event_path(event: &RecorderEvent) -> Option<&Path> {
+    match event {
+        RecorderEvent::RecordingStarted { path }
+        | RecorderEvent::RecordingStopped { path, .. }
+        | RecorderEvent::RecordingFinalized { path, .. }
+        | RecorderEvent::RecordingFailed { path, .. }
+        | RecorderEvent::SegmentFinalized { path, .. } => Some(path),
+        RecorderEvent::DiskSpaceLow { .. } | RecorderEvent::StoppedForDiskSpace { .. } => None,
+    }
+}
+
+/// The [`SIGNATURE_HEADER`] value for `body`.
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Posts `body` to `webhook`, retrying timeouts, connection failures, 408,
+/// 429 and 5xx responses with exponential backoff.
+pub fn deliver(webhook: &WebhookConfig, event: &str, body: &str) -> Result<Delivery> {
+    let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(webhook.timeout_secs)).build();
+    let signature = webhook.secret.as_deref().map(|secret| sign(secret, body));
+    let mut delay = Duration::from_millis(webhook.retry_delay_ms).min(MAX_RETRY_DELAY);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let mut request = agent
+            .post(&webhook.url)
+            .set("Content-Type", "application/json")
+            .set("User-Agent", concat!("tft-recorder/", env!("CARGO_PKG_VERSION")))
+            .set(EVENT_HEADER, event);
+        if let Some(signature) = &signature {
+            request = request.set(SIGNATURE_HEADER, signature);
+        }
+        let error = match request.send_string(body) {
+            Ok(response) => return Ok(Delivery { status: response.status(), attempts }),
+            Err(ureq::Error::Status(status, _)) if !retryable(status) => anyhow::bail!("the server answered {}", status),
+            Err(ureq::Error::Status(status, _)) => format!("the server answered {}", status),
+            Err(e) => e.to_string(),
+        };
+        anyhow::ensure!(attempts <= webhook.retries, "giving up after {} attempt(s); last error: {}", attempts, error);
+        std::thread::sleep(delay);
+        delay = backoff(delay);
+    }
+}
+
+fn backoff(delay: Duration) -> Duration {
+    delay.saturating_mul(2).min(MAX_RETRY_DELAY)
+}
+
+fn retryable(status: u16) -> bool {
+    status == 408 || status == 429 || status >= 500
+}
+
+/// Posts a sample `recording_finalized` payload marked `"test": true` to
+/// `webhook`, whatever events it is subscribed to.
+pub fn send_test(webhook: &WebhookConfig, recordings: &Path) -> Result<Delivery> {
+    let event = RecorderEvent::RecordingFinalized { path: recordings.join("TFT-webhook-test.mp4"), duration_secs: 1800.0, bytes: 0 };
+    deliver(webhook, "recording_finalized", &payload(&event, true).to_string())
+}
+
+/// The webhooks `recorder webhooks test` posts to: the configured ones, or
+/// only those matching `url`, which needn't be configured.
+pub fn test_targets(configured: &[WebhookConfig], url: Option<&str>) -> Vec<WebhookConfig> {
+    match url {
+        None => configured.to_vec(),
+        Some(url) => {
+            let matching: Vec<WebhookConfig> = configured.iter().filter(|webhook| webhook.url == url).cloned().collect();
+            if matching.is_empty() {
+                vec![WebhookConfig { url: url.to_string(), ..Default::default() }]
+            } else {
+                matching
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread::JoinHandle;
+    use std::time::Instant;
+
+    /// A request as the stub server received it.
+    struct StubRequest {
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    impl StubRequest {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+        }
+    }
+
+    /// Answers one request with each of `statuses` in turn, then returns what
+    /// it received. Gives up waiting for requests after a few seconds.
+    fn stub_server(statuses: Vec<u16>) -> (String, JoinHandle<Vec<StubRequest>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut requests = Vec::new();
+            for status in statuses {
+                let stream = loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => break Some(stream),
+                        Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+                        Err(_) => break None,
+                    }
+                };
+                let Some(mut stream) = stream else { break };
+                stream.set_nonblocking(false).unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut headers = Vec::new();
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let Some((key, value)) = line.trim_end().split_once(": ") else { break };
+                    headers.push((key.to_string(), value.to_string()));
+                }
+                let mut request = StubRequest { headers, body: String::new() };
+                let length: usize = request.header("Content-Length").unwrap().parse().unwrap();
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                request.body = String::from_utf8(body).unwrap();
+                write!(stream, "HTTP/1.1 {} Stub\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    fn webhook(url: &str) -> WebhookConfig {
+        WebhookConfig { url: url.to_string(), retry_delay_ms: 10, ..Default::default() }
+    }
+
+    #[test]
+    fn test_sign() {
+        // The widely published HMAC-SHA256 example
+        assert_eq!(sign("key", "The quick brown fox jumps over the lazy dog"), "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    }
+
+    #[test]
+    fn test_delivery_is_signed_and_retried() {
+        let (url, server) = stub_server(vec![503, 200]);
+        let webhook = WebhookConfig { secret: Some("s3cret".into()), ..webhook(&url) };
+        let delivery = deliver(&webhook, "recording_finalized", r#"{"event":"recording_finalized"}"#).unwrap();
+        assert_eq!(delivery, Delivery { status: 200, attempts: 2 });
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        for request in &requests {
+            assert_eq!(request.body, r#"{"event":"recording_finalized"}"#);
+            assert_eq!(request.header(SIGNATURE_HEADER), Some(sign("s3cret", &request.body).as_str()));
+            assert_eq!(request.header(EVENT_HEADER), Some("recording_finalized"));
+            assert_eq!(request.header("Content-Type"), Some("application/json"));
+        }
+    }
+
+    #[test]
+    fn test_delivery_gives_up() {
+        // Client errors aren't retried
+        let (url, server) = stub_server(vec![404]);
+        let err = deliver(&webhook(&url), "recording_started", "{}").unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+        assert_eq!(server.join().unwrap().len(), 1);
+
+        let (url, server) = stub_server(vec![500, 502]);
+        let err = deliver(&WebhookConfig { retries: 1, ..webhook(&url) }, "recording_started", "{}").unwrap_err();
+        assert!(err.to_string().contains("after 2 attempt(s)"), "{}", err);
+        assert!(server.join().unwrap().iter().all(|request| request.header(SIGNATURE_HEADER).is_none()));
+    }
+
+    #[test]
+    fn test_only_subscribed_events_are_posted() {
+        let (url, server) = stub_server(vec![204]);
+        let jobs = BackgroundJobs::default();
+        let webhooks = Webhooks::new(vec![WebhookConfig { events: vec!["recording_finalized".into()], ..webhook(&url) }], jobs.clone());
+        let path = PathBuf::from("/nonexistent/TFT-1.mp4");
+        webhooks.handle(&RecorderEvent::RecordingStarted { path: path.clone() });
+        webhooks.handle(&RecorderEvent::RecordingFinalized { path, duration_secs: 1500.0, bytes: 4096 });
+        jobs.wait();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        let payload: Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(payload["event"], "recording_finalized");
+        assert_eq!(payload["duration_secs"], 1500.0);
+        assert!(payload["sent_at"].is_string() && payload.get("test").is_none() && payload.get("metadata").is_none());
+    }
+
+    #[test]
+    fn test_send_test_and_targets() {
+        let configured = [webhook("https://bot.example/a"), webhook("https://bot.example/b")];
+        assert_eq!(test_targets(&configured, None).len(), 2);
+        assert_eq!(test_targets(&configured, Some("https://bot.example/b"))[0], configured[1]);
+        assert_eq!(test_targets(&configured, Some("http://localhost:1/x"))[0].url, "http://localhost:1/x");
+
+        let (url, server) = stub_server(vec![200]);
+        let dir = std::env::temp_dir();
+        assert_eq!(send_test(&webhook(&url), &dir).unwrap().status, 200);
+        let payload: Value = serde_json::from_str(&server.join().unwrap()[0].body).unwrap();
+        assert_eq!((payload["event"].as_str(), payload["test"].as_bool()), (Some("recording_finalized"), Some(true)));
+
+        assert!(webhook("ftp://x").validate().is_err());
+        assert!(WebhookConfig { events: vec!["finished".into()], ..webhook("https://x") }.validate().is_err());
+        assert!(webhook("https://x").validate().is_ok());
+        assert!(WebhookConfig { retry_delay_ms: u64::MAX, ..webhook("https://x") }.validate().is_err());
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        assert_eq!(backoff(Duration::from_secs(1)), Duration::from_secs(2));
+        assert_eq!(backoff(Duration::from_secs(200)), MAX_RETRY_DELAY);
+        assert_eq!(backoff(Duration::MAX), MAX_RETRY_DELAY);
+    }
+}
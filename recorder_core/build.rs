@@ -38,10 +38,16 @@ fn main() {
     // Also add the development path for non-bundled usage
     println!("cargo:rustc-link-arg=-Wl,-rpath,{}", lib_dir.display());
 
+    // AppleCapture calls back into recorder_core (the frame pacer), so the
+    // binary must export those symbols for the dylib to bind to
+    println!("cargo:rustc-link-arg=-Wl,-export_dynamic");
+
     // 3. Link system frameworks
     for fw in ["AVFoundation", "CoreMedia", "CoreVideo", "VideoToolbox", "CoreGraphics"] {
         println!("cargo:rustc-link-lib=framework={}", fw);
     }
+    // Game audio uses ScreenCaptureKit, which only exists on macOS 12.3+
+    println!("cargo:rustc-link-arg=-Wl,-weak_framework,ScreenCaptureKit");
 
     // 4. Rebuild if Swift code changed
     println!("cargo:rerun-if-changed=../apple_capture/Sources");
@@ -0,0 +1,260 @@
+// ABOUTME: Exports a short range of a recording as an animated GIF or WebP for sharing in chat
+// ABOUTME: Decodes and scales the frames once, then lowers fps and width until a size budget is met
+
+use crate::thumbnail::{self, RgbFrame};
+use anyhow::{Context, Result};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, RgbImage};
+use std::path::Path;
+use std::time::Duration;
+use webp_animation::{ColorMode, Encoder as WebPEncoder, EncoderOptions, EncodingConfig};
+
+/// Longest range that can be exported; every frame is held in memory.
+pub const MAX_ANIMATION_LENGTH: Duration = Duration::from_secs(60);
+
+/// Lowest fps and width the size budget may reduce an export to.
+pub const MIN_BUDGET_FPS: u32 = 5;
+pub const MIN_BUDGET_WIDTH: u32 = 160;
+
+/// NeuQuant sampling factor for the GIF palettes (1 is best, 30 fastest).
+const GIF_QUANTIZER_SPEED: i32 = 10;
+const WEBP_QUALITY: f32 = 75.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    WebP,
+}
+
+impl AnimationFormat {
+    /// The format matching the extension of `path`.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("gif") => Ok(Self::Gif),
+            Some("webp") => Ok(Self::WebP),
+            _ => anyhow::bail!("{} should end in .gif or .webp", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationOptions {
+    pub from: Duration,
+    /// End of the range; the end of the recording when `None`.
+    pub to: Option<Duration>,
+    /// Maximum width in pixels; the height follows the aspect ratio.
+    pub width: u32,
+    pub fps: u32,
+    /// Lower fps, then width, until the file fits in this many bytes.
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self { from: Duration::ZERO, to: None, width: 480, fps: 15, max_bytes: None }
+    }
+}
+
+/// What `export_animation` wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationResult {
+    pub format: AnimationFormat,
+    pub frames: usize,
+    pub width: u32,
+    pub height: u32,
+    /// Frame rate used, lower than asked for if the size budget needed it.
+    pub fps: u32,
+    /// Whether fps or width were lowered to fit the size budget.
+    pub reduced: bool,
+    pub duration: Duration,
+    pub bytes: u64,
+}
+
+/// Writes `[options.from, options.to)` of the recording at `input` as an
+/// animated GIF or WebP, picked by the extension of `output`. Frames are
+/// sampled at `options.fps` and scaled to `options.width`; with a size budget
+/// the clip is re-encoded at lower settings until it fits.
+pub fn export_animation(input: impl AsRef<Path>, output: impl AsRef<Path>, options: &AnimationOptions) -> Result<AnimationResult> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    let format = AnimationFormat::from_path(output)?;
+    anyhow::ensure!(options.fps > 0, "Frame rate must be at least 1");
+
+    let length = crate::mp4::Mp4Reader::open(input)?.duration;
+    let to = options.to.map_or(length, |to| to.min(length));
+    anyhow::ensure!(options.from < to, "Nothing to export between {:?} and {:?}", options.from, to);
+    let duration = to - options.from;
+    anyhow::ensure!(
+        duration <= MAX_ANIMATION_LENGTH,
+        "{:.1} s is too long for an animation (at most {} s); trim the recording instead",
+        duration.as_secs_f64(),
+        MAX_ANIMATION_LENGTH.as_secs()
+    );
+
+    let frames = sample_frames(input, options.from, to, options.fps, options.width)?;
+    let (mut fps, mut width) = (options.fps, frames[0].width);
+    let mut reduce_fps = true;
+    loop {
+        let clip = resample(&frames, options.fps, fps, width);
+        let data = encode(format, &clip, fps)?;
+        let fits = options.max_bytes.is_none_or(|max| data.len() as u64 <= max);
+        let can_reduce = fps > MIN_BUDGET_FPS || width > MIN_BUDGET_WIDTH;
+        if fits || !can_reduce {
+            anyhow::ensure!(
+                fits,
+                "Smallest export ({}x{} at {} fps) is {} bytes, over the {} byte budget; export a shorter range",
+                clip[0].width,
+                clip[0].height,
+                fps,
+                data.len(),
+                options.max_bytes.unwrap_or_default()
+            );
+            std::fs::write(output, &data).with_context(|| format!("Failed to write {}", output.display()))?;
+            return Ok(AnimationResult {
+                format,
+                frames: clip.len(),
+                width: clip[0].width,
+                height: clip[0].height,
+                fps,
+                reduced: fps < options.fps || width < frames[0].width,
+                duration,
+                bytes: data.len() as u64,
+            });
+        }
+
+        // Alternate between the two, skipping whichever is already at its floor
+        if (reduce_fps && fps > MIN_BUDGET_FPS) || width <= MIN_BUDGET_WIDTH {
+            fps = (fps * 3 / 4).max(MIN_BUDGET_FPS);
+        } else {
+            width = (width * 4 / 5).max(MIN_BUDGET_WIDTH);
+        }
+        reduce_fps = !reduce_fps;
+    }
+}
+
+/// The frames on screen at `from`, `from + 1/fps`, ... up to `to`, scaled to `width`.
+fn sample_frames(input: &Path, from: Duration, to: Duration, fps: u32, width: u32) -> Result<Vec<RgbFrame>> {
+    let count = ((to - from).as_secs_f64() * f64::from(fps)).ceil().max(1.0) as usize;
+    let target = |i: usize| from + Duration::from_secs_f64(i as f64 / f64::from(fps));
+    let mut frames: Vec<RgbFrame> = Vec::with_capacity(count);
+    let mut shown: Option<RgbFrame> = None;
+    thumbnail::decode_frames(input, from, Some(to), |frame| {
+        // Everything before this frame's time shows the previous one
+        if let Some(previous) = &shown {
+            while frames.len() < count && target(frames.len()) < frame.time {
+                frames.push(previous.clone());
+            }
+        }
+        shown = Some(frame.scaled_to_width(width));
+        Ok(())
+    })?;
+    let last = shown.context("No video frames in the range")?;
+    frames.resize(count, last);
+    Ok(frames)
+}
+
+/// `frames` sampled at `source_fps`, re-sampled at `fps` and scaled to `width`.
+fn resample(frames: &[RgbFrame], source_fps: u32, fps: u32, width: u32) -> Vec<RgbFrame> {
+    let count = (frames.len() as u64 * u64::from(fps)).div_ceil(u64::from(source_fps)) as usize;
+    (0..count)
+        .map(|i| {
+            let source = (i as u64 * u64::from(source_fps) / u64::from(fps)) as usize;
+            frames[source.min(frames.len() - 1)].scaled_to_width(width)
+        })
+        .collect()
+}
+
+fn encode(format: AnimationFormat, frames: &[RgbFrame], fps: u32) -> Result<Vec<u8>> {
+    let frame_ms = |i: usize| (i as u64 * 1000 / u64::from(fps)) as i32;
+    match format {
+        AnimationFormat::Gif => {
+            let mut data = Vec::new();
+            {
+                let mut encoder = GifEncoder::new_with_speed(&mut data, GIF_QUANTIZER_SPEED);
+                encoder.set_repeat(Repeat::Infinite)?;
+                let delay = Delay::from_numer_denom_ms(1000, fps);
+                for frame in frames {
+                    let image = RgbImage::from_raw(frame.width, frame.height, frame.data.clone()).context("Frame buffer doesn't match its size")?;
+                    encoder.encode_frame(Frame::from_parts(DynamicImage::ImageRgb8(image).into_rgba8(), 0, 0, delay))?;
+                }
+            }
+            Ok(data)
+        }
+        AnimationFormat::WebP => {
+            let options = EncoderOptions {
+                color_mode: ColorMode::Rgb,
+                encoding_config: Some(EncodingConfig::new_lossy(WEBP_QUALITY)),
+                ..Default::default()
+            };
+            let mut encoder = WebPEncoder::new_with_options((frames[0].width, frames[0].height), options)?;
+            for (i, frame) in frames.iter().enumerate() {
+                encoder.add_frame(&frame.data, frame_ms(i))?;
+            }
+            Ok(encoder.finalize(frame_ms(frames.len()))?.to_vec())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FrameRateMode, RecordingConfig};
+    use crate::test_support::{temp_file, write_synthetic};
+    use image::AnimationDecoder;
+
+    /// 2 s of moving test pattern at 15 fps.
+    fn write_recording(name: &str) -> std::path::PathBuf {
+        let path = temp_file(&format!("anim_{}", name));
+        let config = RecordingConfig::new(name, 240, 136).with_frame_rate(15, FrameRateMode::Constant);
+        write_synthetic(&path, &config, 30).finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_gif_and_webp_export() {
+        let input = write_recording("formats");
+        let options = AnimationOptions { from: Duration::from_millis(500), to: Some(Duration::from_millis(1500)), width: 120, fps: 10, max_bytes: None };
+
+        let gif = input.with_extension("gif");
+        let result = export_animation(&input, &gif, &options).unwrap();
+        assert_eq!((result.format, result.frames, result.width, result.height, result.fps), (AnimationFormat::Gif, 10, 120, 68, 10));
+        assert_eq!(result.bytes, std::fs::metadata(&gif).unwrap().len());
+        let decoded = image::codecs::gif::GifDecoder::new(std::fs::File::open(&gif).unwrap()).unwrap().into_frames().collect_frames().unwrap();
+        assert_eq!(decoded.len(), 10);
+        assert_eq!(decoded[0].buffer().dimensions(), (120, 68));
+        assert_eq!(decoded[0].delay(), Delay::from_numer_denom_ms(100, 1));
+
+        let webp = input.with_extension("webp");
+        let result = export_animation(&input, &webp, &options).unwrap();
+        assert_eq!((result.format, result.frames), (AnimationFormat::WebP, 10));
+        let data = std::fs::read(&webp).unwrap();
+        let decoder = webp_animation::Decoder::new(&data).unwrap();
+        assert_eq!(decoder.dimensions(), (120, 68));
+        assert_eq!(decoder.into_iter().count(), 10);
+
+        assert!(export_animation(&input, input.with_extension("avi"), &options).is_err());
+        for path in [gif, webp, input] {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    #[test]
+    fn test_size_budget_lowers_fps_and_width() {
+        let input = write_recording("budget");
+        let gif = input.with_extension("gif");
+        let full = export_animation(&input, &gif, &AnimationOptions { width: 240, fps: 15, ..Default::default() }).unwrap();
+
+        let budget = full.bytes / 3;
+        let options = AnimationOptions { width: 240, fps: 15, max_bytes: Some(budget), ..Default::default() };
+        let reduced = export_animation(&input, &gif, &options).unwrap();
+        assert!(reduced.bytes <= budget, "{} > {}", reduced.bytes, budget);
+        assert!(reduced.reduced && reduced.fps < 15);
+        assert!(!full.reduced);
+        assert_eq!(reduced.duration, Duration::from_secs(2));
+
+        let impossible = AnimationOptions { max_bytes: Some(100), ..options };
+        let error = export_animation(&input, &gif, &impossible).unwrap_err();
+        assert!(error.to_string().contains("over the 100 byte budget"), "{}", error);
+        std::fs::remove_file(gif).ok();
+        std::fs::remove_file(input).ok();
+    }
+}
@@ -0,0 +1,141 @@
+// ABOUTME: Audio track kinds, PCM buffers and a synthetic tone source for the encoders
+// ABOUTME: Capture happens inside AppleCapture on macOS; there is no Linux audio capture yet
+
+use crate::config::AudioConfig;
+use anyhow::Result;
+use std::time::Duration;
+
+/// Which input an audio track records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AudioTrackKind {
+    /// Whatever is playing on the default output device.
+    Game,
+    Microphone,
+}
+
+impl AudioTrackKind {
+    /// Human-readable track name, also used as the MP4 handler name.
+    pub fn label(&self) -> &'static str {
+        match self {
+            AudioTrackKind::Game => "Game audio",
+            AudioTrackKind::Microphone => "Microphone",
+        }
+    }
+
+    /// Tracks enabled by `config`, in the order they are written to the file.
+    pub fn enabled(config: &AudioConfig) -> Vec<AudioTrackKind> {
+        let mut kinds = Vec::new();
+        if config.game {
+            kinds.push(AudioTrackKind::Game);
+        }
+        if config.mic {
+            kinds.push(AudioTrackKind::Microphone);
+        }
+        kinds
+    }
+}
+
+/// Interleaved signed 16-bit PCM with the capture time of its first sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioBuffer {
+    pub pts: Duration,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<i16>,
+}
+
+impl AudioBuffer {
+    pub fn new(pts: Duration, sample_rate: u32, channels: u16, samples: Vec<i16>) -> Self {
+        Self { pts, sample_rate, channels, samples }
+    }
+
+    /// Number of sample frames (one sample per channel).
+    pub fn frames(&self) -> usize {
+        self.samples.len() / usize::from(self.channels.max(1))
+    }
+
+    pub fn duration(&self) -> Duration {
+        frames_to_duration(self.frames() as u64, self.sample_rate)
+    }
+}
+
+/// Anything that produces PCM buffers timestamped on the capture clock.
+pub trait AudioSource: Send {
+    /// Returns the next buffer, or `None` once the source is exhausted.
+    fn next_buffer(&mut self) -> Result<Option<AudioBuffer>>;
+}
+
+pub(crate) fn frames_to_duration(frames: u64, sample_rate: u32) -> Duration {
+    let nanos = u128::from(frames) * 1_000_000_000 / u128::from(sample_rate.max(1));
+    Duration::from_nanos(nanos as u64)
+}
+
+fn duration_to_frames(duration: Duration, sample_rate: u32) -> u64 {
+    let frames = duration.as_nanos() * u128::from(sample_rate) + 500_000_000;
+    (frames / 1_000_000_000) as u64
+}
+
+/// Generates a sine tone in fixed-size buffers. Timestamps are simulated, so
+/// it never sleeps.
+pub struct SyntheticAudioSource {
+    sample_rate: u32,
+    channels: u16,
+    frequency: f32,
+    buffer_frames: usize,
+    total_frames: u64,
+    produced: u64,
+    clock: Duration,
+}
+
+impl SyntheticAudioSource {
+    pub fn new(sample_rate: u32, channels: u16, duration: Duration) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            frequency: 440.0,
+            buffer_frames: (sample_rate / 100) as usize,
+            total_frames: duration_to_frames(duration, sample_rate),
+            produced: 0,
+            clock: Duration::ZERO,
+        }
+    }
+}
+
+impl AudioSource for SyntheticAudioSource {
+    fn next_buffer(&mut self) -> Result<Option<AudioBuffer>> {
+        if self.produced >= self.total_frames {
+            return Ok(None);
+        }
+
+        let frames = (self.total_frames - self.produced).min(self.buffer_frames as u64);
+        let step = std::f32::consts::TAU * self.frequency / self.sample_rate as f32;
+        let mut samples = Vec::with_capacity(frames as usize * usize::from(self.channels));
+        for n in self.produced..self.produced + frames {
+            let value = ((n as f32 * step).sin() * f32::from(i16::MAX) * 0.25) as i16;
+            samples.extend(std::iter::repeat_n(value, usize::from(self.channels)));
+        }
+
+        let buffer = AudioBuffer::new(self.clock, self.sample_rate, self.channels, samples);
+        self.produced += frames;
+        self.clock += buffer.duration();
+        Ok(Some(buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthetic_source_is_continuous() {
+        let mut source = SyntheticAudioSource::new(48_000, 2, Duration::from_millis(25));
+        let mut buffers = Vec::new();
+        while let Some(b) = source.next_buffer().unwrap() {
+            buffers.push(b);
+        }
+
+        assert_eq!(buffers.iter().map(|b| b.frames()).sum::<usize>(), 1200);
+        assert_eq!(buffers[1].pts, Duration::from_millis(10));
+        assert_eq!(buffers[2].frames(), 240);
+    }
+}
@@ -0,0 +1,380 @@
+// ABOUTME: Software audio encoders (AAC via fdk-aac, Opus behind the `opus` feature)
+// ABOUTME: Buffers aligned PCM into codec frames and emits packets timed by sample count
+
+use crate::audio::frames_to_duration;
+use crate::config::{AudioCodec, AudioConfig};
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+/// One compressed audio frame, ready to be written as an MP4 sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioPacket {
+    pub pts: Duration,
+    /// Length of the packet in sample frames.
+    pub duration: u32,
+    pub data: Vec<u8>,
+}
+
+pub trait AudioEncoder: Send {
+    fn codec(&self) -> AudioCodec;
+
+    /// Samples per channel in every packet.
+    fn frame_size(&self) -> usize;
+
+    /// Codec setup bytes for the sample description: the AudioSpecificConfig
+    /// for AAC (`esds`) or the OpusSpecificBox payload (`dOps`).
+    fn decoder_config(&self) -> Vec<u8>;
+
+    /// Leading samples of decoder output that are encoder delay, not audio.
+    fn priming_samples(&self) -> u32;
+
+    /// Encodes interleaved PCM; samples short of a full frame are kept for the next call.
+    fn encode(&mut self, samples: &[i16]) -> Result<Vec<AudioPacket>>;
+
+    /// Pads the last partial frame with silence and drains the encoder.
+    fn flush(&mut self) -> Result<Vec<AudioPacket>>;
+}
+
+pub fn create_encoder(config: &AudioConfig) -> Result<Box<dyn AudioEncoder>> {
+    config.validate()?;
+    match config.codec {
+        AudioCodec::Aac => Ok(Box::new(AacEncoder::new(config)?)),
+        #[cfg(feature = "opus")]
+        AudioCodec::Opus => Ok(Box::new(OpusEncoder::new(config)?)),
+        #[cfg(not(feature = "opus"))]
+        AudioCodec::Opus => anyhow::bail!("Opus audio requires building recorder_core with the `opus` feature"),
+    }
+}
+
+/// Collects interleaved samples until whole codec frames are available.
+struct FrameQueue {
+    channels: usize,
+    frame_size: usize,
+    pending: Vec<i16>,
+}
+
+impl FrameQueue {
+    fn new(channels: u16, frame_size: usize) -> Self {
+        Self { channels: usize::from(channels), frame_size, pending: Vec::new() }
+    }
+
+    fn frame_len(&self) -> usize {
+        self.frame_size * self.channels
+    }
+
+    fn push(&mut self, samples: &[i16]) -> Vec<Vec<i16>> {
+        self.pending.extend_from_slice(samples);
+        let len = self.frame_len();
+        let whole = self.pending.len() / len * len;
+        let frames = self.pending.drain(..whole).collect::<Vec<_>>();
+        frames.chunks_exact(len).map(<[i16]>::to_vec).collect()
+    }
+
+    /// Remaining samples padded to a full frame, if any are left.
+    fn take_padded(&mut self) -> Option<Vec<i16>> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let mut frame = std::mem::take(&mut self.pending);
+        frame.resize(self.frame_len(), 0);
+        Some(frame)
+    }
+}
+
+pub struct AacEncoder {
+    inner: fdk_aac::enc::Encoder,
+    sample_rate: u32,
+    queue: FrameQueue,
+    asc: Vec<u8>,
+    delay: u32,
+    max_packet: usize,
+    frames_in: u64,
+    packets_out: u64,
+}
+
+impl AacEncoder {
+    pub fn new(config: &AudioConfig) -> Result<Self> {
+        use fdk_aac::enc::{AudioObjectType, BitRate, ChannelMode, Encoder, EncoderParams, Transport};
+
+        let inner = Encoder::new(EncoderParams {
+            bit_rate: BitRate::Cbr(config.bitrate),
+            sample_rate: config.sample_rate,
+            transport: Transport::Raw,
+            channels: if config.channels == 1 { ChannelMode::Mono } else { ChannelMode::Stereo },
+            audio_object_type: AudioObjectType::Mpeg4LowComplexity,
+        })
+        .map_err(|e| anyhow!("Failed to create AAC encoder: {}", e))?;
+        let info = inner.info().map_err(|e| anyhow!("Failed to query AAC encoder: {}", e))?;
+
+        Ok(Self {
+            inner,
+            sample_rate: config.sample_rate,
+            queue: FrameQueue::new(config.channels, info.frameLength as usize),
+            asc: info.confBuf[..info.confSize as usize].to_vec(),
+            delay: info.nDelay,
+            max_packet: info.maxOutBufBytes as usize,
+            frames_in: 0,
+            packets_out: 0,
+        })
+    }
+
+    fn encode_frame(&mut self, frame: &[i16], count_input: bool) -> Result<Option<AudioPacket>> {
+        let mut out = vec![0u8; self.max_packet];
+        let info = self
+            .inner
+            .encode(frame, &mut out)
+            .map_err(|e| anyhow!("AAC encoding failed: {}", e))?;
+        if count_input {
+            self.frames_in += (frame.len() / self.queue.channels) as u64;
+        }
+        if info.output_size == 0 {
+            return Ok(None);
+        }
+
+        // Raw AAC packets always cover exactly one frame; time them by count
+        // so the track stays locked to the sample clock.
+        let frame_size = self.queue.frame_size as u64;
+        let pts = frames_to_duration(self.packets_out * frame_size, self.sample_rate);
+        self.packets_out += 1;
+        out.truncate(info.output_size);
+        Ok(Some(AudioPacket { pts, duration: frame_size as u32, data: out }))
+    }
+}
+
+impl AudioEncoder for AacEncoder {
+    fn codec(&self) -> AudioCodec {
+        AudioCodec::Aac
+    }
+
+    fn frame_size(&self) -> usize {
+        self.queue.frame_size
+    }
+
+    fn decoder_config(&self) -> Vec<u8> {
+        self.asc.clone()
+    }
+
+    fn priming_samples(&self) -> u32 {
+        self.delay
+    }
+
+    fn encode(&mut self, samples: &[i16]) -> Result<Vec<AudioPacket>> {
+        let mut packets = Vec::new();
+        for frame in self.queue.push(samples) {
+            packets.extend(self.encode_frame(&frame, true)?);
+        }
+        Ok(packets)
+    }
+
+    fn flush(&mut self) -> Result<Vec<AudioPacket>> {
+        let mut packets = Vec::new();
+        if let Some(frame) = self.queue.take_padded() {
+            packets.extend(self.encode_frame(&frame, true)?);
+        }
+
+        // The fdk wrapper has no end-of-stream call, so push silence until the
+        // delayed input (priming included) has come out the other side.
+        let frame_size = self.queue.frame_size as u64;
+        let expected = (self.frames_in + u64::from(self.delay)).div_ceil(frame_size);
+        let silence = vec![0i16; self.queue.frame_len()];
+        let mut attempts = 0;
+        while self.packets_out < expected && attempts < 8 {
+            packets.extend(self.encode_frame(&silence, false)?);
+            attempts += 1;
+        }
+        Ok(packets)
+    }
+}
+
+#[cfg(feature = "opus")]
+pub struct OpusEncoder {
+    state: *mut opusic_sys::OpusEncoder,
+    channels: u16,
+    sample_rate: u32,
+    queue: FrameQueue,
+    lookahead: u32,
+    packets_out: u64,
+}
+
+// SAFETY: the encoder state is owned exclusively by this struct and libopus
+// keeps no thread-local state, so moving it between threads is fine.
+#[cfg(feature = "opus")]
+unsafe impl Send for OpusEncoder {}
+
+#[cfg(feature = "opus")]
+impl OpusEncoder {
+    /// 20 ms at 48 kHz, the frame length libopus is tuned for.
+    const FRAME_SIZE: usize = 960;
+    const MAX_PACKET: usize = 4000;
+
+    pub fn new(config: &AudioConfig) -> Result<Self> {
+        use opusic_sys::*;
+
+        let mut error = 0;
+        // SAFETY: plain constructor call; `error` outlives it.
+        let state = unsafe {
+            opus_encoder_create(
+                config.sample_rate as i32,
+                i32::from(config.channels),
+                OPUS_APPLICATION_AUDIO,
+                &mut error,
+            )
+        };
+        anyhow::ensure!(!state.is_null() && error == OPUS_OK, "Failed to create Opus encoder (code {})", error);
+
+        let mut encoder = Self {
+            state,
+            channels: config.channels,
+            sample_rate: config.sample_rate,
+            queue: FrameQueue::new(config.channels, Self::FRAME_SIZE),
+            lookahead: 0,
+            packets_out: 0,
+        };
+
+        let mut lookahead = 0i32;
+        // SAFETY: `state` is a live encoder and both requests take the
+        // argument types passed here.
+        let (rc_bitrate, rc_lookahead) = unsafe {
+            (
+                opus_encoder_ctl(state, OPUS_SET_BITRATE_REQUEST, config.bitrate as i32),
+                opus_encoder_ctl(state, OPUS_GET_LOOKAHEAD_REQUEST, &mut lookahead as *mut i32),
+            )
+        };
+        anyhow::ensure!(rc_bitrate == OPUS_OK, "Opus rejected bitrate {}", config.bitrate);
+        anyhow::ensure!(rc_lookahead == OPUS_OK, "Failed to query Opus lookahead");
+        encoder.lookahead = lookahead.max(0) as u32;
+        Ok(encoder)
+    }
+
+    fn encode_frame(&mut self, frame: &[i16]) -> Result<AudioPacket> {
+        let mut out = vec![0u8; Self::MAX_PACKET];
+        // SAFETY: `frame` holds exactly FRAME_SIZE samples per channel and
+        // `out` is MAX_PACKET bytes long.
+        let len = unsafe {
+            opusic_sys::opus_encode(
+                self.state,
+                frame.as_ptr(),
+                Self::FRAME_SIZE as i32,
+                out.as_mut_ptr(),
+                Self::MAX_PACKET as i32,
+            )
+        };
+        anyhow::ensure!(len > 0, "Opus encoding failed (code {})", len);
+        out.truncate(len as usize);
+
+        let pts = frames_to_duration(self.packets_out * Self::FRAME_SIZE as u64, self.sample_rate);
+        self.packets_out += 1;
+        Ok(AudioPacket { pts, duration: Self::FRAME_SIZE as u32, data: out })
+    }
+}
+
+#[cfg(feature = "opus")]
+impl AudioEncoder for OpusEncoder {
+    fn codec(&self) -> AudioCodec {
+        AudioCodec::Opus
+    }
+
+    fn frame_size(&self) -> usize {
+        Self::FRAME_SIZE
+    }
+
+    fn decoder_config(&self) -> Vec<u8> {
+        opus_specific_box(self.channels, self.lookahead, self.sample_rate)
+    }
+
+    fn priming_samples(&self) -> u32 {
+        self.lookahead
+    }
+
+    fn encode(&mut self, samples: &[i16]) -> Result<Vec<AudioPacket>> {
+        self.queue.push(samples).iter().map(|f| self.encode_frame(f)).collect()
+    }
+
+    fn flush(&mut self) -> Result<Vec<AudioPacket>> {
+        let mut packets = Vec::new();
+        if let Some(frame) = self.queue.take_padded() {
+            packets.push(self.encode_frame(&frame)?);
+        }
+        // One more frame carries the lookahead tail of the real audio.
+        if self.lookahead > 0 {
+            packets.push(self.encode_frame(&vec![0i16; self.queue.frame_len()])?);
+        }
+        Ok(packets)
+    }
+}
+
+#[cfg(feature = "opus")]
+impl Drop for OpusEncoder {
+    fn drop(&mut self) {
+        // SAFETY: `state` came from opus_encoder_create and is freed once.
+        unsafe { opusic_sys::opus_encoder_destroy(self.state) };
+    }
+}
+
+/// Payload of the ISO-BMFF `dOps` box (Opus in MP4, §4.3.2).
+pub fn opus_specific_box(channels: u16, pre_skip: u32, input_sample_rate: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(11);
+    out.push(0); // version
+    out.push(channels as u8);
+    out.extend_from_slice(&(pre_skip.min(u32::from(u16::MAX)) as u16).to_be_bytes());
+    out.extend_from_slice(&input_sample_rate.to_be_bytes());
+    out.extend_from_slice(&0i16.to_be_bytes()); // output gain
+    out.push(0); // channel mapping family: mono/stereo
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioSource, SyntheticAudioSource};
+
+    fn encode_tone(config: &AudioConfig, duration: Duration) -> (Box<dyn AudioEncoder>, Vec<AudioPacket>) {
+        let mut encoder = create_encoder(config).unwrap();
+        let mut source = SyntheticAudioSource::new(config.sample_rate, config.channels, duration);
+        let mut packets = Vec::new();
+        while let Some(buffer) = source.next_buffer().unwrap() {
+            packets.extend(encoder.encode(&buffer.samples).unwrap());
+        }
+        packets.extend(encoder.flush().unwrap());
+        (encoder, packets)
+    }
+
+    #[test]
+    fn test_aac_stereo_48k() {
+        let config = AudioConfig::default();
+        let (encoder, packets) = encode_tone(&config, Duration::from_secs(1));
+
+        // AudioSpecificConfig: AAC-LC (2), 48 kHz (index 3), 2 channels
+        assert_eq!(encoder.decoder_config(), vec![0x11, 0x90]);
+        assert_eq!(encoder.frame_size(), 1024);
+
+        // 48000 samples plus priming, rounded up to whole 1024-sample frames
+        let expected = (48_000 + u64::from(encoder.priming_samples())).div_ceil(1024);
+        assert_eq!(packets.len() as u64, expected);
+        for (i, p) in packets.iter().enumerate() {
+            assert_eq!(p.pts, frames_to_duration(i as u64 * 1024, 48_000));
+            assert!(!p.data.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_aac_bitrate_is_respected() {
+        let config = AudioConfig { bitrate: 96_000, channels: 1, ..Default::default() };
+        let (_, packets) = encode_tone(&config, Duration::from_secs(2));
+        let bytes: usize = packets.iter().map(|p| p.data.len()).sum();
+        let kbps = bytes as f64 * 8.0 / 2.0 / 1000.0;
+        assert!((60.0..=110.0).contains(&kbps), "got {:.1} kbps", kbps);
+    }
+
+    #[test]
+    fn test_opus_specific_box_layout() {
+        assert_eq!(opus_specific_box(2, 312, 48_000), vec![0, 2, 0x01, 0x38, 0, 0, 0xbb, 0x80, 0, 0, 0]);
+    }
+
+    #[cfg(not(feature = "opus"))]
+    #[test]
+    fn test_opus_without_feature_is_an_error() {
+        let config = AudioConfig { codec: AudioCodec::Opus, ..Default::default() };
+        assert!(create_encoder(&config).is_err());
+    }
+}
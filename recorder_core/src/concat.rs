@@ -0,0 +1,292 @@
+// ABOUTME: Joins recordings (e.g. the segments of one session) into a single MP4 without re-encoding
+// ABOUTME: Checks that every input shares codec parameters, then appends their sample tables end to end
+
+use crate::markers::{Marker, MarkerList};
+use crate::mp4::reader::{ticks_to_duration, SampleEntry, Track};
+use crate::mp4::{write_chapters, write_tags, AudioTrackInfo, Mp4Reader, Mp4Writer, Sample, TrackInfo};
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+
+/// What `concat` wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConcatResult {
+    pub inputs: usize,
+    pub duration: Duration,
+    pub video_frames: usize,
+    pub markers: usize,
+    pub bytes: u64,
+}
+
+/// Writes `inputs` one after the other to `output`. All inputs need the same
+/// video parameters (resolution, SPS/PPS) and the same audio tracks; markers
+/// are shifted onto the joined timeline and the first input's embedded
+/// metadata is kept.
+pub fn concat(inputs: &[impl AsRef<Path>], output: impl AsRef<Path>) -> Result<ConcatResult> {
+    let output = output.as_ref();
+    anyhow::ensure!(inputs.len() >= 2, "Concatenation needs at least two recordings");
+    anyhow::ensure!(
+        inputs.iter().all(|input| input.as_ref() != output),
+        "Concatenation output must differ from its inputs"
+    );
+
+    let mut readers = Vec::with_capacity(inputs.len());
+    let mut layouts = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let input = input.as_ref();
+        let reader = Mp4Reader::open(input)?;
+        layouts.push(layout(&reader).with_context(|| format!("Can't concatenate {}", input.display()))?);
+        readers.push((input.to_path_buf(), reader));
+    }
+    let expected = &layouts[0];
+    for ((path, _), actual) in readers.iter().zip(&layouts).skip(1) {
+        check_compatible(expected, actual, &readers[0].0, path)?;
+    }
+
+    let mut writer = Mp4Writer::create(output)?;
+    let video_track = writer.add_track(expected.video.clone());
+    let audio_tracks: Vec<usize> = expected
+        .audio
+        .iter()
+        .map(|info| writer.add_track(TrackInfo::Audio(info.clone())))
+        .collect();
+
+    let mut offset = Duration::ZERO;
+    let mut video_frames = 0;
+    let mut bytes = 0u64;
+    let mut markers = Vec::new();
+    for (path, reader) in &mut readers {
+        let tracks = reader.tracks.clone();
+        let mut next_audio = audio_tracks.iter();
+        for track in &tracks {
+            let id = if track.is_video() {
+                video_track
+            } else {
+                *next_audio.next().context("More audio tracks than the first input")?
+            };
+            for sample in playable_samples(track) {
+                let data = reader.read_sample(sample)?;
+                writer.write_sample(
+                    id,
+                    Sample {
+                        pts: offset + track.sample_time(sample),
+                        duration: Some(ticks_to_duration(u64::from(sample.duration), track.timescale)),
+                        keyframe: sample.keyframe,
+                        data: &data,
+                    },
+                )?;
+                bytes += data.len() as u64;
+            }
+            if track.is_video() {
+                video_frames += track.samples.len();
+            }
+        }
+
+        let part = MarkerList::for_recording(path, &reader.chapters)?;
+        markers.extend(part.markers().iter().map(|m| Marker { time: offset + m.time, label: m.label.clone() }));
+        offset += reader.duration;
+    }
+    writer.finish()?;
+
+    let list = MarkerList::with_markers(MarkerList::sidecar_path(output), markers);
+    if !list.is_empty() {
+        list.save()?;
+        write_chapters(output, &list.chapters(Duration::ZERO, offset))?;
+    }
+    let tags = &readers[0].1.tags;
+    if !tags.is_empty() {
+        write_tags(output, tags)?;
+    }
+
+    Ok(ConcatResult { inputs: readers.len(), duration: offset, video_frames, markers: list.markers().len(), bytes })
+}
+
+/// The sample descriptions an input contributes, in output track order.
+struct Layout {
+    video: TrackInfo,
+    /// Audio tracks with their encoder delay dropped.
+    audio: Vec<AudioTrackInfo>,
+}
+
+fn layout(reader: &Mp4Reader) -> Result<Layout> {
+    let videos: Vec<&Track> = reader.tracks.iter().filter(|t| t.is_video()).collect();
+    anyhow::ensure!(videos.len() == 1, "expected one video track, found {}", videos.len());
+    let video = videos[0];
+    anyhow::ensure!(!video.reordered, "it uses B-frames");
+    anyhow::ensure!(!video.samples.is_empty(), "it has no video frames");
+    anyhow::ensure!(
+        video.sample_descriptions.len() == 1,
+        "its video switches parameter sets mid-way (an exact trim?)"
+    );
+
+    let audio = reader
+        .tracks
+        .iter()
+        .filter_map(|t| match &t.info {
+            // Each part's encoder delay is dropped sample by sample instead
+            TrackInfo::Audio(a) => Some(AudioTrackInfo { priming_samples: 0, ..a.clone() }),
+            TrackInfo::Video(_) => None,
+        })
+        .collect();
+    Ok(Layout { video: video.info.clone(), audio })
+}
+
+fn check_compatible(expected: &Layout, actual: &Layout, first: &Path, path: &Path) -> Result<()> {
+    let (TrackInfo::Video(want), TrackInfo::Video(got)) = (&expected.video, &actual.video) else {
+        unreachable!("layout() only returns video tracks here");
+    };
+    anyhow::ensure!(
+        (want.width, want.height) == (got.width, got.height),
+        "{} is {}x{} but {} is {}x{}",
+        path.display(),
+        got.width,
+        got.height,
+        first.display(),
+        want.width,
+        want.height
+    );
+    anyhow::ensure!(
+        want.parameter_sets == got.parameter_sets,
+        "{} was encoded with different H.264 parameters (SPS/PPS) than {}; re-encode one of them first",
+        path.display(),
+        first.display()
+    );
+    anyhow::ensure!(
+        expected.audio.len() == actual.audio.len(),
+        "{} has {} audio tracks but {} has {}",
+        path.display(),
+        actual.audio.len(),
+        first.display(),
+        expected.audio.len()
+    );
+    for (index, (want, got)) in expected.audio.iter().zip(&actual.audio).enumerate() {
+        anyhow::ensure!(
+            want == got,
+            "Audio track {} of {} ({:?} {} Hz, {} ch, {}) doesn't match {} ({:?} {} Hz, {} ch, {})",
+            index + 1,
+            path.display(),
+            got.codec,
+            got.sample_rate,
+            got.channels,
+            got.kind.label(),
+            first.display(),
+            want.codec,
+            want.sample_rate,
+            want.channels,
+            want.kind.label()
+        );
+    }
+    Ok(())
+}
+
+/// Samples of `track` that are presented, i.e. without audio that lies
+/// entirely inside the encoder delay the track's edit list hides.
+pub(crate) fn playable_samples(track: &Track) -> impl Iterator<Item = &SampleEntry> {
+    let priming = match &track.info {
+        TrackInfo::Audio(a) => u64::from(a.priming_samples),
+        TrackInfo::Video(_) => 0,
+    };
+    track.samples.iter().filter(move |s| s.ticks + u64::from(s.duration) > priming)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioSource, AudioTrackKind, SyntheticAudioSource};
+    use crate::audio_encoder::create_encoder;
+    use crate::config::{AudioCodec, AudioConfig, FrameRateMode, RecordingConfig};
+    use crate::mp4::Chapter;
+    use crate::test_support::{cleanup, temp_file, write_synthetic};
+
+    /// A recording as the software pipeline would write it: 30 fps video
+    /// and, optionally, an AAC track with its encoder delay.
+    fn write_part(path: &Path, width: u32, frames: u64, audio: bool) -> u32 {
+        let config = RecordingConfig::new("concat", width, 48)
+            .with_frame_rate(30, FrameRateMode::Constant)
+            .with_keyframe_interval(30);
+        let mut writer = write_synthetic(path, &config, frames);
+
+        let mut priming = 0;
+        if audio {
+            let length = Duration::from_millis(frames * 1000 / 30);
+            let audio_config = AudioConfig { codec: AudioCodec::Aac, ..Default::default() };
+            let mut source = SyntheticAudioSource::new(48_000, 2, length);
+            let mut encoder = create_encoder(&audio_config).unwrap();
+            let mut packets = Vec::new();
+            while let Some(buffer) = source.next_buffer().unwrap() {
+                packets.extend(encoder.encode(&buffer.samples).unwrap());
+            }
+            packets.extend(encoder.flush().unwrap());
+            priming = encoder.priming_samples();
+            let id = writer.add_track(TrackInfo::Audio(AudioTrackInfo {
+                kind: AudioTrackKind::Game,
+                codec: AudioCodec::Aac,
+                sample_rate: 48_000,
+                channels: 2,
+                decoder_config: encoder.decoder_config(),
+                priming_samples: priming,
+            }));
+            for packet in &packets {
+                let sample = Sample { pts: packet.pts, duration: None, keyframe: true, data: &packet.data };
+                writer.write_sample(id, sample).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+        priming
+    }
+
+    #[test]
+    fn test_parts_are_joined_end_to_end() {
+        let (a, b, out) = (temp_file("concat_a"), temp_file("concat_b"), temp_file("concat_ab"));
+        let priming = write_part(&a, 64, 60, true);
+        write_part(&b, 64, 30, true);
+        let mut markers = MarkerList::new(MarkerList::sidecar_path(&a));
+        markers.add(Marker { time: Duration::from_millis(500), label: "Stage 1-1".into() }).unwrap();
+        // The second part only has chapters, as after a copy without its sidecar
+        write_chapters(&b, &[Chapter { start: Duration::from_millis(250), title: "Stage 2-1".into() }]).unwrap();
+
+        let (first, second) = (Mp4Reader::open(&a).unwrap(), Mp4Reader::open(&b).unwrap());
+        let result = concat(&[&a, &b], &out).unwrap();
+        assert_eq!(result.inputs, 2);
+        assert_eq!(result.video_frames, 90);
+        assert_eq!(result.duration, first.duration + second.duration);
+
+        let reader = Mp4Reader::open(&out).unwrap();
+        assert_eq!(reader.tracks.len(), 2);
+        let video = reader.video_track().unwrap();
+        assert_eq!(video.samples.len(), 90);
+        assert!(video.samples[60].keyframe);
+        assert_eq!(video.sample_time(&video.samples[60]), first.duration);
+
+        // Each part's fully-primed packets are dropped so no silence gap is heard
+        let dropped = (priming / 1024) as usize;
+        let audio_in = first.tracks[1].samples.len() + second.tracks[1].samples.len();
+        assert_eq!(reader.tracks[1].samples.len(), audio_in - 2 * dropped);
+        let TrackInfo::Audio(info) = &reader.tracks[1].info else { panic!("Expected audio") };
+        assert_eq!(info.priming_samples, 0);
+
+        let times: Vec<_> = reader.chapters.iter().map(|c| (c.start, c.title.as_str())).collect();
+        let stage_2 = first.duration + Duration::from_millis(250);
+        assert_eq!(times, [(Duration::ZERO, "Start"), (Duration::from_millis(500), "Stage 1-1"), (stage_2, "Stage 2-1")]);
+        assert_eq!(result.markers, 2);
+        assert_eq!(MarkerList::load(&MarkerList::sidecar_path(&out)).unwrap()[1].time, stage_2);
+        cleanup(&[&a, &b, &out]);
+    }
+
+    #[test]
+    fn test_mismatched_parameters_are_reported() {
+        let (a, wide, silent, out) = (temp_file("concat_base"), temp_file("concat_wide"), temp_file("concat_silent"), temp_file("concat_bad"));
+        write_part(&a, 64, 30, true);
+        write_part(&wide, 96, 30, true);
+        write_part(&silent, 64, 30, false);
+
+        let error = concat(&[&a, &wide], &out).unwrap_err().to_string();
+        assert!(error.contains("96x48"), "{}", error);
+        let error = concat(&[&a, &silent], &out).unwrap_err().to_string();
+        assert!(error.contains("0 audio tracks"), "{}", error);
+        assert!(concat(&[&a], &out).is_err());
+        assert!(concat(&[&a, &silent], &a).is_err());
+        assert!(!out.exists());
+        cleanup(&[&a, &wide, &silent]);
+    }
+}
@@ -0,0 +1,556 @@
+// ABOUTME: Recording configuration shared by the capture backends and the CLI/GUI
+// ABOUTME: Describes target window, geometry, frame pacing, rate control, audio, replay and segments
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Default GOP length in frames (one keyframe per second at 60 fps).
+pub const DEFAULT_KEYFRAME_INTERVAL: u32 = 60;
+
+/// Highest CRF value accepted by H.264 (0 = lossless-ish, 51 = worst).
+pub const MAX_CRF: u8 = 51;
+
+pub const DEFAULT_FPS: u32 = 60;
+pub const MAX_FPS: u32 = 240;
+
+pub const DEFAULT_SEGMENT_TEMPLATE: &str = "TFT-{date}-part{n}.mp4";
+
+/// Whether output frames land on a fixed grid or keep their capture times.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameRateMode {
+    /// Drop/duplicate frames so the file has exactly `fps` frames per second.
+    #[default]
+    Constant,
+    /// Pass capture timestamps through; static scenes produce fewer frames.
+    Variable,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioCodec {
+    #[default]
+    Aac,
+    /// Requires 48 kHz; the software encoder needs the `opus` feature.
+    Opus,
+}
+
+impl AudioCodec {
+    /// Stable numeric identifier passed across the Swift FFI boundary.
+    pub fn ffi_codec(&self) -> u32 {
+        match self {
+            AudioCodec::Aac => 0,
+            AudioCodec::Opus => 1,
+        }
+    }
+}
+
+/// Which audio tracks to record and how to encode them. Each enabled source
+/// becomes its own track so game sound and comms can be mixed later.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// System output (the game) as captured from the default output device.
+    pub game: bool,
+    /// Microphone input.
+    pub mic: bool,
+    /// Microphone device name; `None` uses the system default input.
+    pub mic_device: Option<String>,
+    pub codec: AudioCodec,
+    /// Per-track bitrate in bits per second.
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl AudioConfig {
+    pub fn enabled(&self) -> bool {
+        self.game || self.mic
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        anyhow::ensure!(
+            matches!(self.sample_rate, 44_100 | 48_000),
+            "Audio sample rate must be 44100 or 48000 Hz (got {})",
+            self.sample_rate
+        );
+        anyhow::ensure!(
+            self.codec != AudioCodec::Opus || self.sample_rate == 48_000,
+            "Opus audio requires a 48000 Hz sample rate"
+        );
+        anyhow::ensure!(
+            (1..=2).contains(&self.channels),
+            "Audio must be mono or stereo (got {} channels)",
+            self.channels
+        );
+        anyhow::ensure!(self.bitrate > 0, "Audio bitrate must be greater than zero");
+        if let Some(device) = self.mic_device.as_deref().filter(|_| self.mic) {
+            anyhow::ensure!(!device.contains('\0'), "Microphone device name {:?} contains a NUL byte", device);
+        }
+        Ok(())
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            game: true,
+            mic: false,
+            mic_device: None,
+            codec: AudioCodec::Aac,
+            bitrate: 160_000,
+            sample_rate: 48_000,
+            channels: 2,
+        }
+    }
+}
+
+/// In-memory instant-replay buffer of encoded packets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplayConfig {
+    pub enabled: bool,
+    /// How much history to keep.
+    pub seconds: u32,
+    /// Hard memory cap; older GOPs are dropped first when it is hit.
+    pub max_megabytes: u32,
+}
+
+impl ReplayConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled {
+            anyhow::ensure!(self.seconds > 0, "Replay buffer length must be at least one second");
+            anyhow::ensure!(self.max_megabytes > 0, "Replay buffer size must be at least 1 MB");
+        }
+        Ok(())
+    }
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self { enabled: false, seconds: 30, max_megabytes: 256 }
+    }
+}
+
+/// Splits a long recording into several files. Each limit is off at 0; when
+/// both are set, whichever is hit first starts the next segment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SegmentConfig {
+    pub max_seconds: u32,
+    pub max_megabytes: u32,
+    /// File name of each segment: `{date}` is the session start, `{n}` the
+    /// 1-based segment number and `{stem}` the output file's stem.
+    pub name_template: String,
+}
+
+impl SegmentConfig {
+    pub fn enabled(&self) -> bool {
+        self.max_seconds > 0 || self.max_megabytes > 0
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled() {
+            anyhow::ensure!(
+                self.name_template.contains("{n}"),
+                "Segment name template must contain {{n}} (got \"{}\")",
+                self.name_template
+            );
+            anyhow::ensure!(
+                !self.name_template.contains('/'),
+                "Segment name template must be a file name, not a path"
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        Self { max_seconds: 0, max_megabytes: 0, name_template: DEFAULT_SEGMENT_TEMPLATE.to_string() }
+    }
+}
+
+/// Free-space limits for the volume the recording is written to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiskConfig {
+    /// Space always left free; recording stops cleanly when it is reached.
+    pub min_free_megabytes: u32,
+    /// Length of recording there must be room for, on top of the reserve, to start.
+    pub expected_minutes: u32,
+    /// Warn once less than this many minutes of recording fit.
+    pub warn_minutes: u32,
+}
+
+impl DiskConfig {
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(self.min_free_megabytes > 0, "Free-space reserve must be at least 1 MB");
+        Ok(())
+    }
+}
+
+impl Default for DiskConfig {
+    fn default() -> Self {
+        Self { min_free_megabytes: 1024, expected_minutes: 45, warn_minutes: 5 }
+    }
+}
+
+/// What is known about the game being recorded. Embedded in the finished
+/// file; the placement usually only arrives once the game is over.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameInfo {
+    pub patch: Option<String>,
+    pub rank: Option<String>,
+    pub placement: Option<u8>,
+}
+
+impl GameInfo {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(placement) = self.placement {
+            anyhow::ensure!((1..=8).contains(&placement), "Placement must be between 1 and 8 (got {})", placement);
+        }
+        Ok(())
+    }
+
+    /// Overwrites the fields that `update` sets.
+    pub fn merge(&mut self, update: GameInfo) {
+        self.patch = update.patch.or(self.patch.take());
+        self.rank = update.rank.or(self.rank.take());
+        self.placement = update.placement.or(self.placement);
+    }
+}
+
+/// How the encoder spends bits over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum RateControl {
+    /// Constant bitrate: every second gets (roughly) the same number of bits.
+    Cbr { bitrate: u32 },
+    /// Average bitrate: hits the target over the whole file, no peak limit.
+    Abr { bitrate: u32 },
+    /// Variable bitrate around `bitrate`, never exceeding `max_bitrate`.
+    Vbr { bitrate: u32, max_bitrate: u32 },
+    /// Constant quality (CRF), optionally capped at `max_bitrate`.
+    Crf { crf: u8, max_bitrate: Option<u32> },
+}
+
+impl RateControl {
+    /// Target bitrate, if the mode has one.
+    pub fn bitrate(&self) -> Option<u32> {
+        match *self {
+            RateControl::Cbr { bitrate }
+            | RateControl::Abr { bitrate }
+            | RateControl::Vbr { bitrate, .. } => Some(bitrate),
+            RateControl::Crf { .. } => None,
+        }
+    }
+
+    /// Peak bitrate the encoder must stay under, if any.
+    pub fn max_bitrate(&self) -> Option<u32> {
+        match *self {
+            RateControl::Cbr { bitrate } => Some(bitrate),
+            RateControl::Abr { .. } => None,
+            RateControl::Vbr { max_bitrate, .. } => Some(max_bitrate),
+            RateControl::Crf { max_bitrate, .. } => max_bitrate,
+        }
+    }
+
+    /// CRF mapped onto the 0.0–1.0 scale used by `AVVideoQualityKey`.
+    pub fn quality(&self) -> Option<f32> {
+        match *self {
+            RateControl::Crf { crf, .. } => Some(crf_to_quality(crf)),
+            _ => None,
+        }
+    }
+
+    /// Stable numeric identifier passed across the Swift FFI boundary.
+    pub fn ffi_mode(&self) -> u32 {
+        match self {
+            RateControl::Cbr { .. } => 0,
+            RateControl::Abr { .. } => 1,
+            RateControl::Vbr { .. } => 2,
+            RateControl::Crf { .. } => 3,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            RateControl::Cbr { bitrate } | RateControl::Abr { bitrate } => {
+                anyhow::ensure!(bitrate > 0, "Bitrate must be greater than zero");
+            }
+            RateControl::Vbr { bitrate, max_bitrate } => {
+                anyhow::ensure!(bitrate > 0, "Bitrate must be greater than zero");
+                anyhow::ensure!(
+                    max_bitrate >= bitrate,
+                    "Max bitrate ({}) must be at least the target bitrate ({})",
+                    max_bitrate,
+                    bitrate
+                );
+            }
+            RateControl::Crf { crf, max_bitrate } => {
+                anyhow::ensure!(crf <= MAX_CRF, "CRF must be between 0 and {}", MAX_CRF);
+                anyhow::ensure!(
+                    max_bitrate != Some(0),
+                    "Max bitrate must be greater than zero"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for RateControl {
+    fn default() -> Self {
+        RateControl::Abr { bitrate: 4_000_000 }
+    }
+}
+
+fn crf_to_quality(crf: u8) -> f32 {
+    1.0 - f32::from(crf.min(MAX_CRF)) / f32::from(MAX_CRF)
+}
+
+/// Everything needed to start a recording apart from the output path.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    pub window_title: String,
+    pub width: u32,
+    pub height: u32,
+    pub rate_control: RateControl,
+    /// Maximum distance between keyframes, in frames.
+    pub keyframe_interval: u32,
+    /// Target (CFR) or maximum (VFR) output frame rate.
+    pub fps: u32,
+    pub frame_rate_mode: FrameRateMode,
+    pub audio: AudioConfig,
+    pub replay: ReplayConfig,
+    pub segment: SegmentConfig,
+    pub disk: DiskConfig,
+    /// Free-form labels saved with the recording's metadata.
+    pub tags: Vec<String>,
+    /// Title embedded in the file; defaults to one built from the start time.
+    pub title: Option<String>,
+    /// Comment embedded in the file.
+    pub comment: Option<String>,
+    pub game: GameInfo,
+}
+
+impl RecordingConfig {
+    pub fn new(window_title: &str, width: u32, height: u32) -> Self {
+        Self {
+            window_title: window_title.to_string(),
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_rate_control(mut self, rate_control: RateControl) -> Self {
+        self.rate_control = rate_control;
+        self
+    }
+
+    pub fn with_keyframe_interval(mut self, frames: u32) -> Self {
+        self.keyframe_interval = frames;
+        self
+    }
+
+    pub fn with_frame_rate(mut self, fps: u32, mode: FrameRateMode) -> Self {
+        self.fps = fps;
+        self.frame_rate_mode = mode;
+        self
+    }
+
+    pub fn with_audio(mut self, audio: AudioConfig) -> Self {
+        self.audio = audio;
+        self
+    }
+
+    pub fn with_replay(mut self, replay: ReplayConfig) -> Self {
+        self.replay = replay;
+        self
+    }
+
+    pub fn with_segments(mut self, segment: SegmentConfig) -> Self {
+        self.segment = segment;
+        self
+    }
+
+    pub fn with_disk(mut self, disk: DiskConfig) -> Self {
+        self.disk = disk;
+        self
+    }
+
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    pub fn with_game(mut self, game: GameInfo) -> Self {
+        self.game = game;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            self.width > 0 && self.height > 0,
+            "Resolution must be non-zero (got {}x{})",
+            self.width,
+            self.height
+        );
+        anyhow::ensure!(
+            self.keyframe_interval > 0,
+            "Keyframe interval must be at least one frame"
+        );
+        anyhow::ensure!(
+            (1..=MAX_FPS).contains(&self.fps),
+            "Frame rate must be between 1 and {} fps (got {})",
+            MAX_FPS,
+            self.fps
+        );
+        self.audio.validate()?;
+        self.replay.validate()?;
+        self.segment.validate()?;
+        self.disk.validate()?;
+        self.game.validate()?;
+        self.rate_control.validate()
+    }
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            window_title: "Teamfight Tactics".to_string(),
+            width: 1280,
+            height: 720,
+            rate_control: RateControl::default(),
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            fps: DEFAULT_FPS,
+            frame_rate_mode: FrameRateMode::Constant,
+            audio: AudioConfig::default(),
+            replay: ReplayConfig::default(),
+            segment: SegmentConfig::default(),
+            disk: DiskConfig::default(),
+            tags: Vec::new(),
+            title: None,
+            comment: None,
+            game: GameInfo::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_matches_cli_defaults() {
+        let config = RecordingConfig::default();
+        assert_eq!(config.width, 1280);
+        assert_eq!(config.height, 720);
+        assert_eq!(config.rate_control.bitrate(), Some(4_000_000));
+        assert_eq!(config.keyframe_interval, 60);
+        assert_eq!(config.fps, 60);
+        assert_eq!(config.frame_rate_mode, FrameRateMode::Constant);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_game_info_merge_and_placement_bounds() {
+        let mut game = GameInfo { patch: Some("14.20".into()), rank: Some("Gold I".into()), placement: None };
+        game.merge(GameInfo { placement: Some(3), ..Default::default() });
+        assert_eq!(game.patch.as_deref(), Some("14.20"));
+        assert_eq!(game.placement, Some(3));
+        assert!(game.validate().is_ok());
+        assert!(GameInfo { placement: Some(9), ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_frame_rate_bounds() {
+        let config = RecordingConfig::default();
+        assert!(config.clone().with_frame_rate(0, FrameRateMode::Constant).validate().is_err());
+        assert!(config.clone().with_frame_rate(241, FrameRateMode::Variable).validate().is_err());
+        assert!(config.with_frame_rate(30, FrameRateMode::Variable).validate().is_ok());
+    }
+
+    #[test]
+    fn test_audio_validation() {
+        let audio = AudioConfig::default();
+        assert!(audio.game && !audio.mic);
+        assert!(audio.validate().is_ok());
+
+        let opus_44k = AudioConfig { codec: AudioCodec::Opus, sample_rate: 44_100, ..audio.clone() };
+        assert!(opus_44k.validate().is_err());
+
+        let surround = AudioConfig { channels: 6, ..audio.clone() };
+        assert!(surround.validate().is_err());
+
+        let nul = AudioConfig { mic: true, mic_device: Some("USB\0Mic".into()), ..audio.clone() };
+        assert!(nul.validate().unwrap_err().to_string().contains("NUL"));
+
+        // Settings don't matter when no track is enabled
+        let off = AudioConfig { game: false, mic: false, channels: 6, ..audio };
+        assert!(off.validate().is_ok());
+    }
+
+    #[test]
+    fn test_segment_template_needs_counter() {
+        let off = SegmentConfig { name_template: "fixed.mp4".into(), ..Default::default() };
+        assert!(!off.enabled());
+        assert!(off.validate().is_ok());
+
+        let fixed = SegmentConfig { max_seconds: 600, ..off };
+        assert!(fixed.validate().is_err());
+        let nested = SegmentConfig { name_template: "a/{n}.mp4".into(), ..fixed.clone() };
+        assert!(nested.validate().is_err());
+        let ok = SegmentConfig { name_template: SegmentConfig::default().name_template, ..fixed };
+        assert!(ok.validate().is_ok());
+    }
+
+    #[test]
+    fn test_vbr_max_below_target_is_rejected() {
+        let rc = RateControl::Vbr { bitrate: 6_000_000, max_bitrate: 4_000_000 };
+        assert!(rc.validate().is_err());
+    }
+
+    #[test]
+    fn test_crf_range_and_quality_mapping() {
+        assert!(RateControl::Crf { crf: 52, max_bitrate: None }.validate().is_err());
+        assert_eq!(RateControl::Crf { crf: 0, max_bitrate: None }.quality(), Some(1.0));
+        assert_eq!(RateControl::Crf { crf: 51, max_bitrate: None }.quality(), Some(0.0));
+
+        let capped = RateControl::Crf { crf: 23, max_bitrate: Some(8_000_000) };
+        assert_eq!(capped.max_bitrate(), Some(8_000_000));
+        assert_eq!(capped.bitrate(), None);
+    }
+
+    #[test]
+    fn test_config_round_trips_through_json() {
+        let config = RecordingConfig::new("Finder", 1920, 1080)
+            .with_rate_control(RateControl::Vbr { bitrate: 6_000_000, max_bitrate: 12_000_000 })
+            .with_keyframe_interval(120)
+            .with_frame_rate(30, FrameRateMode::Variable)
+            .with_audio(AudioConfig { mic: true, codec: AudioCodec::Opus, ..Default::default() })
+            .with_replay(ReplayConfig { enabled: true, seconds: 45, max_megabytes: 128 })
+            .with_segments(SegmentConfig { max_seconds: 900, ..Default::default() });
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains("\"mode\":\"vbr\""));
+        let back: RecordingConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, config);
+    }
+}
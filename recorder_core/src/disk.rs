@@ -0,0 +1,252 @@
+// ABOUTME: Free-space guard for the recording volume: refuses to start when short, stops cleanly before it fills
+// ABOUTME: Space is read through the DiskSpace trait so tests can simulate a filling disk
+
+use crate::config::{RateControl, RecordingConfig};
+use crate::events::{EventBus, RecorderEvent};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often free space is checked while recording.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Minimum time between repeated low-space warnings.
+pub const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Assumed average for CRF without a bitrate cap, in bits per pixel per frame.
+const CRF_BITS_PER_PIXEL: f64 = 0.1;
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// Source of free-space figures.
+pub trait DiskSpace: Send + Sync {
+    /// Bytes available to this user on the volume holding `path`.
+    fn available_bytes(&self, path: &Path) -> Result<u64>;
+}
+
+/// The real volumes, via `statvfs`.
+pub struct SystemDiskSpace;
+
+#[cfg(unix)]
+impl DiskSpace for SystemDiskSpace {
+    fn available_bytes(&self, path: &Path) -> Result<u64> {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: `path` is NUL-terminated and `stat` is a valid out-pointer
+        let result = unsafe { libc::statvfs(path.as_ptr(), &mut stat) };
+        anyhow::ensure!(result == 0, "statvfs failed: {}", std::io::Error::last_os_error());
+        #[allow(clippy::unnecessary_cast)]
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+#[cfg(not(unix))]
+impl DiskSpace for SystemDiskSpace {
+    fn available_bytes(&self, _path: &Path) -> Result<u64> {
+        anyhow::bail!("Free space can't be read on this platform")
+    }
+}
+
+/// Bytes per second `config` is expected to write, from its video and audio
+/// bitrates. Uncapped CRF is estimated from the resolution and frame rate.
+pub fn estimated_byte_rate(config: &RecordingConfig) -> u64 {
+    let video = match config.rate_control {
+        RateControl::Crf { max_bitrate: None, .. } => f64::from(config.width) * f64::from(config.height) * f64::from(config.fps) * CRF_BITS_PER_PIXEL,
+        // A VBR file averages its target, not its cap
+        ref rate => f64::from(rate.bitrate().or(rate.max_bitrate()).unwrap_or_default()),
+    };
+    let audio_tracks = u32::from(config.audio.game) + u32::from(config.audio.mic);
+    let audio = f64::from(config.audio.bitrate) * f64::from(audio_tracks);
+    ((video + audio) / 8.0).ceil() as u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskStatus {
+    Ok,
+    /// Less than the warning time fits before the reserve.
+    Low { available_bytes: u64, remaining: Duration },
+    /// Down to the reserve; the recording must stop.
+    Full { available_bytes: u64 },
+}
+
+/// Free-space checks for one recording.
+pub struct DiskGuard {
+    space: Arc<dyn DiskSpace>,
+    /// The directory written to, or its nearest existing ancestor.
+    dir: PathBuf,
+    reserve: u64,
+    expected: Duration,
+    warn: Duration,
+    byte_rate: u64,
+}
+
+impl DiskGuard {
+    pub fn new(config: &RecordingConfig, output: &Path, space: Arc<dyn DiskSpace>) -> Self {
+        let dir = output.ancestors().skip(1).find(|dir| dir.is_dir()).unwrap_or(Path::new(".")).to_path_buf();
+        Self {
+            space,
+            dir,
+            reserve: u64::from(config.disk.min_free_megabytes) * MEGABYTE,
+            expected: Duration::from_secs(u64::from(config.disk.expected_minutes) * 60),
+            warn: Duration::from_secs(u64::from(config.disk.warn_minutes) * 60),
+            byte_rate: estimated_byte_rate(config).max(1),
+        }
+    }
+
+    /// Fails unless the expected length of recording fits above the reserve.
+    /// A volume whose free space can't be read is let through.
+    pub fn check_start(&self) -> Result<()> {
+        let available = match self.space.available_bytes(&self.dir) {
+            Ok(available) => available,
+            Err(e) => {
+                eprintln!("Can't check free space on {}: {:#}", self.dir.display(), e);
+                return Ok(());
+            }
+        };
+        let needed = self.reserve.saturating_add(self.byte_rate.saturating_mul(self.expected.as_secs()));
+        anyhow::ensure!(
+            available >= needed,
+            "Not enough free space on {}: {} MB free, {} MB needed ({} min at ~{:.1} MB/min plus a {} MB reserve)",
+            self.dir.display(),
+            available / MEGABYTE,
+            needed.div_ceil(MEGABYTE),
+            self.expected.as_secs() / 60,
+            self.byte_rate.saturating_mul(60) as f64 / MEGABYTE as f64,
+            self.reserve / MEGABYTE
+        );
+        Ok(())
+    }
+
+    pub fn check(&self) -> Result<DiskStatus> {
+        let available = self.space.available_bytes(&self.dir)?;
+        if available <= self.reserve {
+            return Ok(DiskStatus::Full { available_bytes: available });
+        }
+        let remaining = Duration::from_secs((available - self.reserve) / self.byte_rate);
+        Ok(if remaining < self.warn { DiskStatus::Low { available_bytes: available, remaining } } else { DiskStatus::Ok })
+    }
+}
+
+/// Checks `guard` every `interval` while `active` says the recording is
+/// running, publishing low-space warnings, and calls `stop` (once) when the
+/// volume is down to the reserve. Blocks; run it on its own thread.
+pub fn monitor(guard: &DiskGuard, interval: Duration, events: &EventBus, active: impl Fn() -> bool, stop: impl FnOnce()) {
+    let mut last_warning: Option<Instant> = None;
+    loop {
+        std::thread::sleep(interval);
+        if !active() {
+            return;
+        }
+        match guard.check() {
+            Ok(DiskStatus::Ok) => last_warning = None,
+            Ok(DiskStatus::Low { available_bytes, remaining }) => {
+                if last_warning.is_none_or(|at| at.elapsed() >= WARNING_INTERVAL) {
+                    last_warning = Some(Instant::now());
+                    events.publish(RecorderEvent::DiskSpaceLow { available_bytes, remaining_secs: remaining.as_secs_f64() });
+                }
+            }
+            Ok(DiskStatus::Full { available_bytes }) => {
+                events.publish(RecorderEvent::StoppedForDiskSpace { available_bytes });
+                stop();
+                return;
+            }
+            Err(e) => eprintln!("Can't check free space on {}: {:#}", guard.dir.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{AudioConfig, DiskConfig};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+    /// A volume with whatever free space the test sets.
+    pub(crate) struct FakeDiskSpace(pub AtomicU64);
+
+    impl DiskSpace for FakeDiskSpace {
+        fn available_bytes(&self, _path: &Path) -> Result<u64> {
+            Ok(self.0.load(Ordering::SeqCst))
+        }
+    }
+
+    /// 8 Mbit/s video, no audio: 1 MB/s, 60 MB a minute.
+    fn config() -> RecordingConfig {
+        RecordingConfig::default()
+            .with_rate_control(RateControl::Abr { bitrate: 8 * 1024 * 1024 })
+            .with_audio(AudioConfig { game: false, ..Default::default() })
+            .with_disk(DiskConfig { min_free_megabytes: 100, expected_minutes: 10, warn_minutes: 2 })
+    }
+
+    #[test]
+    fn test_estimated_byte_rate() {
+        assert_eq!(estimated_byte_rate(&config()), MEGABYTE);
+        let with_audio = config().with_audio(AudioConfig { game: true, mic: true, bitrate: 128_000, ..Default::default() });
+        assert_eq!(estimated_byte_rate(&with_audio), MEGABYTE + 32_000);
+        let crf = RecordingConfig::new("x", 1920, 1080).with_rate_control(RateControl::Crf { crf: 23, max_bitrate: None }).with_audio(AudioConfig { game: false, ..Default::default() });
+        assert_eq!(estimated_byte_rate(&crf), (1920.0 * 1080.0 * 60.0 * 0.1 / 8.0) as u64);
+    }
+
+    #[test]
+    fn test_start_needs_room_for_the_expected_length() {
+        let space = Arc::new(FakeDiskSpace(AtomicU64::new(700 * MEGABYTE)));
+        let guard = DiskGuard::new(&config(), Path::new("/tmp/TFT-1.mp4"), space.clone());
+        // 100 MB reserve + 10 min at 60 MB/min
+        guard.check_start().unwrap();
+        space.0.store(699 * MEGABYTE, Ordering::SeqCst);
+        let error = guard.check_start().unwrap_err().to_string();
+        assert!(error.contains("699 MB free, 700 MB needed"), "{}", error);
+
+        assert_eq!(guard.check().unwrap(), DiskStatus::Ok);
+        space.0.store(160 * MEGABYTE, Ordering::SeqCst);
+        assert_eq!(guard.check().unwrap(), DiskStatus::Low { available_bytes: 160 * MEGABYTE, remaining: Duration::from_secs(60) });
+        space.0.store(100 * MEGABYTE, Ordering::SeqCst);
+        assert_eq!(guard.check().unwrap(), DiskStatus::Full { available_bytes: 100 * MEGABYTE });
+
+        // An estimate too large to add up is simply too much
+        let huge = RecordingConfig { width: u32::MAX, height: u32::MAX, rate_control: RateControl::Crf { crf: 23, max_bitrate: None }, ..config() };
+        let guard = DiskGuard::new(&huge, Path::new("/tmp/TFT-1.mp4"), space);
+        assert!(guard.check_start().unwrap_err().to_string().contains("Not enough free space"));
+    }
+
+    #[test]
+    fn test_monitor_warns_then_stops() {
+        let space = Arc::new(FakeDiskSpace(AtomicU64::new(150 * MEGABYTE)));
+        let guard = DiskGuard::new(&config(), Path::new("/tmp/TFT-1.mp4"), space.clone());
+        let events = EventBus::new();
+        let received = events.subscribe();
+        let stopped = AtomicBool::new(false);
+        let checks = AtomicU64::new(0);
+        monitor(
+            &guard,
+            Duration::from_millis(1),
+            &events,
+            || {
+                // The disk fills up over a few checks
+                let n = checks.fetch_add(1, Ordering::SeqCst);
+                space.0.store((150 - 20 * n.min(5)) * MEGABYTE, Ordering::SeqCst);
+                true
+            },
+            || stopped.store(true, Ordering::SeqCst),
+        );
+        assert!(stopped.load(Ordering::SeqCst));
+        let published: Vec<RecorderEvent> = received.try_iter().collect();
+        // One warning despite several low checks, then the stop
+        assert!(matches!(published[0], RecorderEvent::DiskSpaceLow { available_bytes, .. } if available_bytes == 150 * MEGABYTE));
+        assert_eq!(published[1..], [RecorderEvent::StoppedForDiskSpace { available_bytes: 90 * MEGABYTE }]);
+
+        // Nothing happens once the recording is over
+        let quiet = AtomicBool::new(false);
+        monitor(&guard, Duration::from_millis(1), &events, || false, || quiet.store(true, Ordering::SeqCst));
+        assert!(!quiet.load(Ordering::SeqCst));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_system_disk_space_reads_the_temp_volume() {
+        assert!(SystemDiskSpace.available_bytes(&std::env::temp_dir()).unwrap() > 0);
+    }
+}
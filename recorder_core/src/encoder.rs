@@ -0,0 +1,322 @@
+// ABOUTME: Software H.264 encoder built on OpenH264 for non-VideoToolbox pipelines
+// ABOUTME: Maps RecordingConfig rate control onto encoder settings and emits AVCC packets
+
+use crate::config::{RateControl, RecordingConfig};
+use crate::h264::{self, ParameterSets};
+use anyhow::{Context, Result};
+use openh264::encoder::{
+    BitRate, Encoder, EncoderConfig, FrameRate, FrameType, IntraFramePeriod, QpRange,
+    RateControlMode, UsageType,
+};
+use openh264::formats::{BgraSliceU8, YUVBuffer, YUVSource};
+use openh264::{OpenH264API, Timestamp};
+use std::time::Duration;
+
+// Uncapped CRF pins the QP and needs a bitrate budget the rate controller
+// will never hit; anything past level 4.2 makes OpenH264 refuse to init.
+const CRF_BITRATE_CEILING: u32 = 50_000_000;
+
+/// One uncompressed BGRA frame with its presentation time.
+#[derive(Debug, Clone)]
+pub struct VideoFrame {
+    pub pts: Duration,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl VideoFrame {
+    pub fn new(pts: Duration, width: u32, height: u32, data: Vec<u8>) -> Self {
+        Self { pts, width, height, data }
+    }
+}
+
+/// One compressed access unit, stored AVCC-framed (4-byte NAL lengths).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedPacket {
+    pub pts: Duration,
+    pub keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+pub struct SoftwareEncoder {
+    inner: Encoder,
+    width: u32,
+    height: u32,
+    max_bitrate: Option<u32>,
+    max_bitrate_applied: bool,
+    parameter_sets: Option<ParameterSets>,
+    yuv: YUVBuffer,
+}
+
+impl SoftwareEncoder {
+    pub fn new(config: &RecordingConfig) -> Result<Self> {
+        config.validate()?;
+        anyhow::ensure!(
+            config.width.is_multiple_of(2) && config.height.is_multiple_of(2),
+            "Software encoder requires even dimensions (got {}x{})",
+            config.width,
+            config.height
+        );
+
+        let inner = Encoder::with_api_config(
+            OpenH264API::from_source(),
+            encoder_config(config),
+        )
+        .context("Failed to create OpenH264 encoder")?;
+
+        Ok(Self {
+            inner,
+            width: config.width,
+            height: config.height,
+            max_bitrate: config.rate_control.max_bitrate(),
+            max_bitrate_applied: false,
+            parameter_sets: None,
+            yuv: YUVBuffer::new(config.width as usize, config.height as usize),
+        })
+    }
+
+    /// SPS/PPS of the stream; available once the first frame has been encoded.
+    pub fn parameter_sets(&self) -> Option<&ParameterSets> {
+        self.parameter_sets.as_ref()
+    }
+
+    pub fn force_keyframe(&mut self) {
+        self.inner.force_intra_frame();
+    }
+
+    /// Encodes one frame. Returns `None` when the rate controller skipped it.
+    pub fn encode(&mut self, frame: &VideoFrame) -> Result<Option<EncodedPacket>> {
+        self.encode_at(frame, frame.pts)
+    }
+
+    /// Encodes `frame` as if it had been captured at `pts`; the pacer uses this
+    /// to retime frames and to repeat one without copying its pixels.
+    pub fn encode_at(&mut self, frame: &VideoFrame, pts: Duration) -> Result<Option<EncodedPacket>> {
+        anyhow::ensure!(
+            frame.width == self.width && frame.height == self.height,
+            "Frame is {}x{} but encoder was configured for {}x{}",
+            frame.width,
+            frame.height,
+            self.width,
+            self.height
+        );
+        let needed = (frame.width as usize)
+            .checked_mul(frame.height as usize)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .with_context(|| format!("Frame size {}x{} overflows a BGRA buffer", frame.width, frame.height))?;
+        anyhow::ensure!(
+            frame.data.len() >= needed,
+            "Frame buffer too small for {}x{} BGRA",
+            frame.width,
+            frame.height
+        );
+
+        self.yuv.read_rgb(BgraSliceU8::new(
+            &frame.data,
+            (frame.width as usize, frame.height as usize),
+        ));
+
+        let timestamp = Timestamp::from_millis(pts.as_millis() as u64);
+        let bitstream = self
+            .inner
+            .encode_at(&self.yuv, timestamp)
+            .context("OpenH264 failed to encode frame")?;
+        let (frame_type, annexb) = (bitstream.frame_type(), bitstream.to_vec());
+        self.packetize(frame_type, &annexb, pts)
+    }
+
+    /// Encodes an already-converted YUV picture, e.g. one straight out of the
+    /// decoder when re-encoding part of an existing file.
+    pub fn encode_yuv(&mut self, frame: &impl YUVSource, pts: Duration) -> Result<Option<EncodedPacket>> {
+        let (width, height) = frame.dimensions();
+        anyhow::ensure!(
+            (width as u32, height as u32) == (self.width, self.height),
+            "Frame is {}x{} but encoder was configured for {}x{}",
+            width,
+            height,
+            self.width,
+            self.height
+        );
+
+        let timestamp = Timestamp::from_millis(pts.as_millis() as u64);
+        let bitstream = self
+            .inner
+            .encode_at(frame, timestamp)
+            .context("OpenH264 failed to encode frame")?;
+        let (frame_type, annexb) = (bitstream.frame_type(), bitstream.to_vec());
+        self.packetize(frame_type, &annexb, pts)
+    }
+
+    // Splits the encoder's Annex-B output into parameter sets and an AVCC packet.
+    fn packetize(&mut self, frame_type: FrameType, annexb: &[u8], pts: Duration) -> Result<Option<EncodedPacket>> {
+        if !self.max_bitrate_applied {
+            self.apply_max_bitrate()?;
+        }
+
+        if matches!(frame_type, FrameType::Skip | FrameType::Invalid) || annexb.is_empty() {
+            return Ok(None);
+        }
+
+        let mut sps = None;
+        let mut pps = None;
+        let mut slices = Vec::new();
+        for nal in h264::split_annexb(annexb) {
+            match h264::nal_type(nal) {
+                h264::NAL_SPS => sps = Some(nal.to_vec()),
+                h264::NAL_PPS => pps = Some(nal.to_vec()),
+                h264::NAL_AUD => {}
+                _ => slices.push(nal),
+            }
+        }
+        if let (Some(sps), Some(pps)) = (sps, pps) {
+            self.parameter_sets = Some(ParameterSets { sps, pps });
+        }
+
+        Ok(Some(EncodedPacket {
+            pts,
+            keyframe: frame_type == FrameType::IDR,
+            data: h264::to_avcc(slices),
+        }))
+    }
+
+    // OpenH264 pins the peak bitrate to the target at init time and only
+    // exposes a separate ceiling through SetOption once initialised, which
+    // happens lazily on the first encoded frame.
+    fn apply_max_bitrate(&mut self) -> Result<()> {
+        self.max_bitrate_applied = true;
+        let Some(max) = self.max_bitrate else {
+            return Ok(());
+        };
+
+        let mut info = openh264_sys2::SBitrateInfo {
+            iLayer: openh264_sys2::SPATIAL_LAYER_ALL,
+            iBitrate: i32::try_from(max).unwrap_or(i32::MAX),
+        };
+        // SAFETY: the encoder is initialised after the first encode_at call and
+        // `info` outlives the call; OpenH264 copies the struct.
+        let rc = unsafe {
+            self.inner.raw_api().set_option(
+                openh264_sys2::ENCODER_OPTION_MAX_BITRATE,
+                std::ptr::addr_of_mut!(info).cast(),
+            )
+        };
+        anyhow::ensure!(rc == 0, "OpenH264 rejected max bitrate {} (code {})", max, rc);
+        Ok(())
+    }
+}
+
+/// Translates the recorder's rate-control modes into OpenH264 settings.
+fn encoder_config(config: &RecordingConfig) -> EncoderConfig {
+    let base = EncoderConfig::new()
+        .usage_type(UsageType::ScreenContentRealTime)
+        .adaptive_quantization(false)
+        .background_detection(false)
+        .max_frame_rate(FrameRate::from_hz(config.fps as f32))
+        .intra_frame_period(IntraFramePeriod::from_num_frames(config.keyframe_interval))
+        // OpenH264 can only hold a bitrate by dropping frames; callers see
+        // those as `Ok(None)` from `encode`.
+        .skip_frames(true);
+
+    match config.rate_control {
+        RateControl::Cbr { bitrate } => base
+            .rate_control_mode(RateControlMode::Bitrate)
+            .bitrate(BitRate::from_bps(bitrate)),
+        RateControl::Abr { bitrate } => base
+            .rate_control_mode(RateControlMode::Timestamp)
+            .bitrate(BitRate::from_bps(bitrate)),
+        RateControl::Vbr { bitrate, .. } => base
+            .rate_control_mode(RateControlMode::Quality)
+            .bitrate(BitRate::from_bps(bitrate)),
+        RateControl::Crf { crf, max_bitrate: None } => base
+            .rate_control_mode(RateControlMode::Quality)
+            .bitrate(BitRate::from_bps(CRF_BITRATE_CEILING))
+            .qp(QpRange::new(crf, crf)),
+        RateControl::Crf { crf, max_bitrate: Some(max) } => base
+            .rate_control_mode(RateControlMode::Quality)
+            .bitrate(BitRate::from_bps(max))
+            .qp(QpRange::new(crf, crate::config::MAX_CRF)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient_frame(index: u32, width: u32, height: u32) -> VideoFrame {
+        let mut data = vec![0u8; width as usize * height as usize * 4];
+        for (i, px) in data.chunks_exact_mut(4).enumerate() {
+            let x = i as u32 % width;
+            let y = i as u32 / width;
+            px[0] = ((x * 7 + index * 3) % 256) as u8;
+            px[1] = ((y * 5 + index) % 256) as u8;
+            px[2] = ((x ^ y) % 256) as u8;
+            px[3] = 255;
+        }
+        VideoFrame::new(Duration::from_millis(u64::from(index) * 16), width, height, data)
+    }
+
+    fn encode_all(config: &RecordingConfig, frames: u32) -> Vec<EncodedPacket> {
+        let mut encoder = SoftwareEncoder::new(config).unwrap();
+        (0..frames)
+            .filter_map(|i| encoder.encode(&gradient_frame(i, config.width, config.height)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_first_packet_is_keyframe_with_parameter_sets() {
+        let config = RecordingConfig::new("test", 64, 64);
+        let mut encoder = SoftwareEncoder::new(&config).unwrap();
+        let packet = encoder.encode(&gradient_frame(0, 64, 64)).unwrap().unwrap();
+
+        assert!(packet.keyframe);
+        assert!(!h264::split_avcc(&packet.data).is_empty());
+        assert!(encoder.parameter_sets().is_some());
+    }
+
+    #[test]
+    fn test_keyframe_interval_is_respected() {
+        let config = RecordingConfig::new("test", 64, 64).with_keyframe_interval(10);
+        let packets = encode_all(&config, 30);
+        let keyframes: Vec<usize> = packets
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.keyframe)
+            .map(|(i, _)| i)
+            .collect();
+
+        assert_eq!(keyframes, vec![0, 10, 20]);
+    }
+
+    #[test]
+    fn test_higher_crf_produces_smaller_output() {
+        let size = |crf| {
+            let config = RecordingConfig::new("test", 128, 128)
+                .with_rate_control(RateControl::Crf { crf, max_bitrate: None });
+            encode_all(&config, 10).iter().map(|p| p.data.len()).sum::<usize>()
+        };
+
+        assert!(size(40) < size(10));
+    }
+
+    #[test]
+    fn test_all_rate_control_modes_encode() {
+        let modes = [
+            RateControl::Cbr { bitrate: 500_000 },
+            RateControl::Abr { bitrate: 500_000 },
+            RateControl::Vbr { bitrate: 500_000, max_bitrate: 1_000_000 },
+            RateControl::Crf { crf: 23, max_bitrate: Some(1_000_000) },
+        ];
+        for rc in modes {
+            let config = RecordingConfig::new("test", 64, 64).with_rate_control(rc);
+            assert!(!encode_all(&config, 5).is_empty(), "{:?} produced no packets", rc);
+        }
+    }
+
+    #[test]
+    fn test_mismatched_frame_size_is_rejected() {
+        let config = RecordingConfig::new("test", 64, 64);
+        let mut encoder = SoftwareEncoder::new(&config).unwrap();
+        assert!(encoder.encode(&gradient_frame(0, 32, 32)).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+// ABOUTME: Notifications the recorder emits while a session runs
+// ABOUTME: Fan-out bus handing each event to every subscribed channel, also used for game events
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RecorderEvent {
+    /// Capture started; `path` is the name the recording will have once
+    /// finalized (the playlist name when segmenting).
+    RecordingStarted { path: PathBuf },
+    /// Capture stopped; finalizing the file follows.
+    RecordingStopped { path: PathBuf, duration_secs: f64 },
+    /// The recording is finalized and in place under `path`, its sidecars
+    /// included. For a segmented recording `path` is the playlist, published
+    /// after the `SegmentFinalized` of its last segment; `bytes` is the total.
+    RecordingFinalized { path: PathBuf, duration_secs: f64, bytes: u64 },
+    /// The recording at `path` failed to start or to be finalized.
+    RecordingFailed { path: PathBuf, message: String },
+    /// A segment file is closed and playable.
+    SegmentFinalized {
+        /// 1-based position in the session.
+        index: u32,
+        path: PathBuf,
+        /// Offset of the segment's first frame from the session start.
+        start_secs: f64,
+        duration_secs: f64,
+        bytes: u64,
+    },
+    /// Less than the configured warning time of recording fits on the volume.
+    DiskSpaceLow {
+        available_bytes: u64,
+        /// Estimated recording time left before the free-space reserve.
+        remaining_secs: f64,
+    },
+    /// The volume reached the free-space reserve; the recording was stopped and finalized.
+    StoppedForDiskSpace { available_bytes: u64 },
+}
+
+/// Cloneable handle; every clone publishes to the same subscribers. Carries
+/// `RecorderEvent`s unless another event type is given.
+pub struct EventBus<E = RecorderEvent> {
+    subscribers: Arc<Mutex<Vec<Sender<E>>>>,
+}
+
+impl<E> Clone for EventBus<E> {
+    fn clone(&self) -> Self {
+        Self { subscribers: self.subscribers.clone() }
+    }
+}
+
+impl<E> Default for EventBus<E> {
+    fn default() -> Self {
+        Self { subscribers: Arc::default() }
+    }
+}
+
+impl<E: Clone> EventBus<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self) -> Receiver<E> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Delivers `event` to every live subscriber, forgetting dropped ones.
+    pub fn publish(&self, event: E) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(index: u32) -> RecorderEvent {
+        RecorderEvent::SegmentFinalized {
+            index,
+            path: PathBuf::from("/tmp/part.mp4"),
+            start_secs: 0.0,
+            duration_secs: 1.0,
+            bytes: 10,
+        }
+    }
+
+    #[test]
+    fn test_publish_reaches_live_subscribers_only() {
+        let bus = EventBus::new();
+        let first = bus.subscribe();
+        let dropped = bus.subscribe();
+        drop(dropped);
+
+        bus.clone().publish(event(1));
+        assert_eq!(first.try_recv().unwrap(), event(1));
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_event_wire_name() {
+        let json = serde_json::to_string(&event(2)).unwrap();
+        assert!(json.starts_with(r#"{"event":"segment_finalized","index":2"#));
+        let failed = RecorderEvent::RecordingFailed { path: PathBuf::from("/tmp/a.mp4"), message: "boom".into() };
+        assert_eq!(serde_json::to_string(&failed).unwrap(), r#"{"event":"recording_failed","path":"/tmp/a.mp4","message":"boom"}"#);
+    }
+}
@@ -1,7 +1,17 @@
 // ABOUTME: FFI bridge between Rust and Swift using manual C bindings
 // ABOUTME: Provides low-level interface for cross-language communication
 
-use std::ffi::{c_char, c_void, CString};
+use crate::config::{AudioConfig, FrameRateMode, RateControl, SegmentConfig};
+use crate::pacer::{FramePacer, PacerStats};
+use crate::replay::ReplayBuffer;
+use crate::segment::SegmentSession;
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(any(target_os = "macos", test))]
+use std::ffi::c_char;
+#[cfg(target_os = "macos")]
+use std::ffi::CString;
 
 #[repr(transparent)]
 pub struct SwiftCapture {
@@ -24,7 +34,98 @@ extern "C" {
         bitrate: u32,
         output_path: *const c_char,
     ) -> bool;
+    fn swift_capture_set_encoding(
+        ptr: *mut c_void,
+        rate_mode: u32,
+        bitrate: u32,
+        max_bitrate: u32,
+        quality: f32,
+        keyframe_interval: u32,
+    );
+    fn swift_capture_set_frame_rate(ptr: *mut c_void, fps: u32, constant: bool);
+    fn swift_capture_set_audio(
+        ptr: *mut c_void,
+        game: bool,
+        mic: bool,
+        mic_device: *const c_char,
+        codec: u32,
+        bitrate: u32,
+        sample_rate: u32,
+        channels: u32,
+    );
+    fn swift_capture_set_replay_sink(ptr: *mut c_void, ctx: *const c_void, callback: ReplayPacketCallback);
+    fn swift_capture_set_segmenting(
+        ptr: *mut c_void,
+        max_seconds: u32,
+        max_bytes: u64,
+        path_pattern: *const c_char,
+        ctx: *const c_void,
+        callback: SegmentCallback,
+    );
     fn swift_capture_stop(ptr: *mut c_void);
+    fn swift_capture_get_stats(ptr: *mut c_void, out: *mut PacerStats);
+}
+
+/// Receives one AVCC-framed H.264 access unit from the Swift replay tap.
+/// `sps`/`pps` are non-null on keyframes.
+#[cfg(target_os = "macos")]
+type ReplayPacketCallback = extern "C" fn(
+    ctx: *const c_void,
+    data: *const u8,
+    len: usize,
+    pts_us: i64,
+    keyframe: bool,
+    sps: *const u8,
+    sps_len: usize,
+    pps: *const u8,
+    pps_len: usize,
+);
+
+/// Reports a closed segment file; `path` is a NUL-terminated UTF-8 string.
+#[cfg(target_os = "macos")]
+type SegmentCallback = extern "C" fn(
+    ctx: *const c_void,
+    path: *const c_char,
+    index: u32,
+    start_us: i64,
+    duration_us: i64,
+    bytes: u64,
+);
+
+#[cfg(target_os = "macos")]
+extern "C" fn on_replay_packet(
+    ctx: *const c_void,
+    data: *const u8,
+    len: usize,
+    pts_us: i64,
+    keyframe: bool,
+    sps: *const u8,
+    sps_len: usize,
+    pps: *const u8,
+    pps_len: usize,
+) {
+    use crate::encoder::EncodedPacket;
+    use crate::h264::ParameterSets;
+
+    if ctx.is_null() || data.is_null() {
+        return;
+    }
+    // SAFETY: `ctx` is the Mutex inside the Arc handed to `set_replay_sink`,
+    // which the Recorder keeps alive until after the capture is destroyed.
+    // The byte pointers are valid for the duration of the callback.
+    let (buffer, data) = unsafe { (&*(ctx as *const Mutex<ReplayBuffer>), std::slice::from_raw_parts(data, len)) };
+    let Ok(mut buffer) = buffer.lock() else {
+        return;
+    };
+    if !sps.is_null() && !pps.is_null() {
+        let (sps, pps) = unsafe { (std::slice::from_raw_parts(sps, sps_len), std::slice::from_raw_parts(pps, pps_len)) };
+        buffer.set_parameter_sets(ParameterSets { sps: sps.to_vec(), pps: pps.to_vec() });
+    }
+    buffer.push_video(EncodedPacket {
+        pts: std::time::Duration::from_micros(pts_us.max(0) as u64),
+        keyframe,
+        data: data.to_vec(),
+    });
 }
 
 #[cfg(target_os = "macos")]
@@ -58,11 +159,124 @@ pub fn start_capture(
     }
 }
 
+/// Must be called before `start_capture`; a value of 0 for `max_bitrate`
+/// means "no peak limit".
+#[cfg(target_os = "macos")]
+pub fn set_encoding(cap: &mut SwiftCapture, rate_control: &RateControl, keyframe_interval: u32) {
+    unsafe {
+        swift_capture_set_encoding(
+            cap.ptr,
+            rate_control.ffi_mode(),
+            rate_control.bitrate().unwrap_or(0),
+            rate_control.max_bitrate().unwrap_or(0),
+            rate_control.quality().unwrap_or(0.0),
+            keyframe_interval,
+        )
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn set_frame_rate(cap: &mut SwiftCapture, fps: u32, mode: FrameRateMode) {
+    unsafe { swift_capture_set_frame_rate(cap.ptr, fps, mode == FrameRateMode::Constant) }
+}
+
+/// Must be called before `start_capture`; with both tracks disabled the
+/// recording is video-only.
+#[cfg(target_os = "macos")]
+pub fn set_audio(cap: &mut SwiftCapture, audio: &AudioConfig) {
+    // Only read with the mic on; `AudioConfig::validate` rejects a NUL in it then
+    let device = audio
+        .mic_device
+        .as_deref()
+        .filter(|_| audio.mic)
+        .and_then(|d| CString::new(d).ok());
+    unsafe {
+        swift_capture_set_audio(
+            cap.ptr,
+            audio.game,
+            audio.mic,
+            device.as_ref().map_or(std::ptr::null(), |d| d.as_ptr()),
+            audio.codec.ffi_codec(),
+            audio.bitrate,
+            audio.sample_rate,
+            u32::from(audio.channels),
+        )
+    }
+}
+
+/// Feeds every encoded frame into `buffer` as well as the output file. The
+/// caller must keep `buffer` alive until the capture session is destroyed.
+#[cfg(target_os = "macos")]
+pub fn set_replay_sink(cap: &mut SwiftCapture, buffer: &Arc<Mutex<ReplayBuffer>>) {
+    let ctx = Arc::as_ptr(buffer) as *const c_void;
+    unsafe { swift_capture_set_replay_sink(cap.ptr, ctx, on_replay_packet) }
+}
+
+#[cfg(any(target_os = "macos", test))]
+extern "C" fn on_segment_finalized(
+    ctx: *const c_void,
+    path: *const c_char,
+    index: u32,
+    start_us: i64,
+    duration_us: i64,
+    bytes: u64,
+) {
+    use crate::segment::Segment;
+
+    if ctx.is_null() || path.is_null() {
+        return;
+    }
+    // SAFETY: `ctx` is the Mutex inside the Arc handed to `set_segmenting`,
+    // kept alive by the Recorder until after the capture is destroyed.
+    let (session, path) = unsafe { (&*(ctx as *const Mutex<SegmentSession>), std::ffi::CStr::from_ptr(path)) };
+    let Ok(mut session) = session.lock() else {
+        return;
+    };
+    let segment = Segment {
+        index,
+        path: path.to_string_lossy().into_owned().into(),
+        start: Duration::from_micros(start_us.max(0) as u64),
+        duration: Duration::from_micros(duration_us.max(0) as u64),
+        bytes,
+    };
+    if let Err(e) = session.finalize(segment) {
+        eprintln!("Failed to record segment {}: {:#}", index, e);
+    }
+}
+
+/// Rolls the output over to `session`'s next file name whenever a limit in
+/// `config` is hit. The caller must keep `session` alive until the capture
+/// session is destroyed.
+#[cfg(target_os = "macos")]
+pub fn set_segmenting(cap: &mut SwiftCapture, config: &SegmentConfig, session: &Arc<Mutex<SegmentSession>>) {
+    let pattern = crate::partial::partial_path(&session.lock().unwrap().namer().pattern());
+    let pattern = CString::new(pattern.to_string_lossy().as_bytes()).expect("Invalid segment path");
+    let ctx = Arc::as_ptr(session) as *const c_void;
+    unsafe {
+        swift_capture_set_segmenting(
+            cap.ptr,
+            config.max_seconds,
+            u64::from(config.max_megabytes) * 1024 * 1024,
+            pattern.as_ptr(),
+            ctx,
+            on_segment_finalized,
+        )
+    }
+}
+
 #[cfg(target_os = "macos")]
 pub fn stop_capture(cap: &mut SwiftCapture) {
     unsafe { swift_capture_stop(cap.ptr) }
 }
 
+/// Frame counts of the session's recording; final once `stop_capture` returned.
+#[cfg(target_os = "macos")]
+pub fn frame_stats(cap: &SwiftCapture) -> PacerStats {
+    let mut stats = PacerStats::default();
+    unsafe { swift_capture_get_stats(cap.ptr, &mut stats) };
+    stats
+}
+
 #[cfg(target_os = "macos")]
 impl Drop for SwiftCapture {
     fn drop(&mut self) {
@@ -72,6 +286,84 @@ impl Drop for SwiftCapture {
     }
 }
 
+/// One pacing decision for AppleCapture; see `PaceDecision`. `slot` is the
+/// output grid slot of `pts_ns`, the duplicates fill the slots just before it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FfiPaceDecision {
+    pub duplicates: u32,
+    pub slot: u64,
+    pub pts_ns: u64,
+}
+
+/// Creates a pacer for the VideoToolbox path, so it paces exactly like the
+/// software encoder. Free it with `recorder_pacer_destroy`.
+#[no_mangle]
+pub extern "C" fn recorder_pacer_create(fps: u32, constant: bool) -> *mut FramePacer {
+    let mode = if constant { FrameRateMode::Constant } else { FrameRateMode::Variable };
+    Box::into_raw(Box::new(FramePacer::new(fps.max(1), mode)))
+}
+
+/// Feeds a capture timestamp; returns false if the frame should be dropped.
+///
+/// # Safety
+/// `pacer` must come from `recorder_pacer_create` and `out` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn recorder_pacer_push(pacer: *mut FramePacer, capture_ns: u64, out: *mut FfiPaceDecision) -> bool {
+    let (Some(pacer), Some(out)) = (pacer.as_mut(), out.as_mut()) else {
+        return false;
+    };
+    let decision = pacer.push(Duration::from_nanos(capture_ns));
+    let Some(pts) = decision.pts else {
+        return false;
+    };
+    *out = FfiPaceDecision { duplicates: decision.duplicates, slot: pacer.slot_for(pts), pts_ns: pts.as_nanos() as u64 };
+    true
+}
+
+/// Output time of grid slot `slot`, in nanoseconds.
+///
+/// # Safety
+/// `pacer` must come from `recorder_pacer_create`.
+#[no_mangle]
+pub unsafe extern "C" fn recorder_pacer_slot_ns(pacer: *const FramePacer, slot: u64) -> u64 {
+    pacer.as_ref().map_or(0, |p| p.slot_pts(slot).as_nanos() as u64)
+}
+
+/// Writes the capture timestamp of the first frame to `out`; false before
+/// any frame was pushed.
+///
+/// # Safety
+/// `pacer` must come from `recorder_pacer_create` and `out` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn recorder_pacer_origin_ns(pacer: *const FramePacer, out: *mut u64) -> bool {
+    match (pacer.as_ref().and_then(FramePacer::origin), out.as_mut()) {
+        (Some(origin), Some(out)) => {
+            *out = origin.as_nanos() as u64;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// # Safety
+/// `pacer` must come from `recorder_pacer_create` and `out` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn recorder_pacer_stats(pacer: *const FramePacer, out: *mut PacerStats) {
+    if let (Some(pacer), Some(out)) = (pacer.as_ref(), out.as_mut()) {
+        *out = pacer.stats();
+    }
+}
+
+/// # Safety
+/// `pacer` must come from `recorder_pacer_create` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn recorder_pacer_destroy(pacer: *mut FramePacer) {
+    if !pacer.is_null() {
+        drop(Box::from_raw(pacer));
+    }
+}
+
 // Non-macOS stubs
 #[cfg(not(target_os = "macos"))]
 pub fn create_capture_session() -> SwiftCapture {
@@ -90,10 +382,91 @@ pub fn start_capture(
     false
 }
 
+#[cfg(not(target_os = "macos"))]
+pub fn set_encoding(_cap: &mut SwiftCapture, _rate_control: &RateControl, _keyframe_interval: u32) {}
+
+#[cfg(not(target_os = "macos"))]
+pub fn set_frame_rate(_cap: &mut SwiftCapture, _fps: u32, _mode: FrameRateMode) {}
+
+#[cfg(not(target_os = "macos"))]
+pub fn set_audio(_cap: &mut SwiftCapture, _audio: &AudioConfig) {}
+
+#[cfg(not(target_os = "macos"))]
+pub fn set_replay_sink(_cap: &mut SwiftCapture, _buffer: &Arc<Mutex<ReplayBuffer>>) {}
+
+#[cfg(not(target_os = "macos"))]
+pub fn set_segmenting(_cap: &mut SwiftCapture, _config: &SegmentConfig, _session: &Arc<Mutex<SegmentSession>>) {}
+
 #[cfg(not(target_os = "macos"))]
 pub fn stop_capture(_cap: &mut SwiftCapture) {}
 
+#[cfg(not(target_os = "macos"))]
+pub fn frame_stats(_cap: &SwiftCapture) -> PacerStats {
+    PacerStats::default()
+}
+
 #[cfg(not(target_os = "macos"))]
 impl Drop for SwiftCapture {
     fn drop(&mut self) {}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventBus, RecorderEvent};
+    use crate::segment::SegmentNamer;
+    use crate::test_support::{temp_dir, write_fake_video};
+
+    #[test]
+    fn test_segment_callback_finalizes_into_the_session() {
+        let dir = temp_dir("ffi_segment");
+        let config = SegmentConfig { max_seconds: 2, name_template: "{n}.mp4".into(), ..Default::default() };
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+        let namer = SegmentNamer::new(&config, &dir.join("game.mp4"), chrono::Local::now());
+        let session = Arc::new(Mutex::new(SegmentSession::new(namer, bus)));
+        let partial = crate::partial::partial_path(&dir.join("1.mp4"));
+        write_fake_video(&partial, 20);
+        let path = std::ffi::CString::new(partial.to_str().unwrap()).unwrap();
+
+        let ctx = Arc::as_ptr(&session) as *const c_void;
+        on_segment_finalized(ctx, path.as_ptr(), 1, 0, 2_000_000, 1);
+        // A null context or path is ignored rather than dereferenced
+        on_segment_finalized(std::ptr::null(), path.as_ptr(), 2, 0, 0, 0);
+        on_segment_finalized(ctx, std::ptr::null(), 2, 0, 0, 0);
+
+        let session = session.lock().unwrap();
+        let [segment] = session.segments() else { panic!("Expected one segment") };
+        assert_eq!(segment.path, dir.join("1.mp4"));
+        assert_eq!(segment.duration, Duration::from_secs(2));
+        assert_eq!(segment.bytes, std::fs::metadata(&segment.path).unwrap().len());
+        assert!(!partial.exists());
+        assert!(matches!(events.try_recv(), Ok(RecorderEvent::SegmentFinalized { index: 1, .. })));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_pacer_exports_match_the_pacer() {
+        let pacer = recorder_pacer_create(10, true);
+        let mut decision = FfiPaceDecision::default();
+        let mut origin = 0;
+        unsafe {
+            assert!(!recorder_pacer_origin_ns(pacer, &mut origin));
+            assert!(recorder_pacer_push(pacer, 5_000_000_000, &mut decision));
+            assert_eq!(decision, FfiPaceDecision { duplicates: 0, slot: 0, pts_ns: 0 });
+            // Half a second later: four slots were missed and are repeats
+            assert!(recorder_pacer_push(pacer, 5_500_000_000, &mut decision));
+            assert_eq!(decision, FfiPaceDecision { duplicates: 4, slot: 5, pts_ns: 500_000_000 });
+            assert!(!recorder_pacer_push(pacer, 5_510_000_000, &mut decision));
+            assert!(!recorder_pacer_push(std::ptr::null_mut(), 0, &mut decision));
+
+            assert!(recorder_pacer_origin_ns(pacer, &mut origin));
+            assert_eq!(origin, 5_000_000_000);
+            assert_eq!(recorder_pacer_slot_ns(pacer, 3), 300_000_000);
+            let mut stats = PacerStats::default();
+            recorder_pacer_stats(pacer, &mut stats);
+            assert_eq!(stats, PacerStats { frames_in: 3, frames_out: 6, dropped: 1, duplicated: 4 });
+            recorder_pacer_destroy(pacer);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// ABOUTME: Minimal H.264 bitstream helpers for moving between Annex-B and AVCC framing
+// ABOUTME: Extracts SPS/PPS parameter sets needed by MP4 sample descriptions
+
+pub const NAL_SLICE: u8 = 1;
+pub const NAL_IDR: u8 = 5;
+pub const NAL_SEI: u8 = 6;
+pub const NAL_SPS: u8 = 7;
+pub const NAL_PPS: u8 = 8;
+pub const NAL_AUD: u8 = 9;
+
+/// Sequence and picture parameter sets of one H.264 stream (without start codes).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParameterSets {
+    pub sps: Vec<u8>,
+    pub pps: Vec<u8>,
+}
+
+pub fn nal_type(nal: &[u8]) -> u8 {
+    nal.first().map(|b| b & 0x1f).unwrap_or(0)
+}
+
+/// Splits an Annex-B byte stream into NAL units, stripping 3- and 4-byte start codes.
+pub fn split_annexb(data: &[u8]) -> Vec<&[u8]> {
+    let mut nals = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+
+    while i + 2 < data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(s) = start {
+                nals.push(trim_trailing_zeros(&data[s..i]));
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    if let Some(s) = start {
+        if s < data.len() {
+            nals.push(&data[s..]);
+        }
+    }
+
+    nals.into_iter().filter(|n| !n.is_empty()).collect()
+}
+
+fn trim_trailing_zeros(nal: &[u8]) -> &[u8] {
+    let end = nal.iter().rposition(|&b| b != 0).map(|p| p + 1).unwrap_or(0);
+    &nal[..end]
+}
+
+/// Splits an AVCC sample (4-byte big-endian length prefixes) into NAL units.
+pub fn split_avcc(data: &[u8]) -> Vec<&[u8]> {
+    let mut nals = Vec::new();
+    let mut pos = 0;
+
+    while pos + 4 <= data.len() {
+        let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        pos += 4;
+        if pos + len > data.len() {
+            break;
+        }
+        nals.push(&data[pos..pos + len]);
+        pos += len;
+    }
+
+    nals
+}
+
+/// Length-prefixes NAL units for storage in an MP4 sample.
+pub fn to_avcc<'a>(nals: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for nal in nals {
+        out.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        out.extend_from_slice(nal);
+    }
+    out
+}
+
+/// Converts an AVCC sample back to Annex-B, optionally prefixing parameter sets
+/// so a decoder can start from this sample.
+pub fn avcc_to_annexb(data: &[u8], params: Option<&ParameterSets>) -> Vec<u8> {
+    const START: [u8; 4] = [0, 0, 0, 1];
+    let mut out = Vec::with_capacity(data.len() + 64);
+
+    if let Some(ps) = params {
+        out.extend_from_slice(&START);
+        out.extend_from_slice(&ps.sps);
+        out.extend_from_slice(&START);
+        out.extend_from_slice(&ps.pps);
+    }
+    for nal in split_avcc(data) {
+        out.extend_from_slice(&START);
+        out.extend_from_slice(nal);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_annexb_handles_both_start_code_lengths() {
+        let stream = [0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 1, 0x65, 4, 5];
+        let nals = split_annexb(&stream);
+        assert_eq!(nals, vec![&[0x67, 1, 2][..], &[0x68, 3][..], &[0x65, 4, 5][..]]);
+        assert_eq!(nal_type(nals[0]), NAL_SPS);
+        assert_eq!(nal_type(nals[1]), NAL_PPS);
+        assert_eq!(nal_type(nals[2]), NAL_IDR);
+    }
+
+    #[test]
+    fn test_avcc_round_trip() {
+        let nals: Vec<&[u8]> = vec![&[0x65, 1, 2, 3], &[0x06, 9]];
+        let avcc = to_avcc(nals.iter().copied());
+        assert_eq!(split_avcc(&avcc), nals);
+
+        let annexb = avcc_to_annexb(&avcc, None);
+        assert_eq!(split_annexb(&annexb), nals);
+    }
+}
@@ -0,0 +1,348 @@
+// ABOUTME: Builds a highlight reel from the markers of one or more recordings
+// ABOUTME: Copies a window around each marker without re-encoding and puts a title card in front of each
+
+use crate::audio::frames_to_duration;
+use crate::audio_encoder;
+use crate::concat::playable_samples;
+use crate::config::{AudioConfig, RecordingConfig};
+use crate::encoder::SoftwareEncoder;
+use crate::markers::{Marker, MarkerList};
+use crate::mp4::reader::{ticks_to_duration, Track};
+use crate::mp4::{write_chapters, AudioTrackInfo, Mp4Reader, Mp4Writer, Sample, TrackInfo, VideoTrackInfo};
+use crate::title_card;
+use anyhow::{Context, Result};
+use openh264::formats::{RgbSliceU8, YUVBuffer};
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long each clip's title card is shown.
+pub const TITLE_CARD_LENGTH: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighlightOptions {
+    /// Time kept before each marker.
+    pub pre: Duration,
+    /// Time kept after each marker.
+    pub post: Duration,
+    /// Only markers whose label contains one of these (ignoring case); all
+    /// markers when empty.
+    pub labels: Vec<String>,
+    pub title_card: Duration,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        Self { pre: Duration::from_secs(5), post: Duration::from_secs(10), labels: Vec::new(), title_card: TITLE_CARD_LENGTH }
+    }
+}
+
+impl HighlightOptions {
+    fn matches(&self, label: &str) -> bool {
+        let label = label.to_lowercase();
+        self.labels.is_empty() || self.labels.iter().any(|l| label.contains(&l.to_lowercase()))
+    }
+}
+
+/// A window of a recording around one or more markers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Highlight {
+    pub recording: PathBuf,
+    pub start: Duration,
+    pub end: Duration,
+    /// The markers the window was cut around, in order.
+    pub markers: Vec<Marker>,
+}
+
+/// What `highlights` wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighlightResult {
+    pub clips: usize,
+    pub duration: Duration,
+    pub video_frames: usize,
+    pub bytes: u64,
+}
+
+/// The windows around the matching markers of each recording, in order.
+/// Windows that overlap are merged into one.
+pub fn find_highlights(recordings: &[impl AsRef<Path>], options: &HighlightOptions) -> Result<Vec<Highlight>> {
+    let mut found: Vec<Highlight> = Vec::new();
+    for recording in recordings {
+        let recording = recording.as_ref();
+        let reader = Mp4Reader::open(recording)?;
+        let markers = MarkerList::for_recording(recording, &reader.chapters)?;
+        let first = found.len();
+        for marker in markers.markers().iter().filter(|m| options.matches(&m.label)) {
+            let start = marker.time.saturating_sub(options.pre);
+            let end = (marker.time + options.post).min(reader.duration);
+            if start >= end {
+                continue;
+            }
+            match found[first..].last_mut() {
+                Some(last) if start <= last.end => {
+                    last.end = last.end.max(end);
+                    last.markers.push(marker.clone());
+                }
+                _ => found.push(Highlight { recording: recording.to_path_buf(), start, end, markers: vec![marker.clone()] }),
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Writes a reel of every highlight in `recordings` to `output`: for each
+/// one a title card naming its markers, then the window itself copied from
+/// the keyframe before it. The reel gets a chapter per clip.
+pub fn highlights(recordings: &[impl AsRef<Path>], output: impl AsRef<Path>, options: &HighlightOptions) -> Result<HighlightResult> {
+    let output = output.as_ref();
+    anyhow::ensure!(
+        recordings.iter().all(|r| r.as_ref() != output),
+        "Highlight reel output must differ from its recordings"
+    );
+    let clips = find_highlights(recordings, options)?;
+    anyhow::ensure!(!clips.is_empty(), "No matching markers in {} recording(s)", recordings.len());
+
+    let mut writer = Mp4Writer::create(output)?;
+    let mut tracks: Option<(usize, Vec<usize>)> = None;
+    let mut reader: Option<Mp4Reader> = None;
+    let mut offset = Duration::ZERO;
+    let (mut video_frames, mut bytes) = (0, 0u64);
+    let mut markers = Vec::new();
+    for (index, clip) in clips.iter().enumerate() {
+        if index == 0 || clips[index - 1].recording != clip.recording {
+            reader = Some(Mp4Reader::open(&clip.recording)?);
+        }
+        let reader = reader.as_mut().expect("opened for the first clip");
+        let video = reader.video_track().context("Recording has no video track")?.clone();
+        anyhow::ensure!(!video.reordered, "{} uses B-frames, which can't be cut without re-encoding", clip.recording.display());
+        let audio: Vec<Track> = reader.tracks.iter().filter(|t| !t.is_video()).cloned().collect();
+
+        // Frames from the keyframe before the window up to its end
+        let first = video.samples.partition_point(|s| video.sample_time(s) <= clip.start).saturating_sub(1);
+        let keyframe = video.samples[..=first].iter().rposition(|s| s.keyframe).context("No keyframe before the highlight")?;
+        let end_index = video.samples.partition_point(|s| video.sample_time(s) < clip.end).max(keyframe + 1);
+        let origin = video.sample_time(&video.samples[keyframe]);
+        let last = &video.samples[end_index - 1];
+        let end = video.sample_time(last) + ticks_to_duration(u64::from(last.duration), video.timescale);
+
+        let TrackInfo::Video(source) = &video.sample_descriptions[video.samples[keyframe].description as usize] else {
+            anyhow::bail!("Video track has an audio sample description");
+        };
+        let name = clip.recording.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let labels: Vec<&str> = clip.markers.iter().map(|m| m.label.as_str()).collect();
+        let lines = [
+            labels.join(" / "),
+            format!("{} at {}", name, clock(clip.markers[0].time)),
+            format!("{} / {}", index + 1, clips.len()),
+        ];
+        let (card_info, card) = encode_card(source.width, source.height, &lines.each_ref().map(String::as_str))?;
+
+        let (video_id, audio_ids) = tracks.get_or_insert_with(|| {
+            let video_id = writer.add_track(card_info.clone());
+            let audio_ids = audio
+                .iter()
+                .filter_map(|t| match &t.info {
+                    TrackInfo::Audio(a) => Some(writer.add_track(TrackInfo::Audio(AudioTrackInfo { priming_samples: 0, ..a.clone() }))),
+                    TrackInfo::Video(_) => None,
+                })
+                .collect();
+            (video_id, audio_ids)
+        });
+        anyhow::ensure!(
+            audio_ids.len() == audio.len(),
+            "{} has {} audio tracks but the reel has {}",
+            clip.recording.display(),
+            audio.len(),
+            audio_ids.len()
+        );
+
+        // Title card, with silence under it so the audio stays in sync
+        writer.switch_sample_entry(*video_id, card_info)?;
+        writer.write_sample(*video_id, Sample { pts: offset, duration: Some(options.title_card), keyframe: true, data: &card })?;
+        bytes += card.len() as u64;
+        for (track, &id) in audio.iter().zip(audio_ids.iter()) {
+            let TrackInfo::Audio(info) = &track.info else { continue };
+            bytes += write_silence(&mut writer, id, info, offset, options.title_card)
+                .with_context(|| format!("Can't add {} to the reel", clip.recording.display()))?;
+        }
+        markers.push(Marker { time: offset, label: lines[0].clone() });
+        let clip_start = offset + options.title_card;
+
+        let mut description = None;
+        for sample in &video.samples[keyframe..end_index] {
+            if description != Some(sample.description) {
+                writer.switch_sample_entry(*video_id, video.sample_descriptions[sample.description as usize].clone())?;
+                description = Some(sample.description);
+            }
+            let data = reader.read_sample(sample)?;
+            writer.write_sample(
+                *video_id,
+                Sample {
+                    pts: clip_start + (video.sample_time(sample) - origin),
+                    duration: Some(ticks_to_duration(u64::from(sample.duration), video.timescale)),
+                    keyframe: sample.keyframe,
+                    data: &data,
+                },
+            )?;
+            bytes += data.len() as u64;
+        }
+        video_frames += 1 + (end_index - keyframe);
+
+        for (track, &id) in audio.iter().zip(audio_ids.iter()) {
+            let TrackInfo::Audio(info) = &track.info else { continue };
+            writer
+                .switch_sample_entry(id, TrackInfo::Audio(AudioTrackInfo { priming_samples: 0, ..info.clone() }))
+                .with_context(|| format!("Audio of {} doesn't match the reel", clip.recording.display()))?;
+            for sample in playable_samples(track) {
+                let time = track.sample_time(sample);
+                if time < origin || time >= end {
+                    continue;
+                }
+                let data = reader.read_sample(sample)?;
+                writer.write_sample(
+                    id,
+                    Sample {
+                        pts: clip_start + (time - origin),
+                        duration: Some(ticks_to_duration(u64::from(sample.duration), track.timescale)),
+                        keyframe: true,
+                        data: &data,
+                    },
+                )?;
+                bytes += data.len() as u64;
+            }
+        }
+        offset = clip_start + (end - origin);
+    }
+    writer.finish()?;
+
+    let list = MarkerList::with_markers(MarkerList::sidecar_path(output), markers);
+    list.save()?;
+    write_chapters(output, &list.chapters(Duration::ZERO, offset))?;
+    Ok(HighlightResult { clips: clips.len(), duration: offset, video_frames, bytes })
+}
+
+/// A title card as a single H.264 keyframe, with the sample description it needs.
+fn encode_card(width: u32, height: u32, lines: &[&str]) -> Result<(TrackInfo, Vec<u8>)> {
+    let card = title_card::render(width, height, lines);
+    let mut encoder = SoftwareEncoder::new(&RecordingConfig::new("card", width, height))?;
+    let yuv = YUVBuffer::from_rgb8_source(RgbSliceU8::new(&card.data, (width as usize, height as usize)));
+    let packet = encoder.encode_yuv(&yuv, Duration::ZERO)?.context("Encoder skipped the title card")?;
+    anyhow::ensure!(packet.keyframe, "Title card wasn't encoded as a keyframe");
+    let parameter_sets = encoder.parameter_sets().context("Encoder produced no parameter sets")?.clone();
+    Ok((TrackInfo::Video(VideoTrackInfo { width, height, parameter_sets }), packet.data))
+}
+
+/// Writes `length` of silence at `pts` to audio track `id`, encoded with the
+/// codec, rate and channels of `info`. Returns the bytes written.
+fn write_silence<W: Write + Seek>(writer: &mut Mp4Writer<W>, id: usize, info: &AudioTrackInfo, pts: Duration, length: Duration) -> Result<u64> {
+    let config = AudioConfig { codec: info.codec, sample_rate: info.sample_rate, channels: info.channels, ..Default::default() };
+    let mut encoder = audio_encoder::create_encoder(&config)?;
+    let frames = (length.as_secs_f64() * f64::from(info.sample_rate)).ceil() as usize;
+    let mut packets = encoder.encode(&vec![0; frames * usize::from(info.channels)])?;
+    packets.extend(encoder.flush()?);
+
+    let description = AudioTrackInfo { decoder_config: encoder.decoder_config(), priming_samples: 0, ..info.clone() };
+    writer.switch_sample_entry(id, TrackInfo::Audio(description))?;
+    let mut bytes = 0;
+    for packet in packets.iter().filter(|p| p.pts < length) {
+        writer.write_sample(
+            id,
+            Sample {
+                pts: pts + packet.pts,
+                duration: Some(frames_to_duration(u64::from(packet.duration), info.sample_rate)),
+                keyframe: true,
+                data: &packet.data,
+            },
+        )?;
+        bytes += packet.data.len() as u64;
+    }
+    Ok(bytes)
+}
+
+/// `MM:SS`, or `H:MM:SS` from an hour on.
+fn clock(time: Duration) -> String {
+    let secs = time.as_secs();
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{add_fake_audio, cleanup, config, grey_frame, temp_file, write_frames};
+    use crate::thumbnail;
+
+    /// 3 s at 10 fps with keyframes every second, an AAC track of fake
+    /// 50 ms packets and markers at 1.0 s, 1.5 s and 2.5 s.
+    fn write_recording(path: &Path) {
+        let mut writer = write_frames(path, &config(), (0..30).map(|i| grey_frame(i, 120 + i as u8)));
+        add_fake_audio(&mut writer, 60);
+        writer.finish().unwrap();
+
+        let mut markers = MarkerList::new(MarkerList::sidecar_path(path));
+        for (ms, label) in [(1000, "Stage 2-1"), (1500, "Rolldown"), (2500, "Augment pick")] {
+            markers.add(Marker { time: Duration::from_millis(ms), label: label.into() }).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_windows_merge_and_filter_by_label() {
+        let input = temp_file("highlights_windows");
+        write_recording(&input);
+        let options = HighlightOptions { pre: Duration::from_millis(300), post: Duration::from_millis(300), ..Default::default() };
+
+        let found = find_highlights(&[&input], &options).unwrap();
+        let windows: Vec<(u128, u128, usize)> = found.iter().map(|h| (h.start.as_millis(), h.end.as_millis(), h.markers.len())).collect();
+        assert_eq!(windows, [(700, 1800, 2), (2200, 2800, 1)]);
+
+        let augments = HighlightOptions { labels: vec!["AUGMENT".into()], ..options.clone() };
+        let found = find_highlights(&[&input], &augments).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].markers[0].label, "Augment pick");
+
+        let none = HighlightOptions { labels: vec!["carousel".into()], ..options };
+        assert!(highlights(&[&input], temp_file("highlights_windows_out"), &none).is_err());
+        cleanup(&[&input, &temp_file("highlights_windows_out")]);
+    }
+
+    #[test]
+    fn test_reel_has_a_title_card_and_chapter_per_clip() {
+        let (input, output) = (temp_file("highlights_reel_in"), temp_file("highlights_reel_out"));
+        write_recording(&input);
+        let options = HighlightOptions {
+            pre: Duration::from_millis(300),
+            post: Duration::from_millis(300),
+            labels: Vec::new(),
+            title_card: Duration::from_secs(1),
+        };
+
+        let result = highlights(&[&input], &output, &options).unwrap();
+        // Clips start on the keyframes at 0 s and 2 s: 1 + 1.8 + 1 + 0.8 s
+        assert_eq!((result.clips, result.video_frames), (2, 1 + 18 + 1 + 8));
+        assert_eq!(result.duration, Duration::from_millis(4600));
+
+        let reader = Mp4Reader::open(&output).unwrap();
+        let video = reader.video_track().unwrap();
+        assert_eq!(video.samples.len(), 28);
+        let times: Vec<Duration> = video.samples.iter().filter(|s| s.keyframe).map(|s| video.sample_time(s)).collect();
+        assert_eq!(times, [0, 1000, 2000, 2800, 3800].map(Duration::from_millis));
+        let audio = reader.tracks.iter().find(|t| !t.is_video()).unwrap();
+        let audio_times: Vec<Duration> = audio.samples.iter().map(|s| audio.sample_time(s)).collect();
+        assert!(audio_times.windows(2).all(|w| w[0] < w[1]));
+        assert!(audio_times.contains(&Duration::from_millis(1000)) && audio_times.contains(&Duration::from_millis(3800)));
+
+        let titles: Vec<&str> = reader.chapters.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, ["Stage 2-1 / Rolldown", "Augment pick"]);
+        assert_eq!(reader.chapters[1].start, Duration::from_millis(2800));
+
+        // The card is dark, the clip behind it is the grey source
+        let card = thumbnail::extract_frame(&output, Duration::from_millis(500), false).unwrap();
+        let clip = thumbnail::extract_frame(&output, Duration::from_millis(1500), true).unwrap();
+        let mean = |f: &thumbnail::RgbFrame| f.data.iter().map(|&b| u64::from(b)).sum::<u64>() / f.data.len() as u64;
+        assert!(mean(&card) < 60 && mean(&clip) > 110, "card {} clip {}", mean(&card), mean(&clip));
+        assert_eq!(clock(Duration::from_secs(3725)), "1:02:05");
+        cleanup(&[&input, &output]);
+    }
+}
@@ -1,20 +1,79 @@
 // ABOUTME: Core recorder library providing safe Rust API for Swift integration
 // ABOUTME: Exposes screen recording functionality through FFI bridge
 
+pub mod animation;
+pub mod audio;
+pub mod audio_encoder;
+pub mod concat;
+pub mod config;
+pub mod disk;
+pub mod encoder;
+pub mod events;
 pub mod ffi;
+pub mod h264;
+pub mod highlights;
+pub mod markers;
+pub mod metadata;
+pub mod mp4;
+pub mod naming;
+pub mod pacer;
+pub mod partial;
+pub mod pipeline;
+pub mod replay;
+pub mod segment;
+pub mod source;
+pub mod thumbnail;
+pub mod timelapse;
+pub mod title_card;
+pub mod trim;
+pub mod vertical;
 
-use anyhow::Result;
+#[cfg(test)]
+pub(crate) mod test_support;
+
+pub use animation::{export_animation, AnimationFormat, AnimationOptions, AnimationResult};
+pub use concat::{concat, ConcatResult};
+pub use config::{AudioCodec, AudioConfig, DiskConfig, FrameRateMode, GameInfo, RateControl, RecordingConfig, ReplayConfig, SegmentConfig};
+pub use highlights::{highlights, HighlightOptions, HighlightResult};
+pub use metadata::RecordingMetadata;
+pub use naming::{NameInfo, NamingProfile};
+pub use timelapse::{timelapse, TimelapseOptions, TimelapseResult, TimelapseSampling};
+pub use trim::{trim, TrimOptions, TrimResult};
+pub use vertical::{export_vertical, CropPreset, VerticalOptions, VerticalResult};
+
+use anyhow::{Context, Result};
+use disk::{DiskGuard, DiskSpace, SystemDiskSpace};
+use events::{EventBus, RecorderEvent};
+use markers::{Marker, MarkerList};
+use replay::{ReplayBuffer, ReplayClip};
+use segment::{Segment, SegmentSession};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
 use std::sync::Arc;
 use std::sync::Mutex;
 
 pub struct Recorder {
     inner: Arc<Mutex<RecorderInner>>,
+    events: EventBus,
+    disk_space: Arc<dyn DiskSpace>,
 }
 
 struct RecorderInner {
     #[cfg(target_os = "macos")]
     capture: Option<ffi::SwiftCapture>,
     is_recording: bool,
+    /// Counts recordings so a monitor thread never stops a later one.
+    #[cfg(target_os = "macos")]
+    session: u64,
+    // Declared after `capture` and outlives it: the Swift tap writes into it
+    replay: Option<Arc<Mutex<ReplayBuffer>>>,
+    replay_seconds: u32,
+    segments: Option<Arc<Mutex<SegmentSession>>>,
+    output: PathBuf,
+    started: Option<Instant>,
+    markers: Option<MarkerList>,
+    metadata: Option<RecordingMetadata>,
 }
 
 impl Recorder {
@@ -24,11 +83,29 @@ impl Recorder {
                 #[cfg(target_os = "macos")]
                 capture: None,
                 is_recording: false,
+                #[cfg(target_os = "macos")]
+                session: 0,
+                replay: None,
+                replay_seconds: 0,
+                segments: None,
+                output: PathBuf::new(),
+                started: None,
+                markers: None,
+                metadata: None,
             })),
+            events: EventBus::new(),
+            disk_space: Arc::new(SystemDiskSpace),
         }
     }
 
-    #[cfg(target_os = "macos")]
+    /// Reads free space from `space` instead of the real volumes.
+    pub fn with_disk_space(mut self, space: Arc<dyn DiskSpace>) -> Self {
+        self.disk_space = space;
+        self
+    }
+
+    /// Starts an average-bitrate recording; see [`Recorder::start_with_config`]
+    /// for the other rate-control modes.
     pub fn start(
         &mut self,
         window_title: &str,
@@ -37,62 +114,270 @@ impl Recorder {
         bitrate: u32,
         output_path: &str,
     ) -> Result<()> {
+        let config = RecordingConfig::new(window_title, width, height)
+            .with_rate_control(RateControl::Abr { bitrate });
+        self.start_with_config(&config, output_path)
+    }
+
+    /// Starts recording `config` to `output_path`. A failure is also published
+    /// as [`RecorderEvent::RecordingFailed`].
+    pub fn start_with_config(&mut self, config: &RecordingConfig, output_path: &str) -> Result<()> {
+        let result = self.start_capture(config, output_path);
+        match &result {
+            Ok(()) => self.events.publish(RecorderEvent::RecordingStarted { path: PathBuf::from(output_path) }),
+            Err(e) => self.events.publish(RecorderEvent::RecordingFailed { path: PathBuf::from(output_path), message: format!("{:#}", e) }),
+        }
+        result
+    }
+
+    #[cfg(target_os = "macos")]
+    fn start_capture(&mut self, config: &RecordingConfig, output_path: &str) -> Result<()> {
         let mut inner = self.inner.lock().unwrap();
         
         if inner.is_recording {
             anyhow::bail!("Already recording");
         }
+        config.validate()?;
+        let guard = DiskGuard::new(config, Path::new(output_path), self.disk_space.clone());
+        guard.check_start()?;
 
         let mut capture = ffi::create_capture_session();
+        ffi::set_encoding(&mut capture, &config.rate_control, config.keyframe_interval);
+        ffi::set_frame_rate(&mut capture, config.fps, config.frame_rate_mode);
+        ffi::set_audio(&mut capture, &config.audio);
+        let replay = config.replay.enabled.then(|| {
+            Arc::new(Mutex::new(ReplayBuffer::new(&config.replay, config.width, config.height, config.fps)))
+        });
+        if let Some(buffer) = &replay {
+            ffi::set_replay_sink(&mut capture, buffer);
+        }
+        // With segmenting the output path only names the playlist
+        let segments = config.segment.enabled().then(|| {
+            let namer = segment::SegmentNamer::new(&config.segment, Path::new(output_path), chrono::Local::now());
+            Arc::new(Mutex::new(SegmentSession::new(namer, self.events.clone())))
+        });
+        let first_file = match &segments {
+            Some(session) => {
+                ffi::set_segmenting(&mut capture, &config.segment, session);
+                partial::partial_path(&session.lock().unwrap().namer().path(1)).to_string_lossy().into_owned()
+            }
+            // Written under a temporary name until it is finalized
+            None => partial::partial_path(Path::new(output_path)).to_string_lossy().into_owned(),
+        };
         let success = ffi::start_capture(
             &mut capture,
-            window_title,
-            width,
-            height,
-            bitrate,
-            output_path,
+            &config.window_title,
+            config.width,
+            config.height,
+            config.rate_control.bitrate().unwrap_or(0),
+            &first_file,
         );
 
         if success {
             inner.capture = Some(capture);
             inner.is_recording = true;
+            inner.session += 1;
+            inner.replay = replay;
+            inner.replay_seconds = config.replay.seconds;
+            inner.segments = segments;
+            inner.output = PathBuf::from(output_path);
+            inner.started = Some(Instant::now());
+            inner.markers = Some(MarkerList::new(MarkerList::sidecar_path(Path::new(output_path))));
+            // Written now so a crashed recording still has one, marked incomplete
+            let metadata = RecordingMetadata::new(config, Path::new(output_path));
+            if let Err(e) = metadata.save() {
+                eprintln!("Failed to write recording metadata: {:#}", e);
+            }
+            if let Some(session) = &inner.segments {
+                session.lock().unwrap().set_tags(metadata.embedded_tags());
+            }
+            inner.metadata = Some(metadata);
+            self.monitor_disk(guard, inner.session);
             Ok(())
         } else {
             anyhow::bail!(
                 "Failed to start capture. \
                  Make sure the window title \"{}\" exists and that the app has \
                  Screen Recording permission (System Settings > Privacy & Security).",
-                window_title
+                config.window_title
             )
         }
     }
 
     #[cfg(not(target_os = "macos"))]
-    pub fn start(
-        &mut self,
-        _window_title: &str,
-        _width: u32,
-        _height: u32,
-        _bitrate: u32,
-        _output_path: &str,
-    ) -> Result<()> {
+    fn start_capture(&mut self, config: &RecordingConfig, output_path: &str) -> Result<()> {
+        config.validate()?;
+        DiskGuard::new(config, Path::new(output_path), self.disk_space.clone()).check_start()?;
         anyhow::bail!("Screen recording is only supported on macOS")
     }
 
+    /// Watches free space on a thread of its own until recording `session`
+    /// ends, stopping it once the volume is down to the reserve.
+    #[cfg(target_os = "macos")]
+    fn monitor_disk(&self, guard: DiskGuard, session: u64) {
+        let inner = self.inner.clone();
+        let events = self.events.clone();
+        std::thread::spawn(move || {
+            let current = |inner: &RecorderInner| inner.is_recording && inner.session == session;
+            disk::monitor(&guard, disk::CHECK_INTERVAL, &events, || current(&inner.lock().unwrap()), || {
+                let mut inner = inner.lock().unwrap();
+                if current(&inner) {
+                    finish(&mut inner, &events);
+                }
+            });
+        });
+    }
+
     pub fn stop(&mut self) {
+        finish(&mut self.inner.lock().unwrap(), &self.events);
+    }
+
+    /// Updates what is known about the game being recorded, e.g. its final
+    /// placement. Fields left `None` keep their previous value.
+    pub fn set_game_info(&self, game: GameInfo) -> Result<GameInfo> {
+        game.validate()?;
         let mut inner = self.inner.lock().unwrap();
-        
-        #[cfg(target_os = "macos")]
-        if let Some(mut capture) = inner.capture.take() {
-            ffi::stop_capture(&mut capture);
+        anyhow::ensure!(inner.is_recording, "Not recording");
+        let inner = &mut *inner;
+        let metadata = inner.metadata.as_mut().context("Recording has no metadata")?;
+        metadata.game.merge(game);
+        metadata.save()?;
+        if let Some(session) = &inner.segments {
+            session.lock().unwrap().set_tags(metadata.embedded_tags());
         }
-        
-        inner.is_recording = false;
+        Ok(metadata.game.clone())
+    }
+
+    /// Marks a moment of the current recording. `timestamp` is measured from
+    /// the start of the recording and defaults to now.
+    pub fn add_marker(&self, label: &str, timestamp: Option<Duration>) -> Result<Marker> {
+        let mut inner = self.inner.lock().unwrap();
+        anyhow::ensure!(inner.is_recording, "Not recording");
+        let time = timestamp
+            .or_else(|| inner.started.map(|s| s.elapsed()))
+            .unwrap_or_default();
+        let marker = Marker { time, label: label.to_string() };
+        let inner = &mut *inner;
+        let markers = inner.markers.as_mut().context("Recording has no marker list")?;
+        markers.add(marker.clone())?;
+        // Segments that close from now on get it as a chapter
+        if let Some(session) = &inner.segments {
+            session.lock().unwrap().set_markers(markers.clone());
+        }
+        Ok(marker)
     }
 
     pub fn is_recording(&self) -> bool {
         self.inner.lock().unwrap().is_recording
     }
+
+    /// Writes the last `seconds` (default: the whole buffer) of the replay
+    /// buffer to `path` while capture keeps running. After `stop` the buffer
+    /// of the finished recording can still be saved.
+    ///
+    /// The buffer only exists while a recording with `replay.enabled` runs
+    /// (and after it, until the next one); on macOS it holds video only, as
+    /// the capture doesn't feed it audio yet.
+    pub fn save_replay(&self, seconds: Option<u32>, path: impl AsRef<Path>) -> Result<ReplayClip> {
+        let (buffer, default_seconds) = {
+            let inner = self.inner.lock().unwrap();
+            let buffer = inner
+                .replay
+                .clone()
+                .context("Replay buffer is not enabled for this recording")?;
+            (buffer, inner.replay_seconds)
+        };
+        // The encoder callback feeds the same buffer; hold it only for the copy
+        let snapshot = buffer.lock().unwrap().snapshot(seconds.unwrap_or(default_seconds))?;
+        snapshot.write(path)
+    }
+
+    /// Receives every event emitted from now on, across recordings.
+    pub fn subscribe(&self) -> Receiver<RecorderEvent> {
+        self.events.subscribe()
+    }
+
+    /// Segments finalized so far in the current (or last) segmented recording.
+    pub fn segments(&self) -> Vec<Segment> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .segments
+            .as_ref()
+            .map(|s| s.lock().unwrap().segments().to_vec())
+            .unwrap_or_default()
+    }
+}
+
+/// Ends the recording: stops capture, then finalizes chapters and metadata,
+/// publishing the stop and the outcome on `events`.
+fn finish(inner: &mut RecorderInner, events: &EventBus) {
+    #[cfg(target_os = "macos")]
+    let frames = inner
+        .capture
+        .take()
+        .map(|mut capture| {
+            ffi::stop_capture(&mut capture);
+            ffi::frame_stats(&capture)
+        })
+        .unwrap_or_default();
+    #[cfg(not(target_os = "macos"))]
+    let frames = pacer::PacerStats::default();
+    
+    inner.is_recording = false;
+    let elapsed = inner.started.take().map(|s| s.elapsed()).unwrap_or_default();
+    let segments = inner
+        .segments
+        .as_ref()
+        .map(|s| s.lock().unwrap().segments().to_vec())
+        .unwrap_or_default();
+    let markers = inner.markers.take();
+    let Some(mut metadata) = inner.metadata.take() else {
+        return;
+    };
+    metadata.ended_at = Some(chrono::Local::now());
+    metadata.duration_secs = elapsed.as_secs_f64();
+    metadata.frames = frames;
+    metadata.markers = markers.as_ref().map(|m| m.markers().to_vec()).unwrap_or_default();
+    metadata.segments = segments;
+    events.publish(RecorderEvent::RecordingStopped { path: inner.output.clone(), duration_secs: metadata.duration_secs });
+
+    // Chapters and tags go in while the file still has its .partial name, so
+    // the final name only ever holds a finished recording. Segments got
+    // theirs before they were moved into place as each one was finalized.
+    let staged = partial::partial_path(&inner.output);
+    if metadata.segments.is_empty() {
+        if let Some(markers) = markers.as_ref().filter(|m| !m.is_empty()) {
+            if let Err(e) = mp4::write_chapters(&staged, &markers.chapters(Duration::ZERO, Duration::MAX)) {
+                eprintln!("Failed to write chapters: {:#}", e);
+            }
+        }
+        if let Err(e) = mp4::write_tags(&staged, &metadata.embedded_tags()) {
+            eprintln!("Failed to embed metadata in the recording: {:#}", e);
+        }
+    }
+    // The playlist stands for the whole segmented recording
+    let finalized = match inner.segments.as_ref() {
+        Some(session) => {
+            let bytes = metadata.segments.iter().map(|segment| segment.bytes).sum();
+            Ok((session.lock().unwrap().namer().playlist_path(), bytes))
+        }
+        None => partial::commit(&staged).map(|path| {
+            let bytes = std::fs::metadata(&path).map(|m| m.len()).unwrap_or_default();
+            (path, bytes)
+        }),
+    };
+    if let Err(e) = metadata.save() {
+        eprintln!("Failed to write recording metadata: {:#}", e);
+    }
+    // Announced last, so listeners find the sidecars complete
+    match finalized {
+        Ok((path, bytes)) => events.publish(RecorderEvent::RecordingFinalized { path, duration_secs: metadata.duration_secs, bytes }),
+        Err(e) => {
+            let message = format!("Failed to move the finished recording into place: {:#}", e);
+            eprintln!("{}", message);
+            events.publish(RecorderEvent::RecordingFailed { path: inner.output.clone(), message });
+        }
+    }
 }
 
 impl Default for Recorder {
@@ -111,6 +396,46 @@ mod tests {
         assert!(!recorder.is_recording());
     }
 
+    #[test]
+    fn test_add_marker_requires_recording() {
+        let recorder = Recorder::new();
+        let err = recorder.add_marker("Stage 2-1", None).unwrap_err();
+        assert!(err.to_string().contains("Not recording"));
+    }
+
+    #[test]
+    fn test_set_game_info_requires_recording() {
+        let recorder = Recorder::new();
+        assert!(recorder.set_game_info(GameInfo { placement: Some(4), ..Default::default() }).is_err());
+        let err = recorder.set_game_info(GameInfo { placement: Some(0), ..Default::default() }).unwrap_err();
+        assert!(err.to_string().contains("Placement"));
+    }
+
+    #[test]
+    fn test_save_replay_without_buffer_fails() {
+        let recorder = Recorder::new();
+        assert!(recorder.save_replay(Some(10), "/tmp/replay.mp4").is_err());
+    }
+
+    #[test]
+    fn test_start_refuses_without_free_space() {
+        use std::sync::atomic::AtomicU64;
+        let space = Arc::new(disk::tests::FakeDiskSpace(AtomicU64::new(500 * 1024 * 1024)));
+        let mut recorder = Recorder::new().with_disk_space(space);
+        let events = recorder.subscribe();
+        let err = recorder.start("Test", 640, 480, 1000000, "/tmp/test.mp4").unwrap_err();
+        assert!(err.to_string().contains("Not enough free space"), "{}", err);
+        assert!(!recorder.is_recording());
+        // Hooks and other listeners hear about the failure too
+        match events.try_recv().unwrap() {
+            RecorderEvent::RecordingFailed { path, message } => {
+                assert_eq!(path, PathBuf::from("/tmp/test.mp4"));
+                assert!(message.contains("Not enough free space"));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
     #[test]
     fn test_double_start_fails() {
         let mut recorder = Recorder::new();
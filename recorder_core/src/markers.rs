@@ -0,0 +1,178 @@
+// ABOUTME: Timestamped markers dropped while recording (stage changes, 3-stars, streaks)
+// ABOUTME: Kept in a JSON sidecar as they arrive and turned into MP4 chapters when the file closes
+
+use crate::mp4::chapters::LEADING_CHAPTER_TITLE;
+use crate::mp4::Chapter;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    /// Seconds from the start of the recording.
+    #[serde(rename = "time_secs", with = "secs")]
+    pub time: Duration,
+    pub label: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Sidecar {
+    markers: Vec<Marker>,
+}
+
+/// The markers of one recording and the sidecar file they are saved to.
+#[derive(Debug, Clone)]
+pub struct MarkerList {
+    sidecar: PathBuf,
+    markers: Vec<Marker>,
+}
+
+impl MarkerList {
+    pub fn new(sidecar: impl Into<PathBuf>) -> Self {
+        Self { sidecar: sidecar.into(), markers: Vec::new() }
+    }
+
+    /// A list holding `markers`, e.g. ones carried over from another file.
+    /// Nothing is written until `save` or `add`.
+    pub fn with_markers(sidecar: impl Into<PathBuf>, mut markers: Vec<Marker>) -> Self {
+        markers.sort_by_key(|m| m.time);
+        Self { sidecar: sidecar.into(), markers }
+    }
+
+    /// `TFT-x.mp4` keeps its markers in `TFT-x.markers.json`.
+    pub fn sidecar_path(recording: &Path) -> PathBuf {
+        recording.with_extension("markers.json")
+    }
+
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    /// Inserts `marker` in time order and rewrites the sidecar, so markers
+    /// survive a crash before the recording is finalized.
+    pub fn add(&mut self, marker: Marker) -> Result<()> {
+        let index = self.markers.partition_point(|m| m.time <= marker.time);
+        self.markers.insert(index, marker);
+        self.save()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&Sidecar { markers: self.markers.clone() })?;
+        std::fs::write(&self.sidecar, json)
+            .with_context(|| format!("Failed to write {}", self.sidecar.display()))
+    }
+
+    /// The markers of a finished recording: its sidecar if it has one,
+    /// otherwise the `chapters` embedded in the file, minus the filler
+    /// chapter `write_chapters` puts before the first marker.
+    pub fn for_recording(recording: &Path, chapters: &[Chapter]) -> Result<Self> {
+        let sidecar = Self::sidecar_path(recording);
+        let markers = if sidecar.exists() {
+            Self::load(&sidecar)?
+        } else {
+            chapters
+                .iter()
+                .filter(|c| !(c.start.is_zero() && c.title == LEADING_CHAPTER_TITLE))
+                .map(|c| Marker { time: c.start, label: c.title.clone() })
+                .collect()
+        };
+        Ok(Self::with_markers(sidecar, markers))
+    }
+
+    pub fn load(sidecar: &Path) -> Result<Vec<Marker>> {
+        let json = std::fs::read_to_string(sidecar)
+            .with_context(|| format!("Failed to read {}", sidecar.display()))?;
+        let parsed: Sidecar = serde_json::from_str(&json)
+            .with_context(|| format!("Malformed marker sidecar {}", sidecar.display()))?;
+        Ok(parsed.markers)
+    }
+
+    /// Chapters for a file holding `[start, start + duration)` of the
+    /// recording, with times relative to the file. A file that starts mid-way
+    /// through a marked section opens with that section's label.
+    pub fn chapters(&self, start: Duration, duration: Duration) -> Vec<Chapter> {
+        let end = start.saturating_add(duration);
+        let mut chapters: Vec<Chapter> = self
+            .markers
+            .iter()
+            .filter(|m| m.time >= start && m.time < end)
+            .map(|m| Chapter { start: m.time - start, title: m.label.clone() })
+            .collect();
+
+        let ongoing = self.markers.iter().rev().find(|m| m.time < start);
+        if let Some(marker) = ongoing {
+            if chapters.first().is_none_or(|c| c.start > Duration::ZERO) {
+                chapters.insert(0, Chapter { start: Duration::ZERO, title: marker.label.clone() });
+            }
+        }
+        chapters
+    }
+}
+
+mod secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_f64(d.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(d)?;
+        Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker(secs: u64, label: &str) -> Marker {
+        Marker { time: Duration::from_secs(secs), label: label.to_string() }
+    }
+
+    #[test]
+    fn test_markers_stay_sorted_and_reach_the_sidecar() {
+        let path = std::env::temp_dir().join(format!("markers_{}.markers.json", std::process::id()));
+        let mut list = MarkerList::new(&path);
+        list.add(marker(90, "Stage 2-1")).unwrap();
+        list.add(marker(30, "Carousel")).unwrap();
+        list.add(marker(600, "First 3-star")).unwrap();
+
+        let labels: Vec<_> = list.markers().iter().map(|m| m.label.as_str()).collect();
+        assert_eq!(labels, ["Carousel", "Stage 2-1", "First 3-star"]);
+        assert_eq!(MarkerList::load(&path).unwrap(), list.markers());
+
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\"time_secs\": 30.0"));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_chapters_per_segment() {
+        let mut list = MarkerList::new(std::env::temp_dir().join("unused.markers.json"));
+        list.markers = vec![marker(30, "Carousel"), marker(90, "Stage 2-1"), marker(700, "Loss streak")];
+
+        let whole = list.chapters(Duration::ZERO, Duration::from_secs(900));
+        assert_eq!(whole.len(), 3);
+        assert_eq!(whole[1].start, Duration::from_secs(90));
+
+        // Second 10-minute segment: continues "Stage 2-1", then the streak at 100 s
+        let second = list.chapters(Duration::from_secs(600), Duration::from_secs(600));
+        assert_eq!(second[0], Chapter { start: Duration::ZERO, title: "Stage 2-1".into() });
+        assert_eq!(second[1], Chapter { start: Duration::from_secs(100), title: "Loss streak".into() });
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(
+            MarkerList::sidecar_path(Path::new("/rec/TFT-1.mp4")),
+            PathBuf::from("/rec/TFT-1.markers.json")
+        );
+    }
+}
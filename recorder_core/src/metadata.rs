@@ -0,0 +1,166 @@
+// ABOUTME: Per-recording JSON sidecar describing how and when a recording was made
+// ABOUTME: Written at start, completed at stop, and read back by the GUI and CLI
+
+use crate::config::{GameInfo, RecordingConfig};
+use crate::markers::Marker;
+use crate::mp4::MetadataTags;
+use crate::pacer::PacerStats;
+use crate::segment::Segment;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Version of the sidecar layout, bumped on incompatible changes.
+pub const METADATA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordingMetadata {
+    pub version: u32,
+    /// Version of the recorder that wrote the file.
+    pub app_version: String,
+    /// The recording, or the playlist when the recording is segmented.
+    pub output: PathBuf,
+    /// Title of the captured window; empty for full-screen capture.
+    pub window_title: String,
+    pub config: RecordingConfig,
+    pub started_at: DateTime<Local>,
+    /// `None` while recording, or if the recorder never stopped cleanly.
+    pub ended_at: Option<DateTime<Local>>,
+    pub duration_secs: f64,
+    pub frames: PacerStats,
+    #[serde(default)]
+    pub markers: Vec<Marker>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub segments: Vec<Segment>,
+    #[serde(default)]
+    pub game: GameInfo,
+}
+
+impl RecordingMetadata {
+    /// Metadata for a recording of `output` that starts now.
+    pub fn new(config: &RecordingConfig, output: &Path) -> Self {
+        Self {
+            version: METADATA_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            output: output.to_path_buf(),
+            window_title: config.window_title.clone(),
+            config: config.clone(),
+            started_at: Local::now(),
+            ended_at: None,
+            duration_secs: 0.0,
+            frames: PacerStats::default(),
+            markers: Vec::new(),
+            tags: config.tags.clone(),
+            segments: Vec::new(),
+            game: config.game.clone(),
+        }
+    }
+
+    /// `TFT-x.mp4` is described by `TFT-x.json`.
+    pub fn sidecar_path(recording: &Path) -> PathBuf {
+        recording.with_extension("json")
+    }
+
+    /// Reads the sidecar of `recording`, or `None` if it has none.
+    pub fn for_recording(recording: &Path) -> Result<Option<Self>> {
+        let sidecar = Self::sidecar_path(recording);
+        if !sidecar.exists() {
+            return Ok(None);
+        }
+        Self::load(&sidecar).map(Some)
+    }
+
+    pub fn load(sidecar: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(sidecar)
+            .with_context(|| format!("Failed to read {}", sidecar.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Malformed metadata sidecar {}", sidecar.display()))
+    }
+
+    /// Writes the sidecar next to `output`.
+    pub fn save(&self) -> Result<()> {
+        let sidecar = Self::sidecar_path(&self.output);
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(&sidecar, json).with_context(|| format!("Failed to write {}", sidecar.display()))
+    }
+
+    /// The items embedded in the finished MP4 so the description travels
+    /// with the file.
+    pub fn embedded_tags(&self) -> MetadataTags {
+        let title = self
+            .config
+            .title
+            .clone()
+            .unwrap_or_else(|| format!("TFT {}", self.started_at.format("%Y-%m-%d %H:%M")));
+        MetadataTags {
+            title: Some(title),
+            date: Some(self.started_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)),
+            encoder: Some(format!("TFT Recorder {}", self.app_version)),
+            comment: self.config.comment.clone(),
+            patch: self.game.patch.clone(),
+            rank: self.game.rank.clone(),
+            placement: self.game.placement,
+        }
+    }
+
+    /// Whether the recording was stopped cleanly.
+    pub fn is_complete(&self) -> bool {
+        self.ended_at.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_file;
+    use std::time::Duration;
+
+    #[test]
+    fn test_round_trip_through_sidecar() {
+        let output = temp_file("metadata");
+        let config = RecordingConfig::new("Teamfight Tactics", 1920, 1080)
+            .with_tags(vec!["ranked".into(), "set 13".into()]);
+        let mut metadata = RecordingMetadata::new(&config, &output);
+        assert!(!metadata.is_complete());
+        assert_eq!(metadata.tags, ["ranked", "set 13"]);
+
+        metadata.ended_at = Some(metadata.started_at + chrono::Duration::seconds(1800));
+        metadata.duration_secs = 1800.0;
+        metadata.frames = PacerStats { frames_in: 108_100, frames_out: 108_000, dropped: 120, duplicated: 20 };
+        metadata.markers.push(Marker { time: Duration::from_secs(90), label: "Stage 2-1".into() });
+        metadata.save().unwrap();
+
+        let sidecar = RecordingMetadata::sidecar_path(&output);
+        assert_eq!(sidecar.extension().unwrap(), "json");
+        let loaded = RecordingMetadata::for_recording(&output).unwrap().unwrap();
+        assert_eq!(loaded, metadata);
+        assert!(loaded.is_complete());
+        std::fs::remove_file(sidecar).ok();
+    }
+
+    #[test]
+    fn test_embedded_tags() {
+        let config = RecordingConfig::default()
+            .with_comment("Hyper roll")
+            .with_game(GameInfo { patch: Some("14.20".into()), rank: None, placement: Some(1) });
+        let metadata = RecordingMetadata::new(&config, Path::new("/rec/TFT-1.mp4"));
+        let tags = metadata.embedded_tags();
+        assert!(tags.title.unwrap().starts_with("TFT 20"));
+        assert_eq!(tags.encoder, Some(format!("TFT Recorder {}", env!("CARGO_PKG_VERSION"))));
+        assert_eq!(tags.comment.as_deref(), Some("Hyper roll"));
+        assert_eq!(tags.patch.as_deref(), Some("14.20"));
+        assert_eq!(tags.placement, Some(1));
+        assert!(chrono::DateTime::parse_from_rfc3339(&tags.date.unwrap()).is_ok());
+
+        let titled = RecordingMetadata::new(&config.with_title("Road to Master"), Path::new("/rec/TFT-2.mp4"));
+        assert_eq!(titled.embedded_tags().title.as_deref(), Some("Road to Master"));
+    }
+
+    #[test]
+    fn test_missing_sidecar_is_none() {
+        let output = std::env::temp_dir().join("no_metadata_here.mp4");
+        assert!(RecordingMetadata::for_recording(&output).unwrap().is_none());
+    }
+}
@@ -0,0 +1,86 @@
+// ABOUTME: Byte-level builder for nested MP4 boxes
+// ABOUTME: Sizes are patched in when a box is closed, so callers never compute them
+
+pub(crate) struct BoxBuilder {
+    buf: Vec<u8>,
+    open: Vec<usize>,
+}
+
+impl BoxBuilder {
+    pub fn new() -> Self {
+        Self { buf: Vec::new(), open: Vec::new() }
+    }
+
+    pub fn begin(&mut self, fourcc: &[u8; 4]) -> &mut Self {
+        self.open.push(self.buf.len());
+        self.u32(0).bytes(fourcc)
+    }
+
+    /// Starts a "full box" with a version byte and 24-bit flags.
+    pub fn begin_full(&mut self, fourcc: &[u8; 4], version: u8, flags: u32) -> &mut Self {
+        self.begin(fourcc).u32((u32::from(version) << 24) | (flags & 0x00ff_ffff))
+    }
+
+    pub fn end(&mut self) -> &mut Self {
+        let start = self.open.pop().expect("end() without matching begin()");
+        let size = (self.buf.len() - start) as u32;
+        self.buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+        self
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.buf.push(v);
+        self
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Self {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    pub fn zeros(&mut self, n: usize) -> &mut Self {
+        self.buf.resize(self.buf.len() + n, 0);
+        self
+    }
+
+    pub fn bytes(&mut self, b: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(b);
+        self
+    }
+
+    /// The identity transform used by mvhd and tkhd.
+    pub fn matrix(&mut self) -> &mut Self {
+        for v in [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000u32] {
+            self.u32(v);
+        }
+        self
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        assert!(self.open.is_empty(), "unclosed MP4 box");
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_sizes_are_patched() {
+        let mut b = BoxBuilder::new();
+        b.begin(b"moov").begin_full(b"mvhd", 0, 0).u32(7).end().end();
+        let out = b.finish();
+
+        assert_eq!(&out[0..8], &[0, 0, 0, 24, b'm', b'o', b'o', b'v']);
+        assert_eq!(&out[8..16], &[0, 0, 0, 16, b'm', b'v', b'h', b'd']);
+        assert_eq!(out.len(), 24);
+    }
+}
@@ -0,0 +1,348 @@
+// ABOUTME: Adds chapters to a finished MP4 as a QuickTime text track plus a Nero chpl box
+// ABOUTME: Appends a new mdat and moov and turns the old moov into free space, so samples never move
+
+use super::boxes::BoxBuilder;
+use super::edit::{Movie, MovieFile};
+use super::reader::{child_boxes, find, BoxRef};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// Chapter samples are timed in milliseconds.
+const CHAPTER_TIMESCALE: u32 = 1_000;
+
+/// Title of the chapter that fills the gap before the first real one.
+pub const LEADING_CHAPTER_TITLE: &str = "Start";
+
+/// Nero chapter times are in 100 ns units.
+const CHPL_UNITS_PER_SEC: u128 = 10_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: String,
+}
+
+/// Whether the video track references a chapter track or `udta` has a `chpl`.
+fn has_chapters(movie: &Movie) -> bool {
+    let chapter_ref = movie.children.iter().filter(|b| &b.fourcc == b"trak").any(|trak| {
+        let children = child_boxes(movie.data, trak.start, trak.end);
+        find(&children, b"tref").is_some_and(|tref| {
+            find(&child_boxes(movie.data, tref.start, tref.end), b"chap").is_some()
+        })
+    });
+    chapter_ref || find(&movie.udta_children(), b"chpl").is_some()
+}
+
+/// Writes `chapters` into the MP4 at `path`. Chapters past the end of the
+/// movie are dropped; if the first one starts later than zero, a "Start"
+/// chapter covers the gap. Fails if the file already has chapters.
+pub fn write_chapters(path: impl AsRef<Path>, chapters: &[Chapter]) -> Result<()> {
+    let path = path.as_ref();
+    let (mut file, data) = MovieFile::open(path)?;
+    let mdat_offset = file.end_offset()?;
+    let movie = Movie::parse(&data)?;
+    if has_chapters(&movie) {
+        bail!("{} already has chapters", path.display());
+    }
+    let chapters = normalize(chapters, movie.length());
+    if chapters.is_empty() {
+        return Ok(());
+    }
+
+    let video_trak = movie
+        .children
+        .iter()
+        .find(|b| &b.fourcc == b"trak" && movie.handler(b) == Some(*b"vide"))
+        .copied()
+        .context("No video track to attach chapters to")?;
+    let track_id = movie.next_track_id.max(1);
+
+    // Sample data goes into a fresh mdat at the end of the file
+    let samples: Vec<Vec<u8>> = chapters.iter().map(|c| text_sample(&c.title)).collect();
+    let mut mdat = BoxBuilder::new();
+    mdat.begin(b"mdat");
+    for sample in &samples {
+        mdat.bytes(sample);
+    }
+    mdat.end();
+    let mdat = mdat.finish();
+
+    let mut b = BoxBuilder::new();
+    b.begin(b"moov");
+    let mut has_udta = false;
+    for (child, raw) in movie.raw_children() {
+        match &child.fourcc {
+            b"mvhd" => {
+                let mut mvhd = raw.to_vec();
+                let len = mvhd.len();
+                mvhd[len - 4..].copy_from_slice(&(track_id + 1).to_be_bytes());
+                b.bytes(&mvhd);
+            }
+            b"trak" if child.start == video_trak.start => {
+                b.begin(b"trak");
+                b.bytes(&data[child.start..child.end]);
+                b.begin(b"tref").begin(b"chap").u32(track_id).end().end();
+                b.end();
+            }
+            b"udta" => {
+                has_udta = true;
+                b.begin(b"udta");
+                b.bytes(&data[child.start..child.end]);
+                write_chpl(&mut b, &chapters);
+                b.end();
+            }
+            _ => {
+                b.bytes(raw);
+            }
+        }
+    }
+    if !has_udta {
+        b.begin(b"udta");
+        write_chpl(&mut b, &chapters);
+        b.end();
+    }
+    write_text_trak(&mut b, &movie, track_id, &chapters, &samples, mdat_offset + 8);
+    b.end();
+    file.commit(&mdat, &b.finish())
+}
+
+/// Sorts, drops chapters at or past `length` and duplicates at the same
+/// millisecond, and fills a leading gap.
+fn normalize(chapters: &[Chapter], length: Duration) -> Vec<Chapter> {
+    let mut out: Vec<Chapter> = chapters.iter().filter(|c| c.start < length).cloned().collect();
+    out.sort_by_key(|c| c.start);
+    out.dedup_by_key(|c| c.start.as_millis());
+    if out.first().is_some_and(|c| c.start.as_millis() > 0) {
+        out.insert(0, Chapter { start: Duration::ZERO, title: LEADING_CHAPTER_TITLE.to_string() });
+    }
+    out
+}
+
+/// QuickTime text sample: length-prefixed UTF-8 plus an encoding atom.
+fn text_sample(title: &str) -> Vec<u8> {
+    let text = truncate_utf8(title, u16::MAX as usize - 16);
+    let mut b = BoxBuilder::new();
+    b.u16(text.len() as u16).bytes(text.as_bytes());
+    b.begin(b"encd").u32(0x0000_0100).end(); // UTF-8
+    b.finish()
+}
+
+fn truncate_utf8(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn write_chpl(b: &mut BoxBuilder, chapters: &[Chapter]) {
+    let chapters = &chapters[..chapters.len().min(255)];
+    b.begin_full(b"chpl", 1, 0).u32(0).u8(chapters.len() as u8);
+    for chapter in chapters {
+        let units = chapter.start.as_nanos() * CHPL_UNITS_PER_SEC / 1_000_000_000;
+        let title = truncate_utf8(&chapter.title, 255);
+        b.u64(units as u64).u8(title.len() as u8).bytes(title.as_bytes());
+    }
+    b.end();
+}
+
+fn write_text_trak(
+    b: &mut BoxBuilder,
+    movie: &Movie,
+    track_id: u32,
+    chapters: &[Chapter],
+    samples: &[Vec<u8>],
+    data_offset: u64,
+) {
+    let length = movie.length();
+    let to_ms = |d: Duration| d.as_millis() as u32;
+    let mut durations: Vec<u32> = chapters.windows(2).map(|w| to_ms(w[1].start) - to_ms(w[0].start)).collect();
+    durations.push((to_ms(length) - to_ms(chapters[chapters.len() - 1].start)).max(1));
+    let media_duration: u32 = durations.iter().sum();
+    let movie_duration = u64::from(media_duration) * u64::from(movie.timescale) / u64::from(CHAPTER_TIMESCALE);
+
+    b.begin(b"trak");
+    // Disabled: players list the chapters but never render the track
+    b.begin_full(b"tkhd", 0, 0)
+        .u32(0)
+        .u32(0)
+        .u32(track_id)
+        .u32(0)
+        .u32(movie_duration as u32)
+        .zeros(8)
+        .u16(0)
+        .u16(0)
+        .u16(0)
+        .u16(0)
+        .matrix()
+        .u32(0)
+        .u32(0)
+        .end();
+
+    b.begin(b"mdia");
+    b.begin_full(b"mdhd", 0, 0)
+        .u32(0)
+        .u32(0)
+        .u32(CHAPTER_TIMESCALE)
+        .u32(media_duration)
+        .u16(0x55c4) // "und"
+        .u16(0)
+        .end();
+    b.begin_full(b"hdlr", 0, 0)
+        .u32(0)
+        .bytes(b"text")
+        .zeros(12)
+        .bytes(b"Chapters")
+        .u8(0)
+        .end();
+
+    b.begin(b"minf");
+    b.begin(b"gmhd");
+    b.begin_full(b"gmin", 0, 0)
+        .u16(0x40) // graphics mode: dither copy
+        .u16(0x8000)
+        .u16(0x8000)
+        .u16(0x8000)
+        .u16(0) // balance
+        .u16(0)
+        .end();
+    // Undocumented text media header QuickTime expects on chapter tracks
+    b.begin(b"text").matrix().end();
+    b.end(); // gmhd
+    b.begin(b"dinf")
+        .begin_full(b"dref", 0, 0)
+        .u32(1)
+        .begin_full(b"url ", 0, 1)
+        .end()
+        .end()
+        .end();
+
+    b.begin(b"stbl");
+    b.begin_full(b"stsd", 0, 0).u32(1);
+    b.begin(b"text")
+        .zeros(6)
+        .u16(1) // data reference index
+        .u32(1) // display flags
+        .u8(0) // horizontal justification
+        .u8(0) // vertical justification
+        .zeros(4) // background colour
+        .zeros(8) // default text box
+        .zeros(4) // start/end char
+        .u16(1) // font ID
+        .u8(0) // style flags
+        .u8(0) // font size
+        .zeros(4); // foreground colour
+    b.begin(b"ftab").u16(1).u16(1).u8(0).end();
+    b.end(); // text
+    b.end(); // stsd
+
+    b.begin_full(b"stts", 0, 0).u32(durations.len() as u32);
+    for &d in &durations {
+        b.u32(1).u32(d);
+    }
+    b.end();
+    b.begin_full(b"stsc", 0, 0).u32(1).u32(1).u32(samples.len() as u32).u32(1).end();
+    b.begin_full(b"stsz", 0, 0).u32(0).u32(samples.len() as u32);
+    for sample in samples {
+        b.u32(sample.len() as u32);
+    }
+    b.end();
+    if data_offset > u64::from(u32::MAX) {
+        b.begin_full(b"co64", 0, 0).u32(1).u64(data_offset).end();
+    } else {
+        b.begin_full(b"stco", 0, 0).u32(1).u32(data_offset as u32).end();
+    }
+    b.end(); // stbl
+    b.end(); // minf
+    b.end(); // mdia
+    b.end(); // trak
+}
+
+/// Reads a Nero `chpl` box from `udta`, if present.
+pub(crate) fn parse_chpl(data: &[u8], udta: &BoxRef) -> Vec<Chapter> {
+    let children = child_boxes(data, udta.start, udta.end);
+    let Some(chpl) = find(&children, b"chpl") else {
+        return Vec::new();
+    };
+    let payload = &data[chpl.start..chpl.end];
+    let Some(&version) = payload.first() else {
+        return Vec::new();
+    };
+    let mut pos = if version == 1 { 8 } else { 4 };
+    let Some(&count) = payload.get(pos) else {
+        return Vec::new();
+    };
+    pos += 1;
+
+    let mut chapters = Vec::new();
+    for _ in 0..count {
+        let Some(units) = payload.get(pos..pos + 8) else {
+            break;
+        };
+        let units = u64::from_be_bytes(units.try_into().unwrap());
+        let Some(&len) = payload.get(pos + 8) else {
+            break;
+        };
+        let Some(title) = payload.get(pos + 9..pos + 9 + usize::from(len)) else {
+            break;
+        };
+        chapters.push(Chapter {
+            start: Duration::from_nanos((u128::from(units) * 1_000_000_000 / CHPL_UNITS_PER_SEC) as u64),
+            title: String::from_utf8_lossy(title).into_owned(),
+        });
+        pos += 9 + usize::from(len);
+    }
+    chapters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4::Mp4Reader;
+    use crate::test_support::{temp_file, write_fake_video};
+
+    fn chapter(secs: u64, title: &str) -> Chapter {
+        Chapter { start: Duration::from_secs(secs), title: title.to_string() }
+    }
+
+    #[test]
+    fn test_chapters_round_trip_without_moving_samples() {
+        let path = temp_file("chapters");
+        write_fake_video(&path, 30);
+        let before = Mp4Reader::open(&path).unwrap();
+
+        write_chapters(&path, &[chapter(2, "3-star Jinx"), chapter(1, "Stage 2-1"), chapter(9, "past the end")]).unwrap();
+
+        let mut after = Mp4Reader::open(&path).unwrap();
+        assert_eq!(
+            after.chapters,
+            vec![chapter(0, "Start"), chapter(1, "Stage 2-1"), chapter(2, "3-star Jinx")]
+        );
+        // The text track is skipped by the demuxer; the video is untouched
+        assert_eq!(after.tracks.len(), 1);
+        let samples = after.video_track().unwrap().samples.clone();
+        assert_eq!(samples, before.video_track().unwrap().samples);
+        assert_eq!(after.read_sample(&samples[7]).unwrap(), vec![7u8; 50]);
+
+        let moov = after.moov();
+        let top = child_boxes(moov, 0, moov.len());
+        let children = child_boxes(moov, top[0].start, top[0].end);
+        let traks: Vec<_> = children.iter().filter(|b| &b.fourcc == b"trak").collect();
+        assert_eq!(traks.len(), 2);
+        let video_children = child_boxes(moov, traks[0].start, traks[0].end);
+        let tref = find(&video_children, b"tref").unwrap();
+        assert_eq!(&moov[tref.start + 4..tref.start + 12], b"chap\0\0\0\x02");
+
+        assert!(write_chapters(&path, &[chapter(1, "again")]).is_err());
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_text_sample_layout() {
+        let sample = text_sample("GG");
+        assert_eq!(&sample[..4], &[0, 2, b'G', b'G']);
+        assert_eq!(&sample[4..], &[0, 0, 0, 12, b'e', b'n', b'c', b'd', 0, 0, 1, 0]);
+    }
+}
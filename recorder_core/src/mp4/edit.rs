@@ -0,0 +1,112 @@
+// ABOUTME: In-place editing of a finished MP4's moov without touching its samples
+// ABOUTME: A new moov (and optional mdat) is appended and the old moov becomes free space
+
+use super::reader::{child_boxes, find, locate_top_level, ticks_to_duration, BoxRef};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// A finished MP4 opened for editing.
+pub(crate) struct MovieFile {
+    file: File,
+    moov_offset: u64,
+}
+
+impl MovieFile {
+    /// Opens `path` and reads its moov box (header included).
+    pub fn open(path: &Path) -> Result<(Self, Vec<u8>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let (moov_offset, moov_size) = locate_top_level(&mut file, b"moov")?
+            .with_context(|| format!("{} has no moov box (unfinished recording?)", path.display()))?;
+        let mut data = vec![0u8; moov_size as usize];
+        file.seek(SeekFrom::Start(moov_offset))?;
+        file.read_exact(&mut data)?;
+        Ok((Self { file, moov_offset }, data))
+    }
+
+    /// Where a box appended now would start.
+    pub fn end_offset(&mut self) -> Result<u64> {
+        Ok(self.file.seek(SeekFrom::End(0))?)
+    }
+
+    /// Appends `prefix` (e.g. an mdat for new samples) and `moov`, then
+    /// retires the old moov. The old one is only overwritten once the new one
+    /// is on disk, so a crash leaves a playable file either way.
+    pub fn commit(mut self, prefix: &[u8], moov: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(prefix)?;
+        self.file.write_all(moov)?;
+        self.file.sync_data()?;
+        self.file.seek(SeekFrom::Start(self.moov_offset + 4))?;
+        self.file.write_all(b"free")?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Raw moov pieces a rewrite needs.
+pub(crate) struct Movie<'a> {
+    pub data: &'a [u8],
+    pub moov: BoxRef,
+    pub children: Vec<BoxRef>,
+    pub timescale: u32,
+    pub duration: u64,
+    pub next_track_id: u32,
+}
+
+impl<'a> Movie<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let top = child_boxes(data, 0, data.len());
+        let moov = *find(&top, b"moov").context("Missing moov")?;
+        let children = child_boxes(data, moov.start, moov.end);
+        let mvhd = find(&children, b"mvhd").context("moov without mvhd")?;
+
+        let field = |offset: usize, len: usize| -> Result<u64> {
+            let bytes = data.get(offset..offset + len).context("Truncated mvhd")?;
+            Ok(bytes.iter().fold(0u64, |acc, &b| (acc << 8) | u64::from(b)))
+        };
+        let (timescale, duration) = if data[mvhd.start] == 1 {
+            (field(mvhd.start + 20, 4)?, field(mvhd.start + 24, 8)?)
+        } else {
+            (field(mvhd.start + 12, 4)?, field(mvhd.start + 16, 4)?)
+        };
+        let next_track_id = field(mvhd.end - 4, 4)? as u32;
+
+        Ok(Self { data, moov, children, timescale: timescale as u32, duration, next_track_id })
+    }
+
+    pub fn length(&self) -> Duration {
+        ticks_to_duration(self.duration, self.timescale)
+    }
+
+    /// Each child with its header, in order.
+    pub fn raw_children(&self) -> impl Iterator<Item = (&BoxRef, &'a [u8])> + '_ {
+        let mut raw_start = self.moov.start;
+        self.children.iter().map(move |child| {
+            let raw = &self.data[raw_start..child.end];
+            raw_start = child.end;
+            (child, raw)
+        })
+    }
+
+    pub fn handler(&self, trak: &BoxRef) -> Option<[u8; 4]> {
+        let children = child_boxes(self.data, trak.start, trak.end);
+        let mdia = find(&children, b"mdia")?;
+        let mdia_children = child_boxes(self.data, mdia.start, mdia.end);
+        let hdlr = find(&mdia_children, b"hdlr")?;
+        self.data.get(hdlr.start + 8..hdlr.start + 12)?.try_into().ok()
+    }
+
+    /// Children of the movie's `udta`, if it has one.
+    pub fn udta_children(&self) -> Vec<BoxRef> {
+        find(&self.children, b"udta")
+            .map(|udta| child_boxes(self.data, udta.start, udta.end))
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,74 @@
+// ABOUTME: Minimal ISO-BMFF (MP4) support for the Rust-side pipelines
+// ABOUTME: Track descriptions plus the muxer and demuxer used by replay and post-processing
+
+mod boxes;
+pub mod chapters;
+mod edit;
+pub mod reader;
+pub mod tags;
+pub mod writer;
+
+pub use chapters::{write_chapters, Chapter};
+pub use reader::Mp4Reader;
+pub use tags::{write_tags, MetadataTags};
+pub use writer::Mp4Writer;
+
+use crate::audio::AudioTrackKind;
+use crate::config::AudioCodec;
+use crate::h264::ParameterSets;
+use std::time::Duration;
+
+/// Timescale of video tracks; 90 kHz divides every common frame rate closely.
+pub const VIDEO_TIMESCALE: u32 = 90_000;
+
+/// Timescale of the movie header (milliseconds).
+pub const MOVIE_TIMESCALE: u32 = 1_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoTrackInfo {
+    pub width: u32,
+    pub height: u32,
+    pub parameter_sets: ParameterSets,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioTrackInfo {
+    pub kind: AudioTrackKind,
+    pub codec: AudioCodec,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// AudioSpecificConfig (AAC) or OpusSpecificBox payload (Opus).
+    pub decoder_config: Vec<u8>,
+    /// Encoder delay to hide with an edit list.
+    pub priming_samples: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackInfo {
+    Video(VideoTrackInfo),
+    Audio(AudioTrackInfo),
+}
+
+impl TrackInfo {
+    pub fn timescale(&self) -> u32 {
+        match self {
+            TrackInfo::Video(_) => VIDEO_TIMESCALE,
+            TrackInfo::Audio(a) => a.sample_rate,
+        }
+    }
+}
+
+/// One sample handed to the muxer. `duration` is only needed for the last
+/// sample of a track; the others are derived from the next sample's `pts`.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample<'a> {
+    pub pts: Duration,
+    pub duration: Option<Duration>,
+    pub keyframe: bool,
+    pub data: &'a [u8],
+}
+
+pub(crate) fn to_ticks(time: Duration, timescale: u32) -> u64 {
+    let ticks = time.as_nanos() * u128::from(timescale) + 500_000_000;
+    (ticks / 1_000_000_000) as u64
+}
@@ -0,0 +1,537 @@
+// ABOUTME: MP4 demuxer that parses moov into per-track sample tables
+// ABOUTME: Reads sample payloads on demand so large recordings never load fully into memory
+
+use super::chapters::{parse_chpl, Chapter};
+use super::tags::{parse_tags, MetadataTags};
+use super::{AudioTrackInfo, TrackInfo, VideoTrackInfo, MOVIE_TIMESCALE};
+use crate::audio::AudioTrackKind;
+use crate::config::AudioCodec;
+use crate::h264::ParameterSets;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+/// Location and timing of one sample in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleEntry {
+    pub offset: u64,
+    pub size: u32,
+    /// Decode time in track ticks, from the start of the media.
+    pub ticks: u64,
+    pub duration: u32,
+    pub keyframe: bool,
+    /// Index into the track's `sample_descriptions`.
+    pub description: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub id: u32,
+    /// The first sample description; most files have only this one.
+    pub info: TrackInfo,
+    /// Every sample description, `info` first. Files whose encoder changed
+    /// mid-way (e.g. edge-re-encoded trims) have several.
+    pub sample_descriptions: Vec<TrackInfo>,
+    pub timescale: u32,
+    pub name: String,
+    pub samples: Vec<SampleEntry>,
+    /// Presentation offset of the first sample (leading empty edit).
+    pub start_offset: Duration,
+    /// Whether samples are presented out of decode order (B-frames, `ctts`).
+    /// Sample times here are decode times, so such tracks can't be cut as-is.
+    pub reordered: bool,
+}
+
+impl Track {
+    pub fn is_video(&self) -> bool {
+        matches!(self.info, TrackInfo::Video(_))
+    }
+
+    /// Presentation time of `sample` on the movie timeline.
+    pub fn sample_time(&self, sample: &SampleEntry) -> Duration {
+        let priming = match &self.info {
+            TrackInfo::Audio(a) => u64::from(a.priming_samples),
+            TrackInfo::Video(_) => 0,
+        };
+        self.start_offset + ticks_to_duration(sample.ticks.saturating_sub(priming), self.timescale)
+    }
+
+    pub fn duration(&self) -> Duration {
+        let ticks: u64 = self.samples.iter().map(|s| u64::from(s.duration)).sum();
+        ticks_to_duration(ticks, self.timescale)
+    }
+}
+
+pub(crate) fn ticks_to_duration(ticks: u64, timescale: u32) -> Duration {
+    let nanos = u128::from(ticks) * 1_000_000_000 / u128::from(timescale.max(1));
+    Duration::from_nanos(nanos as u64)
+}
+
+/// A parsed box: type plus the byte range of its payload.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BoxRef {
+    pub fourcc: [u8; 4],
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Iterates over the boxes inside `data[range]`.
+pub(crate) fn child_boxes(data: &[u8], start: usize, end: usize) -> Vec<BoxRef> {
+    let mut boxes = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let fourcc: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let (header, size) = match size {
+            0 => (8, end - pos),
+            1 if pos + 16 <= end => (16, u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap()) as usize),
+            _ => (8, size),
+        };
+        if size < header || pos + size > end {
+            break;
+        }
+        boxes.push(BoxRef { fourcc, start: pos + header, end: pos + size });
+        pos += size;
+    }
+    boxes
+}
+
+pub(crate) fn find<'a>(boxes: &'a [BoxRef], fourcc: &[u8; 4]) -> Option<&'a BoxRef> {
+    boxes.iter().find(|b| &b.fourcc == fourcc)
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .context("Truncated MP4 box")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.take(n).map(|_| ())
+    }
+}
+
+pub struct Mp4Reader {
+    file: File,
+    moov: Vec<u8>,
+    pub tracks: Vec<Track>,
+    pub duration: Duration,
+    /// Nero-style chapter list, if the file has one.
+    pub chapters: Vec<Chapter>,
+    /// iTunes-style metadata items from `udta/meta/ilst`.
+    pub tags: MetadataTags,
+}
+
+impl Mp4Reader {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let moov = read_top_level(&mut file, b"moov")?
+            .with_context(|| format!("{} has no moov box (unfinished recording?)", path.display()))?;
+
+        let top = child_boxes(&moov, 0, moov.len());
+        let moov_box = find(&top, b"moov").context("Missing moov")?;
+        let children = child_boxes(&moov, moov_box.start, moov_box.end);
+
+        let mut duration = Duration::ZERO;
+        if let Some(mvhd) = find(&children, b"mvhd") {
+            let mut c = Cursor::new(&moov, mvhd.start);
+            let version = c.u8()?;
+            c.skip(3)?;
+            let (timescale, ticks) = if version == 1 {
+                c.skip(16)?;
+                (c.u32()?, c.u64()?)
+            } else {
+                c.skip(8)?;
+                (c.u32()?, u64::from(c.u32()?))
+            };
+            duration = ticks_to_duration(ticks, timescale);
+        }
+
+        let mut tracks = Vec::new();
+        for trak in children.iter().filter(|b| &b.fourcc == b"trak") {
+            if let Some(track) = parse_trak(&moov, trak)? {
+                tracks.push(track);
+            }
+        }
+
+        let (chapters, tags) = match find(&children, b"udta") {
+            Some(udta) => (parse_chpl(&moov, udta), parse_tags(&moov, udta)),
+            None => (Vec::new(), MetadataTags::default()),
+        };
+
+        Ok(Self { file, moov, tracks, duration, chapters, tags })
+    }
+
+    /// Raw bytes of the moov box (header included).
+    pub fn moov(&self) -> &[u8] {
+        &self.moov
+    }
+
+    pub fn video_track(&self) -> Option<&Track> {
+        self.tracks.iter().find(|t| t.is_video())
+    }
+
+    pub fn read_sample(&mut self, sample: &SampleEntry) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; sample.size as usize];
+        self.file.seek(SeekFrom::Start(sample.offset))?;
+        self.file.read_exact(&mut buf).context("Sample lies outside the file")?;
+        Ok(buf)
+    }
+}
+
+/// Reads the complete top-level box `fourcc` (header included), if present.
+pub(crate) fn read_top_level(file: &mut File, fourcc: &[u8; 4]) -> Result<Option<Vec<u8>>> {
+    let Some((offset, size)) = locate_top_level(file, fourcc)? else {
+        return Ok(None);
+    };
+    let mut data = vec![0u8; size as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+/// Offset and size (header included) of the first top-level box `fourcc`.
+pub(crate) fn locate_top_level(file: &mut File, fourcc: &[u8; 4]) -> Result<Option<(u64, u64)>> {
+    let len = file.metadata()?.len();
+    let mut pos = 0u64;
+    while pos + 8 <= len {
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header[..8])?;
+        let mut size = u64::from(u32::from_be_bytes(header[0..4].try_into().unwrap()));
+        if size == 1 {
+            file.read_exact(&mut header[8..16])?;
+            size = u64::from_be_bytes(header[8..16].try_into().unwrap());
+        } else if size == 0 {
+            size = len - pos;
+        }
+        anyhow::ensure!(size >= 8 && pos + size <= len, "Corrupt MP4 box at offset {}", pos);
+
+        if &header[4..8] == fourcc {
+            return Ok(Some((pos, size)));
+        }
+        pos += size;
+    }
+    Ok(None)
+}
+
+fn parse_trak(data: &[u8], trak: &BoxRef) -> Result<Option<Track>> {
+    let children = child_boxes(data, trak.start, trak.end);
+
+    let tkhd = find(&children, b"tkhd").context("trak without tkhd")?;
+    let mut c = Cursor::new(data, tkhd.start);
+    let version = c.u8()?;
+    c.skip(3 + if version == 1 { 16 } else { 8 })?;
+    let id = c.u32()?;
+
+    let mdia = find(&children, b"mdia").context("trak without mdia")?;
+    let mdia_children = child_boxes(data, mdia.start, mdia.end);
+
+    let mdhd = find(&mdia_children, b"mdhd").context("mdia without mdhd")?;
+    let mut c = Cursor::new(data, mdhd.start);
+    let version = c.u8()?;
+    c.skip(3 + if version == 1 { 16 } else { 8 })?;
+    let timescale = c.u32()?;
+
+    let hdlr = find(&mdia_children, b"hdlr").context("mdia without hdlr")?;
+    let mut c = Cursor::new(data, hdlr.start + 8);
+    let handler: [u8; 4] = c.take(4)?.try_into().unwrap();
+    let name_bytes = data.get(hdlr.start + 24..hdlr.end).unwrap_or_default();
+    let name = String::from_utf8_lossy(name_bytes).trim_end_matches('\0').to_string();
+
+    let minf = find(&mdia_children, b"minf").context("mdia without minf")?;
+    let minf_children = child_boxes(data, minf.start, minf.end);
+    let stbl = find(&minf_children, b"stbl").context("minf without stbl")?;
+    let stbl_children = child_boxes(data, stbl.start, stbl.end);
+
+    let mut sample_descriptions = parse_stsd(data, &stbl_children, &handler, &name)?;
+    if sample_descriptions.is_empty() {
+        // Text/chapter and other tracks we don't mux ourselves
+        return Ok(None);
+    }
+
+    let (start_offset, media_time) = parse_edits(data, &children)?;
+    if let Some(media_time) = media_time {
+        for description in &mut sample_descriptions {
+            if let TrackInfo::Audio(a) = description {
+                a.priming_samples = media_time;
+            }
+        }
+    }
+
+    let samples = parse_samples(data, &stbl_children)?;
+    anyhow::ensure!(
+        samples.iter().all(|s| (s.description as usize) < sample_descriptions.len()),
+        "Track {} refers to a missing sample description",
+        id
+    );
+    let info = sample_descriptions[0].clone();
+    let reordered = find(&stbl_children, b"ctts").is_some();
+    Ok(Some(Track { id, info, sample_descriptions, timescale, name, samples, start_offset, reordered }))
+}
+
+/// Leading empty edit (as a duration) and the media start time of the first real edit.
+fn parse_edits(data: &[u8], trak_children: &[BoxRef]) -> Result<(Duration, Option<u32>)> {
+    let Some(edts) = find(trak_children, b"edts") else {
+        return Ok((Duration::ZERO, None));
+    };
+    let Some(elst) = find(&child_boxes(data, edts.start, edts.end), b"elst").copied() else {
+        return Ok((Duration::ZERO, None));
+    };
+
+    let mut c = Cursor::new(data, elst.start);
+    let version = c.u8()?;
+    c.skip(3)?;
+    let count = c.u32()?;
+    let mut offset = Duration::ZERO;
+    let mut media_time = None;
+    for _ in 0..count {
+        let (segment, time) = if version == 1 {
+            (c.u64()?, c.u64()? as i64)
+        } else {
+            (u64::from(c.u32()?), i64::from(c.u32()? as i32))
+        };
+        c.skip(4)?;
+        if time == -1 {
+            offset += ticks_to_duration(segment, MOVIE_TIMESCALE);
+        } else if media_time.is_none() {
+            media_time = Some(time.max(0) as u32);
+        }
+    }
+    Ok((offset, media_time))
+}
+
+/// All sample descriptions of a track, or none if any is a format we don't handle.
+fn parse_stsd(data: &[u8], stbl: &[BoxRef], handler: &[u8; 4], name: &str) -> Result<Vec<TrackInfo>> {
+    let stsd = find(stbl, b"stsd").context("stbl without stsd")?;
+    let mut descriptions = Vec::new();
+    for entry in child_boxes(data, stsd.start + 8, stsd.end) {
+        match parse_sample_entry(data, &entry, handler, name)? {
+            Some(info) => descriptions.push(info),
+            None => return Ok(Vec::new()),
+        }
+    }
+    Ok(descriptions)
+}
+
+fn parse_sample_entry(data: &[u8], entry: &BoxRef, handler: &[u8; 4], name: &str) -> Result<Option<TrackInfo>> {
+    match (handler, &entry.fourcc) {
+        (b"vide", b"avc1") | (b"vide", b"avc3") => {
+            let mut c = Cursor::new(data, entry.start + 24);
+            let width = u32::from(c.u16()?);
+            let height = u32::from(c.u16()?);
+            let children = child_boxes(data, entry.start + 78, entry.end);
+            let avcc = find(&children, b"avcC").context("avc1 without avcC")?;
+
+            let mut c = Cursor::new(data, avcc.start + 5);
+            let sps_count = c.u8()? & 0x1f;
+            anyhow::ensure!(sps_count >= 1, "avcC without SPS");
+            let len = c.u16()? as usize;
+            let sps = c.take(len)?.to_vec();
+            for _ in 1..sps_count {
+                let len = c.u16()? as usize;
+                c.skip(len)?;
+            }
+            let pps_count = c.u8()?;
+            anyhow::ensure!(pps_count >= 1, "avcC without PPS");
+            let len = c.u16()? as usize;
+            let pps = c.take(len)?.to_vec();
+
+            Ok(Some(TrackInfo::Video(VideoTrackInfo {
+                width,
+                height,
+                parameter_sets: ParameterSets { sps, pps },
+            })))
+        }
+        (b"soun", b"mp4a") | (b"soun", b"Opus") => {
+            let mut c = Cursor::new(data, entry.start + 16);
+            let channels = c.u16()?;
+            c.skip(6)?;
+            let sample_rate = c.u32()? >> 16;
+            let children = child_boxes(data, entry.start + 28, entry.end);
+
+            let (codec, decoder_config) = if &entry.fourcc == b"Opus" {
+                let dops = find(&children, b"dOps").context("Opus entry without dOps")?;
+                (AudioCodec::Opus, data[dops.start..dops.end].to_vec())
+            } else {
+                let esds = find(&children, b"esds").context("mp4a without esds")?;
+                (AudioCodec::Aac, parse_esds(&data[esds.start + 4..esds.end]).unwrap_or_default())
+            };
+
+            let kind = if name == AudioTrackKind::Microphone.label() {
+                AudioTrackKind::Microphone
+            } else {
+                AudioTrackKind::Game
+            };
+            Ok(Some(TrackInfo::Audio(AudioTrackInfo {
+                kind,
+                codec,
+                sample_rate,
+                channels,
+                decoder_config,
+                priming_samples: 0,
+            })))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Pulls the DecoderSpecificInfo (AudioSpecificConfig) out of an ES descriptor.
+fn parse_esds(mut data: &[u8]) -> Option<Vec<u8>> {
+    fn descriptor(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        let tag = *data.first()?;
+        let mut len = 0usize;
+        let mut i = 1;
+        loop {
+            let b = *data.get(i)?;
+            len = (len << 7) | usize::from(b & 0x7f);
+            i += 1;
+            if b & 0x80 == 0 || i > 4 {
+                break;
+            }
+        }
+        let body = data.get(i..i + len)?;
+        Some((tag, body, &data[i + len..]))
+    }
+
+    while !data.is_empty() {
+        let (tag, body, rest) = descriptor(data)?;
+        match tag {
+            0x03 => {
+                let flags = *body.get(2)?;
+                let mut skip = 3;
+                if flags & 0x80 != 0 {
+                    skip += 2;
+                }
+                if flags & 0x40 != 0 {
+                    skip += 1 + usize::from(*body.get(skip)?);
+                }
+                if flags & 0x20 != 0 {
+                    skip += 2;
+                }
+                data = body.get(skip..)?;
+            }
+            0x04 => data = body.get(13..)?,
+            0x05 => return Some(body.to_vec()),
+            _ => data = rest,
+        }
+    }
+    None
+}
+
+fn parse_samples(data: &[u8], stbl: &[BoxRef]) -> Result<Vec<SampleEntry>> {
+    // Sample sizes
+    let stsz = find(stbl, b"stsz").context("stbl without stsz")?;
+    let mut c = Cursor::new(data, stsz.start + 4);
+    let fixed = c.u32()?;
+    let count = c.u32()? as usize;
+    let sizes: Vec<u32> = if fixed != 0 {
+        vec![fixed; count]
+    } else {
+        (0..count).map(|_| c.u32()).collect::<Result<_>>()?
+    };
+
+    // Durations
+    let mut durations = Vec::with_capacity(count);
+    if let Some(stts) = find(stbl, b"stts") {
+        let mut c = Cursor::new(data, stts.start + 4);
+        for _ in 0..c.u32()? {
+            let (n, delta) = (c.u32()?, c.u32()?);
+            durations.extend(std::iter::repeat_n(delta, n as usize));
+        }
+    }
+    durations.resize(count, 0);
+
+    // Sync samples; without stss every sample is a sync sample
+    let mut keyframes = vec![true; count];
+    if let Some(stss) = find(stbl, b"stss") {
+        keyframes = vec![false; count];
+        let mut c = Cursor::new(data, stss.start + 4);
+        for _ in 0..c.u32()? {
+            let index = c.u32()? as usize;
+            if let Some(k) = index.checked_sub(1).and_then(|i| keyframes.get_mut(i)) {
+                *k = true;
+            }
+        }
+    }
+
+    // Chunk offsets
+    let chunk_offsets: Vec<u64> = if let Some(stco) = find(stbl, b"stco") {
+        let mut c = Cursor::new(data, stco.start + 4);
+        (0..c.u32()?).map(|_| c.u32().map(u64::from)).collect::<Result<_>>()?
+    } else {
+        let co64 = find(stbl, b"co64").context("stbl without chunk offsets")?;
+        let mut c = Cursor::new(data, co64.start + 4);
+        (0..c.u32()?).map(|_| c.u64()).collect::<Result<_>>()?
+    };
+
+    // Sample-to-chunk runs: (first_chunk, samples_per_chunk, description)
+    let stsc = find(stbl, b"stsc").context("stbl without stsc")?;
+    let mut c = Cursor::new(data, stsc.start + 4);
+    let runs: Vec<(u32, u32, u32)> = (0..c.u32()?)
+        .map(|_| Ok((c.u32()?, c.u32()?, c.u32()?)))
+        .collect::<Result<_>>()?;
+
+    let mut samples = Vec::with_capacity(count);
+    let mut ticks = 0u64;
+    let mut index = 0usize;
+    for (chunk, &chunk_offset) in chunk_offsets.iter().enumerate() {
+        let chunk_number = chunk as u32 + 1;
+        let (per_chunk, description) = runs
+            .iter()
+            .rev()
+            .find(|(first, _, _)| *first <= chunk_number)
+            .map_or((0, 1), |r| (r.1, r.2));
+        let mut offset = chunk_offset;
+        for _ in 0..per_chunk {
+            if index >= count {
+                break;
+            }
+            samples.push(SampleEntry {
+                offset,
+                size: sizes[index],
+                ticks,
+                duration: durations[index],
+                keyframe: keyframes[index],
+                description: description.saturating_sub(1),
+            });
+            offset += u64::from(sizes[index]);
+            ticks += u64::from(durations[index]);
+            index += 1;
+        }
+    }
+    anyhow::ensure!(samples.len() == count, "Sample tables disagree ({} of {} samples)", samples.len(), count);
+    Ok(samples)
+}
@@ -0,0 +1,252 @@
+// ABOUTME: iTunes-style metadata (udta/meta/ilst) for finished MP4s: title, date, encoder, comment
+// ABOUTME: TFT-specific fields (patch, rank, placement) go into freeform `----` items
+
+use super::boxes::BoxBuilder;
+use super::edit::{Movie, MovieFile};
+use super::reader::{child_boxes, find, BoxRef};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Namespace (`mean`) of the freeform items this recorder writes.
+pub const FREEFORM_NAMESPACE: &str = "com.tft-recorder";
+
+/// `data` atom type for UTF-8 text.
+const DATA_TYPE_UTF8: u32 = 1;
+
+/// The metadata items this recorder reads and writes. Items of other
+/// applications are dropped when the list is rewritten.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataTags {
+    /// `©nam`
+    pub title: Option<String>,
+    /// `©day`, ISO 8601.
+    pub date: Option<String>,
+    /// `©too`
+    pub encoder: Option<String>,
+    /// `©cmt`
+    pub comment: Option<String>,
+    /// Game patch, e.g. "14.20".
+    pub patch: Option<String>,
+    /// Ranked tier at the start of the game, e.g. "Diamond II".
+    pub rank: Option<String>,
+    /// Final placement, 1 to 8.
+    pub placement: Option<u8>,
+}
+
+impl MetadataTags {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Standard items as (atom, value), in the order they are written.
+    fn standard(&self) -> [(&'static [u8; 4], Option<&str>); 4] {
+        [
+            (b"\xa9nam", self.title.as_deref()),
+            (b"\xa9day", self.date.as_deref()),
+            (b"\xa9too", self.encoder.as_deref()),
+            (b"\xa9cmt", self.comment.as_deref()),
+        ]
+    }
+
+    /// Freeform items as (name, value).
+    fn freeform(&self) -> [(&'static str, Option<String>); 3] {
+        [
+            ("patch", self.patch.clone()),
+            ("rank", self.rank.clone()),
+            ("placement", self.placement.map(|p| p.to_string())),
+        ]
+    }
+
+    fn set(&mut self, atom: &[u8; 4], value: String) {
+        match atom {
+            b"\xa9nam" => self.title = Some(value),
+            b"\xa9day" => self.date = Some(value),
+            b"\xa9too" => self.encoder = Some(value),
+            b"\xa9cmt" => self.comment = Some(value),
+            _ => {}
+        }
+    }
+
+    fn set_freeform(&mut self, name: &str, value: String) {
+        match name {
+            "patch" => self.patch = Some(value),
+            "rank" => self.rank = Some(value),
+            "placement" => self.placement = value.trim().parse().ok(),
+            _ => {}
+        }
+    }
+}
+
+/// Replaces the metadata of the MP4 at `path` with `tags`. Only the moov is
+/// rewritten; sample data stays where it is.
+pub fn write_tags(path: impl AsRef<Path>, tags: &MetadataTags) -> Result<()> {
+    let (file, data) = MovieFile::open(path.as_ref())?;
+    let movie = Movie::parse(&data)?;
+
+    let mut b = BoxBuilder::new();
+    b.begin(b"moov");
+    let mut has_udta = false;
+    for (child, raw) in movie.raw_children() {
+        if &child.fourcc == b"udta" {
+            has_udta = true;
+            write_udta(&mut b, &data, Some(child), tags);
+        } else {
+            b.bytes(raw);
+        }
+    }
+    if !has_udta {
+        write_udta(&mut b, &data, None, tags);
+    }
+    b.end();
+    file.commit(&[], &b.finish())
+}
+
+/// Copies `udta` minus its old `meta`, then appends the new one.
+fn write_udta(b: &mut BoxBuilder, data: &[u8], udta: Option<&BoxRef>, tags: &MetadataTags) {
+    b.begin(b"udta");
+    if let Some(udta) = udta {
+        let mut raw_start = udta.start;
+        for child in child_boxes(data, udta.start, udta.end) {
+            if &child.fourcc != b"meta" {
+                b.bytes(&data[raw_start..child.end]);
+            }
+            raw_start = child.end;
+        }
+    }
+    if !tags.is_empty() {
+        write_meta(b, tags);
+    }
+    b.end();
+}
+
+fn write_meta(b: &mut BoxBuilder, tags: &MetadataTags) {
+    b.begin_full(b"meta", 0, 0);
+    b.begin_full(b"hdlr", 0, 0)
+        .u32(0)
+        .bytes(b"mdir")
+        .bytes(b"appl")
+        .zeros(8)
+        .u8(0)
+        .end();
+    b.begin(b"ilst");
+    for (atom, value) in tags.standard() {
+        if let Some(value) = value {
+            b.begin(atom);
+            write_data(b, value);
+            b.end();
+        }
+    }
+    for (name, value) in tags.freeform() {
+        if let Some(value) = value {
+            b.begin(b"----");
+            b.begin_full(b"mean", 0, 0).bytes(FREEFORM_NAMESPACE.as_bytes()).end();
+            b.begin_full(b"name", 0, 0).bytes(name.as_bytes()).end();
+            write_data(b, &value);
+            b.end();
+        }
+    }
+    b.end(); // ilst
+    b.end(); // meta
+}
+
+fn write_data(b: &mut BoxBuilder, value: &str) {
+    b.begin(b"data").u32(DATA_TYPE_UTF8).u32(0).bytes(value.as_bytes()).end();
+}
+
+/// Reads the metadata items from `udta`, if any.
+pub(crate) fn parse_tags(data: &[u8], udta: &BoxRef) -> MetadataTags {
+    let mut tags = MetadataTags::default();
+    let children = child_boxes(data, udta.start, udta.end);
+    let Some(meta) = find(&children, b"meta") else {
+        return tags;
+    };
+    // ISO files give meta a version/flags word; QuickTime-style ones don't
+    let start = if data.get(meta.start + 4..meta.start + 8) == Some(b"hdlr") { meta.start } else { meta.start + 4 };
+    let Some(ilst) = find(&child_boxes(data, start.min(meta.end), meta.end), b"ilst").copied() else {
+        return tags;
+    };
+
+    for item in child_boxes(data, ilst.start, ilst.end) {
+        let parts = child_boxes(data, item.start, item.end);
+        let Some(value) = find(&parts, b"data").and_then(|d| text_value(data, d)) else {
+            continue;
+        };
+        if &item.fourcc == b"----" {
+            let field = |fourcc: &[u8; 4]| {
+                find(&parts, fourcc)
+                    .and_then(|b| data.get(b.start + 4..b.end))
+                    .map(|s| String::from_utf8_lossy(s).into_owned())
+            };
+            if field(b"mean").as_deref() == Some(FREEFORM_NAMESPACE) {
+                if let Some(name) = field(b"name") {
+                    tags.set_freeform(&name, value);
+                }
+            }
+        } else {
+            tags.set(&item.fourcc, value);
+        }
+    }
+    tags
+}
+
+fn text_value(data: &[u8], atom: &BoxRef) -> Option<String> {
+    let payload = data.get(atom.start..atom.end)?;
+    let kind = u32::from_be_bytes(payload.get(..4)?.try_into().ok()?) & 0x00ff_ffff;
+    (kind == DATA_TYPE_UTF8).then(|| String::from_utf8_lossy(&payload[8.min(payload.len())..]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4::{write_chapters, Chapter, Mp4Reader};
+    use crate::test_support::{temp_file, write_fake_video};
+    use std::time::Duration;
+
+    fn tags() -> MetadataTags {
+        MetadataTags {
+            title: Some("Ranked – Jinx reroll".into()),
+            date: Some("2026-10-19T21:03:00+02:00".into()),
+            encoder: Some("TFT Recorder 0.1.1".into()),
+            comment: None,
+            patch: Some("14.20".into()),
+            rank: Some("Diamond II".into()),
+            placement: Some(2),
+        }
+    }
+
+    #[test]
+    fn test_tags_round_trip_and_replace() {
+        let path = temp_file("tags");
+        write_fake_video(&path, 10);
+        let before = Mp4Reader::open(&path).unwrap();
+        assert!(before.tags.is_empty());
+
+        write_chapters(&path, &[Chapter { start: Duration::ZERO, title: "Stage 1-1".into() }]).unwrap();
+        write_tags(&path, &tags()).unwrap();
+        let mut after = Mp4Reader::open(&path).unwrap();
+        assert_eq!(after.tags, tags());
+        // Chapters in the same udta survive, samples don't move
+        assert_eq!(after.chapters.len(), 1);
+        let samples = after.video_track().unwrap().samples.clone();
+        assert_eq!(samples, before.video_track().unwrap().samples);
+        assert_eq!(after.read_sample(&samples[3]).unwrap(), vec![3u8; 50]);
+
+        let updated = MetadataTags { placement: Some(1), comment: Some("GG".into()), ..tags() };
+        write_tags(&path, &updated).unwrap();
+        assert_eq!(Mp4Reader::open(&path).unwrap().tags, updated);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_freeform_item_layout() {
+        let mut b = BoxBuilder::new();
+        write_meta(&mut b, &MetadataTags { placement: Some(4), ..Default::default() });
+        let meta = b.finish();
+        let expected_name = [&[0u8, 0, 0, 21][..], b"name", &[0, 0, 0, 0], b"placement"].concat();
+        assert!(meta.windows(expected_name.len()).any(|w| w == expected_name));
+        let expected_data = [&[0u8, 0, 0, 17][..], b"data", &[0, 0, 0, 1, 0, 0, 0, 0], b"4"].concat();
+        assert!(meta.windows(expected_data.len()).any(|w| w == expected_data));
+    }
+}
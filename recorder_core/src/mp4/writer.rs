@@ -0,0 +1,604 @@
+// ABOUTME: Streaming MP4 muxer for H.264 video and AAC/Opus audio tracks
+// ABOUTME: Sample data goes straight to mdat; sample tables are written into moov on finish
+
+use super::boxes::BoxBuilder;
+use super::{to_ticks, AudioTrackInfo, Sample, TrackInfo, VideoTrackInfo, MOVIE_TIMESCALE};
+use crate::config::AudioCodec;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds between the MP4 epoch (1904-01-01) and the Unix epoch.
+const MP4_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+struct SampleRecord {
+    offset: u64,
+    size: u32,
+    ticks: u64,
+    keyframe: bool,
+    /// Index into `TrackState::entries`.
+    entry: u32,
+}
+
+struct TrackState {
+    /// Sample descriptions; the first is the one the track was added with.
+    entries: Vec<TrackInfo>,
+    /// Description of the samples written next.
+    current: u32,
+    samples: Vec<SampleRecord>,
+    last_duration: Option<u64>,
+}
+
+impl TrackState {
+    fn info(&self) -> &TrackInfo {
+        &self.entries[0]
+    }
+
+    fn timescale(&self) -> u32 {
+        self.info().timescale()
+    }
+
+    /// Per-sample durations in track ticks.
+    fn durations(&self) -> Vec<u32> {
+        let mut out: Vec<u32> = self
+            .samples
+            .windows(2)
+            .map(|w| (w[1].ticks - w[0].ticks) as u32)
+            .collect();
+        if !self.samples.is_empty() {
+            let last = self
+                .last_duration
+                .or_else(|| out.last().map(|&d| u64::from(d)))
+                .unwrap_or(1);
+            out.push(last as u32);
+        }
+        out
+    }
+
+    fn first_ticks(&self) -> u64 {
+        self.samples.first().map_or(0, |s| s.ticks)
+    }
+
+    fn priming(&self) -> u64 {
+        match self.info() {
+            TrackInfo::Audio(a) => u64::from(a.priming_samples),
+            TrackInfo::Video(_) => 0,
+        }
+    }
+}
+
+pub struct Mp4Writer<W: Write + Seek> {
+    out: W,
+    tracks: Vec<TrackState>,
+    mdat_start: u64,
+    position: u64,
+    creation_time: u64,
+}
+
+impl Mp4Writer<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        Self::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write + Seek> Mp4Writer<W> {
+    pub fn new(mut out: W) -> Result<Self> {
+        let mut ftyp = BoxBuilder::new();
+        ftyp.begin(b"ftyp").bytes(b"isom").u32(0x200);
+        for brand in [b"isom", b"iso2", b"avc1", b"mp41"] {
+            ftyp.bytes(brand);
+        }
+        ftyp.end();
+        let ftyp = ftyp.finish();
+        out.write_all(&ftyp)?;
+
+        // 64-bit mdat header so recordings can pass 4 GiB; size patched on finish
+        let mdat_start = ftyp.len() as u64;
+        out.write_all(&1u32.to_be_bytes())?;
+        out.write_all(b"mdat")?;
+        out.write_all(&0u64.to_be_bytes())?;
+
+        let creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+            + MP4_EPOCH_OFFSET;
+
+        Ok(Self {
+            out,
+            tracks: Vec::new(),
+            mdat_start,
+            position: mdat_start + 16,
+            creation_time,
+        })
+    }
+
+    /// Registers a track and returns its index for `write_sample`.
+    pub fn add_track(&mut self, info: TrackInfo) -> usize {
+        self.tracks.push(TrackState { entries: vec![info], current: 0, samples: Vec::new(), last_duration: None });
+        self.tracks.len() - 1
+    }
+
+    /// Describes the samples written to `track` from now on with `info`,
+    /// e.g. after switching between encoders with different parameter sets.
+    /// The codec and timescale must stay the same.
+    pub fn switch_sample_entry(&mut self, track: usize, info: TrackInfo) -> Result<()> {
+        let state = self
+            .tracks
+            .get_mut(track)
+            .with_context(|| format!("No track {}", track))?;
+        let compatible = match (state.info(), &info) {
+            (TrackInfo::Video(_), TrackInfo::Video(_)) => true,
+            (TrackInfo::Audio(a), TrackInfo::Audio(b)) => a.codec == b.codec && a.sample_rate == b.sample_rate,
+            _ => false,
+        };
+        anyhow::ensure!(compatible, "Track {} can't switch to an incompatible sample description", track);
+
+        state.current = match state.entries.iter().position(|e| *e == info) {
+            Some(index) => index as u32,
+            None => {
+                state.entries.push(info);
+                state.entries.len() as u32 - 1
+            }
+        };
+        Ok(())
+    }
+
+    pub fn sample_count(&self, track: usize) -> usize {
+        self.tracks.get(track).map_or(0, |t| t.samples.len())
+    }
+
+    /// Appends one sample; timestamps within a track must increase.
+    pub fn write_sample(&mut self, track: usize, sample: Sample) -> Result<()> {
+        let state = self
+            .tracks
+            .get_mut(track)
+            .with_context(|| format!("No track {}", track))?;
+        let timescale = state.timescale();
+        let ticks = to_ticks(sample.pts, timescale);
+        if let Some(prev) = state.samples.last() {
+            anyhow::ensure!(
+                ticks > prev.ticks,
+                "Track {} timestamps must increase ({:?} after tick {})",
+                track,
+                sample.pts,
+                prev.ticks
+            );
+        }
+
+        self.out.write_all(sample.data)?;
+        state.samples.push(SampleRecord {
+            offset: self.position,
+            size: sample.data.len() as u32,
+            ticks,
+            keyframe: sample.keyframe,
+            entry: state.current,
+        });
+        state.last_duration = sample.duration.map(|d| to_ticks(d, timescale).max(1));
+        self.position += sample.data.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the moov box and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let moov = self.build_moov();
+        self.out.write_all(&moov)?;
+
+        let mdat_size = self.position - self.mdat_start;
+        self.out.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        self.out.write_all(&mdat_size.to_be_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Presentation length of the file so far.
+    pub fn duration(&self) -> Duration {
+        let ms = self.tracks.iter().map(|t| self.presented_duration(t)).max().unwrap_or(0);
+        Duration::from_millis(ms)
+    }
+
+    // Track length in movie ticks, including any leading empty edit.
+    fn presented_duration(&self, track: &TrackState) -> u64 {
+        let media: u64 = track.durations().iter().map(|&d| u64::from(d)).sum();
+        let end = track.first_ticks() + media.saturating_sub(track.priming());
+        end * u64::from(MOVIE_TIMESCALE) / u64::from(track.timescale())
+    }
+
+    fn build_moov(&self) -> Vec<u8> {
+        let mut b = BoxBuilder::new();
+        let duration = self.duration().as_millis() as u32;
+
+        b.begin(b"moov");
+        b.begin_full(b"mvhd", 0, 0)
+            .u32(self.creation_time as u32)
+            .u32(self.creation_time as u32)
+            .u32(MOVIE_TIMESCALE)
+            .u32(duration)
+            .u32(0x0001_0000) // rate 1.0
+            .u16(0x0100) // volume 1.0
+            .zeros(10)
+            .matrix()
+            .zeros(24)
+            .u32(self.tracks.len() as u32 + 1)
+            .end();
+
+        for (index, track) in self.tracks.iter().enumerate() {
+            self.write_trak(&mut b, index as u32 + 1, track);
+        }
+        b.end();
+        b.finish()
+    }
+
+    fn write_trak(&self, b: &mut BoxBuilder, id: u32, track: &TrackState) {
+        let time = self.creation_time as u32;
+        let presented = self.presented_duration(track) as u32;
+        let durations = track.durations();
+        let media_duration: u64 = durations.iter().map(|&d| u64::from(d)).sum();
+        let (is_audio, width, height) = match track.info() {
+            TrackInfo::Video(v) => (false, v.width, v.height),
+            TrackInfo::Audio(_) => (true, 0, 0),
+        };
+
+        b.begin(b"trak");
+        b.begin_full(b"tkhd", 0, 0x3) // enabled | in movie
+            .u32(time)
+            .u32(time)
+            .u32(id)
+            .u32(0)
+            .u32(presented)
+            .zeros(8)
+            .u16(0) // layer
+            .u16(u16::from(is_audio)) // alternate group
+            .u16(if is_audio { 0x0100 } else { 0 })
+            .u16(0)
+            .matrix()
+            .u32(width << 16)
+            .u32(height << 16)
+            .end();
+
+        self.write_edits(b, track, media_duration);
+
+        b.begin(b"mdia");
+        b.begin_full(b"mdhd", 0, 0)
+            .u32(time)
+            .u32(time)
+            .u32(track.timescale())
+            .u32(media_duration as u32)
+            .u16(0x55c4) // "und"
+            .u16(0)
+            .end();
+        let (handler, name) = match track.info() {
+            TrackInfo::Video(_) => (b"vide", "Video"),
+            TrackInfo::Audio(a) => (b"soun", a.kind.label()),
+        };
+        b.begin_full(b"hdlr", 0, 0)
+            .u32(0)
+            .bytes(handler)
+            .zeros(12)
+            .bytes(name.as_bytes())
+            .u8(0)
+            .end();
+
+        b.begin(b"minf");
+        if is_audio {
+            b.begin_full(b"smhd", 0, 0).u16(0).u16(0).end();
+        } else {
+            b.begin_full(b"vmhd", 0, 1).zeros(8).end();
+        }
+        b.begin(b"dinf")
+            .begin_full(b"dref", 0, 0)
+            .u32(1)
+            .begin_full(b"url ", 0, 1)
+            .end()
+            .end()
+            .end();
+        self.write_stbl(b, track, &durations);
+        b.end(); // minf
+        b.end(); // mdia
+        b.end(); // trak
+    }
+
+    fn write_edits(&self, b: &mut BoxBuilder, track: &TrackState, media_duration: u64) {
+        let to_movie = |ticks: u64| ticks * u64::from(MOVIE_TIMESCALE) / u64::from(track.timescale());
+        let offset = track.first_ticks();
+        let priming = track.priming();
+        if offset == 0 && priming == 0 {
+            return;
+        }
+
+        let mut entries = Vec::new();
+        if offset > 0 {
+            entries.push((to_movie(offset), -1i32));
+        }
+        entries.push((to_movie(media_duration.saturating_sub(priming)), priming as i32));
+
+        b.begin(b"edts").begin_full(b"elst", 0, 0).u32(entries.len() as u32);
+        for (segment, media_time) in entries {
+            b.u32(segment as u32).u32(media_time as u32).u32(0x0001_0000);
+        }
+        b.end().end();
+    }
+
+    fn write_stbl(&self, b: &mut BoxBuilder, track: &TrackState, durations: &[u32]) {
+        b.begin(b"stbl");
+
+        b.begin_full(b"stsd", 0, 0).u32(track.entries.len() as u32);
+        for entry in &track.entries {
+            match entry {
+                TrackInfo::Video(v) => write_avc1(b, v),
+                TrackInfo::Audio(a) => write_audio_entry(b, a),
+            }
+        }
+        b.end();
+
+        // Run-length encoded sample durations
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for &d in durations {
+            match runs.last_mut() {
+                Some((count, delta)) if *delta == d => *count += 1,
+                _ => runs.push((1, d)),
+            }
+        }
+        b.begin_full(b"stts", 0, 0).u32(runs.len() as u32);
+        for (count, delta) in runs {
+            b.u32(count).u32(delta);
+        }
+        b.end();
+
+        if matches!(track.info(), TrackInfo::Video(_)) {
+            let sync: Vec<u32> = (1..)
+                .zip(&track.samples)
+                .filter(|(_, s)| s.keyframe)
+                .map(|(i, _)| i)
+                .collect();
+            b.begin_full(b"stss", 0, 0).u32(sync.len() as u32);
+            for i in sync {
+                b.u32(i);
+            }
+            b.end();
+        }
+
+        // One sample per chunk keeps the tables trivially correct for
+        // interleaved real-time writes. A new run starts wherever the sample
+        // description changes.
+        let mut chunk_runs: Vec<(u32, u32)> = Vec::new();
+        for (chunk, s) in (1..).zip(&track.samples) {
+            if chunk_runs.last().is_none_or(|&(_, entry)| entry != s.entry) {
+                chunk_runs.push((chunk, s.entry));
+            }
+        }
+        if chunk_runs.is_empty() {
+            chunk_runs.push((1, 0));
+        }
+        b.begin_full(b"stsc", 0, 0).u32(chunk_runs.len() as u32);
+        for (first_chunk, entry) in chunk_runs {
+            b.u32(first_chunk).u32(1).u32(entry + 1);
+        }
+        b.end();
+
+        b.begin_full(b"stsz", 0, 0).u32(0).u32(track.samples.len() as u32);
+        for s in &track.samples {
+            b.u32(s.size);
+        }
+        b.end();
+
+        let large = track.samples.last().is_some_and(|s| s.offset > u64::from(u32::MAX));
+        if large {
+            b.begin_full(b"co64", 0, 0).u32(track.samples.len() as u32);
+            for s in &track.samples {
+                b.u64(s.offset);
+            }
+        } else {
+            b.begin_full(b"stco", 0, 0).u32(track.samples.len() as u32);
+            for s in &track.samples {
+                b.u32(s.offset as u32);
+            }
+        }
+        b.end();
+
+        b.end(); // stbl
+    }
+}
+
+fn write_avc1(b: &mut BoxBuilder, v: &VideoTrackInfo) {
+    let sps = &v.parameter_sets.sps;
+    let pps = &v.parameter_sets.pps;
+
+    b.begin(b"avc1")
+        .zeros(6)
+        .u16(1) // data reference index
+        .zeros(16)
+        .u16(v.width as u16)
+        .u16(v.height as u16)
+        .u32(0x0048_0000) // 72 dpi
+        .u32(0x0048_0000)
+        .u32(0)
+        .u16(1) // frame count
+        .zeros(32) // compressor name
+        .u16(0x0018)
+        .u16(0xffff);
+
+    b.begin(b"avcC")
+        .u8(1)
+        .u8(sps.get(1).copied().unwrap_or(0x42))
+        .u8(sps.get(2).copied().unwrap_or(0))
+        .u8(sps.get(3).copied().unwrap_or(0x1f))
+        .u8(0xff) // 4-byte NAL lengths
+        .u8(0xe1) // one SPS
+        .u16(sps.len() as u16)
+        .bytes(sps)
+        .u8(1)
+        .u16(pps.len() as u16)
+        .bytes(pps)
+        .end();
+    b.end();
+}
+
+fn write_audio_entry(b: &mut BoxBuilder, a: &AudioTrackInfo) {
+    let fourcc = match a.codec {
+        AudioCodec::Aac => b"mp4a",
+        AudioCodec::Opus => b"Opus",
+    };
+    b.begin(fourcc)
+        .zeros(6)
+        .u16(1)
+        .zeros(8)
+        .u16(a.channels)
+        .u16(16)
+        .u32(0)
+        .u32(a.sample_rate << 16);
+
+    match a.codec {
+        AudioCodec::Aac => write_esds(b, &a.decoder_config),
+        AudioCodec::Opus => {
+            b.begin(b"dOps").bytes(&a.decoder_config).end();
+        }
+    }
+    b.end();
+}
+
+fn write_esds(b: &mut BoxBuilder, asc: &[u8]) {
+    let dsi_len = asc.len() as u8;
+    let dcd_len = 13 + 2 + dsi_len;
+    let es_len = 3 + 2 + dcd_len + 3;
+
+    b.begin_full(b"esds", 0, 0)
+        .u8(0x03)
+        .u8(es_len)
+        .u16(0) // ES_ID
+        .u8(0)
+        .u8(0x04)
+        .u8(dcd_len)
+        .u8(0x40) // MPEG-4 audio
+        .u8(0x15) // audio stream
+        .zeros(3) // buffer size
+        .u32(0) // max bitrate
+        .u32(0) // avg bitrate
+        .u8(0x05)
+        .u8(dsi_len)
+        .bytes(asc)
+        .u8(0x06)
+        .u8(1)
+        .u8(0x02)
+        .end();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioTrackKind;
+    use crate::h264::ParameterSets;
+    use crate::mp4::Mp4Reader;
+
+    fn video_info() -> TrackInfo {
+        TrackInfo::Video(VideoTrackInfo {
+            width: 64,
+            height: 48,
+            parameter_sets: ParameterSets { sps: vec![0x67, 0x42, 0xc0, 0x1e, 1], pps: vec![0x68, 2] },
+        })
+    }
+
+    fn audio_info() -> TrackInfo {
+        TrackInfo::Audio(AudioTrackInfo {
+            kind: AudioTrackKind::Microphone,
+            codec: AudioCodec::Aac,
+            sample_rate: 48_000,
+            channels: 2,
+            decoder_config: vec![0x11, 0x90],
+            priming_samples: 2048,
+        })
+    }
+
+    #[test]
+    fn test_round_trip_through_reader() {
+        let path = std::env::temp_dir().join(format!("mp4_writer_{}.mp4", std::process::id()));
+        let mut writer = Mp4Writer::create(&path).unwrap();
+        let video = writer.add_track(video_info());
+        let audio = writer.add_track(audio_info());
+
+        for i in 0..30u64 {
+            let data = vec![i as u8; 100 + i as usize];
+            let sample = Sample {
+                pts: Duration::from_nanos(i * 1_000_000_000 / 30),
+                duration: Some(Duration::from_nanos(1_000_000_000 / 30)),
+                keyframe: i % 10 == 0,
+                data: &data,
+            };
+            writer.write_sample(video, sample).unwrap();
+        }
+        for i in 0..50u64 {
+            let sample = Sample {
+                pts: Duration::from_millis(500) + crate::audio::frames_to_duration(i * 1024, 48_000),
+                duration: Some(crate::audio::frames_to_duration(1024, 48_000)),
+                keyframe: true,
+                data: &[0xaa; 12],
+            };
+            writer.write_sample(audio, sample).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = Mp4Reader::open(&path).unwrap();
+        assert_eq!(reader.tracks.len(), 2);
+        assert_eq!(reader.duration, Duration::from_millis(1524));
+
+        let v = reader.tracks[0].clone();
+        assert_eq!(v.info, video_info());
+        assert_eq!(v.samples.len(), 30);
+        assert_eq!(v.samples.iter().filter(|s| s.keyframe).count(), 3);
+        assert_eq!(v.sample_time(&v.samples[15]), Duration::from_millis(500));
+        assert_eq!(reader.read_sample(&v.samples[5]).unwrap(), vec![5u8; 105]);
+
+        let a = &reader.tracks[1];
+        assert_eq!(a.info, audio_info());
+        assert_eq!(a.name, "Microphone");
+        assert_eq!(a.start_offset, Duration::from_millis(500));
+        assert_eq!(a.sample_time(&a.samples[2]), Duration::from_millis(500));
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_switching_sample_descriptions() {
+        let path = std::env::temp_dir().join(format!("mp4_writer_entries_{}.mp4", std::process::id()));
+        let other = TrackInfo::Video(VideoTrackInfo {
+            width: 64,
+            height: 48,
+            parameter_sets: ParameterSets { sps: vec![0x67, 0x64, 0, 0x1f], pps: vec![0x68, 3] },
+        });
+        let mut writer = Mp4Writer::create(&path).unwrap();
+        let video = writer.add_track(video_info());
+        for i in 0..9u64 {
+            // Entries 0, 0, 0, 1, 1, 1, 0, 0, 0
+            match i {
+                3 => writer.switch_sample_entry(video, other.clone()).unwrap(),
+                6 => writer.switch_sample_entry(video, video_info()).unwrap(),
+                _ => {}
+            }
+            let sample = Sample { pts: Duration::from_millis(i * 40), duration: None, keyframe: i % 3 == 0, data: &[i as u8] };
+            writer.write_sample(video, sample).unwrap();
+        }
+        assert!(writer.switch_sample_entry(video, audio_info()).is_err());
+        writer.finish().unwrap();
+
+        let reader = Mp4Reader::open(&path).unwrap();
+        let track = &reader.tracks[0];
+        assert_eq!(track.sample_descriptions, [video_info(), other]);
+        let entries: Vec<u32> = track.samples.iter().map(|s| s.description).collect();
+        assert_eq!(entries, [0, 0, 0, 1, 1, 1, 0, 0, 0]);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_non_increasing_timestamps_are_rejected() {
+        let mut writer = Mp4Writer::new(std::io::Cursor::new(Vec::new())).unwrap();
+        let track = writer.add_track(video_info());
+        let sample = |ms| Sample { pts: Duration::from_millis(ms), duration: None, keyframe: true, data: &[1] };
+
+        writer.write_sample(track, sample(40)).unwrap();
+        assert!(writer.write_sample(track, sample(40)).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+// ABOUTME: Output file naming: path templates with {date}, {window}, {patch}, {seq}... and per-profile directory layouts
+// ABOUTME: Substituted values are sanitized for file systems and taken names get a free sequence number
+
+use crate::config::RecordingConfig;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// File name template of the default profile, e.g. `TFT-2024-09-01-201500.mp4`.
+pub const DEFAULT_FILE_TEMPLATE: &str = "TFT-{date}-{time}.mp4";
+
+/// Placeholders a template may use.
+pub const PLACEHOLDERS: &[&str] = &["date", "time", "year", "month", "day", "profile", "window", "patch", "placement", "seq"];
+
+/// Stand-in for values that aren't known (yet), such as the placement.
+const UNKNOWN: &str = "unknown";
+
+/// Highest `{seq}` tried before giving up on a free name.
+const MAX_SEQ: u32 = 9999;
+
+/// A built-in naming profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfilePreset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub directory: &'static str,
+    pub file_name: &'static str,
+}
+
+pub const PROFILES: &[ProfilePreset] = &[
+    ProfilePreset { name: "default", description: "Every recording directly in the recordings directory", directory: "", file_name: DEFAULT_FILE_TEMPLATE },
+    ProfilePreset { name: "monthly", description: "A folder per month", directory: "{year}/{month}", file_name: DEFAULT_FILE_TEMPLATE },
+    ProfilePreset { name: "daily", description: "A folder per day", directory: "{year}/{month}/{day}", file_name: "TFT-{time}.mp4" },
+    ProfilePreset { name: "patch", description: "A folder per game patch", directory: "{patch}", file_name: DEFAULT_FILE_TEMPLATE },
+];
+
+/// Where recordings go below the recordings directory and what they're called.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NamingProfile {
+    /// Filled in for `{profile}`.
+    pub name: String,
+    /// Directory template relative to the recordings directory; empty for none.
+    pub directory: String,
+    pub file_name: String,
+}
+
+impl Default for NamingProfile {
+    fn default() -> Self {
+        Self::from(PROFILES[0])
+    }
+}
+
+impl From<ProfilePreset> for NamingProfile {
+    fn from(preset: ProfilePreset) -> Self {
+        Self { name: preset.name.to_string(), directory: preset.directory.to_string(), file_name: preset.file_name.to_string() }
+    }
+}
+
+impl NamingProfile {
+    pub fn named(name: &str) -> Result<Self> {
+        let preset = PROFILES.iter().copied().find(|p| p.name == name).with_context(|| {
+            let names: Vec<&str> = PROFILES.iter().map(|p| p.name).collect();
+            format!("Unknown naming profile '{}' (expected one of: {})", name, names.join(", "))
+        })?;
+        Ok(preset.into())
+    }
+
+    pub fn with_directory(mut self, directory: &str) -> Self {
+        self.directory = directory.to_string();
+        self
+    }
+
+    pub fn with_file_name(mut self, file_name: &str) -> Self {
+        self.file_name = file_name.to_string();
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        check_template(&self.directory)?;
+        check_template(&self.file_name)?;
+        anyhow::ensure!(!self.file_name.contains(['/', '\\']), "File name template \"{}\" can't contain a directory; use the directory layout", self.file_name);
+        anyhow::ensure!(self.file_name.ends_with(".mp4"), "File name template \"{}\" should end in .mp4", self.file_name);
+        anyhow::ensure!(!Path::new(&self.directory).is_absolute(), "Directory layout \"{}\" must be relative to the recordings directory", self.directory);
+        Ok(())
+    }
+
+    /// A free path for a new recording below `root`.
+    pub fn path(&self, root: &Path, info: &NameInfo) -> Result<PathBuf> {
+        self.validate()?;
+        let template = Path::new(&self.directory).join(&self.file_name).to_string_lossy().into_owned();
+        free_path(&template, |seq| Ok(root.join(expand(&template, &self.name, info, seq)?)))
+    }
+}
+
+/// The facts about a recording that its name can be built from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameInfo {
+    pub started: DateTime<Local>,
+    pub window: String,
+    pub patch: Option<String>,
+    pub placement: Option<u8>,
+}
+
+impl NameInfo {
+    pub fn new(config: &RecordingConfig, started: DateTime<Local>) -> Self {
+        Self { started, window: config.window_title.clone(), patch: config.game.patch.clone(), placement: config.game.placement }
+    }
+}
+
+/// Replaces the placeholders in `template`. Values are sanitized, so they
+/// never add path separators; unknown ones become `unknown`.
+pub fn expand(template: &str, profile: &str, info: &NameInfo, seq: u32) -> Result<String> {
+    check_template(template)?;
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = start + rest[start..].find('}').context("Unclosed placeholder")?;
+        let value = match &rest[start + 1..end] {
+            "date" => info.started.format("%Y-%m-%d").to_string(),
+            "time" => info.started.format("%H%M%S").to_string(),
+            "year" => info.started.format("%Y").to_string(),
+            "month" => info.started.format("%m").to_string(),
+            "day" => info.started.format("%d").to_string(),
+            "profile" => profile.to_string(),
+            "window" => info.window.clone(),
+            "patch" => info.patch.clone().unwrap_or_default(),
+            "placement" => info.placement.map(|p| p.to_string()).unwrap_or_default(),
+            "seq" => seq.to_string(),
+            other => anyhow::bail!("Unknown placeholder {{{}}}", other),
+        };
+        expanded.push_str(&sanitize(&value));
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Expands `template` into a path that isn't taken yet: `{seq}` counts up
+/// from 1 until the name is free, and a template without it gets `-2`, `-3`...
+/// appended to the file stem when the first choice exists.
+pub fn available_path(template: &str, profile: &str, info: &NameInfo) -> Result<PathBuf> {
+    free_path(template, |seq| Ok(PathBuf::from(expand(template, profile, info, seq)?)))
+}
+
+fn free_path(template: &str, path_for: impl Fn(u32) -> Result<PathBuf>) -> Result<PathBuf> {
+    if template.contains("{seq}") {
+        for seq in 1..=MAX_SEQ {
+            let path = path_for(seq)?;
+            if !is_taken(&path) {
+                return Ok(path);
+            }
+        }
+        anyhow::bail!("No free name for \"{}\" up to {{seq}} = {}", template, MAX_SEQ);
+    }
+    let path = path_for(1)?;
+    if !is_taken(&path) {
+        return Ok(path);
+    }
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (2..=MAX_SEQ)
+        .map(|n| path.with_file_name(format!("{}-{}{}", stem, n, extension)))
+        .find(|candidate| !is_taken(candidate))
+        .with_context(|| format!("No free name for {}", path.display()))
+}
+
+/// Makes `value` safe as (part of) a file name on macOS, Linux and Windows:
+/// separators, reserved and control characters become `_`, and surrounding
+/// dots and spaces are trimmed. Empty values become `unknown`.
+pub fn sanitize(value: &str) -> String {
+    let replaced: String = value
+        .chars()
+        .map(|c| if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') { '_' } else { c })
+        .collect();
+    let trimmed = replaced.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if trimmed.is_empty() { UNKNOWN.to_string() } else { trimmed.to_string() }
+}
+
+/// A recording (finished or still being written, or for segmented ones its
+/// playlist) already uses the name.
+fn is_taken(path: &Path) -> bool {
+    path.exists() || crate::partial::partial_path(path).exists() || path.with_extension("m3u8").exists()
+}
+
+fn check_template(template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').with_context(|| format!("Unclosed placeholder in \"{}\"", template))? + start;
+        let name = &rest[start + 1..end];
+        anyhow::ensure!(
+            PLACEHOLDERS.contains(&name),
+            "Unknown placeholder {{{}}} in \"{}\" (expected one of: {})",
+            name,
+            template,
+            PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", ")
+        );
+        rest = &rest[end + 1..];
+    }
+    anyhow::ensure!(!Path::new(template).components().any(|c| c == std::path::Component::ParentDir), "Template \"{}\" can't use ..", template);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use chrono::TimeZone;
+
+    fn info() -> NameInfo {
+        NameInfo {
+            started: Local.with_ymd_and_hms(2024, 9, 1, 20, 15, 0).unwrap(),
+            window: "League of Legends (TM) Client".into(),
+            patch: Some("14.17".into()),
+            placement: None,
+        }
+    }
+
+    #[test]
+    fn test_expand_and_sanitize() {
+        let name = expand("{year}/{month}/{day}/{profile}-{window}-{patch}-{placement}-{date}-{time}-{seq}.mp4", "ranked", &info(), 3).unwrap();
+        assert_eq!(name, "2024/09/01/ranked-League of Legends (TM) Client-14.17-unknown-2024-09-01-201500-3.mp4");
+
+        let hostile = NameInfo { window: "../Game: \"Set 12\" *beta*?".into(), patch: Some(" . ".into()), placement: Some(1), ..info() };
+        assert_eq!(expand("{window}/{patch}-{placement}.mp4", "x", &hostile, 1).unwrap(), "_Game_ _Set 12_ _beta__/unknown-1.mp4");
+
+        assert!(expand("{wat}.mp4", "x", &info(), 1).unwrap_err().to_string().contains("Unknown placeholder {wat}"));
+        assert!(expand("{date.mp4", "x", &info(), 1).is_err());
+        assert!(expand("../{date}.mp4", "x", &info(), 1).is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        assert_eq!(NamingProfile::default().file_name, DEFAULT_FILE_TEMPLATE);
+        assert!(NamingProfile::named("weekly").unwrap_err().to_string().contains("monthly"));
+        for preset in PROFILES {
+            NamingProfile::from(*preset).validate().unwrap();
+        }
+        let root = Path::new("/recordings");
+        assert!(NamingProfile::default().with_file_name("{window}/x.mp4").validate().is_err());
+        assert!(NamingProfile::default().with_file_name("TFT-{date}.mov").validate().is_err());
+        assert!(NamingProfile::default().with_directory("/abs").validate().is_err());
+
+        let monthly = NamingProfile::named("monthly").unwrap();
+        assert_eq!(monthly.path(root, &info()).unwrap(), root.join("2024/09/TFT-2024-09-01-201500.mp4"));
+        let custom = NamingProfile::named("patch").unwrap().with_file_name("{profile}-{date}.mp4");
+        assert_eq!(custom.path(root, &info()).unwrap(), root.join("14.17/patch-2024-09-01.mp4"));
+    }
+
+    #[test]
+    fn test_taken_names_get_a_number() {
+        let dir = temp_dir("naming_collisions");
+        let profile = NamingProfile::default().with_file_name("TFT-{date}.mp4");
+        let first = profile.path(&dir, &info()).unwrap();
+        assert_eq!(first, dir.join("TFT-2024-09-01.mp4"));
+        std::fs::write(&first, b"").unwrap();
+        let second = profile.path(&dir, &info()).unwrap();
+        assert_eq!(second, dir.join("TFT-2024-09-01-2.mp4"));
+        // A segmented recording's playlist holds its name too
+        std::fs::write(second.with_extension("m3u8"), b"").unwrap();
+        assert_eq!(profile.path(&dir, &info()).unwrap(), dir.join("TFT-2024-09-01-3.mp4"));
+
+        let template = dir.join("Game {seq}.mp4");
+        let template = template.to_str().unwrap();
+        assert_eq!(available_path(template, "default", &info()).unwrap(), dir.join("Game 1.mp4"));
+        std::fs::write(dir.join("Game 1.mp4"), b"").unwrap();
+        assert_eq!(available_path(template, "default", &info()).unwrap(), dir.join("Game 2.mp4"));
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,249 @@
+// ABOUTME: Frame pacer that turns irregular capture timestamps into a constant output rate
+// ABOUTME: Decides per frame whether to drop it, emit it, or repeat the previous frame first
+
+use crate::config::{FrameRateMode, RecordingConfig};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// What to do with one captured frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaceDecision {
+    /// How many times the previously emitted frame must be repeated (on the
+    /// slots immediately before this one) to fill a gap in capture.
+    pub duplicates: u32,
+    /// Output timestamp for this frame, or `None` if it should be dropped.
+    pub pts: Option<Duration>,
+}
+
+/// Frame counts of one recording. Laid out for the FFI, which hands it to
+/// AppleCapture and back.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacerStats {
+    pub frames_in: u64,
+    pub frames_out: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+}
+
+pub struct FramePacer {
+    fps: u32,
+    mode: FrameRateMode,
+    origin: Option<Duration>,
+    next_slot: u64,
+    last_pts: Option<Duration>,
+    stats: PacerStats,
+}
+
+impl FramePacer {
+    pub fn new(fps: u32, mode: FrameRateMode) -> Self {
+        assert!(fps > 0, "frame rate must be non-zero");
+        Self {
+            fps,
+            mode,
+            origin: None,
+            next_slot: 0,
+            last_pts: None,
+            stats: PacerStats::default(),
+        }
+    }
+
+    pub fn from_config(config: &RecordingConfig) -> Self {
+        Self::new(config.fps, config.frame_rate_mode)
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        self.slot_pts(1)
+    }
+
+    pub fn stats(&self) -> PacerStats {
+        self.stats
+    }
+
+    /// Capture timestamp of the first frame pushed, which is output time zero.
+    pub fn origin(&self) -> Option<Duration> {
+        self.origin
+    }
+
+    /// Output timestamp of grid slot `slot`, computed without accumulating error.
+    pub fn slot_pts(&self, slot: u64) -> Duration {
+        let nanos = u128::from(slot) * NANOS_PER_SEC / u128::from(self.fps);
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// Grid slot nearest to an output timestamp.
+    pub fn slot_for(&self, elapsed: Duration) -> u64 {
+        let fps = u128::from(self.fps);
+        ((elapsed.as_nanos() * fps + NANOS_PER_SEC / 2) / NANOS_PER_SEC) as u64
+    }
+
+    /// Feeds the capture timestamp of the next frame. Timestamps are relative
+    /// to the first frame pushed, which always lands on zero.
+    pub fn push(&mut self, capture_pts: Duration) -> PaceDecision {
+        self.stats.frames_in += 1;
+        let origin = *self.origin.get_or_insert(capture_pts);
+
+        let Some(elapsed) = capture_pts.checked_sub(origin) else {
+            return self.drop_frame();
+        };
+
+        match self.mode {
+            FrameRateMode::Constant => {
+                let slot = self.slot_for(elapsed);
+                if slot < self.next_slot {
+                    return self.drop_frame();
+                }
+
+                let duplicates = if self.last_pts.is_some() {
+                    (slot - self.next_slot) as u32
+                } else {
+                    0
+                };
+                let pts = self.slot_pts(slot);
+                self.next_slot = slot + 1;
+                self.emit(pts, duplicates)
+            }
+            FrameRateMode::Variable => {
+                // Still honour the configured rate as an upper bound (one frame
+                // per grid slot) so a burst of callbacks can't blow past the
+                // encoder's budget, but keep the capture timestamp.
+                let slot = self.slot_for(elapsed);
+                if self.last_pts.is_some() && slot < self.next_slot {
+                    return self.drop_frame();
+                }
+                self.next_slot = slot + 1;
+                self.emit(elapsed, 0)
+            }
+        }
+    }
+
+    /// Number of times the last frame must be repeated so a CFR stream runs
+    /// until `capture_end` (e.g. the moment the user pressed stop).
+    pub fn finish(&mut self, capture_end: Duration) -> u32 {
+        let (Some(origin), FrameRateMode::Constant) = (self.origin, self.mode) else {
+            return 0;
+        };
+        let end_slot = self.slot_for(capture_end.saturating_sub(origin));
+        let padding = end_slot.saturating_sub(self.next_slot) as u32;
+        self.next_slot += u64::from(padding);
+        self.stats.duplicated += u64::from(padding);
+        self.stats.frames_out += u64::from(padding);
+        padding
+    }
+
+    fn emit(&mut self, pts: Duration, duplicates: u32) -> PaceDecision {
+        self.last_pts = Some(pts);
+        self.stats.frames_out += 1 + u64::from(duplicates);
+        self.stats.duplicated += u64::from(duplicates);
+        PaceDecision { duplicates, pts: Some(pts) }
+    }
+
+    fn drop_frame(&mut self) -> PaceDecision {
+        self.stats.dropped += 1;
+        PaceDecision { duplicates: 0, pts: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{FrameSource, SyntheticSource};
+
+    fn pace_source(pacer: &mut FramePacer, mut source: SyntheticSource) -> Vec<Duration> {
+        let mut out = Vec::new();
+        let mut last = None;
+        while let Some(frame) = source.next_frame().unwrap() {
+            let decision = pacer.push(frame.pts);
+            if let Some(pts) = decision.pts {
+                for _ in 0..decision.duplicates {
+                    out.push(pacer.slot_pts(out.len() as u64));
+                }
+                out.push(pts);
+                last = Some(frame.pts);
+            }
+        }
+        if let Some(end) = last {
+            let padding = pacer.finish(end + source.frame_interval());
+            for _ in 0..padding {
+                out.push(pacer.slot_pts(out.len() as u64));
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_exact_rate_passes_through() {
+        let mut pacer = FramePacer::new(30, FrameRateMode::Constant);
+        let out = pace_source(&mut pacer, SyntheticSource::new(16, 16, 30, 90));
+
+        assert_eq!(out.len(), 90);
+        assert_eq!(pacer.stats().dropped, 0);
+        assert_eq!(pacer.stats().duplicated, 0);
+        assert_eq!(out[30], Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_faster_source_is_decimated() {
+        let mut pacer = FramePacer::new(30, FrameRateMode::Constant);
+        let out = pace_source(&mut pacer, SyntheticSource::new(16, 16, 60, 120));
+
+        assert_eq!(out.len(), 60);
+        assert_eq!(pacer.stats().dropped, 60);
+        for (i, pts) in out.iter().enumerate() {
+            assert_eq!(*pts, pacer.slot_pts(i as u64));
+        }
+    }
+
+    #[test]
+    fn test_stall_is_filled_with_duplicates() {
+        let mut pacer = FramePacer::new(60, FrameRateMode::Constant);
+        let source = SyntheticSource::new(16, 16, 60, 60).with_stall(30, Duration::from_millis(500));
+        let out = pace_source(&mut pacer, source);
+
+        // 1 s of frames plus a 0.5 s freeze must still give 1.5 s at 60 fps.
+        assert_eq!(out.len(), 90);
+        assert_eq!(pacer.stats().duplicated, 30);
+        assert_eq!(pacer.stats().dropped, 0);
+    }
+
+    #[test]
+    fn test_jitter_keeps_constant_output_grid() {
+        let mut pacer = FramePacer::new(60, FrameRateMode::Constant);
+        let source = SyntheticSource::new(16, 16, 60, 600).with_jitter(Duration::from_millis(4), 7);
+        let out = pace_source(&mut pacer, source);
+
+        assert_eq!(pacer.stats().frames_out, out.len() as u64);
+        assert!((598..=602).contains(&out.len()), "got {} frames", out.len());
+        for (i, pts) in out.iter().enumerate() {
+            assert_eq!(*pts, pacer.slot_pts(i as u64));
+        }
+    }
+
+    #[test]
+    fn test_variable_mode_passes_timestamps_through() {
+        let mut pacer = FramePacer::new(60, FrameRateMode::Variable);
+        let source = SyntheticSource::new(16, 16, 30, 30).with_stall(10, Duration::from_secs(2));
+        let out = pace_source(&mut pacer, source);
+
+        assert_eq!(out.len(), 30);
+        assert_eq!(pacer.stats().duplicated, 0);
+        assert!(out[10] - out[9] > Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_variable_mode_caps_rate() {
+        let mut pacer = FramePacer::new(30, FrameRateMode::Variable);
+        let out = pace_source(&mut pacer, SyntheticSource::new(16, 16, 120, 120));
+
+        // Capture spans 0–992 ms, i.e. grid slots 0..=30 at 30 fps.
+        assert_eq!(out.len(), 31);
+        assert_eq!(pacer.stats().dropped, 89);
+        assert!(out.windows(2).all(|w| w[1] - w[0] >= Duration::from_millis(16)));
+    }
+}
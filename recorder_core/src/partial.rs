@@ -0,0 +1,189 @@
+// ABOUTME: In-progress recordings are written as `<name>.mp4.partial` and renamed into place once finalized
+// ABOUTME: Finds partials left behind by a crash and repairs the ones whose file was finished
+
+use crate::metadata::RecordingMetadata;
+use crate::mp4::Mp4Reader;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const PARTIAL_EXTENSION: &str = "partial";
+
+/// A partial modified this recently is assumed to still be recording.
+pub const STALE_AFTER: Duration = Duration::from_secs(60);
+
+/// `TFT-x.mp4` is written as `TFT-x.mp4.partial`.
+pub fn partial_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".");
+    name.push(PARTIAL_EXTENSION);
+    PathBuf::from(name)
+}
+
+/// The name `partial` gets once finished, or `None` if it isn't a partial.
+pub fn final_path(partial: &Path) -> Option<PathBuf> {
+    (partial.extension()? == PARTIAL_EXTENSION).then(|| partial.with_extension(""))
+}
+
+/// Moves a finished `partial` to its final name, refusing to replace a file.
+pub fn commit(partial: &Path) -> Result<PathBuf> {
+    let output = final_path(partial).with_context(|| format!("{} is not a partial recording", partial.display()))?;
+    anyhow::ensure!(!output.exists(), "{} already exists", output.display());
+    std::fs::rename(partial, &output).with_context(|| format!("Failed to move {} to {}", partial.display(), output.display()))?;
+    Ok(output)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartialState {
+    /// The file was finalized but never renamed; it only needs moving into place.
+    Finished,
+    /// The recording was cut off before its index (moov) was written, so it
+    /// can't be played.
+    Truncated,
+}
+
+/// A partial left behind by a recording that didn't stop cleanly.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LeftoverPartial {
+    pub path: PathBuf,
+    /// The name it would have had.
+    pub output: PathBuf,
+    pub bytes: u64,
+    pub modified: DateTime<Local>,
+    pub state: PartialState,
+}
+
+/// The stale partials in `root` and its subdirectories, sorted by path.
+/// Partials modified within [`STALE_AFTER`] are skipped as still recording.
+pub fn find_leftovers(root: &Path) -> Result<Vec<LeftoverPartial>> {
+    let mut leftovers = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+        for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let Some(output) = final_path(&path) else {
+                continue;
+            };
+            let metadata = std::fs::metadata(&path)?;
+            let modified = metadata.modified()?;
+            if modified.elapsed().unwrap_or_default() < STALE_AFTER {
+                continue;
+            }
+            let state = if Mp4Reader::open(&path).is_ok() { PartialState::Finished } else { PartialState::Truncated };
+            leftovers.push(LeftoverPartial { path, output, bytes: metadata.len(), modified: modified.into(), state });
+        }
+    }
+    leftovers.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(leftovers)
+}
+
+impl LeftoverPartial {
+    /// Moves a finished partial into place and completes its metadata
+    /// sidecar from the file. Truncated partials can't be repaired.
+    pub fn repair(&self) -> Result<PathBuf> {
+        anyhow::ensure!(
+            self.state == PartialState::Finished,
+            "{} was cut off before it was finalized and can't be played; delete it instead",
+            self.path.display()
+        );
+        let duration = Mp4Reader::open(&self.path)?.duration;
+        let output = commit(&self.path)?;
+        if let Some(mut metadata) = RecordingMetadata::for_recording(&output)? {
+            if !metadata.is_complete() {
+                metadata.ended_at = Some(self.modified);
+                metadata.duration_secs = duration.as_secs_f64();
+                metadata.save()?;
+            }
+        }
+        Ok(output)
+    }
+
+    /// Deletes the partial and, unless another file already took its name,
+    /// the metadata and marker sidecars it left.
+    pub fn delete(&self) -> Result<()> {
+        std::fs::remove_file(&self.path).with_context(|| format!("Failed to delete {}", self.path.display()))?;
+        if !self.output.exists() {
+            for sidecar in [RecordingMetadata::sidecar_path(&self.output), crate::markers::MarkerList::sidecar_path(&self.output)] {
+                if sidecar.exists() {
+                    std::fs::remove_file(&sidecar).with_context(|| format!("Failed to delete {}", sidecar.display()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{config, temp_dir, write_synthetic};
+    use std::time::SystemTime;
+
+    /// 1 s of test pattern at 10 fps.
+    fn write_recording(path: &Path) {
+        write_synthetic(path, &config(), 10).finish().unwrap();
+    }
+
+    /// Backdates `path` so it no longer looks like it's being recorded.
+    fn make_stale(path: &Path) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - STALE_AFTER * 2).unwrap();
+    }
+
+    #[test]
+    fn test_paths() {
+        let partial = partial_path(Path::new("/rec/TFT-1.mp4"));
+        assert_eq!(partial, PathBuf::from("/rec/TFT-1.mp4.partial"));
+        assert_eq!(final_path(&partial), Some(PathBuf::from("/rec/TFT-1.mp4")));
+        assert_eq!(final_path(Path::new("/rec/TFT-1.mp4")), None);
+    }
+
+    #[test]
+    fn test_leftovers_are_found_and_repaired() {
+        let dir = temp_dir("partial_leftovers");
+        let config = config();
+
+        // Finalized, then the app died before the rename
+        let finished = dir.join("2024").join("TFT-1.mp4");
+        std::fs::create_dir_all(finished.parent().unwrap()).unwrap();
+        write_recording(&partial_path(&finished));
+        RecordingMetadata::new(&config, &finished).save().unwrap();
+        make_stale(&partial_path(&finished));
+        // Cut off mid-recording: no moov
+        let truncated = dir.join("TFT-2.mp4");
+        std::fs::write(partial_path(&truncated), b"\0\0\0\x10ftypisom\0\0\0\0\0\0\0\x08mdat").unwrap();
+        RecordingMetadata::new(&config, &truncated).save().unwrap();
+        make_stale(&partial_path(&truncated));
+        // Still being written
+        std::fs::write(partial_path(&dir.join("TFT-3.mp4")), b"").unwrap();
+
+        let leftovers = find_leftovers(&dir).unwrap();
+        let states: Vec<(&Path, PartialState)> = leftovers.iter().map(|l| (l.output.as_path(), l.state)).collect();
+        assert_eq!(states, [(finished.as_path(), PartialState::Finished), (truncated.as_path(), PartialState::Truncated)]);
+
+        assert_eq!(leftovers[0].repair().unwrap(), finished);
+        let metadata = RecordingMetadata::for_recording(&finished).unwrap().unwrap();
+        assert!(metadata.is_complete() && (metadata.duration_secs - 1.0).abs() < 0.05, "{}", metadata.duration_secs);
+        assert!(Mp4Reader::open(&finished).is_ok());
+
+        assert!(leftovers[1].repair().unwrap_err().to_string().contains("delete it instead"));
+        leftovers[1].delete().unwrap();
+        assert!(!partial_path(&truncated).exists() && !RecordingMetadata::sidecar_path(&truncated).exists());
+        assert!(find_leftovers(&dir).unwrap().is_empty());
+
+        // Never replaces a recording that took the name meanwhile
+        let taken = dir.join("TFT-4.mp4");
+        std::fs::write(&taken, b"keep").unwrap();
+        std::fs::write(partial_path(&taken), b"").unwrap();
+        assert!(commit(&partial_path(&taken)).is_err());
+        assert_eq!(std::fs::read(&taken).unwrap(), b"keep");
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
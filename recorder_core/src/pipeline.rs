@@ -0,0 +1,175 @@
+// ABOUTME: Software video pipeline: frame pacing + OpenH264
+// ABOUTME: Turns captured frames into encoded packets on the output timeline
+
+use crate::config::RecordingConfig;
+use crate::encoder::{EncodedPacket, SoftwareEncoder, VideoFrame};
+use crate::h264::ParameterSets;
+use crate::pacer::{FramePacer, PacerStats};
+use crate::source::FrameSource;
+use anyhow::Result;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub pacer: PacerStats,
+    pub packets: u64,
+    /// Frames the encoder's rate control chose not to emit.
+    pub encoder_skipped: u64,
+}
+
+pub struct SoftwarePipeline {
+    encoder: SoftwareEncoder,
+    pacer: FramePacer,
+    previous: Option<VideoFrame>,
+    last_pts: Option<Duration>,
+    stats: PipelineStats,
+}
+
+impl SoftwarePipeline {
+    pub fn new(config: &RecordingConfig) -> Result<Self> {
+        Ok(Self {
+            encoder: SoftwareEncoder::new(config)?,
+            pacer: FramePacer::from_config(config),
+            previous: None,
+            last_pts: None,
+            stats: PipelineStats::default(),
+        })
+    }
+
+    pub fn parameter_sets(&self) -> Option<&ParameterSets> {
+        self.encoder.parameter_sets()
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        PipelineStats { pacer: self.pacer.stats(), ..self.stats }
+    }
+
+    /// Paces and encodes one captured frame, returning every packet it produced
+    /// (repeats of the previous frame first, then the frame itself).
+    pub fn push(&mut self, frame: VideoFrame) -> Result<Vec<EncodedPacket>> {
+        let decision = self.pacer.push(frame.pts);
+        let Some(pts) = decision.pts else {
+            return Ok(Vec::new());
+        };
+
+        let mut packets = Vec::new();
+        if decision.duplicates > 0 {
+            let first = self.pacer.slot_for(pts) - u64::from(decision.duplicates);
+            self.repeat_previous(first, decision.duplicates, &mut packets)?;
+        }
+
+        self.encode(&frame, pts, &mut packets)?;
+        self.last_pts = Some(pts);
+        self.previous = Some(frame);
+        Ok(packets)
+    }
+
+    /// Pads a constant-rate stream with the last frame up to `capture_end`.
+    pub fn finish(&mut self, capture_end: Duration) -> Result<Vec<EncodedPacket>> {
+        let padding = self.pacer.finish(capture_end);
+        let mut packets = Vec::new();
+        if let (Some(last), true) = (self.last_pts, padding > 0) {
+            let first = self.pacer.slot_for(last) + 1;
+            self.repeat_previous(first, padding, &mut packets)?;
+        }
+        Ok(packets)
+    }
+
+    fn repeat_previous(&mut self, first_slot: u64, count: u32, out: &mut Vec<EncodedPacket>) -> Result<()> {
+        let Some(previous) = self.previous.take() else {
+            return Ok(());
+        };
+        for slot in first_slot..first_slot + u64::from(count) {
+            let pts = self.pacer.slot_pts(slot);
+            self.encode(&previous, pts, out)?;
+        }
+        self.previous = Some(previous);
+        Ok(())
+    }
+
+    fn encode(&mut self, frame: &VideoFrame, pts: Duration, out: &mut Vec<EncodedPacket>) -> Result<()> {
+        match self.encoder.encode_at(frame, pts)? {
+            Some(packet) => {
+                self.stats.packets += 1;
+                out.push(packet);
+            }
+            None => self.stats.encoder_skipped += 1,
+        }
+        Ok(())
+    }
+}
+
+/// A fully encoded stream, as produced by [`encode_source`].
+pub struct EncodedStream {
+    pub packets: Vec<EncodedPacket>,
+    pub parameter_sets: Option<ParameterSets>,
+    pub stats: PipelineStats,
+}
+
+/// Drains `source` through a fresh pipeline. The stream ends one nominal
+/// frame after the last captured frame.
+pub fn encode_source(source: &mut dyn FrameSource, config: &RecordingConfig) -> Result<EncodedStream> {
+    let mut pipeline = SoftwarePipeline::new(config)?;
+    let mut packets = Vec::new();
+    let mut last_capture = None;
+
+    while let Some(frame) = source.next_frame()? {
+        last_capture = Some(frame.pts);
+        packets.extend(pipeline.push(frame)?);
+    }
+    if let Some(end) = last_capture {
+        let frame_duration = Duration::from_nanos(1_000_000_000 / u64::from(config.fps));
+        packets.extend(pipeline.finish(end + frame_duration)?);
+    }
+
+    Ok(EncodedStream {
+        packets,
+        parameter_sets: pipeline.parameter_sets().cloned(),
+        stats: pipeline.stats(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FrameRateMode;
+    use crate::source::SyntheticSource;
+
+    #[test]
+    fn test_cfr_output_has_exact_frame_count() {
+        let config = RecordingConfig::new("synthetic", 64, 48).with_frame_rate(30, FrameRateMode::Constant);
+        // 60 fps capture with a one-second freeze in the middle
+        let mut source = SyntheticSource::new(64, 48, 60, 120).with_stall(60, Duration::from_secs(1));
+        let stream = encode_source(&mut source, &config).unwrap();
+
+        // 2 s of capture + 1 s freeze at 30 fps
+        assert_eq!(stream.packets.len(), 90);
+        assert_eq!(stream.stats.pacer.duplicated, 30);
+        assert!(stream.parameter_sets.is_some());
+        for (i, packet) in stream.packets.iter().enumerate() {
+            assert_eq!(packet.pts, Duration::from_nanos(i as u64 * 1_000_000_000 / 30));
+        }
+    }
+
+    #[test]
+    fn test_vfr_keeps_capture_gaps() {
+        let config = RecordingConfig::new("synthetic", 64, 48).with_frame_rate(60, FrameRateMode::Variable);
+        let mut source = SyntheticSource::new(64, 48, 60, 60).with_stall(30, Duration::from_secs(1));
+        let stream = encode_source(&mut source, &config).unwrap();
+
+        assert_eq!(stream.packets.len(), 60);
+        assert!(stream.packets[30].pts - stream.packets[29].pts > Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_keyframes_follow_output_frame_count() {
+        let config = RecordingConfig::new("synthetic", 64, 48)
+            .with_frame_rate(30, FrameRateMode::Constant)
+            .with_keyframe_interval(30);
+        let mut source = SyntheticSource::new(64, 48, 60, 180);
+        let stream = encode_source(&mut source, &config).unwrap();
+
+        let keyframes: Vec<usize> = (0..stream.packets.len()).filter(|&i| stream.packets[i].keyframe).collect();
+        assert_eq!(keyframes, vec![0, 30, 60]);
+    }
+}
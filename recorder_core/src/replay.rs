@@ -0,0 +1,316 @@
+// ABOUTME: Instant-replay ring buffer holding the most recent encoded GOPs in memory
+// ABOUTME: Evicts whole GOPs by age and size and writes the tail to an MP4 on demand
+
+use crate::audio_encoder::AudioPacket;
+use crate::config::ReplayConfig;
+use crate::encoder::EncodedPacket;
+use crate::h264::ParameterSets;
+use crate::mp4::{AudioTrackInfo, Mp4Writer, Sample, TrackInfo, VideoTrackInfo};
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::Duration;
+
+/// What `save` wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayClip {
+    pub duration: Duration,
+    pub video_frames: usize,
+    pub bytes: u64,
+}
+
+struct AudioRing {
+    info: AudioTrackInfo,
+    packets: VecDeque<AudioPacket>,
+}
+
+pub struct ReplayBuffer {
+    max_duration: Duration,
+    max_bytes: u64,
+    width: u32,
+    height: u32,
+    frame_duration: Duration,
+    parameter_sets: Option<ParameterSets>,
+    video: VecDeque<EncodedPacket>,
+    audio: Vec<AudioRing>,
+    bytes: u64,
+}
+
+impl ReplayBuffer {
+    pub fn new(config: &ReplayConfig, width: u32, height: u32, fps: u32) -> Self {
+        Self {
+            max_duration: Duration::from_secs(u64::from(config.seconds)),
+            max_bytes: u64::from(config.max_megabytes) * 1024 * 1024,
+            width,
+            height,
+            frame_duration: Duration::from_nanos(1_000_000_000 / u64::from(fps.max(1))),
+            parameter_sets: None,
+            video: VecDeque::new(),
+            audio: Vec::new(),
+            bytes: 0,
+        }
+    }
+
+    pub fn set_parameter_sets(&mut self, parameter_sets: ParameterSets) {
+        self.parameter_sets = Some(parameter_sets);
+    }
+
+    /// Registers an audio track and returns its index for `push_audio`.
+    pub fn add_audio_track(&mut self, info: AudioTrackInfo) -> usize {
+        self.audio.push(AudioRing { info, packets: VecDeque::new() });
+        self.audio.len() - 1
+    }
+
+    /// Time span currently held, from the oldest keyframe to the newest frame.
+    pub fn duration(&self) -> Duration {
+        match (self.video.front(), self.video.back()) {
+            (Some(first), Some(last)) => last.pts - first.pts + self.frame_duration,
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.video.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.video.clear();
+        self.audio.iter_mut().for_each(|a| a.packets.clear());
+        self.bytes = 0;
+    }
+
+    pub fn push_video(&mut self, packet: EncodedPacket) {
+        // The buffer always starts on a keyframe so any saved clip is decodable
+        if self.video.is_empty() && !packet.keyframe {
+            return;
+        }
+        self.bytes += packet.data.len() as u64;
+        self.video.push_back(packet);
+        self.evict();
+    }
+
+    pub fn push_audio(&mut self, track: usize, packet: AudioPacket) {
+        if let Some(ring) = self.audio.get_mut(track) {
+            self.bytes += packet.data.len() as u64;
+            ring.packets.push_back(packet);
+        }
+        self.evict();
+    }
+
+    /// Drops the oldest GOP while the buffer is over either budget, but never
+    /// the last one.
+    fn evict(&mut self) {
+        while self.duration() > self.max_duration || self.bytes > self.max_bytes {
+            let Some(next_key) = self.video.iter().skip(1).position(|p| p.keyframe) else {
+                break;
+            };
+            for packet in self.video.drain(..=next_key) {
+                self.bytes -= packet.data.len() as u64;
+            }
+        }
+
+        let Some(start) = self.video.front().map(|p| p.pts) else {
+            return;
+        };
+        for ring in &mut self.audio {
+            while ring.packets.front().is_some_and(|p| p.pts < start) {
+                let packet = ring.packets.pop_front().unwrap();
+                self.bytes -= packet.data.len() as u64;
+            }
+        }
+    }
+
+    /// Writes (at least) the last `seconds` to `path`, starting on the nearest
+    /// keyframe before the requested point. Capture continues untouched.
+    pub fn save(&self, seconds: u32, path: impl AsRef<Path>) -> Result<ReplayClip> {
+        self.snapshot(seconds)?.write(path)
+    }
+
+    /// Copies out the packets `save` would write, so a shared buffer can be
+    /// unlocked before the (slow) file write and the encoder keeps feeding it.
+    pub fn snapshot(&self, seconds: u32) -> Result<ReplaySnapshot> {
+        let parameter_sets = self
+            .parameter_sets
+            .clone()
+            .context("Replay buffer has no parameter sets yet")?;
+        let last = self.video.back().context("Replay buffer is empty")?;
+
+        let wanted = Duration::from_secs(u64::from(seconds));
+        // Half a frame of slack absorbs nanosecond rounding in the frame grid
+        let cutoff = (last.pts + self.frame_duration + self.frame_duration / 2).saturating_sub(wanted);
+        let start_index = self
+            .video
+            .iter()
+            .rposition(|p| p.keyframe && p.pts <= cutoff)
+            .unwrap_or(0);
+        let origin = self.video[start_index].pts;
+
+        Ok(ReplaySnapshot {
+            width: self.width,
+            height: self.height,
+            frame_duration: self.frame_duration,
+            parameter_sets,
+            origin,
+            video: self.video.iter().skip(start_index).cloned().collect(),
+            audio: self
+                .audio
+                .iter()
+                .map(|ring| (ring.info.clone(), ring.packets.iter().filter(|p| p.pts >= origin).cloned().collect()))
+                .collect(),
+        })
+    }
+}
+
+/// The packets of one replay clip, detached from the buffer they came from.
+pub struct ReplaySnapshot {
+    width: u32,
+    height: u32,
+    frame_duration: Duration,
+    parameter_sets: ParameterSets,
+    /// Timestamp of the first (key)frame; the clip starts at zero.
+    origin: Duration,
+    video: Vec<EncodedPacket>,
+    audio: Vec<(AudioTrackInfo, Vec<AudioPacket>)>,
+}
+
+impl ReplaySnapshot {
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<ReplayClip> {
+        let origin = self.origin;
+        let mut writer = Mp4Writer::create(path.as_ref())?;
+        let video_track = writer.add_track(TrackInfo::Video(VideoTrackInfo {
+            width: self.width,
+            height: self.height,
+            parameter_sets: self.parameter_sets.clone(),
+        }));
+        let mut audio_tracks = Vec::new();
+        for (info, _) in &self.audio {
+            // Mid-stream AAC has no encoder delay to hide
+            let info = AudioTrackInfo { priming_samples: 0, ..info.clone() };
+            audio_tracks.push(writer.add_track(TrackInfo::Audio(info)));
+        }
+
+        let mut bytes = 0u64;
+        for packet in &self.video {
+            writer.write_sample(
+                video_track,
+                Sample {
+                    pts: packet.pts - origin,
+                    duration: Some(self.frame_duration),
+                    keyframe: packet.keyframe,
+                    data: &packet.data,
+                },
+            )?;
+            bytes += packet.data.len() as u64;
+        }
+
+        for ((info, packets), &track) in self.audio.iter().zip(&audio_tracks) {
+            for packet in packets {
+                writer.write_sample(
+                    track,
+                    Sample {
+                        pts: packet.pts - origin,
+                        duration: Some(crate::audio::frames_to_duration(u64::from(packet.duration), info.sample_rate)),
+                        keyframe: true,
+                        data: &packet.data,
+                    },
+                )?;
+                bytes += packet.data.len() as u64;
+            }
+        }
+
+        let duration = writer.duration();
+        writer.finish()?;
+        Ok(ReplayClip { duration, video_frames: self.video.len(), bytes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FrameRateMode, RecordingConfig};
+    use crate::mp4::Mp4Reader;
+    use crate::pipeline::encode_source;
+    use crate::source::SyntheticSource;
+    use crate::test_support::temp_file;
+
+    fn packet(index: u64, keyframe: bool, size: usize) -> EncodedPacket {
+        EncodedPacket { pts: Duration::from_millis(index * 100), keyframe, data: vec![0; size] }
+    }
+
+    fn buffer(seconds: u32, max_megabytes: u32) -> ReplayBuffer {
+        let config = ReplayConfig { enabled: true, seconds, max_megabytes };
+        ReplayBuffer::new(&config, 64, 48, 10)
+    }
+
+    #[test]
+    fn test_evicts_whole_gops_by_age() {
+        let mut replay = buffer(2, 64);
+        // 10 fps with a keyframe every 5 frames (every 0.5 s)
+        for i in 0..60 {
+            replay.push_video(packet(i, i % 5 == 0, 10));
+        }
+
+        assert!(replay.video.front().unwrap().keyframe);
+        assert_eq!(replay.video.front().unwrap().pts, Duration::from_millis(4000));
+        assert_eq!(replay.duration(), Duration::from_secs(2));
+        assert_eq!(replay.bytes(), 200);
+    }
+
+    #[test]
+    fn test_evicts_by_size_but_keeps_one_gop() {
+        let mut replay = buffer(600, 1);
+        for i in 0..40 {
+            replay.push_video(packet(i, i % 10 == 0, 200 * 1024));
+        }
+        // 1 MiB holds five 200 KiB frames, less than a GOP: only the newest GOP survives
+        assert_eq!(replay.video.len(), 10);
+        assert_eq!(replay.video.front().unwrap().pts, Duration::from_millis(3000));
+    }
+
+    #[test]
+    fn test_leading_delta_frames_are_skipped() {
+        let mut replay = buffer(10, 64);
+        replay.push_video(packet(0, false, 10));
+        assert!(replay.is_empty());
+        replay.push_video(packet(1, true, 10));
+        assert!(!replay.is_empty());
+    }
+
+    #[test]
+    fn test_save_writes_keyframe_aligned_tail() {
+        let config = RecordingConfig::new("replay", 64, 48)
+            .with_frame_rate(30, FrameRateMode::Constant)
+            .with_keyframe_interval(30);
+        let mut source = SyntheticSource::new(64, 48, 30, 150);
+        let stream = encode_source(&mut source, &config).unwrap();
+
+        let mut replay = ReplayBuffer::new(&ReplayConfig { enabled: true, seconds: 3, max_megabytes: 64 }, 64, 48, 30);
+        replay.set_parameter_sets(stream.parameter_sets.unwrap());
+        for packet in stream.packets {
+            replay.push_video(packet);
+        }
+        assert_eq!(replay.video.front().unwrap().pts, Duration::from_secs(2));
+
+        let path = temp_file("replay");
+        let clip = replay.save(2, &path).unwrap();
+        assert_eq!(clip.video_frames, 60);
+        assert_eq!(clip.duration, Duration::from_secs(2));
+
+        let reader = Mp4Reader::open(&path).unwrap();
+        let video = reader.video_track().unwrap();
+        assert_eq!(video.samples.len(), 60);
+        assert!(video.samples[0].keyframe);
+        assert!(video.samples[30].keyframe);
+
+        // A snapshot no longer depends on the buffer it was taken from
+        let snapshot = replay.snapshot(1).unwrap();
+        replay.clear();
+        assert_eq!(snapshot.write(&path).unwrap().video_frames, 30);
+        assert!(replay.snapshot(1).is_err());
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,290 @@
+// ABOUTME: Segmented recording: AppleCapture rolls the output on keyframes, this side keeps the books
+// ABOUTME: Names segments from a template, keeps an M3U playlist per session and emits events
+
+use crate::config::SegmentConfig;
+use crate::events::{EventBus, RecorderEvent};
+use crate::markers::MarkerList;
+use crate::mp4::{self, MetadataTags};
+use crate::partial;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Expands the segment name template for one session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentNamer {
+    dir: PathBuf,
+    stem: String,
+    /// Template with `{date}` and `{stem}` filled in; only `{n}` remains.
+    pattern: String,
+}
+
+impl SegmentNamer {
+    /// Segments go next to `output`; the playlist takes its place.
+    pub fn new(config: &SegmentConfig, output: &Path, started: DateTime<Local>) -> Self {
+        let dir = output.parent().map(Path::to_path_buf).unwrap_or_default();
+        let stem = output
+            .file_stem()
+            .map_or_else(|| "recording".to_string(), |s| s.to_string_lossy().into_owned());
+        let pattern = config
+            .name_template
+            .replace("{date}", &started.format("%Y-%m-%d-%H%M%S").to_string())
+            .replace("{stem}", &stem);
+        Self { dir, stem, pattern }
+    }
+
+    /// Path of segment `index` (1-based).
+    pub fn path(&self, index: u32) -> PathBuf {
+        self.dir.join(self.pattern.replace("{n}", &index.to_string()))
+    }
+
+    /// Full path with `{n}` still in place, for capture backends that name
+    /// later segments themselves.
+    pub fn pattern(&self) -> PathBuf {
+        self.dir.join(&self.pattern)
+    }
+
+    pub fn playlist_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.m3u8", self.stem))
+    }
+}
+
+/// A finished segment file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub index: u32,
+    pub path: PathBuf,
+    /// Offset of the first frame from the start of the session.
+    pub start: Duration,
+    pub duration: Duration,
+    pub bytes: u64,
+}
+
+/// Bookkeeping for the segments of one recording session.
+pub struct SegmentSession {
+    namer: SegmentNamer,
+    segments: Vec<Segment>,
+    events: EventBus,
+    /// Copies of the recording's markers and tags, kept current by the
+    /// recorder and written into each segment as it closes.
+    markers: Option<MarkerList>,
+    tags: Option<MetadataTags>,
+}
+
+impl SegmentSession {
+    pub fn new(namer: SegmentNamer, events: EventBus) -> Self {
+        Self { namer, segments: Vec::new(), events, markers: None, tags: None }
+    }
+
+    pub fn set_markers(&mut self, markers: MarkerList) {
+        self.markers = Some(markers);
+    }
+
+    pub fn set_tags(&mut self, tags: MetadataTags) {
+        self.tags = Some(tags);
+    }
+
+    pub fn namer(&self) -> &SegmentNamer {
+        &self.namer
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Records a closed segment, rewrites the playlist and announces it. A
+    /// segment written under its `.partial` name first gets its chapters and
+    /// tags, then is moved into place: once announced it may be uploading
+    /// and is never edited again.
+    pub fn finalize(&mut self, mut segment: Segment) -> Result<()> {
+        if partial::final_path(&segment.path).is_some() {
+            self.stamp(&mut segment);
+            segment.path = partial::commit(&segment.path)?;
+        }
+        self.events.publish(RecorderEvent::SegmentFinalized {
+            index: segment.index,
+            path: segment.path.clone(),
+            start_secs: segment.start.as_secs_f64(),
+            duration_secs: segment.duration.as_secs_f64(),
+            bytes: segment.bytes,
+        });
+        self.segments.push(segment);
+        self.write_playlist()
+    }
+
+    /// Writes the markers that fall in `segment` as its chapters, and the
+    /// tags as known now; the recording's sidecar keeps the final ones. A
+    /// failure leaves the segment as it is rather than losing it.
+    fn stamp(&self, segment: &mut Segment) {
+        if let Some(markers) = &self.markers {
+            let chapters = markers.chapters(segment.start, segment.duration);
+            if !chapters.is_empty() {
+                if let Err(e) = mp4::write_chapters(&segment.path, &chapters) {
+                    eprintln!("Failed to write chapters to segment {}: {:#}", segment.index, e);
+                }
+            }
+        }
+        if let Some(tags) = &self.tags {
+            if let Err(e) = mp4::write_tags(&segment.path, tags) {
+                eprintln!("Failed to embed metadata in segment {}: {:#}", segment.index, e);
+            }
+        }
+        if let Ok(file) = std::fs::metadata(&segment.path) {
+            segment.bytes = file.len();
+        }
+    }
+
+    fn write_playlist(&self) -> Result<()> {
+        let mut playlist = String::from("#EXTM3U\n");
+        for segment in &self.segments {
+            let name = segment.path.file_name().map_or_else(
+                || segment.path.display().to_string(),
+                |n| n.to_string_lossy().into_owned(),
+            );
+            writeln!(playlist, "#EXTINF:{:.3},Part {}", segment.duration.as_secs_f64(), segment.index)?;
+            writeln!(playlist, "{}", name)?;
+        }
+        let path = self.namer.playlist_path();
+        std::fs::write(&path, playlist).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// The segment files listed in an M3U playlist, resolved against the
+/// playlist's directory.
+pub fn read_playlist(path: &Path) -> Result<Vec<PathBuf>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| dir.join(line))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markers::Marker;
+    use crate::mp4::Mp4Reader;
+    use crate::test_support::{temp_dir, write_fake_video};
+    use chrono::TimeZone;
+
+    fn started() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 3, 14, 21, 5, 9).unwrap()
+    }
+
+    /// Writes segment `index` of `frames` 100 ms frames under its `.partial`
+    /// name, as AppleCapture leaves it when the cut is made.
+    fn closed_segment(namer: &SegmentNamer, index: u32, start_ms: u64, frames: u64) -> Segment {
+        let path = partial::partial_path(&namer.path(index));
+        write_fake_video(&path, frames);
+        let bytes = std::fs::metadata(&path).unwrap().len();
+        Segment { index, path, start: Duration::from_millis(start_ms), duration: Duration::from_millis(frames * 100), bytes }
+    }
+
+    #[test]
+    fn test_namer_expands_template() {
+        let config = SegmentConfig { max_seconds: 60, ..Default::default() };
+        let namer = SegmentNamer::new(&config, Path::new("/rec/TFT-x.mp4"), started());
+        assert_eq!(namer.path(2), PathBuf::from("/rec/TFT-2026-03-14-210509-part2.mp4"));
+        assert_eq!(namer.pattern(), PathBuf::from("/rec/TFT-2026-03-14-210509-part{n}.mp4"));
+        assert_eq!(namer.playlist_path(), PathBuf::from("/rec/TFT-x.m3u8"));
+
+        let custom = SegmentConfig { name_template: "{stem}.{n}.mp4".into(), ..config };
+        assert_eq!(SegmentNamer::new(&custom, Path::new("/rec/game.mp4"), started()).path(3), PathBuf::from("/rec/game.3.mp4"));
+    }
+
+    #[test]
+    fn test_finalize_commits_announces_and_lists_segments() {
+        let dir = temp_dir("segments_finalize");
+        let config = SegmentConfig { max_seconds: 2, ..Default::default() };
+        let namer = SegmentNamer::new(&config, &dir.join("TFT.mp4"), started());
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+        let mut session = SegmentSession::new(namer.clone(), bus);
+        session.finalize(closed_segment(&namer, 1, 0, 20)).unwrap();
+        session.finalize(closed_segment(&namer, 2, 2000, 15)).unwrap();
+
+        let segments = session.segments();
+        assert_eq!(segments.iter().map(|s| s.path.clone()).collect::<Vec<_>>(), [namer.path(1), namer.path(2)]);
+        assert!(segments.iter().all(|s| s.path.exists()));
+        // Each segment was written under its .partial name and moved into place
+        assert!(std::fs::read_dir(&dir).unwrap().all(|e| e.unwrap().path().extension().is_some_and(|e| e != "partial")));
+
+        let received: Vec<_> = events.try_iter().collect();
+        let RecorderEvent::SegmentFinalized { index, path, start_secs, duration_secs, .. } = &received[1] else {
+            panic!("Expected a segment")
+        };
+        assert_eq!((received.len(), *index, path, *start_secs, *duration_secs), (2, 2, &namer.path(2), 2.0, 1.5));
+
+        let playlist = std::fs::read_to_string(namer.playlist_path()).unwrap();
+        assert_eq!(
+            playlist,
+            "#EXTM3U\n#EXTINF:2.000,Part 1\nTFT-2026-03-14-210509-part1.mp4\n#EXTINF:1.500,Part 2\nTFT-2026-03-14-210509-part2.mp4\n"
+        );
+        assert_eq!(read_playlist(&namer.playlist_path()).unwrap(), [namer.path(1), namer.path(2)]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_finalize_leaves_committed_segments_alone() {
+        let dir = temp_dir("segments_committed");
+        let config = SegmentConfig { max_seconds: 2, name_template: "{n}.mp4".into(), ..Default::default() };
+        let namer = SegmentNamer::new(&config, &dir.join("game.mp4"), started());
+        let mut session = SegmentSession::new(namer.clone(), EventBus::new());
+        session.set_tags(MetadataTags { title: Some("TFT".into()), ..Default::default() });
+        write_fake_video(&namer.path(1), 10);
+        let before = std::fs::read(namer.path(1)).unwrap();
+        let segment = Segment { index: 1, path: namer.path(1), start: Duration::ZERO, duration: Duration::from_secs(1), bytes: 1 };
+        session.finalize(segment).unwrap();
+
+        assert_eq!(std::fs::read(namer.path(1)).unwrap(), before);
+        assert_eq!(session.segments()[0].bytes, 1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_segments_carry_chapters_and_tags() {
+        let dir = temp_dir("segments_stamped");
+        let config = SegmentConfig { max_seconds: 2, name_template: "{n}.mp4".into(), ..Default::default() };
+        let namer = SegmentNamer::new(&config, &dir.join("game.mp4"), started());
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+        let mut session = SegmentSession::new(namer.clone(), bus);
+        let markers = vec![
+            Marker { time: Duration::from_secs(1), label: "Stage 1-1".into() },
+            Marker { time: Duration::from_millis(3500), label: "Stage 2-1".into() },
+        ];
+        session.set_markers(MarkerList::with_markers(dir.join("game.markers.json"), markers));
+        let tags = MetadataTags { title: Some("TFT".into()), placement: Some(2), ..Default::default() };
+        session.set_tags(tags.clone());
+        for (index, start_ms, frames) in [(1, 0, 20), (2, 2000, 20), (3, 4000, 10)] {
+            session.finalize(closed_segment(&namer, index, start_ms, frames)).unwrap();
+        }
+
+        let chapters: Vec<Vec<(u128, String)>> = session
+            .segments()
+            .iter()
+            .map(|segment| {
+                let reader = Mp4Reader::open(&segment.path).unwrap();
+                assert_eq!(reader.tags, tags);
+                reader.chapters.iter().map(|c| (c.start.as_millis(), c.title.clone())).collect()
+            })
+            .collect();
+        assert_eq!(chapters, [
+            vec![(0, "Start".to_string()), (1000, "Stage 1-1".to_string())],
+            vec![(0, "Stage 1-1".to_string()), (1500, "Stage 2-1".to_string())],
+            vec![(0, "Stage 2-1".to_string())],
+        ]);
+        // Announced with the size of the finished file, which isn't touched again
+        for event in events.try_iter() {
+            let RecorderEvent::SegmentFinalized { path, bytes, .. } = event else { panic!("Expected a segment") };
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), bytes);
+        }
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,155 @@
+// ABOUTME: Frame sources feeding the software pipeline, including a deterministic synthetic one
+// ABOUTME: SyntheticSource simulates capture timing (jitter, stalls) for reproducible tests
+
+use crate::encoder::VideoFrame;
+use anyhow::Result;
+use std::time::Duration;
+
+/// Anything that produces BGRA frames with capture timestamps.
+pub trait FrameSource: Send {
+    /// Returns the next frame, or `None` once the source is exhausted.
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>>;
+}
+
+/// Generates a moving test pattern at a nominal rate, with optional timing
+/// irregularities. Timestamps are simulated, so it never sleeps.
+pub struct SyntheticSource {
+    width: u32,
+    height: u32,
+    interval: Duration,
+    frame_count: u64,
+    index: u64,
+    clock: Duration,
+    jitter: Duration,
+    rng_state: u64,
+    stalls: Vec<(u64, Duration)>,
+}
+
+impl SyntheticSource {
+    pub fn new(width: u32, height: u32, fps: u32, frame_count: u64) -> Self {
+        assert!(fps > 0, "frame rate must be non-zero");
+        Self {
+            width,
+            height,
+            interval: Duration::from_nanos(1_000_000_000 / u64::from(fps)),
+            frame_count,
+            index: 0,
+            clock: Duration::ZERO,
+            jitter: Duration::ZERO,
+            rng_state: 0,
+            stalls: Vec::new(),
+        }
+    }
+
+    /// Offsets every timestamp by up to ±`max` using a seeded generator.
+    pub fn with_jitter(mut self, max: Duration, seed: u64) -> Self {
+        self.jitter = max;
+        self.rng_state = seed.max(1);
+        self
+    }
+
+    /// Freezes the clock for `pause` right before frame `at_frame`, the way a
+    /// static scene stops ScreenCaptureKit from delivering frames.
+    pub fn with_stall(mut self, at_frame: u64, pause: Duration) -> Self {
+        self.stalls.push((at_frame, pause));
+        self
+    }
+
+    pub fn frame_interval(&self) -> Duration {
+        self.interval
+    }
+
+    // xorshift64: tiny, deterministic and good enough for timing noise.
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        x
+    }
+
+    fn jittered(&mut self, pts: Duration) -> Duration {
+        if self.jitter.is_zero() {
+            return pts;
+        }
+        let span = self.jitter.as_nanos() as u64 * 2 + 1;
+        let offset = Duration::from_nanos(self.next_random() % span);
+        (pts + offset).saturating_sub(self.jitter)
+    }
+
+    fn render(&self, index: u64) -> Vec<u8> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let bar_x = (index as usize * 4) % w.max(1);
+        let mut data = vec![0u8; w * h * 4];
+
+        for (i, px) in data.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % w, i / w);
+            let on_bar = x >= bar_x && x < bar_x + 8;
+            px[0] = if on_bar { 255 } else { (x * 255 / w.max(1)) as u8 };
+            px[1] = if on_bar { 255 } else { (y * 255 / h.max(1)) as u8 };
+            px[2] = (index % 256) as u8;
+            px[3] = 255;
+        }
+        data
+    }
+}
+
+impl FrameSource for SyntheticSource {
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>> {
+        if self.index >= self.frame_count {
+            return Ok(None);
+        }
+
+        let index = self.index;
+        for &(at, pause) in &self.stalls {
+            if at == index {
+                self.clock += pause;
+            }
+        }
+
+        let pts = self.jittered(self.clock);
+        let frame = VideoFrame::new(pts, self.width, self.height, self.render(index));
+
+        self.index += 1;
+        self.clock += self.interval;
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamps(mut source: SyntheticSource) -> Vec<Duration> {
+        std::iter::from_fn(|| source.next_frame().unwrap().map(|f| f.pts)).collect()
+    }
+
+    #[test]
+    fn test_nominal_timing() {
+        let pts = timestamps(SyntheticSource::new(8, 8, 50, 5));
+        assert_eq!(pts, (0..5).map(|i| Duration::from_millis(i * 20)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_jitter_is_deterministic_and_bounded() {
+        let a = timestamps(SyntheticSource::new(8, 8, 60, 100).with_jitter(Duration::from_millis(3), 42));
+        let b = timestamps(SyntheticSource::new(8, 8, 60, 100).with_jitter(Duration::from_millis(3), 42));
+        assert_eq!(a, b);
+
+        let nominal = timestamps(SyntheticSource::new(8, 8, 60, 100));
+        for (j, n) in a.iter().zip(&nominal) {
+            let delta = if j > n { *j - *n } else { *n - *j };
+            assert!(delta <= Duration::from_millis(3));
+        }
+    }
+
+    #[test]
+    fn test_frames_have_requested_geometry() {
+        let mut source = SyntheticSource::new(32, 18, 30, 1);
+        let frame = source.next_frame().unwrap().unwrap();
+        assert_eq!((frame.width, frame.height), (32, 18));
+        assert_eq!(frame.data.len(), 32 * 18 * 4);
+        assert!(source.next_frame().unwrap().is_none());
+    }
+}
@@ -0,0 +1,125 @@
+// ABOUTME: Fixtures shared by the unit tests of recorder_core: scratch paths and small recordings
+// ABOUTME: Recordings are H.264 from the software encoder muxed with Mp4Writer, plus fake AAC when asked
+
+use crate::audio::AudioTrackKind;
+use crate::config::{AudioCodec, FrameRateMode, RecordingConfig};
+use crate::encoder::{SoftwareEncoder, VideoFrame};
+use crate::h264::ParameterSets;
+use crate::markers::MarkerList;
+use crate::mp4::{AudioTrackInfo, Mp4Writer, Sample, TrackInfo, VideoTrackInfo};
+use crate::pipeline::encode_source;
+use crate::source::SyntheticSource;
+use crate::thumbnail::RgbFrame;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A scratch `.mp4` path; `name` should be unique across the crate's tests.
+pub(crate) fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("recorder_core_{}_{}.mp4", name, std::process::id()))
+}
+
+/// An empty scratch directory; `name` should be unique across the crate's tests.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("recorder_core_{}_{}", name, std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Removes recordings and their marker sidecars.
+pub(crate) fn cleanup(paths: &[&Path]) {
+    for path in paths {
+        std::fs::remove_file(path).ok();
+        std::fs::remove_file(MarkerList::sidecar_path(path)).ok();
+    }
+}
+
+/// 64x48 at a constant 10 fps with a keyframe every second.
+pub(crate) fn config() -> RecordingConfig {
+    RecordingConfig::new("test", 64, 48).with_frame_rate(10, FrameRateMode::Constant).with_keyframe_interval(10)
+}
+
+/// Frame `index` of a 10 fps recording, flat grey at `shade`.
+pub(crate) fn grey_frame(index: u64, shade: u8) -> VideoFrame {
+    VideoFrame::new(Duration::from_millis(index * 100), 64, 48, [shade, shade, shade, 255].repeat(64 * 48))
+}
+
+pub(crate) fn mean(frame: &RgbFrame) -> u64 {
+    frame.data.iter().map(|&b| u64::from(b)).sum::<u64>() / frame.data.len() as u64
+}
+
+/// Encodes `frames` with `config` into a new MP4 at `path`. The writer is
+/// returned unfinished so more tracks can be added.
+pub(crate) fn write_frames(path: &Path, config: &RecordingConfig, frames: impl IntoIterator<Item = VideoFrame>) -> Mp4Writer<BufWriter<File>> {
+    let mut encoder = SoftwareEncoder::new(config).unwrap();
+    let mut writer = Mp4Writer::create(path).unwrap();
+    let frame_duration = Duration::from_nanos(1_000_000_000 / u64::from(config.fps));
+    let mut track = None;
+    for frame in frames {
+        let packet = encoder.encode(&frame).unwrap().unwrap();
+        let track = *track.get_or_insert_with(|| {
+            writer.add_track(TrackInfo::Video(VideoTrackInfo {
+                width: config.width,
+                height: config.height,
+                parameter_sets: encoder.parameter_sets().unwrap().clone(),
+            }))
+        });
+        let sample = Sample { pts: packet.pts, duration: Some(frame_duration), keyframe: packet.keyframe, data: &packet.data };
+        writer.write_sample(track, sample).unwrap();
+    }
+    writer
+}
+
+/// `frames` of the moving test pattern, paced and encoded by [`encode_source`]
+/// with `config`. Returned unfinished like [`write_frames`].
+pub(crate) fn write_synthetic(path: &Path, config: &RecordingConfig, frames: u64) -> Mp4Writer<BufWriter<File>> {
+    let stream = encode_source(&mut SyntheticSource::new(config.width, config.height, config.fps, frames), config).unwrap();
+    let mut writer = Mp4Writer::create(path).unwrap();
+    let video = writer.add_track(TrackInfo::Video(VideoTrackInfo {
+        width: config.width,
+        height: config.height,
+        parameter_sets: stream.parameter_sets.clone().unwrap(),
+    }));
+    for packet in &stream.packets {
+        let sample = Sample { pts: packet.pts, duration: None, keyframe: packet.keyframe, data: &packet.data };
+        writer.write_sample(video, sample).unwrap();
+    }
+    writer
+}
+
+/// Adds a game audio track of `packets` fake 50 ms AAC packets; enough for
+/// code that copies audio without decoding it.
+pub(crate) fn add_fake_audio(writer: &mut Mp4Writer<BufWriter<File>>, packets: u64) {
+    let audio = writer.add_track(TrackInfo::Audio(AudioTrackInfo {
+        kind: AudioTrackKind::Game,
+        codec: AudioCodec::Aac,
+        sample_rate: 48_000,
+        channels: 2,
+        decoder_config: vec![0x11, 0x90],
+        priming_samples: 0,
+    }));
+    for i in 0..packets {
+        let sample = Sample { pts: Duration::from_millis(i * 50), duration: None, keyframe: true, data: &[i as u8; 8] };
+        writer.write_sample(audio, sample).unwrap();
+    }
+}
+
+/// A 64x48 video track whose parameter sets only look like H.264.
+pub(crate) fn fake_video_info() -> VideoTrackInfo {
+    VideoTrackInfo { width: 64, height: 48, parameter_sets: ParameterSets { sps: vec![0x67, 0x42, 0, 0x1e], pps: vec![0x68, 0xce] } }
+}
+
+/// `frames` of 100 ms that aren't H.264, for code that only moves samples
+/// around: frame `i` is 50 bytes of `i`, with a keyframe every tenth.
+pub(crate) fn write_fake_video(path: &Path, frames: u64) {
+    let mut writer = Mp4Writer::create(path).unwrap();
+    let video = writer.add_track(TrackInfo::Video(fake_video_info()));
+    for i in 0..frames {
+        let data = vec![i as u8; 50];
+        let sample = Sample { pts: Duration::from_millis(i * 100), duration: Some(Duration::from_millis(100)), keyframe: i % 10 == 0, data: &data };
+        writer.write_sample(video, sample).unwrap();
+    }
+    writer.finish().unwrap();
+}
@@ -0,0 +1,308 @@
+// ABOUTME: Decodes frames out of finished recordings with OpenH264
+// ABOUTME: Saves them as PNG/JPEG, with thumbnails cached next to the recording they show
+
+use crate::h264;
+use crate::mp4::reader::Track;
+use crate::mp4::{Mp4Reader, TrackInfo};
+use anyhow::{Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{ImageFormat, RgbImage};
+use openh264::decoder::Decoder;
+use openh264::formats::YUVSource;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Width of cached thumbnails; the height follows the aspect ratio.
+pub const THUMBNAIL_WIDTH: u32 = 320;
+
+/// Where a thumbnail is taken when no time is given, unless the recording
+/// is shorter than twice that (then it's taken half-way).
+pub const DEFAULT_THUMBNAIL_AT: Duration = Duration::from_secs(5 * 60);
+
+const JPEG_QUALITY: u8 = 85;
+
+/// A decoded frame as packed 8-bit RGB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbFrame {
+    /// Presentation time of the frame in the recording.
+    pub time: Duration,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl RgbFrame {
+    /// Reads a PNG or JPEG, e.g. a cached thumbnail.
+    pub fn open(path: &Path) -> Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .into_rgb8();
+        let (width, height) = image.dimensions();
+        Ok(Self { time: Duration::ZERO, width, height, data: image.into_raw() })
+    }
+
+    /// The frame scaled down to at most `width` pixels across.
+    pub fn scaled_to_width(&self, width: u32) -> Self {
+        if width == 0 || width >= self.width {
+            return self.clone();
+        }
+        let height = ((u64::from(self.height) * u64::from(width)) / u64::from(self.width)).max(1) as u32;
+        let scaled = image::imageops::resize(&self.image(), width, height, FilterType::Triangle);
+        Self { time: self.time, width, height, data: scaled.into_raw() }
+    }
+
+    /// Writes the frame as PNG or JPEG, picked by the extension of `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let format = ImageFormat::from_path(path)
+            .ok()
+            .filter(|f| matches!(f, ImageFormat::Png | ImageFormat::Jpeg))
+            .with_context(|| format!("{} should end in .png, .jpg or .jpeg", path.display()))?;
+        let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        match format {
+            ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY).encode_image(&self.image()),
+            _ => self.image().write_to(&mut out, format),
+        }
+        .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn image(&self) -> RgbImage {
+        RgbImage::from_raw(self.width, self.height, self.data.clone()).expect("frame buffer matches its size")
+    }
+}
+
+/// Decodes the frame shown at `at`. Without `exact` this is the keyframe at
+/// or before `at`, which needs a single decode; with it, every frame from
+/// that keyframe up to `at` is decoded.
+pub fn extract_frame(recording: &Path, at: Duration, exact: bool) -> Result<RgbFrame> {
+    let mut reader = Mp4Reader::open(recording)?;
+    let video = reader.video_track().context("Recording has no video track")?.clone();
+    anyhow::ensure!(!video.samples.is_empty(), "Recording has no video frames");
+    anyhow::ensure!(
+        at < reader.duration,
+        "{:.3} s is past the end of the recording ({:.3} s)",
+        at.as_secs_f64(),
+        reader.duration.as_secs_f64()
+    );
+    anyhow::ensure!(
+        !(exact && video.reordered),
+        "{} uses B-frames; only keyframes can be extracted",
+        recording.display()
+    );
+
+    let shown = shown_at(&video, at);
+    let keyframe = keyframe_before(&video, shown)?;
+    let target = if exact { shown } else { keyframe };
+
+    let mut decoder = FrameDecoder::new()?;
+    for index in keyframe..target {
+        decoder.decode(&mut reader, &video, index, false)?;
+    }
+    decoder
+        .decode(&mut reader, &video, target, true)?
+        .with_context(|| format!("Decoder produced no picture for frame {}", target))
+}
+
+/// Decodes every frame shown from `from` up to `to` (or the end) and hands
+/// them to `frame` in order, starting with the one on screen at `from`.
+pub fn decode_frames(recording: &Path, from: Duration, to: Option<Duration>, mut frame: impl FnMut(RgbFrame) -> Result<()>) -> Result<()> {
+    let mut reader = Mp4Reader::open(recording)?;
+    let video = reader.video_track().context("Recording has no video track")?.clone();
+    anyhow::ensure!(!video.samples.is_empty(), "Recording has no video frames");
+    anyhow::ensure!(
+        from < reader.duration,
+        "{:.3} s is past the end of the recording ({:.3} s)",
+        from.as_secs_f64(),
+        reader.duration.as_secs_f64()
+    );
+    anyhow::ensure!(!video.reordered, "{} uses B-frames, which can't be decoded in order", recording.display());
+
+    let first = shown_at(&video, from);
+    let end = match to {
+        Some(to) => video.samples.partition_point(|s| video.sample_time(s) < to).max(first + 1),
+        None => video.samples.len(),
+    };
+    let mut decoder = FrameDecoder::new()?;
+    for index in keyframe_before(&video, first)?..end {
+        if let Some(picture) = decoder.decode(&mut reader, &video, index, index >= first)? {
+            frame(picture)?;
+        }
+    }
+    Ok(())
+}
+
+/// Index of the frame on screen at `at`.
+fn shown_at(video: &Track, at: Duration) -> usize {
+    video.samples.partition_point(|s| video.sample_time(s) <= at).saturating_sub(1)
+}
+
+fn keyframe_before(video: &Track, index: usize) -> Result<usize> {
+    video.samples[..=index]
+        .iter()
+        .rposition(|s| s.keyframe)
+        .context("No keyframe before the requested time")
+}
+
+/// OpenH264 fed with a track's samples in decode order.
+struct FrameDecoder {
+    decoder: Decoder,
+    description: Option<u32>,
+}
+
+impl FrameDecoder {
+    fn new() -> Result<Self> {
+        Ok(Self { decoder: Decoder::new().context("Failed to create OpenH264 decoder")?, description: None })
+    }
+
+    /// Decodes frame `index`, converting the picture to RGB only when `keep` is set.
+    fn decode(&mut self, reader: &mut Mp4Reader, video: &Track, index: usize, keep: bool) -> Result<Option<RgbFrame>> {
+        let sample = video.samples[index];
+        let data = reader.read_sample(&sample)?;
+        // Parameter sets go in front of the first frame and after every switch
+        let params = match &video.sample_descriptions[sample.description as usize] {
+            TrackInfo::Video(v) if self.description != Some(sample.description) => Some(&v.parameter_sets),
+            _ => None,
+        };
+        self.description = Some(sample.description);
+        let annexb = h264::avcc_to_annexb(&data, params);
+        let picture = self
+            .decoder
+            .decode(&annexb)
+            .with_context(|| format!("Failed to decode frame {}", index))?;
+        let Some(picture) = picture.filter(|_| keep) else {
+            return Ok(None);
+        };
+
+        let (width, height) = picture.dimensions();
+        let mut rgb = vec![0u8; width * height * 3];
+        picture.write_rgb8(&mut rgb);
+        Ok(Some(RgbFrame {
+            time: video.sample_time(&sample),
+            width: width as u32,
+            height: height as u32,
+            data: rgb,
+        }))
+    }
+}
+
+/// `TFT-x.mp4` caches its default thumbnail as `TFT-x.thumb.jpg`, and the one
+/// at 5:00 as `TFT-x.thumb-300s.jpg`.
+pub fn thumbnail_path(recording: &Path, at: Option<Duration>) -> PathBuf {
+    match at {
+        Some(at) => recording.with_extension(format!("thumb-{}s.jpg", at.as_secs())),
+        None => recording.with_extension("thumb.jpg"),
+    }
+}
+
+/// The cached thumbnail of `recording` at `at` (whole seconds), made from the
+/// nearest keyframe if it's missing or older than the recording.
+pub fn thumbnail(recording: &Path, at: Option<Duration>) -> Result<PathBuf> {
+    let path = thumbnail_path(recording, at);
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    if let (Some(cached), Some(source)) = (modified(&path), modified(recording)) {
+        if cached >= source {
+            return Ok(path);
+        }
+    }
+
+    let time = match at {
+        Some(at) => Duration::from_secs(at.as_secs()),
+        None => DEFAULT_THUMBNAIL_AT.min(Mp4Reader::open(recording)?.duration / 2),
+    };
+    let frame = extract_frame(recording, time, false)?.scaled_to_width(THUMBNAIL_WIDTH);
+    if let Err(e) = frame.save(&path) {
+        // A half-written file would look like a fresh cache entry
+        std::fs::remove_file(&path).ok();
+        return Err(e);
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{config, grey_frame, mean, temp_file, write_frames};
+
+    /// 3 s of flat grey at 10 fps, one shade lighter every frame, with
+    /// keyframes every second.
+    fn write_recording(path: &Path) {
+        write_frames(path, &config(), (0..30).map(|i| grey_frame(i, 40 + 4 * i as u8))).finish().unwrap();
+    }
+
+    #[test]
+    fn test_keyframe_and_exact_extraction() {
+        let path = temp_file("thumbs_extract");
+        write_recording(&path);
+
+        let keyframe = extract_frame(&path, Duration::from_millis(1250), false).unwrap();
+        assert_eq!((keyframe.time, keyframe.width, keyframe.height), (Duration::from_secs(1), 64, 48));
+        assert!(mean(&keyframe).abs_diff(80) <= 3, "{}", mean(&keyframe));
+
+        let exact = extract_frame(&path, Duration::from_millis(1250), true).unwrap();
+        assert_eq!(exact.time, Duration::from_millis(1200));
+        assert!(mean(&exact).abs_diff(88) <= 3, "{}", mean(&exact));
+
+        assert!(extract_frame(&path, Duration::from_secs(3), false).is_err());
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_decoding_a_range() {
+        let path = temp_file("thumbs_range");
+        write_recording(&path);
+
+        let mut times = Vec::new();
+        decode_frames(&path, Duration::from_millis(1250), Some(Duration::from_millis(1600)), |frame| {
+            times.push(frame.time);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(times, [1200, 1300, 1400, 1500].map(Duration::from_millis));
+
+        let mut frames = 0;
+        decode_frames(&path, Duration::from_millis(2900), None, |_| {
+            frames += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(frames, 1);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_thumbnails_are_cached_next_to_the_recording() {
+        let path = temp_file("thumbs_cache");
+        write_recording(&path);
+        assert_eq!(thumbnail_path(&path, Some(Duration::from_millis(2500))), path.with_extension("thumb-2s.jpg"));
+
+        let cached = thumbnail(&path, None).unwrap();
+        assert_eq!(cached, path.with_extension("thumb.jpg"));
+        let first = std::fs::metadata(&cached).unwrap().modified().unwrap();
+        // Half-way through a short recording: the keyframe at 1 s
+        let image = RgbFrame::open(&cached).unwrap();
+        assert_eq!((image.width, image.height), (64, 48));
+        assert!(mean(&image).abs_diff(80) <= 4);
+
+        assert_eq!(thumbnail(&path, None).unwrap(), cached);
+        assert_eq!(std::fs::metadata(&cached).unwrap().modified().unwrap(), first);
+        std::fs::remove_file(cached).ok();
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_scaling_and_png_round_trip() {
+        let frame = RgbFrame { time: Duration::ZERO, width: 64, height: 36, data: (0..64 * 36 * 3).map(|i| (i % 251) as u8).collect() };
+        let small = frame.scaled_to_width(32);
+        assert_eq!((small.width, small.height, small.data.len()), (32, 18, 32 * 18 * 3));
+        assert_eq!(frame.scaled_to_width(THUMBNAIL_WIDTH), frame);
+
+        let png = std::env::temp_dir().join(format!("thumbs_frame_{}.png", std::process::id()));
+        frame.save(&png).unwrap();
+        assert_eq!(RgbFrame::open(&png).unwrap(), frame);
+        assert!(frame.save(&png.with_extension("bmp")).is_err());
+        std::fs::remove_file(png).ok();
+    }
+}
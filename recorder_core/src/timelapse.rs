@@ -0,0 +1,255 @@
+// ABOUTME: Turns a full recording into a short timelapse by sampling frames and re-encoding them
+// ABOUTME: Samples at a speed-up factor, at fixed intervals or at the recording's markers
+
+use crate::config::{FrameRateMode, RateControl, RecordingConfig};
+use crate::encoder::SoftwareEncoder;
+use crate::markers::{Marker, MarkerList};
+use crate::mp4::{write_chapters, Mp4Reader, Mp4Writer, Sample, TrackInfo, VideoTrackInfo};
+use crate::thumbnail::{self, RgbFrame};
+use anyhow::{Context, Result};
+use openh264::formats::{RgbSliceU8, YUVBuffer};
+use std::io::{Seek, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// How long each frame is shown when sampling at markers.
+pub const MARKER_FRAME_LENGTH: Duration = Duration::from_secs(1);
+
+const TIMELAPSE_CRF: u8 = 23;
+
+/// Most frames a timelapse samples; finer steps are mistakes, not timelapses.
+const MAX_FRAMES: usize = 1_000_000;
+
+/// Which frames of the recording end up in the timelapse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimelapseSampling {
+    /// Play the recording this many times faster.
+    Speed(f64),
+    /// One frame per interval of the recording.
+    Interval(Duration),
+    /// One frame per marker, each shown for `MARKER_FRAME_LENGTH`.
+    Markers,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimelapseOptions {
+    pub sampling: TimelapseSampling,
+    /// Frame rate of the timelapse; ignored when sampling at markers.
+    pub fps: u32,
+}
+
+impl Default for TimelapseOptions {
+    fn default() -> Self {
+        Self { sampling: TimelapseSampling::Speed(20.0), fps: 30 }
+    }
+}
+
+/// What `timelapse` wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelapseResult {
+    pub frames: usize,
+    pub duration: Duration,
+    pub markers: usize,
+    pub bytes: u64,
+}
+
+/// Writes a timelapse of the recording at `input` to `output`. Markers are
+/// carried over as chapters at the first frame sampled at or after them.
+pub fn timelapse(input: impl AsRef<Path>, output: impl AsRef<Path>, options: &TimelapseOptions) -> Result<TimelapseResult> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    anyhow::ensure!(input != output, "Timelapse output must differ from the input");
+    anyhow::ensure!(options.fps > 0, "Frame rate must be at least 1");
+
+    let reader = Mp4Reader::open(input)?;
+    let markers = MarkerList::for_recording(input, &reader.chapters)?;
+    let length = reader.duration;
+    let (targets, frame_length) = match options.sampling {
+        TimelapseSampling::Speed(speed) => {
+            anyhow::ensure!(speed > 0.0, "Speed must be above 0x");
+            let step = Duration::try_from_secs_f64(speed / f64::from(options.fps)).ok().filter(|step| !step.is_zero());
+            let step = step.with_context(|| format!("Speed {}x is out of range", speed))?;
+            (every(step, length)?, Duration::from_secs_f64(1.0 / f64::from(options.fps)))
+        }
+        TimelapseSampling::Interval(interval) => {
+            anyhow::ensure!(!interval.is_zero(), "Sampling interval must be above 0");
+            (every(interval, length)?, Duration::from_secs_f64(1.0 / f64::from(options.fps)))
+        }
+        TimelapseSampling::Markers => {
+            let times: Vec<Duration> = markers.markers().iter().map(|m| m.time).filter(|&t| t < length).collect();
+            anyhow::ensure!(!times.is_empty(), "{} has no markers to sample at", input.display());
+            (times, MARKER_FRAME_LENGTH)
+        }
+    };
+
+    let video = reader.video_track().context("Recording has no video track")?;
+    let TrackInfo::Video(info) = &video.info else {
+        anyhow::bail!("Video track has an audio sample description");
+    };
+    let fps = (1.0 / frame_length.as_secs_f64()).round().max(1.0) as u32;
+    let config = RecordingConfig::new("timelapse", info.width, info.height)
+        .with_rate_control(RateControl::Crf { crf: TIMELAPSE_CRF, max_bitrate: None })
+        .with_frame_rate(fps, FrameRateMode::Variable);
+    let mut output_file = Output { encoder: SoftwareEncoder::new(&config)?, writer: Mp4Writer::create(output)?, track: None, frame_length, frames: 0, bytes: 0 };
+
+    if options.sampling == TimelapseSampling::Markers {
+        // Markers are sparse; decoding from each one's keyframe beats decoding everything
+        for &time in &targets {
+            output_file.add(&thumbnail::extract_frame(input, time, true)?)?;
+        }
+    } else {
+        // The frame shown at each target is the last one decoded before the next frame passes it
+        let mut next = 0;
+        let mut shown: Option<RgbFrame> = None;
+        thumbnail::decode_frames(input, Duration::ZERO, None, |frame| {
+            if let Some(previous) = &shown {
+                while next < targets.len() && targets[next] < frame.time {
+                    output_file.add(previous)?;
+                    next += 1;
+                }
+            }
+            shown = Some(frame);
+            Ok(())
+        })?;
+        let last = shown.context("Recording has no video frames")?;
+        for _ in next..targets.len() {
+            output_file.add(&last)?;
+        }
+    }
+    let (frames, bytes) = (output_file.frames, output_file.bytes);
+    anyhow::ensure!(output_file.track.is_some(), "Encoder produced no frames");
+    output_file.writer.finish()?;
+
+    let duration = frame_length * frames as u32;
+    let shifted: Vec<Marker> = markers
+        .markers()
+        .iter()
+        .map(|m| Marker { time: frame_length * targets.partition_point(|&t| t < m.time) as u32, label: m.label.clone() })
+        .filter(|m| m.time < duration)
+        .collect();
+    let list = MarkerList::with_markers(MarkerList::sidecar_path(output), shifted);
+    if !list.is_empty() {
+        list.save()?;
+        write_chapters(output, &list.chapters(Duration::ZERO, duration))?;
+    }
+    Ok(TimelapseResult { frames, duration, markers: list.markers().len(), bytes })
+}
+
+/// `0, step, 2 * step, ...` up to `length`, refusing steps so fine they
+/// would sample more than `MAX_FRAMES`.
+fn every(step: Duration, length: Duration) -> Result<Vec<Duration>> {
+    anyhow::ensure!(!step.is_zero(), "Sampling step must be above 0");
+    let count = length.as_nanos().div_ceil(step.as_nanos());
+    anyhow::ensure!(count <= MAX_FRAMES as u128, "Sampling every {:?} would make {} frames (at most {})", step, count, MAX_FRAMES);
+    Ok((0..).map_while(|i| step.checked_mul(i)).take_while(|&t| t < length).collect())
+}
+
+/// The timelapse being written: each frame added lasts `frame_length`.
+struct Output<W: Write + Seek> {
+    encoder: SoftwareEncoder,
+    writer: Mp4Writer<W>,
+    track: Option<usize>,
+    frame_length: Duration,
+    frames: usize,
+    bytes: u64,
+}
+
+impl<W: Write + Seek> Output<W> {
+    fn add(&mut self, frame: &RgbFrame) -> Result<()> {
+        let pts = self.frame_length * self.frames as u32;
+        self.frames += 1;
+        let yuv = YUVBuffer::from_rgb8_source(RgbSliceU8::new(&frame.data, (frame.width as usize, frame.height as usize)));
+        let Some(packet) = self.encoder.encode_yuv(&yuv, pts)? else {
+            return Ok(());
+        };
+        let track = match self.track {
+            Some(track) => track,
+            None => {
+                let parameter_sets = self.encoder.parameter_sets().context("Encoder produced no parameter sets")?.clone();
+                let info = VideoTrackInfo { width: frame.width, height: frame.height, parameter_sets };
+                *self.track.insert(self.writer.add_track(TrackInfo::Video(info)))
+            }
+        };
+        let sample = Sample { pts: packet.pts, duration: Some(self.frame_length), keyframe: packet.keyframe, data: &packet.data };
+        self.writer.write_sample(track, sample)?;
+        self.bytes += packet.data.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{cleanup, config, grey_frame, mean, temp_file, write_frames};
+
+    /// 3 s at 10 fps, getting lighter every frame, with markers at 0.5 s and 2 s.
+    fn write_recording(path: &Path) {
+        write_frames(path, &config(), (0..30).map(|i| grey_frame(i, 40 + 5 * i as u8))).finish().unwrap();
+
+        let mut markers = MarkerList::new(MarkerList::sidecar_path(path));
+        markers.add(Marker { time: Duration::from_millis(500), label: "Stage 1-3".into() }).unwrap();
+        markers.add(Marker { time: Duration::from_secs(2), label: "Stage 2-5".into() }).unwrap();
+    }
+
+    #[test]
+    fn test_speed_and_interval_sampling() {
+        let (input, output) = (temp_file("timelapse_speed_in"), temp_file("timelapse_speed_out"));
+        write_recording(&input);
+
+        // 10x at 10 fps: one frame per second of the recording
+        let options = TimelapseOptions { sampling: TimelapseSampling::Speed(10.0), fps: 10 };
+        let result = timelapse(&input, &output, &options).unwrap();
+        assert_eq!((result.frames, result.duration), (3, Duration::from_millis(300)));
+        let reader = Mp4Reader::open(&output).unwrap();
+        assert_eq!(reader.video_track().unwrap().samples.len(), 3);
+        // The 2 s marker lands on the third frame
+        let starts: Vec<Duration> = reader.chapters.iter().map(|c| c.start).collect();
+        assert!(starts.contains(&Duration::from_millis(200)), "{:?}", starts);
+        let last = thumbnail::extract_frame(&output, Duration::from_millis(250), true).unwrap();
+        assert!(mean(&last).abs_diff(140) <= 4, "{}", mean(&last));
+
+        let options = TimelapseOptions { sampling: TimelapseSampling::Interval(Duration::from_millis(500)), fps: 10 };
+        assert_eq!(timelapse(&input, &output, &options).unwrap().frames, 6);
+        cleanup(&[&input, &output]);
+    }
+
+    #[test]
+    fn test_sampling_steps_out_of_range() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(every(Duration::from_secs(25), minute).unwrap(), [Duration::ZERO, Duration::from_secs(25), Duration::from_secs(50)]);
+        assert!(every(Duration::ZERO, minute).is_err());
+        assert!(every(Duration::from_nanos(1), minute).is_err());
+        // Multiples past Duration::MAX end the list instead of overflowing
+        assert_eq!(every(Duration::MAX, Duration::MAX).unwrap(), [Duration::ZERO]);
+
+        let (input, output) = (temp_file("timelapse_range_in"), temp_file("timelapse_range_out"));
+        write_recording(&input);
+        for speed in [1e-12, 1e-6, f64::INFINITY, 1e300, f64::NAN, -2.0] {
+            let options = TimelapseOptions { sampling: TimelapseSampling::Speed(speed), fps: 30 };
+            assert!(timelapse(&input, &output, &options).is_err(), "{}x", speed);
+        }
+        let options = TimelapseOptions { sampling: TimelapseSampling::Interval(Duration::MAX), fps: 30 };
+        assert_eq!(timelapse(&input, &output, &options).unwrap().frames, 1);
+        cleanup(&[&input, &output]);
+    }
+
+    #[test]
+    fn test_marker_sampling_holds_each_frame() {
+        let (input, output) = (temp_file("timelapse_markers_in"), temp_file("timelapse_markers_out"));
+        write_recording(&input);
+
+        let options = TimelapseOptions { sampling: TimelapseSampling::Markers, ..Default::default() };
+        let result = timelapse(&input, &output, &options).unwrap();
+        assert_eq!((result.frames, result.duration, result.markers), (2, Duration::from_secs(2), 2));
+        let reader = Mp4Reader::open(&output).unwrap();
+        let titles: Vec<(Duration, &str)> = reader.chapters.iter().map(|c| (c.start, c.title.as_str())).collect();
+        assert_eq!(titles, [(Duration::ZERO, "Stage 1-3"), (Duration::from_secs(1), "Stage 2-5")]);
+        let second = thumbnail::extract_frame(&output, Duration::from_millis(1500), true).unwrap();
+        assert!(mean(&second).abs_diff(140) <= 4, "{}", mean(&second));
+
+        cleanup(&[&output]);
+        std::fs::remove_file(MarkerList::sidecar_path(&input)).ok();
+        let error = timelapse(&input, &output, &options).unwrap_err();
+        assert!(error.to_string().contains("has no markers"), "{}", error);
+        cleanup(&[&input, &output]);
+    }
+}
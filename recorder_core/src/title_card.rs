@@ -0,0 +1,149 @@
+// ABOUTME: Renders simple title-card frames (centred lines of text on a dark background)
+// ABOUTME: Uses a built-in 5x7 bitmap font so no font files or rasterizer are needed
+
+use crate::thumbnail::RgbFrame;
+use std::time::Duration;
+
+const BACKGROUND: [u8; 3] = [18, 22, 32];
+const TITLE_COLOR: [u8; 3] = [240, 190, 80];
+const TEXT_COLOR: [u8; 3] = [230, 230, 230];
+
+/// Glyph size in font pixels; glyphs are one column apart and lines three rows.
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+/// A `width`x`height` card with `lines` centred on it; the first line is the
+/// title, drawn larger. Lines too long for the card are drawn smaller.
+pub fn render(width: u32, height: u32, lines: &[&str]) -> RgbFrame {
+    let mut data = BACKGROUND.repeat((width * height) as usize);
+    let base = (height / 60).max(1);
+    let scales: Vec<u32> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let wanted = if i == 0 { base * 2 } else { base };
+            let cells = line.chars().count().max(1) as u32 * (GLYPH_WIDTH + 1);
+            wanted.min(width * 9 / 10 / cells).max(1)
+        })
+        .collect();
+
+    let line_height = |scale: u32| (GLYPH_HEIGHT + 3) * scale;
+    let total: u32 = scales.iter().map(|&s| line_height(s)).sum();
+    let mut y = height.saturating_sub(total) / 2;
+    for (i, (line, &scale)) in lines.iter().zip(&scales).enumerate() {
+        let color = if i == 0 { TITLE_COLOR } else { TEXT_COLOR };
+        // The spacing after the last glyph isn't part of the line
+        let line_width = (line.chars().count() as u32 * (GLYPH_WIDTH + 1)).saturating_sub(1) * scale;
+        let mut x = width.saturating_sub(line_width) / 2;
+        for c in line.chars() {
+            draw_glyph(&mut data, width, height, x, y, scale, glyph(c), color);
+            x += (GLYPH_WIDTH + 1) * scale;
+        }
+        y += line_height(scale);
+    }
+    RgbFrame { time: Duration::ZERO, width, height, data }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_glyph(data: &mut [u8], width: u32, height: u32, left: u32, top: u32, scale: u32, rows: [u8; 7], color: [u8; 3]) {
+    for (row, bits) in rows.iter().enumerate() {
+        for col in 0..GLYPH_WIDTH {
+            if bits & (0x10 >> col) == 0 {
+                continue;
+            }
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    let (x, y) = (left + col * scale + dx, top + row as u32 * scale + dy);
+                    if x < width && y < height {
+                        let offset = ((y * width + x) * 3) as usize;
+                        data[offset..offset + 3].copy_from_slice(&color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rows of `c`, top to bottom, with the leftmost pixel in bit 4. Lower case
+/// is drawn as upper case and anything unknown as `?`.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        '\'' => [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x00, 0x00, 0x04],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(frame: &RgbFrame, color: [u8; 3]) -> usize {
+        frame.data.chunks_exact(3).filter(|px| *px == color).count()
+    }
+
+    #[test]
+    fn test_text_is_centred_and_sized_to_fit() {
+        let card = render(120, 60, &["I"]);
+        assert_eq!((card.width, card.height, card.data.len()), (120, 60, 120 * 60 * 3));
+        // "I" has 11 pixels, drawn at twice the base scale of 1
+        assert_eq!(lit(&card, TITLE_COLOR), 11 * 4);
+        let columns: Vec<usize> = (0..120).filter(|x| (0..60).any(|y| card.data[(y * 120 + x) * 3..][..3] == TITLE_COLOR)).collect();
+        let (left, right) = (columns[0], *columns.last().unwrap());
+        assert_eq!(left + right, 120 - 1, "glyph should sit in the middle ({}..={})", left, right);
+
+        // Far too long for the card: falls back to the smallest scale
+        let long = "X".repeat(40);
+        let card = render(120, 60, &["T", &long]);
+        assert!(lit(&card, TEXT_COLOR) > 0);
+        assert_eq!(glyph('q'), glyph('Q'));
+        assert_eq!(glyph('€'), glyph('?'));
+    }
+}
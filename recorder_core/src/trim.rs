@@ -0,0 +1,358 @@
+// ABOUTME: Cuts a time range out of a finished recording without re-encoding it
+// ABOUTME: Copies samples from the keyframe before the cut, optionally re-encoding that first GOP
+
+use crate::config::{FrameRateMode, RateControl, RecordingConfig, MAX_FPS};
+use crate::encoder::{EncodedPacket, SoftwareEncoder};
+use crate::h264;
+use crate::markers::{Marker, MarkerList};
+use crate::mp4::reader::{ticks_to_duration, Track};
+use crate::mp4::{
+    write_chapters, write_tags, AudioTrackInfo, Mp4Reader, Mp4Writer, Sample, TrackInfo, VideoTrackInfo,
+};
+use anyhow::{Context, Result};
+use openh264::decoder::Decoder;
+use std::io::{Seek, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Quality of the re-encoded edge GOP; high enough that the seam doesn't show.
+const EDGE_CRF: u8 = 18;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrimOptions {
+    pub from: Duration,
+    /// End of the range; the end of the recording when `None`.
+    pub to: Option<Duration>,
+    /// Start exactly at `from` by re-encoding the frames between it and the
+    /// next keyframe. Otherwise the cut moves back to the previous keyframe.
+    pub exact: bool,
+}
+
+/// What `trim` wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrimResult {
+    /// Where the output starts in the source recording.
+    pub start: Duration,
+    /// Where the output ends in the source recording.
+    pub end: Duration,
+    pub video_frames: usize,
+    /// Frames re-encoded for an exact start; 0 for a lossless trim.
+    pub reencoded_frames: usize,
+    pub markers: usize,
+    pub bytes: u64,
+}
+
+impl TrimResult {
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// Writes `[options.from, options.to)` of the recording at `input` to
+/// `output`. Markers (from the sidecar, or the chapters of the file) and
+/// embedded metadata are carried over with times shifted to the new start.
+pub fn trim(input: impl AsRef<Path>, output: impl AsRef<Path>, options: &TrimOptions) -> Result<TrimResult> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    anyhow::ensure!(input != output, "Trim output must differ from the input");
+
+    let mut reader = Mp4Reader::open(input)?;
+    let video = reader.video_track().context("Recording has no video track")?.clone();
+    anyhow::ensure!(
+        !video.reordered,
+        "{} uses B-frames, which can't be trimmed without re-encoding",
+        input.display()
+    );
+    anyhow::ensure!(!video.samples.is_empty(), "Recording has no video frames");
+
+    let length = reader.duration;
+    let to = options.to.map_or(length, |to| to.min(length));
+    anyhow::ensure!(options.from < to, "Nothing to trim between {:?} and {:?}", options.from, to);
+
+    let times: Vec<Duration> = video.samples.iter().map(|s| video.sample_time(s)).collect();
+    // The frame on screen at `from`, and the first frame shown at or after `to`
+    let first = times.partition_point(|&t| t <= options.from).saturating_sub(1);
+    let end_index = times.partition_point(|&t| t < to);
+    anyhow::ensure!(first < end_index, "No video frames between {:?} and {:?}", options.from, to);
+    let keyframe = video.samples[..=first]
+        .iter()
+        .rposition(|s| s.keyframe)
+        .context("No keyframe before the start of the trim")?;
+
+    let start_index = if options.exact { first } else { keyframe };
+    let origin = times[start_index];
+    let last = &video.samples[end_index - 1];
+    let end = times[end_index - 1] + ticks_to_duration(u64::from(last.duration), video.timescale);
+
+    // The edge GOP is re-encoded up to the next keyframe, where copying resumes
+    let copy_from = if start_index == keyframe {
+        start_index
+    } else {
+        (start_index..end_index).find(|&i| video.samples[i].keyframe).unwrap_or(end_index)
+    };
+    let (first_description, reencoded) = if copy_from > start_index {
+        reencode_edge(&mut reader, &video, keyframe, start_index..copy_from, origin)?
+    } else {
+        let description = video.sample_descriptions[video.samples[start_index].description as usize].clone();
+        (description, Vec::new())
+    };
+
+    let mut writer = Mp4Writer::create(output)?;
+    let video_track = writer.add_track(first_description.clone());
+    let mut bytes = 0u64;
+    for packet in &reencoded {
+        writer.write_sample(
+            video_track,
+            Sample { pts: packet.pts, duration: None, keyframe: packet.keyframe, data: &packet.data },
+        )?;
+        bytes += packet.data.len() as u64;
+    }
+
+    let mut description = first_description;
+    for sample in &video.samples[copy_from..end_index] {
+        let wanted = &video.sample_descriptions[sample.description as usize];
+        if *wanted != description {
+            writer.switch_sample_entry(video_track, wanted.clone())?;
+            description = wanted.clone();
+        }
+        let data = reader.read_sample(sample)?;
+        writer.write_sample(
+            video_track,
+            Sample {
+                pts: video.sample_time(sample) - origin,
+                duration: Some(ticks_to_duration(u64::from(sample.duration), video.timescale)),
+                keyframe: sample.keyframe,
+                data: &data,
+            },
+        )?;
+        bytes += data.len() as u64;
+    }
+
+    bytes += copy_audio(&mut reader, &mut writer, origin, end)?;
+    writer.finish()?;
+
+    let markers = carry_markers(input, &reader, output, origin, end)?;
+    if !reader.tags.is_empty() {
+        write_tags(output, &reader.tags)?;
+    }
+
+    Ok(TrimResult {
+        start: origin,
+        end,
+        video_frames: reencoded.len() + (end_index - copy_from),
+        reencoded_frames: reencoded.len(),
+        markers,
+        bytes,
+    })
+}
+
+/// Decodes from `keyframe` and re-encodes the frames in `range` so the output
+/// can start on a frame that isn't a keyframe in the source. Returns the
+/// sample description of the new packets along with them.
+fn reencode_edge(
+    reader: &mut Mp4Reader,
+    video: &Track,
+    keyframe: usize,
+    range: std::ops::Range<usize>,
+    origin: Duration,
+) -> Result<(TrackInfo, Vec<EncodedPacket>)> {
+    let TrackInfo::Video(info) = &video.sample_descriptions[video.samples[keyframe].description as usize] else {
+        anyhow::bail!("Video track has an audio sample description");
+    };
+    let frame_ticks = video.samples[keyframe].duration.max(1);
+    let fps = (f64::from(video.timescale) / f64::from(frame_ticks)).round().clamp(1.0, f64::from(MAX_FPS)) as u32;
+    let config = RecordingConfig::new("trim", info.width, info.height)
+        .with_rate_control(RateControl::Crf { crf: EDGE_CRF, max_bitrate: None })
+        .with_frame_rate(fps, FrameRateMode::Variable)
+        .with_keyframe_interval(range.len() as u32 + 1);
+    let mut encoder = SoftwareEncoder::new(&config)?;
+    let mut decoder = Decoder::new().context("Failed to create OpenH264 decoder")?;
+
+    let mut packets = Vec::new();
+    for index in keyframe..range.end {
+        let sample = video.samples[index];
+        let data = reader.read_sample(&sample)?;
+        let params = match &video.sample_descriptions[sample.description as usize] {
+            TrackInfo::Video(v) if index == keyframe => Some(&v.parameter_sets),
+            _ => None,
+        };
+        let annexb = h264::avcc_to_annexb(&data, params);
+        let picture = decoder
+            .decode(&annexb)
+            .with_context(|| format!("Failed to decode frame {}", index))?;
+        if index < range.start {
+            continue;
+        }
+        let picture = picture.with_context(|| format!("Decoder produced no picture for frame {}", index))?;
+        if let Some(packet) = encoder.encode_yuv(&picture, video.sample_time(&sample) - origin)? {
+            packets.push(packet);
+        }
+    }
+
+    anyhow::ensure!(
+        packets.first().is_some_and(|p| p.keyframe),
+        "Re-encoded edge doesn't start with a keyframe"
+    );
+    let parameter_sets = encoder.parameter_sets().context("Encoder produced no parameter sets")?.clone();
+    let description = TrackInfo::Video(VideoTrackInfo { parameter_sets, ..info.clone() });
+    Ok((description, packets))
+}
+
+/// Copies the audio samples that start inside `[start, end)` to new tracks
+/// of `writer`, shifted to begin at `start`. Returns the bytes written.
+pub(crate) fn copy_audio<W: Write + Seek>(reader: &mut Mp4Reader, writer: &mut Mp4Writer<W>, start: Duration, end: Duration) -> Result<u64> {
+    let audio: Vec<Track> = reader.tracks.iter().filter(|t| !t.is_video()).cloned().collect();
+    let mut bytes = 0;
+    for track in &audio {
+        let TrackInfo::Audio(info) = &track.info else { continue };
+        // Mid-stream audio has no encoder delay to hide
+        let id = writer.add_track(TrackInfo::Audio(AudioTrackInfo { priming_samples: 0, ..info.clone() }));
+        for sample in &track.samples {
+            let time = track.sample_time(sample);
+            if time < start || time >= end {
+                continue;
+            }
+            let data = reader.read_sample(sample)?;
+            writer.write_sample(
+                id,
+                Sample {
+                    pts: time - start,
+                    duration: Some(ticks_to_duration(u64::from(sample.duration), track.timescale)),
+                    keyframe: true,
+                    data: &data,
+                },
+            )?;
+            bytes += data.len() as u64;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Writes the markers inside `[start, end)` next to `output` and as its
+/// chapters. Returns how many markers were carried over.
+fn carry_markers(input: &Path, reader: &Mp4Reader, output: &Path, start: Duration, end: Duration) -> Result<usize> {
+    let source = MarkerList::for_recording(input, &reader.chapters)?;
+
+    let shifted: Vec<Marker> = source
+        .markers()
+        .iter()
+        .filter(|m| m.time >= start && m.time < end)
+        .map(|m| Marker { time: m.time - start, label: m.label.clone() })
+        .collect();
+    let count = shifted.len();
+    if count > 0 {
+        MarkerList::with_markers(MarkerList::sidecar_path(output), shifted).save()?;
+    }
+
+    let chapters = source.chapters(start, end - start);
+    if !chapters.is_empty() {
+        write_chapters(output, &chapters)?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::VideoFrame;
+    use crate::mp4::{Chapter, MetadataTags};
+    use crate::test_support::{add_fake_audio, cleanup, config, temp_file, write_frames};
+
+    /// 3 s at 10 fps with keyframes every second, one audio track, two
+    /// markers and some embedded tags.
+    fn write_source(path: &Path) {
+        let frame = |index: u64| {
+            let mut data = vec![0u8; 64 * 48 * 4];
+            for (i, px) in data.chunks_exact_mut(4).enumerate() {
+                let (x, y) = (i as u64 % 64, i as u64 / 64);
+                px.copy_from_slice(&[(x * 4 + index) as u8, (y * 5) as u8, 128, 255]);
+            }
+            VideoFrame::new(Duration::from_millis(index * 100), 64, 48, data)
+        };
+        let mut writer = write_frames(path, &config(), (0..30).map(frame));
+        add_fake_audio(&mut writer, 60);
+        writer.finish().unwrap();
+
+        write_chapters(path, &[Chapter { start: Duration::ZERO, title: "Carousel".into() }]).unwrap();
+        let mut markers = MarkerList::new(MarkerList::sidecar_path(path));
+        markers.add(Marker { time: Duration::from_millis(500), label: "Stage 1-2".into() }).unwrap();
+        markers.add(Marker { time: Duration::from_millis(1800), label: "Stage 2-1".into() }).unwrap();
+        write_tags(path, &MetadataTags { title: Some("Ranked".into()), ..Default::default() }).unwrap();
+    }
+
+    #[test]
+    fn test_lossless_trim_starts_on_the_previous_keyframe() {
+        let (input, output) = (temp_file("trim_lossless_in"), temp_file("trim_lossless_out"));
+        write_source(&input);
+
+        let options = TrimOptions { from: Duration::from_millis(1250), to: Some(Duration::from_millis(2500)), exact: false };
+        let result = trim(&input, &output, &options).unwrap();
+        assert_eq!(result.start, Duration::from_secs(1));
+        assert_eq!(result.end, Duration::from_millis(2500));
+        assert_eq!((result.video_frames, result.reencoded_frames), (15, 0));
+
+        let mut source = Mp4Reader::open(&input).unwrap();
+        let mut reader = Mp4Reader::open(&output).unwrap();
+        assert_eq!(reader.duration, Duration::from_millis(1500));
+        let video = reader.video_track().unwrap().clone();
+        assert!(video.samples[0].keyframe);
+        assert_eq!(video.samples.iter().filter(|s| s.keyframe).count(), 2);
+        // Samples are copied byte for byte
+        let original = source.video_track().unwrap().samples[12];
+        assert_eq!(reader.read_sample(&video.samples[2]).unwrap(), source.read_sample(&original).unwrap());
+        assert_eq!(reader.tracks[1].samples.len(), 30);
+        assert_eq!(reader.read_sample(&reader.tracks[1].samples[0].clone()).unwrap(), vec![20u8; 8]);
+
+        // The marker inside the range moves with the cut; the one before it names the opening chapter
+        let markers = MarkerList::load(&MarkerList::sidecar_path(&output)).unwrap();
+        assert_eq!(markers, [Marker { time: Duration::from_millis(800), label: "Stage 2-1".into() }]);
+        let titles: Vec<_> = reader.chapters.iter().map(|c| (c.start, c.title.as_str())).collect();
+        assert_eq!(titles, [(Duration::ZERO, "Stage 1-2"), (Duration::from_millis(800), "Stage 2-1")]);
+        assert_eq!(reader.tags.title.as_deref(), Some("Ranked"));
+        cleanup(&[&input, &output]);
+    }
+
+    #[test]
+    fn test_exact_trim_reencodes_the_edge_gop() {
+        let (input, output) = (temp_file("trim_exact_in"), temp_file("trim_exact_out"));
+        write_source(&input);
+
+        let options = TrimOptions { from: Duration::from_millis(1250), to: None, exact: true };
+        let result = trim(&input, &output, &options).unwrap();
+        assert_eq!(result.start, Duration::from_millis(1200));
+        assert_eq!(result.end, Duration::from_secs(3));
+        assert_eq!((result.video_frames, result.reencoded_frames), (18, 8));
+
+        let mut reader = Mp4Reader::open(&output).unwrap();
+        let video = reader.video_track().unwrap().clone();
+        assert_eq!(video.sample_descriptions.len(), 2);
+        assert_eq!(video.samples[0].description, 0);
+        assert_eq!(video.samples[8].description, 1);
+        assert!(video.samples[0].keyframe && video.samples[8].keyframe);
+
+        // Every frame decodes, across the switch back to the original stream
+        let mut decoder = Decoder::new().unwrap();
+        let mut previous = None;
+        for sample in &video.samples {
+            let TrackInfo::Video(info) = &video.sample_descriptions[sample.description as usize] else { unreachable!() };
+            let params = (previous != Some(sample.description)).then_some(&info.parameter_sets);
+            previous = Some(sample.description);
+            let data = reader.read_sample(sample).unwrap();
+            let annexb = h264::avcc_to_annexb(&data, params);
+            assert!(decoder.decode(&annexb).unwrap().is_some());
+        }
+        cleanup(&[&input, &output]);
+    }
+
+    #[test]
+    fn test_invalid_ranges_are_rejected() {
+        let (input, output) = (temp_file("trim_invalid_in"), temp_file("trim_invalid_out"));
+        write_source(&input);
+
+        let backwards = TrimOptions { from: Duration::from_secs(2), to: Some(Duration::from_secs(1)), exact: false };
+        assert!(trim(&input, &output, &backwards).is_err());
+        let past_the_end = TrimOptions { from: Duration::from_secs(5), to: None, exact: false };
+        assert!(trim(&input, &output, &past_the_end).is_err());
+        assert!(trim(&input, &input, &TrimOptions::default()).is_err());
+        cleanup(&[&input, &output]);
+    }
+}
@@ -0,0 +1,230 @@
+// ABOUTME: Exports a time range as a vertical 9:16 clip for short-form platforms
+// ABOUTME: Crops a named region of the game screen, fits it onto a 1080x1920 canvas and re-encodes it with OpenH264
+
+use crate::config::{FrameRateMode, RateControl, RecordingConfig, MAX_FPS};
+use crate::encoder::SoftwareEncoder;
+use crate::mp4::{Mp4Reader, Mp4Writer, Sample, TrackInfo, VideoTrackInfo};
+use crate::thumbnail::{self, RgbFrame};
+use crate::trim::copy_audio;
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::RgbImage;
+use openh264::formats::{RgbSliceU8, YUVBuffer};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const VERTICAL_WIDTH: u32 = 1080;
+pub const VERTICAL_HEIGHT: u32 = 1920;
+
+/// Quality of the exported clip; platforms re-encode uploads anyway.
+const CLIP_CRF: u8 = 20;
+
+/// A region of the game screen, as fractions of the frame size, that a
+/// vertical clip shows. Regions that aren't 9:16 are letterboxed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CropPreset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Presets for a 16:9 TFT client.
+pub const CROP_PRESETS: &[CropPreset] = &[
+    // A full-height 9:16 slice of a 16:9 frame is 0.316 of its width
+    CropPreset { name: "board", description: "The hex board in the middle of the screen", x: 0.342, y: 0.0, width: 0.316, height: 1.0 },
+    CropPreset { name: "bench-shop", description: "The bench and the shop along the bottom", x: 0.18, y: 0.62, width: 0.64, height: 0.38 },
+    CropPreset { name: "augments", description: "The augment choices in the middle", x: 0.2, y: 0.22, width: 0.6, height: 0.5 },
+];
+
+impl CropPreset {
+    pub fn named(name: &str) -> Result<Self> {
+        CROP_PRESETS.iter().copied().find(|p| p.name == name).with_context(|| {
+            let names: Vec<&str> = CROP_PRESETS.iter().map(|p| p.name).collect();
+            format!("Unknown crop preset '{}' (expected one of: {})", name, names.join(", "))
+        })
+    }
+
+    /// The region in pixels of a `width`x`height` frame, at least 1x1.
+    fn rect(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let x = ((self.x * width as f32) as u32).min(width - 1);
+        let y = ((self.y * height as f32) as u32).min(height - 1);
+        let w = ((self.width * width as f32).round() as u32).clamp(1, width - x);
+        let h = ((self.height * height as f32).round() as u32).clamp(1, height - y);
+        (x, y, w, h)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerticalOptions {
+    pub from: Duration,
+    /// End of the range; the end of the recording when `None`.
+    pub to: Option<Duration>,
+    pub preset: CropPreset,
+    /// Output size; both must be even for 4:2:0 video.
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for VerticalOptions {
+    fn default() -> Self {
+        Self { from: Duration::ZERO, to: None, preset: CROP_PRESETS[0], width: VERTICAL_WIDTH, height: VERTICAL_HEIGHT }
+    }
+}
+
+/// What `export_vertical` wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerticalResult {
+    pub start: Duration,
+    pub end: Duration,
+    pub video_frames: usize,
+    pub bytes: u64,
+}
+
+impl VerticalResult {
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// Writes `[options.from, options.to)` of the recording at `input` to
+/// `output` as a vertical clip showing the preset's region, with the audio
+/// tracks copied over.
+pub fn export_vertical(input: impl AsRef<Path>, output: impl AsRef<Path>, options: &VerticalOptions) -> Result<VerticalResult> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    anyhow::ensure!(input != output, "Clip output must differ from the input");
+    anyhow::ensure!(
+        options.width > 0 && options.height > 0 && options.width.is_multiple_of(2) && options.height.is_multiple_of(2),
+        "Clip size must be even, got {}x{}",
+        options.width,
+        options.height
+    );
+
+    let mut reader = Mp4Reader::open(input)?;
+    let video = reader.video_track().context("Recording has no video track")?;
+    let frame_ticks = video.samples.first().context("Recording has no video frames")?.duration.max(1);
+    let fps = (f64::from(video.timescale) / f64::from(frame_ticks)).round().clamp(1.0, f64::from(MAX_FPS)) as u32;
+    let end = options.to.map_or(reader.duration, |to| to.min(reader.duration));
+    anyhow::ensure!(options.from < end, "Nothing to export between {:?} and {:?}", options.from, end);
+
+    let config = RecordingConfig::new("clip", options.width, options.height)
+        .with_rate_control(RateControl::Crf { crf: CLIP_CRF, max_bitrate: None })
+        .with_frame_rate(fps, FrameRateMode::Variable);
+    let mut encoder = SoftwareEncoder::new(&config)?;
+    let mut writer = Mp4Writer::create(output)?;
+    let mut track = None;
+    let (mut video_frames, mut bytes) = (0, 0u64);
+    thumbnail::decode_frames(input, options.from, Some(end), |frame| {
+        // The frame on screen at `from` may have started a little earlier
+        let pts = frame.time.saturating_sub(options.from);
+        let canvas = fit(frame, &options.preset, options.width, options.height);
+        let yuv = YUVBuffer::from_rgb8_source(RgbSliceU8::new(&canvas, (options.width as usize, options.height as usize)));
+        let Some(packet) = encoder.encode_yuv(&yuv, pts)? else {
+            return Ok(());
+        };
+        let id = match track {
+            Some(id) => id,
+            None => {
+                let parameter_sets = encoder.parameter_sets().context("Encoder produced no parameter sets")?.clone();
+                let id = writer.add_track(TrackInfo::Video(VideoTrackInfo { width: options.width, height: options.height, parameter_sets }));
+                *track.insert(id)
+            }
+        };
+        writer.write_sample(id, Sample { pts: packet.pts, duration: None, keyframe: packet.keyframe, data: &packet.data })?;
+        video_frames += 1;
+        bytes += packet.data.len() as u64;
+        Ok(())
+    })?;
+    anyhow::ensure!(track.is_some(), "No video frames between {:?} and {:?}", options.from, end);
+
+    bytes += copy_audio(&mut reader, &mut writer, options.from, end)?;
+    writer.finish()?;
+    Ok(VerticalResult { start: options.from, end, video_frames, bytes })
+}
+
+/// `TFT-x.mp4` is exported to `TFT-x-vertical.mp4` next to it.
+pub fn vertical_path(recording: &Path) -> PathBuf {
+    let stem = recording.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    recording.with_file_name(format!("{}-vertical.mp4", stem))
+}
+
+/// The preset's region of `frame`, scaled to fit a black `width`x`height`
+/// canvas and centred on it, as packed RGB.
+fn fit(frame: RgbFrame, preset: &CropPreset, width: u32, height: u32) -> Vec<u8> {
+    let (x, y, w, h) = preset.rect(frame.width, frame.height);
+    let source = RgbImage::from_raw(frame.width, frame.height, frame.data).expect("frame buffer matches its size");
+    let region = image::imageops::crop_imm(&source, x, y, w, h).to_image();
+
+    let scale = (width as f32 / w as f32).min(height as f32 / h as f32);
+    let scaled_width = ((w as f32 * scale).round() as u32).clamp(1, width);
+    let scaled_height = ((h as f32 * scale).round() as u32).clamp(1, height);
+    let scaled = image::imageops::resize(&region, scaled_width, scaled_height, FilterType::Triangle);
+
+    let mut canvas = RgbImage::new(width, height);
+    let (left, top) = ((width - scaled_width) / 2, (height - scaled_height) / 2);
+    image::imageops::replace(&mut canvas, &scaled, i64::from(left), i64::from(top));
+    canvas.into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::VideoFrame;
+    use crate::test_support::{add_fake_audio, temp_file, write_frames};
+
+    /// 2 s at 10 fps of a 128x72 frame: green down the middle third, red
+    /// either side, plus 40 fake audio packets.
+    fn write_source(path: &Path) {
+        let (width, height) = (128u32, 72u32);
+        let config = RecordingConfig::new("test", width, height).with_frame_rate(10, FrameRateMode::Constant);
+        let data: Vec<u8> = (0..width * height)
+            .flat_map(|p| if (43..85).contains(&(p % width)) { [0, 200, 0, 255] } else { [0, 0, 200, 255] })
+            .collect();
+        let frames = (0..20u64).map(|i| VideoFrame::new(Duration::from_millis(i * 100), width, height, data.clone()));
+        let mut writer = write_frames(path, &config, frames);
+        add_fake_audio(&mut writer, 40);
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_presets_by_name() {
+        assert_eq!(CropPreset::named("bench-shop").unwrap().name, "bench-shop");
+        let error = CropPreset::named("scoreboard").unwrap_err().to_string();
+        assert!(error.contains("board, bench-shop, augments"), "{}", error);
+        // The board preset is a 9:16 slice of a 16:9 frame
+        let (_, _, w, h) = CropPreset::named("board").unwrap().rect(1920, 1080);
+        assert!((w as f32 / h as f32 - 9.0 / 16.0).abs() < 0.01);
+        assert_eq!(vertical_path(Path::new("/m/TFT-1.mp4")), Path::new("/m/TFT-1-vertical.mp4"));
+    }
+
+    #[test]
+    fn test_board_export_crops_the_middle_and_keeps_audio() {
+        let (input, output) = (temp_file("vertical_board_in"), temp_file("vertical_board_out"));
+        write_source(&input);
+
+        let options = VerticalOptions { from: Duration::from_millis(500), to: Some(Duration::from_millis(1500)), width: 36, height: 64, ..Default::default() };
+        let result = export_vertical(&input, &output, &options).unwrap();
+        assert_eq!((result.video_frames, result.duration()), (10, Duration::from_secs(1)));
+
+        let reader = Mp4Reader::open(&output).unwrap();
+        let video = reader.video_track().unwrap().clone();
+        assert!(matches!(&video.info, TrackInfo::Video(v) if (v.width, v.height) == (36, 64)));
+        assert_eq!(video.sample_time(&video.samples[0]), Duration::ZERO);
+        let audio = reader.tracks.iter().find(|t| !t.is_video()).unwrap();
+        assert_eq!(audio.samples.len(), 20);
+
+        // Only the green middle of the source makes it into the clip
+        let frame = thumbnail::extract_frame(&output, Duration::ZERO, false).unwrap();
+        let (mut red, mut green) = (0u64, 0u64);
+        for px in frame.data.chunks_exact(3) {
+            red += u64::from(px[0]);
+            green += u64::from(px[1]);
+        }
+        assert!(green > red * 5, "red {} green {}", red, green);
+
+        std::fs::remove_file(input).ok();
+        std::fs::remove_file(output).ok();
+    }
+}
@@ -0,0 +1,148 @@
+// ABOUTME: Typed responses of the game client's Live Client Data API (`/liveclientdata/allgamedata`)
+// ABOUTME: Fields the client leaves out between patches or game modes fall back to defaults
+
+use serde::{Deserialize, Serialize};
+
+/// Everything the client knows about the running match.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllGameData {
+    /// The player on this machine; missing while spectating.
+    #[serde(default)]
+    pub active_player: Option<ActivePlayer>,
+    #[serde(default)]
+    pub all_players: Vec<Player>,
+    #[serde(default)]
+    pub events: EventList,
+    pub game_data: GameStats,
+}
+
+impl AllGameData {
+    /// The entry of `all_players` for the player on this machine.
+    pub fn me(&self) -> Option<&Player> {
+        let me = self.active_player.as_ref()?;
+        self.all_players.iter().find(|player| player.name() == me.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ActivePlayer {
+    pub summoner_name: String,
+    /// `name#tag`; newer clients identify players by it.
+    pub riot_id: Option<String>,
+    pub level: u32,
+    pub current_gold: f64,
+}
+
+impl ActivePlayer {
+    pub fn name(&self) -> &str {
+        self.riot_id.as_deref().filter(|id| !id.is_empty()).unwrap_or(&self.summoner_name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Player {
+    pub summoner_name: String,
+    pub riot_id: Option<String>,
+    pub champion_name: String,
+    pub team: String,
+    pub level: u32,
+    pub is_bot: bool,
+    pub is_dead: bool,
+    pub scores: Scores,
+}
+
+impl Player {
+    pub fn name(&self) -> &str {
+        self.riot_id.as_deref().filter(|id| !id.is_empty()).unwrap_or(&self.summoner_name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Scores {
+    pub kills: u32,
+    pub deaths: u32,
+    pub assists: u32,
+    pub creep_score: u32,
+    pub ward_score: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct EventList {
+    #[serde(rename = "Events", default)]
+    pub events: Vec<ClientEvent>,
+}
+
+/// An entry of the match's event log, e.g. `GameStart`, `ChampionKill` or
+/// `GameEnd`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientEvent {
+    #[serde(rename = "EventID")]
+    pub id: u64,
+    #[serde(rename = "EventName")]
+    pub name: String,
+    /// Seconds of game time.
+    #[serde(rename = "EventTime")]
+    pub time: f64,
+    /// `Win` or `Lose` on `GameEnd`.
+    #[serde(rename = "Result", default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    /// The event's other fields, such as `KillerName`.
+    #[serde(flatten)]
+    pub details: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GameStats {
+    /// `TFT`, `CLASSIC`, `ARAM`...
+    pub game_mode: String,
+    /// Seconds since the match started.
+    pub game_time: f64,
+    pub map_name: String,
+    pub map_number: u32,
+    pub map_terrain: String,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A trimmed `allgamedata` response of a TFT match, as the client sends it.
+    pub(crate) const SAMPLE: &str = r#"{
+        "activePlayer": {"summonerName": "Tactician", "riotId": "Tactician#EUW", "level": 7, "currentGold": 32.0, "teamRelativeColors": true},
+        "allPlayers": [
+            {"championName": "TFT Tactician", "isBot": false, "isDead": false, "items": [], "level": 7, "position": "", "riotId": "Tactician#EUW", "scores": {"assists": 0, "creepScore": 0, "deaths": 0, "kills": 0, "wardScore": 0.0}, "summonerName": "Tactician", "team": "ORDER"},
+            {"championName": "TFT Tactician", "isBot": true, "isDead": true, "level": 5, "summonerName": "Bot 2", "team": "CHAOS"}
+        ],
+        "events": {"Events": [
+            {"EventID": 0, "EventName": "GameStart", "EventTime": 0.05},
+            {"EventID": 1, "EventName": "ChampionKill", "EventTime": 612.4, "KillerName": "Tactician", "VictimName": "Bot 2"}
+        ]},
+        "gameData": {"gameMode": "TFT", "gameTime": 640.7, "mapName": "Map22", "mapNumber": 22, "mapTerrain": "Default"}
+    }"#;
+
+    #[test]
+    fn test_parse_all_game_data() {
+        let data: AllGameData = serde_json::from_str(SAMPLE).unwrap();
+        assert_eq!(data.game_data.game_mode, "TFT");
+        assert_eq!(data.game_data.map_number, 22);
+        assert_eq!(data.all_players.len(), 2);
+        assert!(data.all_players[1].is_dead);
+        assert_eq!(data.all_players[1].name(), "Bot 2");
+        assert_eq!(data.me().unwrap().level, 7);
+
+        let kill = &data.events.events[1];
+        assert_eq!((kill.id, kill.name.as_str(), kill.result.as_deref()), (1, "ChampionKill", None));
+        assert_eq!(kill.details["VictimName"], "Bot 2");
+
+        // Loading screens and spectating leave most of it out
+        let sparse: AllGameData = serde_json::from_str(r#"{"gameData": {"gameTime": 0.0}}"#).unwrap();
+        assert!(sparse.active_player.is_none() && sparse.all_players.is_empty() && sparse.me().is_none());
+        let end: ClientEvent = serde_json::from_str(r#"{"EventID": 9, "EventName": "GameEnd", "EventTime": 1802.2, "Result": "Win"}"#).unwrap();
+        assert_eq!(end.result.as_deref(), Some("Win"));
+    }
+}
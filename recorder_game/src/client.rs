@@ -0,0 +1,143 @@
+// ABOUTME: HTTP client for the Live Client Data API the game serves on port 2999 while a match runs
+// ABOUTME: Tells "no game", "loading" and "in game" apart and accepts the game's self-signed certificate
+
+use crate::api::AllGameData;
+use anyhow::{Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const DEFAULT_URL: &str = "https://127.0.0.1:2999";
+
+const ALL_GAME_DATA: &str = "/liveclientdata/allgamedata";
+
+/// What one poll of the API found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Snapshot {
+    /// Nothing listens: the game isn't running.
+    NoGame,
+    /// The game runs but has no match data yet (loading screen).
+    Loading,
+    InGame(Box<AllGameData>),
+}
+
+pub struct LiveClient {
+    base_url: String,
+    agent: ureq::Agent,
+}
+
+impl LiveClient {
+    /// A client for the API at `base_url`, e.g. [`DEFAULT_URL`] or a local
+    /// mock such as `http://127.0.0.1:8999`.
+    pub fn new(base_url: &str, timeout: Duration) -> Result<Self> {
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let (scheme, rest) = base_url.split_once("://").with_context(|| format!("Game API URL {} has no scheme", base_url))?;
+        anyhow::ensure!(scheme == "http" || scheme == "https", "Game API URL must be http or https (got {})", base_url);
+        let mut agent = ureq::AgentBuilder::new().timeout(timeout);
+        if scheme == "https" && is_loopback(rest) {
+            agent = agent.tls_config(Arc::new(game_tls_config()?));
+        }
+        Ok(Self { base_url, agent: agent.build() })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn poll(&self) -> Result<Snapshot> {
+        let url = format!("{}{}", self.base_url, ALL_GAME_DATA);
+        match self.agent.get(&url).call() {
+            Ok(response) => {
+                let body = response.into_string().with_context(|| format!("Failed to read {}", url))?;
+                let data = serde_json::from_str(&body).with_context(|| format!("Unexpected game data from {}", url))?;
+                Ok(Snapshot::InGame(Box::new(data)))
+            }
+            // Served until the match has loaded
+            Err(ureq::Error::Status(404 | 503, _)) => Ok(Snapshot::Loading),
+            Err(ureq::Error::Transport(e)) if e.kind() == ureq::ErrorKind::ConnectionFailed => Ok(Snapshot::NoGame),
+            Err(e) => Err(anyhow::Error::from(e).context(format!("GET {} failed", url))),
+        }
+    }
+}
+
+fn is_loopback(authority: &str) -> bool {
+    let authority = authority.split('/').next().unwrap_or_default();
+    let host = match authority.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    host == "localhost" || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// TLS settings for the game's own endpoint, whose certificate is issued by
+/// Riot's private root for `127.0.0.1` and can't be checked against the
+/// system's roots. Signatures are still verified; only the chain isn't.
+fn game_tls_config() -> Result<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(GameCertificate(provider)))
+        .with_no_client_auth();
+    Ok(config)
+}
+
+#[derive(Debug)]
+struct GameCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for GameCertificate {
+    fn verify_server_cert(&self, _: &CertificateDer<'_>, _: &[CertificateDer<'_>], _: &ServerName<'_>, _: &[u8], _: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::MockGame;
+
+    #[test]
+    fn test_poll_states() {
+        let game = MockGame::start();
+        let client = LiveClient::new(&format!("{}/", game.url), Duration::from_secs(2)).unwrap();
+        assert_eq!(client.base_url(), game.url);
+
+        assert_eq!(client.poll().unwrap(), Snapshot::Loading);
+        game.serve(200, crate::api::tests::SAMPLE);
+        let Snapshot::InGame(data) = client.poll().unwrap() else { panic!("Expected game data") };
+        assert_eq!(data.game_data.game_mode, "TFT");
+        game.serve(200, "<html>");
+        assert!(format!("{:#}", client.poll().unwrap_err()).contains("Unexpected game data"));
+        game.serve(500, "");
+        assert!(client.poll().is_err());
+
+        let url = game.url.clone();
+        drop(game);
+        let closed = LiveClient::new(&url, Duration::from_secs(2)).unwrap();
+        assert_eq!(closed.poll().unwrap(), Snapshot::NoGame);
+    }
+
+    #[test]
+    fn test_urls() {
+        assert!(LiveClient::new(DEFAULT_URL, Duration::from_secs(1)).is_ok());
+        assert!(LiveClient::new("127.0.0.1:2999", Duration::from_secs(1)).is_err());
+        assert!(LiveClient::new("ftp://127.0.0.1:2999", Duration::from_secs(1)).is_err());
+        assert!(is_loopback("127.0.0.1:2999") && is_loopback("localhost") && is_loopback("[::1]:2999/x"));
+        assert!(!is_loopback("example.com:2999") && !is_loopback("10.0.0.2"));
+    }
+}
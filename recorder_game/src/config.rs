@@ -0,0 +1,48 @@
+// ABOUTME: Game API settings: where to poll, how often, and whether the daemon marks recordings
+// ABOUTME: Read from the [game] section of the settings file
+
+use crate::client::{LiveClient, DEFAULT_URL};
+use crate::watcher::DEFAULT_INTERVAL;
+use anyhow::Result;
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    /// Base URL of the Live Client Data API; point it at a mock to test
+    /// without the game.
+    pub url: String,
+    pub poll_ms: u64,
+    /// Limit for each poll, connecting included.
+    pub timeout_ms: u64,
+    /// Add `Game start` and `Game end` markers to the recording in progress.
+    pub markers: bool,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self { url: DEFAULT_URL.to_string(), poll_ms: DEFAULT_INTERVAL.as_millis() as u64, timeout_ms: 2000, markers: true }
+    }
+}
+
+impl GameConfig {
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            self.url.starts_with("http://") || self.url.starts_with("https://"),
+            "game.url must be an http:// or https:// URL, got {:?}",
+            self.url
+        );
+        anyhow::ensure!(self.poll_ms >= 100, "game.poll_ms must be at least 100");
+        anyhow::ensure!(self.timeout_ms > 0, "game.timeout_ms must be at least 1");
+        Ok(())
+    }
+
+    pub fn client(&self) -> Result<LiveClient> {
+        LiveClient::new(&self.url, Duration::from_millis(self.timeout_ms))
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.poll_ms)
+    }
+}
@@ -0,0 +1,78 @@
+// ABOUTME: Match awareness from the game client's Live Client Data API (https://127.0.0.1:2999)
+// ABOUTME: Polls it, parses match and player data and publishes typed game events to subscribers
+
+pub mod api;
+pub mod client;
+pub mod config;
+pub mod watcher;
+
+pub use api::{ActivePlayer, AllGameData, ClientEvent, Player};
+pub use client::{LiveClient, Snapshot, DEFAULT_URL};
+pub use config::GameConfig;
+pub use watcher::{GameEvent, GameTracker, GameWatcher, DEFAULT_INTERVAL};
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::JoinHandle;
+
+    /// A stand-in for the game's API over plain HTTP, answering every request
+    /// with the status and body last given to [`MockGame::serve`] (404, the
+    /// loading screen, at first). Dropping it closes the port like a game
+    /// that quit.
+    pub(crate) struct MockGame {
+        pub url: String,
+        addr: SocketAddr,
+        reply: Arc<Mutex<(u16, String)>>,
+        closed: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl MockGame {
+        pub fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let reply = Arc::new(Mutex::new((404, r#"{"errorCode":"RESOURCE_NOT_FOUND","httpStatus":404}"#.to_string())));
+            let closed = Arc::new(AtomicBool::new(false));
+            let (shared, stop) = (reply.clone(), closed.clone());
+            let thread = std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let (status, body) = shared.lock().unwrap().clone();
+                    answer(stream, status, &body).ok();
+                }
+            });
+            Self { url: format!("http://{}", addr), addr, reply, closed, thread: Some(thread) }
+        }
+
+        pub fn serve(&self, status: u16, body: &str) {
+            *self.reply.lock().unwrap() = (status, body.to_string());
+        }
+    }
+
+    impl Drop for MockGame {
+        fn drop(&mut self) {
+            self.closed.store(true, Ordering::SeqCst);
+            // Wakes the accept loop so it sees the flag and drops the listener
+            TcpStream::connect(self.addr).ok();
+            if let Some(thread) = self.thread.take() {
+                thread.join().ok();
+            }
+        }
+    }
+
+    fn answer(stream: TcpStream, status: u16, body: &str) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+        let mut stream = stream;
+        write!(stream, "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body)
+    }
+}
@@ -0,0 +1,251 @@
+// ABOUTME: Turns successive polls of the game API into typed match events (start, players, log entries, end)
+// ABOUTME: GameWatcher polls on a thread and publishes the events on an EventBus until dropped
+
+use crate::api::{AllGameData, Player};
+use crate::client::{LiveClient, Snapshot};
+use recorder_core::events::EventBus;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GameEvent {
+    /// A match is running: it just loaded, or was already under way when
+    /// watching began (`game_time` tells which).
+    GameStarted {
+        mode: String,
+        map: String,
+        game_time: f64,
+        /// Name of the player on this machine.
+        active_player: Option<String>,
+        players: Vec<Player>,
+    },
+    /// A player's level, score or whether they are out changed.
+    PlayersUpdated { game_time: f64, players: Vec<Player> },
+    /// A new entry of the match's event log other than its start and end,
+    /// e.g. `ChampionKill`.
+    MatchEvent {
+        name: String,
+        game_time: f64,
+        details: serde_json::Map<String, serde_json::Value>,
+    },
+    /// The match is over. `result` is `Win` or `Lose` when the game said so,
+    /// and missing when the game went away without a `GameEnd`.
+    GameEnded { game_time: f64, result: Option<String> },
+}
+
+/// Match state between polls; [`GameTracker::observe`] yields the events a
+/// new snapshot implies.
+#[derive(Debug, Default)]
+pub struct GameTracker {
+    current: Option<AllGameData>,
+    /// ID of the last log entry handled.
+    last_event: Option<u64>,
+    /// `GameEnd` was seen; the game keeps serving data until it closes.
+    ended: bool,
+}
+
+impl GameTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The last data of the running match, if any.
+    pub fn current(&self) -> Option<&AllGameData> {
+        self.current.as_ref().filter(|_| !self.ended)
+    }
+
+    pub fn observe(&mut self, snapshot: Snapshot) -> Vec<GameEvent> {
+        let data = match snapshot {
+            Snapshot::InGame(data) => *data,
+            // The loading screen; the API never goes back to it mid-match
+            Snapshot::Loading => return Vec::new(),
+            Snapshot::NoGame => {
+                let mut events = Vec::new();
+                if let (Some(last), false) = (&self.current, self.ended) {
+                    events.push(GameEvent::GameEnded { game_time: last.game_data.game_time, result: None });
+                }
+                *self = Self::default();
+                return events;
+            }
+        };
+        if self.ended {
+            return Vec::new();
+        }
+
+        let mut events = Vec::new();
+        let new_entries = match &self.current {
+            None => {
+                events.push(GameEvent::GameStarted {
+                    mode: data.game_data.game_mode.clone(),
+                    map: data.game_data.map_name.clone(),
+                    game_time: data.game_data.game_time,
+                    active_player: data.active_player.as_ref().map(|me| me.name().to_string()),
+                    players: data.all_players.clone(),
+                });
+                // Joined late: earlier log entries aren't news, except an end already reached
+                let ended = data.events.events.iter().filter(|event| event.name == "GameEnd").cloned();
+                ended.collect::<Vec<_>>()
+            }
+            Some(previous) => {
+                if previous.all_players != data.all_players {
+                    events.push(GameEvent::PlayersUpdated { game_time: data.game_data.game_time, players: data.all_players.clone() });
+                }
+                data.events.events.iter().filter(|event| self.last_event.is_none_or(|last| event.id > last)).cloned().collect()
+            }
+        };
+        for entry in new_entries {
+            match entry.name.as_str() {
+                "GameStart" => {}
+                "GameEnd" => {
+                    self.ended = true;
+                    events.push(GameEvent::GameEnded { game_time: entry.time, result: entry.result });
+                }
+                _ => events.push(GameEvent::MatchEvent { name: entry.name, game_time: entry.time, details: entry.details }),
+            }
+        }
+        self.last_event = data.events.events.iter().map(|event| event.id).max().or(self.last_event);
+        self.current = Some(data);
+        events
+    }
+}
+
+/// Polls the game API every `interval` until dropped.
+pub struct GameWatcher {
+    events: EventBus<GameEvent>,
+    current: Arc<Mutex<Option<AllGameData>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GameWatcher {
+    pub fn start(client: LiveClient, interval: Duration) -> Self {
+        let events = EventBus::new();
+        let current = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (bus, shared, stopped) = (events.clone(), current.clone(), stop.clone());
+        let thread = std::thread::spawn(move || {
+            let mut tracker = GameTracker::new();
+            let mut last_error = None;
+            while !stopped.load(Ordering::SeqCst) {
+                match client.poll() {
+                    Ok(snapshot) => {
+                        last_error = None;
+                        let events = tracker.observe(snapshot);
+                        *shared.lock().unwrap() = tracker.current().cloned();
+                        for event in events {
+                            bus.publish(event);
+                        }
+                    }
+                    // A slow or garbled answer doesn't end the match; report each new problem once
+                    Err(e) => {
+                        let message = format!("{:#}", e);
+                        if last_error.as_ref() != Some(&message) {
+                            eprintln!("Can't read game data from {}: {}", client.base_url(), message);
+                            last_error = Some(message);
+                        }
+                    }
+                }
+                std::thread::park_timeout(interval);
+            }
+        });
+        Self { events, current, stop, thread: Some(thread) }
+    }
+
+    pub fn subscribe(&self) -> Receiver<GameEvent> {
+        self.events.subscribe()
+    }
+
+    /// The last data of the running match, if any.
+    pub fn current(&self) -> Option<AllGameData> {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+impl Drop for GameWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::tests::SAMPLE;
+    use crate::tests::MockGame;
+
+    fn sample() -> AllGameData {
+        serde_json::from_str(SAMPLE).unwrap()
+    }
+
+    fn in_game(data: &AllGameData) -> Snapshot {
+        Snapshot::InGame(Box::new(data.clone()))
+    }
+
+    fn log_entry(data: &mut AllGameData, json: &str) {
+        data.events.events.push(serde_json::from_str(json).unwrap());
+    }
+
+    #[test]
+    fn test_tracker_follows_a_match() {
+        let mut tracker = GameTracker::new();
+        assert!(tracker.observe(Snapshot::NoGame).is_empty());
+        assert!(tracker.observe(Snapshot::Loading).is_empty());
+
+        let mut data = sample();
+        let events = tracker.observe(in_game(&data));
+        let [GameEvent::GameStarted { mode, active_player, players, .. }] = &events[..] else { panic!("Expected a start, got {:?}", events) };
+        assert_eq!((mode.as_str(), active_player.as_deref(), players.len()), ("TFT", Some("Tactician#EUW"), 2));
+        // The kill happened before watching began
+        assert!(tracker.observe(in_game(&data)).is_empty());
+        assert_eq!(tracker.current().unwrap().game_data.map_name, "Map22");
+
+        data.all_players[0].level = 8;
+        log_entry(&mut data, r#"{"EventID": 2, "EventName": "ChampionKill", "EventTime": 700.0, "VictimName": "Bot 3"}"#);
+        let events = tracker.observe(in_game(&data));
+        assert!(matches!(&events[0], GameEvent::PlayersUpdated { players, .. } if players[0].level == 8));
+        assert!(matches!(&events[1], GameEvent::MatchEvent { name, details, .. } if name == "ChampionKill" && details["VictimName"] == "Bot 3"));
+
+        log_entry(&mut data, r#"{"EventID": 3, "EventName": "GameEnd", "EventTime": 1802.0, "Result": "Win"}"#);
+        assert_eq!(tracker.observe(in_game(&data)), [GameEvent::GameEnded { game_time: 1802.0, result: Some("Win".into()) }]);
+        // The end screen keeps serving the same data
+        assert!(tracker.observe(in_game(&data)).is_empty());
+        assert!(tracker.current().is_none());
+        assert!(tracker.observe(Snapshot::NoGame).is_empty());
+
+        // A game that closes without a GameEnd still ends
+        assert_eq!(tracker.observe(in_game(&sample())).len(), 1);
+        assert_eq!(tracker.observe(Snapshot::NoGame), [GameEvent::GameEnded { game_time: 640.7, result: None }]);
+    }
+
+    #[test]
+    fn test_watcher_publishes_events() {
+        let game = MockGame::start();
+        let watcher = GameWatcher::start(LiveClient::new(&game.url, Duration::from_secs(2)).unwrap(), Duration::from_millis(20));
+        let events = watcher.subscribe();
+        let next = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        game.serve(200, SAMPLE);
+        assert!(matches!(next(), GameEvent::GameStarted { .. }));
+        assert_eq!(watcher.current().unwrap().game_data.game_mode, "TFT");
+
+        let mut data = sample();
+        log_entry(&mut data, r#"{"EventID": 2, "EventName": "GameEnd", "EventTime": 900.0, "Result": "Lose"}"#);
+        game.serve(200, &serde_json::to_string(&data).unwrap());
+        assert_eq!(next(), GameEvent::GameEnded { game_time: 900.0, result: Some("Lose".into()) });
+        assert!(watcher.current().is_none());
+
+        let json = serde_json::to_string(&GameEvent::GameEnded { game_time: 900.0, result: Some("Lose".into()) }).unwrap();
+        assert_eq!(json, r#"{"event":"game_ended","game_time":900.0,"result":"Lose"}"#);
+    }
+}
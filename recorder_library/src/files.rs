@@ -0,0 +1,146 @@
+// ABOUTME: Changes to recordings made through the library: tagging, deleting and moving them
+// ABOUTME: Tags live in the metadata sidecar; deletes and moves take sidecars and cached thumbnails along
+
+use crate::index::Library;
+use anyhow::{Context, Result};
+use recorder_core::RecordingMetadata;
+use std::path::{Path, PathBuf};
+
+impl Library {
+    /// Adds `add` and drops `remove` (both case-insensitive) from the tags in
+    /// the recording's metadata sidecar, then re-indexes it. Returns the tags.
+    pub fn retag(&mut self, recording: &Path, add: &[String], remove: &[String]) -> Result<Vec<String>> {
+        let mut metadata = RecordingMetadata::for_recording(recording)?
+            .with_context(|| format!("{} has no metadata sidecar to hold tags", recording.display()))?;
+        metadata.tags.retain(|tag| !remove.iter().any(|r| r.eq_ignore_ascii_case(tag)));
+        for tag in add {
+            if !metadata.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+                metadata.tags.push(tag.clone());
+            }
+        }
+        // The sidecar is written next to `output`, which may be stale after a manual move
+        metadata.output = recording.to_path_buf();
+        metadata.save()?;
+        self.refresh(recording)?;
+        Ok(metadata.tags)
+    }
+
+    /// Deletes the recording, its sidecars and cached thumbnails. Returns the
+    /// files deleted.
+    pub fn delete(&mut self, recording: &Path) -> Result<Vec<PathBuf>> {
+        anyhow::ensure!(recording.is_file(), "{} does not exist", recording.display());
+        let mut files = vec![recording.to_path_buf()];
+        files.extend(companions(recording)?);
+        for file in &files {
+            std::fs::remove_file(file).with_context(|| format!("Failed to delete {}", file.display()))?;
+        }
+        self.refresh(recording)?;
+        Ok(files)
+    }
+
+    /// Moves the recording, with its sidecars and cached thumbnails, to `to`
+    /// (a new name, or a directory to move it into). Returns the new path.
+    pub fn rename(&mut self, recording: &Path, to: &Path) -> Result<PathBuf> {
+        anyhow::ensure!(recording.is_file(), "{} does not exist", recording.display());
+        let target = if to.is_dir() { to.join(recording.file_name().context("Recording has no file name")?) } else { to.to_path_buf() };
+        anyhow::ensure!(target.extension().is_some_and(|e| e == "mp4"), "{} should end in .mp4", target.display());
+        anyhow::ensure!(!target.exists(), "{} already exists", target.display());
+
+        let stem = file_stem(recording)?;
+        let new_stem = file_stem(&target)?;
+        let companions = companions(recording)?;
+        std::fs::rename(recording, &target).with_context(|| format!("Failed to move {} to {}", recording.display(), target.display()))?;
+        for companion in companions {
+            let name = companion.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            let moved = target.with_file_name(format!("{}{}", new_stem, &name[stem.len()..]));
+            std::fs::rename(&companion, &moved).with_context(|| format!("Failed to move {}", companion.display()))?;
+        }
+        if let Some(mut metadata) = RecordingMetadata::for_recording(&target)? {
+            metadata.output = target.clone();
+            metadata.save()?;
+        }
+
+        self.refresh(recording)?;
+        if self.contains(&target) {
+            self.refresh(&target)?;
+        }
+        Ok(target)
+    }
+}
+
+/// The files belonging to `recording` that exist next to it: `TFT-x.json`,
+/// `TFT-x.markers.json` and cached thumbnails such as `TFT-x.thumb.jpg`.
+pub fn companions(recording: &Path) -> Result<Vec<PathBuf>> {
+    let stem = file_stem(recording)?;
+    let dir = recording.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            let Some(suffix) = name.strip_prefix(stem).and_then(|rest| rest.strip_prefix('.')) else {
+                return false;
+            };
+            suffix == "json" || suffix == "markers.json" || (suffix.starts_with("thumb") && suffix.ends_with(".jpg"))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn file_stem(path: &Path) -> Result<&str> {
+    path.file_stem().and_then(|s| s.to_str()).with_context(|| format!("{} has no valid file name", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{temp_dir, write_recording};
+    use recorder_core::markers::{Marker, MarkerList};
+    use recorder_core::RecordingConfig;
+    use std::time::Duration;
+
+    #[test]
+    fn test_tag_move_and_delete_take_companions_along() {
+        let dir = temp_dir("files");
+        let recording = dir.join("TFT-5.mp4");
+        write_recording(&recording);
+        RecordingMetadata::new(&RecordingConfig::default().with_tags(vec!["ranked".into()]), &recording).save().unwrap();
+        let mut markers = MarkerList::new(MarkerList::sidecar_path(&recording));
+        markers.add(Marker { time: Duration::from_millis(200), label: "Stage 1-2".into() }).unwrap();
+        std::fs::write(dir.join("TFT-5.thumb.jpg"), b"jpg").unwrap();
+        std::fs::write(dir.join("TFT-5-trim.mp4"), b"other recording").unwrap();
+        let mut library = Library::open(&dir).unwrap();
+        library.scan().unwrap();
+
+        let names = |files: Vec<PathBuf>| files.iter().map(|f| f.file_name().unwrap().to_string_lossy().into_owned()).collect::<Vec<_>>();
+        assert_eq!(names(companions(&recording).unwrap()), ["TFT-5.json", "TFT-5.markers.json", "TFT-5.thumb.jpg"]);
+
+        let tags = library.retag(&recording, &["Hyper Roll".into(), "RANKED".into()], &[]).unwrap();
+        assert_eq!(tags, ["ranked", "Hyper Roll"]);
+        assert_eq!(library.retag(&recording, &[], &["hyper roll".into()]).unwrap(), ["ranked"]);
+        assert_eq!(library.get(&recording).unwrap().unwrap().tags, ["ranked"]);
+        assert!(library.retag(&dir.join("TFT-5-trim.mp4"), &["x".into()], &[]).is_err());
+
+        let moved = library.rename(&recording, &dir.join("Ranked-win.mp4")).unwrap();
+        assert!(!recording.exists());
+        assert_eq!(names(companions(&moved).unwrap()), ["Ranked-win.json", "Ranked-win.markers.json", "Ranked-win.thumb.jpg"]);
+        assert_eq!(RecordingMetadata::for_recording(&moved).unwrap().unwrap().output, moved);
+        assert!(library.get(&recording).unwrap().is_none());
+        assert_eq!(library.get(&moved).unwrap().unwrap().markers.len(), 1);
+        assert!(library.rename(&moved, &dir.join("TFT-5-trim.mp4")).is_err());
+
+        // Out of the library: gone from the index
+        let elsewhere = temp_dir("files_elsewhere");
+        let outside = library.rename(&moved, &elsewhere).unwrap();
+        assert_eq!(outside, elsewhere.join("Ranked-win.mp4"));
+        assert!(library.get(&moved).unwrap().is_none() && library.get(&outside).unwrap().is_none());
+
+        let deleted = library.delete(&outside).unwrap();
+        assert_eq!(deleted.len(), 4);
+        assert_eq!(std::fs::read_dir(&elsewhere).unwrap().count(), 0);
+        assert!(dir.join("TFT-5-trim.mp4").exists());
+        std::fs::remove_dir_all(dir).ok();
+        std::fs::remove_dir_all(elsewhere).ok();
+    }
+}
@@ -0,0 +1,457 @@
+// ABOUTME: SQLite index of the recordings in a directory: file facts, tags, markers and match info
+// ABOUTME: Kept up to date incrementally by comparing sizes and modification times with what is stored
+
+use crate::query::LibraryQuery;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, TimeZone};
+use recorder_core::markers::{Marker, MarkerList};
+use recorder_core::mp4::{Mp4Reader, TrackInfo};
+use recorder_core::{GameInfo, RecordingMetadata};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Name of the index database inside the recordings directory.
+pub const LIBRARY_FILE: &str = ".library.sqlite";
+
+/// Bumped whenever the tables change; older indexes are rebuilt from the files.
+const SCHEMA_VERSION: i32 = 2;
+
+const SCHEMA: &str = "
+    CREATE TABLE recordings (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        name TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        modified_ms INTEGER NOT NULL,
+        duration_secs REAL,
+        video_codec TEXT,
+        audio_codecs TEXT NOT NULL,
+        width INTEGER,
+        height INTEGER,
+        fps INTEGER,
+        started_ms INTEGER,
+        complete INTEGER NOT NULL,
+        patch TEXT,
+        rank TEXT,
+        placement INTEGER
+    );
+    CREATE TABLE tags (
+        recording_id INTEGER NOT NULL REFERENCES recordings(id) ON DELETE CASCADE,
+        tag TEXT NOT NULL
+    );
+    CREATE TABLE markers (
+        recording_id INTEGER NOT NULL REFERENCES recordings(id) ON DELETE CASCADE,
+        time_ms INTEGER NOT NULL,
+        label TEXT NOT NULL
+    );
+    CREATE INDEX tags_by_recording ON tags(recording_id);
+    CREATE INDEX markers_by_recording ON markers(recording_id);
+";
+
+/// One indexed recording.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Latest modification of the recording or its sidecars.
+    pub modified: DateTime<Local>,
+    /// From the file, or its metadata while the file can't be read yet.
+    pub duration_secs: Option<f64>,
+    pub video_codec: Option<String>,
+    pub audio_codecs: Vec<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    /// When recording started; `None` for files without a metadata sidecar.
+    pub started_at: Option<DateTime<Local>>,
+    /// Stopped cleanly, or readable as an MP4 when there is no metadata.
+    pub complete: bool,
+    pub tags: Vec<String>,
+    pub markers: Vec<Marker>,
+    pub game: GameInfo,
+}
+
+impl LibraryEntry {
+    pub fn file_name(&self) -> &str {
+        self.path.file_name().and_then(|n| n.to_str()).unwrap_or("Unknown")
+    }
+
+    /// Start of the recording, or its last modification without metadata.
+    pub fn date(&self) -> DateTime<Local> {
+        self.started_at.unwrap_or(self.modified)
+    }
+}
+
+/// What a `Library::scan` changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/// The index of one recordings directory, stored in `LIBRARY_FILE` inside it.
+pub struct Library {
+    conn: Connection,
+    root: PathBuf,
+}
+
+impl Library {
+    /// Opens (or creates) the index of `root`. It is not scanned; call
+    /// `scan` to pick up changes made while nothing was watching.
+    pub fn open(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        anyhow::ensure!(root.is_dir(), "{} is not a directory", root.display());
+        let db = Self::db_path(&root);
+        let conn = Connection::open(&db).with_context(|| format!("Failed to open {}", db.display()))?;
+        // The GUI and the watcher thread each hold a connection
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+
+        let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            conn.execute_batch("DROP TABLE IF EXISTS markers; DROP TABLE IF EXISTS tags; DROP TABLE IF EXISTS recordings;")?;
+            conn.execute_batch(SCHEMA)?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        Ok(Self { conn, root })
+    }
+
+    /// `<root>/.library.sqlite`.
+    pub fn db_path(root: &Path) -> PathBuf {
+        root.join(LIBRARY_FILE)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Brings the index in line with the directory: new and changed
+    /// recordings are (re)read, deleted ones dropped.
+    pub fn scan(&mut self) -> Result<ScanStats> {
+        let files = recordings_in(&self.root)?;
+        let tx = self.conn.transaction()?;
+        let stored: HashMap<PathBuf, (u64, i64)> = {
+            let mut statement = tx.prepare("SELECT path, size, modified_ms FROM recordings")?;
+            let rows = statement.query_map([], |row| Ok((PathBuf::from(row.get::<_, String>(0)?), (row.get(1)?, row.get(2)?))))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let mut stats = ScanStats::default();
+        for file in &files {
+            let signature = signature(file)?;
+            match stored.get(file) {
+                Some(&known) if known == signature => stats.unchanged += 1,
+                Some(_) => {
+                    index(&tx, file, signature)?;
+                    stats.updated += 1;
+                }
+                None => {
+                    index(&tx, file, signature)?;
+                    stats.added += 1;
+                }
+            }
+        }
+        for path in stored.keys().filter(|path| !files.contains(path)) {
+            tx.execute("DELETE FROM recordings WHERE path = ?1", params![path_text(path)?])?;
+            stats.removed += 1;
+        }
+        tx.commit()?;
+        Ok(stats)
+    }
+
+    /// Re-reads one recording (or drops it if it's gone). Returns whether
+    /// the index changed.
+    pub fn refresh(&mut self, recording: &Path) -> Result<bool> {
+        let tx = self.conn.transaction()?;
+        let changed = if recording.is_file() {
+            let signature = signature(recording)?;
+            let known: Option<(u64, i64)> = tx
+                .query_row("SELECT size, modified_ms FROM recordings WHERE path = ?1", params![path_text(recording)?], |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()?;
+            if known == Some(signature) {
+                false
+            } else {
+                index(&tx, recording, signature)?;
+                true
+            }
+        } else {
+            tx.execute("DELETE FROM recordings WHERE path = ?1", params![path_text(recording)?])? > 0
+        };
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    /// Every indexed recording, newest first.
+    pub fn recordings(&self) -> Result<Vec<LibraryEntry>> {
+        self.find(&LibraryQuery::default())
+    }
+
+    /// The indexed recordings matching `query`, in its order.
+    pub fn find(&self, query: &LibraryQuery) -> Result<Vec<LibraryEntry>> {
+        let (clause, params) = query.to_sql();
+        self.query(&clause, rusqlite::params_from_iter(params))
+    }
+
+    /// The indexed entry for `recording`, if there is one.
+    pub fn get(&self, recording: &Path) -> Result<Option<LibraryEntry>> {
+        Ok(self.query("WHERE path = ?1", params![path_text(recording)?])?.pop())
+    }
+
+    /// Whether `recording` would be indexed: an `.mp4` inside the root or one
+    /// of its subdirectories.
+    pub fn contains(&self, recording: &Path) -> bool {
+        let parent = recording.parent().and_then(|p| p.canonicalize().ok());
+        let root = self.root.canonicalize().ok();
+        recording.extension().is_some_and(|e| e == "mp4") && parent.zip(root).is_some_and(|(parent, root)| parent.starts_with(root))
+    }
+
+    fn query(&self, clause: &str, params: impl rusqlite::Params) -> Result<Vec<LibraryEntry>> {
+        let sql = format!(
+            "SELECT id, path, size, modified_ms, duration_secs, video_codec, audio_codecs, width, height, fps, started_ms, complete, patch, rank, placement FROM recordings {}",
+            clause
+        );
+        let mut statement = self.conn.prepare(&sql)?;
+        let rows = statement.query_map(params, |row| {
+            let audio_codecs: String = row.get(6)?;
+            let entry = LibraryEntry {
+                path: PathBuf::from(row.get::<_, String>(1)?),
+                size: row.get(2)?,
+                modified: from_millis(row.get(3)?),
+                duration_secs: row.get(4)?,
+                video_codec: row.get(5)?,
+                audio_codecs: audio_codecs.split(',').filter(|c| !c.is_empty()).map(String::from).collect(),
+                width: row.get(7)?,
+                height: row.get(8)?,
+                fps: row.get(9)?,
+                started_at: row.get::<_, Option<i64>>(10)?.map(from_millis),
+                complete: row.get(11)?,
+                tags: Vec::new(),
+                markers: Vec::new(),
+                game: GameInfo { patch: row.get(12)?, rank: row.get(13)?, placement: row.get(14)? },
+            };
+            Ok((row.get::<_, i64>(0)?, entry))
+        })?;
+        let mut entries: Vec<(i64, LibraryEntry)> = rows.collect::<rusqlite::Result<_>>()?;
+
+        let mut tags = self.conn.prepare_cached("SELECT tag FROM tags WHERE recording_id = ?1 ORDER BY rowid")?;
+        let mut markers = self.conn.prepare_cached("SELECT time_ms, label FROM markers WHERE recording_id = ?1 ORDER BY time_ms, rowid")?;
+        for (id, entry) in &mut entries {
+            entry.tags = tags.query_map(params![*id], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+            entry.markers = markers
+                .query_map(params![*id], |row| Ok(Marker { time: Duration::from_millis(row.get(0)?), label: row.get(1)? }))?
+                .collect::<rusqlite::Result<_>>()?;
+        }
+        Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+}
+
+/// The `.mp4` files inside `root` and its subdirectories (such as the
+/// `{year}/{month}` folders of a naming profile), sorted by path. Hidden
+/// directories are skipped.
+pub fn recordings_in(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+        for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            let hidden = path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('.'));
+            if path.is_dir() && !hidden {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == "mp4") && path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The recording a file in the recordings directory belongs to: itself for
+/// an `.mp4`, the recording next to it for a metadata or marker sidecar.
+pub fn recording_for(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    if name.ends_with(".mp4") {
+        return Some(path.to_path_buf());
+    }
+    let stem = name.strip_suffix(".markers.json").or_else(|| name.strip_suffix(".json"))?;
+    Some(path.with_file_name(format!("{}.mp4", stem)))
+}
+
+/// Size of the recording and the latest modification time, in ms since the
+/// epoch, of it and its sidecars.
+fn signature(recording: &Path) -> Result<(u64, i64)> {
+    let metadata = std::fs::metadata(recording).with_context(|| format!("Failed to read {}", recording.display()))?;
+    let sidecars = [RecordingMetadata::sidecar_path(recording), MarkerList::sidecar_path(recording)];
+    let modified = sidecars
+        .iter()
+        .filter_map(|sidecar| std::fs::metadata(sidecar).ok())
+        .chain([metadata.clone()])
+        .filter_map(|m| m.modified().ok())
+        .max()
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let millis = modified.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    Ok((metadata.len(), millis))
+}
+
+/// Reads `recording` and replaces its rows. Files still being written (or
+/// otherwise unreadable) are indexed from whatever metadata they have.
+fn index(tx: &Transaction, recording: &Path, (size, modified_ms): (u64, i64)) -> Result<()> {
+    let metadata = RecordingMetadata::for_recording(recording).ok().flatten();
+    let reader = Mp4Reader::open(recording).ok();
+    let video = reader.as_ref().and_then(|r| r.video_track()).and_then(|track| match &track.info {
+        TrackInfo::Video(info) => Some((info.width, info.height)),
+        TrackInfo::Audio(_) => None,
+    });
+    let audio_codecs: Vec<String> = reader
+        .iter()
+        .flat_map(|r| &r.tracks)
+        .filter_map(|track| match &track.info {
+            TrackInfo::Audio(info) => Some(format!("{:?}", info.codec).to_lowercase()),
+            TrackInfo::Video(_) => None,
+        })
+        .collect();
+    let chapters = reader.as_ref().map(|r| r.chapters.as_slice()).unwrap_or_default();
+    let mut markers = MarkerList::for_recording(recording, chapters).map(|list| list.markers().to_vec()).unwrap_or_default();
+    if markers.is_empty() {
+        markers = metadata.as_ref().map(|m| m.markers.clone()).unwrap_or_default();
+    }
+    let duration = reader.as_ref().map(|r| r.duration.as_secs_f64()).or(metadata.as_ref().map(|m| m.duration_secs));
+    let config = metadata.as_ref().map(|m| &m.config);
+    let game = metadata.as_ref().map(|m| m.game.clone()).unwrap_or_default();
+
+    let path = path_text(recording)?;
+    tx.execute("DELETE FROM recordings WHERE path = ?1", params![path])?;
+    tx.execute(
+        "INSERT INTO recordings (path, name, size, modified_ms, duration_secs, video_codec, audio_codecs, width, height, fps, started_ms, complete, patch, rank, placement)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            path,
+            recording.file_name().map(|n| n.to_string_lossy()).unwrap_or_default(),
+            size,
+            modified_ms,
+            duration,
+            video.map(|_| "h264"),
+            audio_codecs.join(","),
+            video.map(|(w, _)| w).or(config.map(|c| c.width)),
+            video.map(|(_, h)| h).or(config.map(|c| c.height)),
+            config.map(|c| c.fps),
+            metadata.as_ref().map(|m| m.started_at.timestamp_millis()),
+            metadata.as_ref().map_or(reader.is_some(), |m| m.is_complete()),
+            game.patch,
+            game.rank,
+            game.placement,
+        ],
+    )?;
+    let id = tx.last_insert_rowid();
+    for tag in metadata.iter().flat_map(|m| &m.tags) {
+        tx.execute("INSERT INTO tags (recording_id, tag) VALUES (?1, ?2)", params![id, tag])?;
+    }
+    for marker in &markers {
+        tx.execute("INSERT INTO markers (recording_id, time_ms, label) VALUES (?1, ?2, ?3)", params![id, marker.time.as_millis() as i64, marker.label])?;
+    }
+    Ok(())
+}
+
+fn path_text(path: &Path) -> Result<&str> {
+    path.to_str().with_context(|| format!("{} is not valid UTF-8", path.display()))
+}
+
+fn from_millis(millis: i64) -> DateTime<Local> {
+    Local.timestamp_millis_opt(millis).single().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{temp_dir, write_recording};
+    use recorder_core::RecordingConfig;
+
+    #[test]
+    fn test_scan_tracks_added_changed_and_removed_files() {
+        let dir = temp_dir("scan");
+        let first = dir.join("TFT-1.mp4");
+        write_recording(&first);
+        let mut config = RecordingConfig::new("Teamfight Tactics", 64, 48).with_tags(vec!["ranked".into()]);
+        config.game.placement = Some(2);
+        let mut metadata = RecordingMetadata::new(&config, &first);
+        metadata.ended_at = Some(metadata.started_at);
+        metadata.save().unwrap();
+        let mut marker_list = MarkerList::new(MarkerList::sidecar_path(&first));
+        marker_list.add(Marker { time: Duration::from_millis(500), label: "Stage 2-1".into() }).unwrap();
+        // Half-written: no moov yet, so only what the metadata says
+        std::fs::write(dir.join("TFT-2.mp4"), b"\0\0\0\x08free").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not a recording").unwrap();
+
+        let mut library = Library::open(&dir).unwrap();
+        assert_eq!(library.scan().unwrap(), ScanStats { added: 2, ..Default::default() });
+        let entry = library.get(&first).unwrap().unwrap();
+        assert_eq!((entry.width, entry.height, entry.fps), (Some(64), Some(48), Some(config.fps)));
+        assert_eq!(entry.video_codec.as_deref(), Some("h264"));
+        assert!((entry.duration_secs.unwrap() - 1.0).abs() < 0.05, "{:?}", entry.duration_secs);
+        assert_eq!(entry.tags, ["ranked"]);
+        assert_eq!(entry.markers, [Marker { time: Duration::from_millis(500), label: "Stage 2-1".into() }]);
+        assert_eq!(entry.game.placement, Some(2));
+        assert!(entry.complete);
+        let partial = library.get(&dir.join("TFT-2.mp4")).unwrap().unwrap();
+        assert_eq!((partial.duration_secs, partial.video_codec, partial.complete), (None, None, false));
+        // Newest first: TFT-2 has no metadata, so its modification time counts
+        assert_eq!(library.recordings().unwrap().len(), 2);
+
+        assert_eq!(library.scan().unwrap(), ScanStats { unchanged: 2, ..Default::default() });
+        std::fs::remove_file(dir.join("TFT-2.mp4")).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        metadata.tags.push("hyper roll".into());
+        metadata.save().unwrap();
+        assert_eq!(library.scan().unwrap(), ScanStats { updated: 1, removed: 1, ..Default::default() });
+        assert_eq!(library.get(&first).unwrap().unwrap().tags, ["ranked", "hyper roll"]);
+
+        // A second connection (the watcher's) sees the same index
+        let other = Library::open(&dir).unwrap();
+        assert_eq!(other.recordings().unwrap().len(), 1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_refresh_and_sidecar_paths() {
+        let dir = temp_dir("refresh");
+        let recording = dir.join("TFT-3.mp4");
+        let mut library = Library::open(&dir).unwrap();
+        assert!(!library.refresh(&recording).unwrap());
+        write_recording(&recording);
+        assert!(library.refresh(&recording).unwrap());
+        assert!(!library.refresh(&recording).unwrap());
+        std::fs::remove_file(&recording).unwrap();
+        assert!(library.refresh(&recording).unwrap());
+        assert!(library.recordings().unwrap().is_empty());
+
+        assert_eq!(recording_for(&dir.join("TFT-3.markers.json")), Some(recording.clone()));
+        assert_eq!(recording_for(&dir.join("TFT-3.json")), Some(recording.clone()));
+        assert_eq!(recording_for(&recording), Some(recording.clone()));
+        assert_eq!(recording_for(&dir.join("TFT-3.thumb.jpg")), None);
+        assert_eq!(recording_for(&dir.join(LIBRARY_FILE)), None);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_layout_subdirectories_are_indexed() {
+        let dir = temp_dir("layout");
+        let nested = dir.join("2024").join("09").join("TFT-6.mp4");
+        std::fs::create_dir_all(nested.parent().unwrap()).unwrap();
+        write_recording(&nested);
+        std::fs::create_dir_all(dir.join(".trash")).unwrap();
+        std::fs::write(dir.join(".trash").join("TFT-7.mp4"), b"").unwrap();
+
+        let mut library = Library::open(&dir).unwrap();
+        assert_eq!(library.scan().unwrap(), ScanStats { added: 1, ..Default::default() });
+        assert_eq!(library.recordings().unwrap()[0].path, nested);
+        assert!(library.contains(&nested));
+        assert!(!library.contains(&std::env::temp_dir().join("TFT-6.mp4")));
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,42 @@
+// ABOUTME: Recording library: an SQLite index of a recordings directory shared by the GUI and CLI
+// ABOUTME: Scanned on demand and kept current by a filesystem watcher
+
+pub mod files;
+pub mod index;
+pub mod query;
+pub mod retention;
+pub mod watch;
+
+pub use index::{Library, LibraryEntry, ScanStats, LIBRARY_FILE};
+pub use query::{LibraryQuery, SortKey};
+pub use retention::{PruneReport, RetentionPolicy};
+pub use watch::LibraryWatcher;
+
+#[cfg(test)]
+mod tests {
+    use recorder_core::config::{FrameRateMode, RecordingConfig};
+    use recorder_core::mp4::{Mp4Writer, Sample, TrackInfo, VideoTrackInfo};
+    use recorder_core::pipeline::encode_source;
+    use recorder_core::source::SyntheticSource;
+    use std::path::{Path, PathBuf};
+
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("library_{}_{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 1 s of test pattern at 10 fps.
+    pub(crate) fn write_recording(path: &Path) {
+        let config = RecordingConfig::new("test", 64, 48).with_frame_rate(10, FrameRateMode::Constant);
+        let stream = encode_source(&mut SyntheticSource::new(64, 48, 10, 10), &config).unwrap();
+        let mut writer = Mp4Writer::create(path).unwrap();
+        let video = writer.add_track(TrackInfo::Video(VideoTrackInfo { width: 64, height: 48, parameter_sets: stream.parameter_sets.clone().unwrap() }));
+        for packet in &stream.packets {
+            let sample = Sample { pts: packet.pts, duration: None, keyframe: packet.keyframe, data: &packet.data };
+            writer.write_sample(video, sample).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+}
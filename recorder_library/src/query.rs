@@ -0,0 +1,158 @@
+// ABOUTME: Filters and sort orders for listing the library (date range, duration, tags, patch, placement)
+// ABOUTME: Turned into a SQL WHERE/ORDER BY over the index tables
+
+use chrono::{DateTime, Local};
+use rusqlite::types::Value;
+use std::time::Duration;
+
+/// Column to order a listing by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    /// Newest first.
+    #[default]
+    Date,
+    /// Longest first.
+    Duration,
+    /// Largest first.
+    Size,
+    /// Best placement first; recordings without one last.
+    Placement,
+    /// By file name, A to Z.
+    Name,
+}
+
+/// Which recordings to list and in what order. Every filter that is set must match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LibraryQuery {
+    /// Recorded (or, without metadata, modified) at or after this.
+    pub since: Option<DateTime<Local>>,
+    /// Recorded before this.
+    pub until: Option<DateTime<Local>>,
+    pub min_duration: Option<Duration>,
+    pub max_duration: Option<Duration>,
+    /// Tags the recording must all have (case-insensitive).
+    pub tags: Vec<String>,
+    pub patch: Option<String>,
+    /// Inclusive placement range, e.g. `(1, 4)` for a top 4.
+    pub placement: Option<(u8, u8)>,
+    /// Text to find in the file name, a tag or a marker label (case-insensitive).
+    pub text: Option<String>,
+    pub sort: SortKey,
+    /// Flip the sort key's natural order.
+    pub reverse: bool,
+    pub limit: Option<usize>,
+}
+
+impl LibraryQuery {
+    /// The `WHERE ... ORDER BY ... LIMIT` clause and its parameters.
+    pub(crate) fn to_sql(&self) -> (String, Vec<Value>) {
+        let mut conditions: Vec<&str> = Vec::new();
+        let mut params: Vec<Value> = Vec::new();
+        let millis = |time: &DateTime<Local>| Value::Integer(time.timestamp_millis());
+        if let Some(since) = &self.since {
+            conditions.push("COALESCE(started_ms, modified_ms) >= ?");
+            params.push(millis(since));
+        }
+        if let Some(until) = &self.until {
+            conditions.push("COALESCE(started_ms, modified_ms) < ?");
+            params.push(millis(until));
+        }
+        if let Some(min) = self.min_duration {
+            conditions.push("duration_secs >= ?");
+            params.push(Value::Real(min.as_secs_f64()));
+        }
+        if let Some(max) = self.max_duration {
+            conditions.push("duration_secs <= ?");
+            params.push(Value::Real(max.as_secs_f64()));
+        }
+        for tag in &self.tags {
+            conditions.push("EXISTS (SELECT 1 FROM tags WHERE recording_id = recordings.id AND tag = ? COLLATE NOCASE)");
+            params.push(Value::Text(tag.clone()));
+        }
+        if let Some(patch) = &self.patch {
+            conditions.push("patch = ?");
+            params.push(Value::Text(patch.clone()));
+        }
+        if let Some((best, worst)) = self.placement {
+            conditions.push("placement BETWEEN ? AND ?");
+            params.extend([Value::Integer(best.into()), Value::Integer(worst.into())]);
+        }
+        if let Some(text) = &self.text {
+            conditions.push(
+                "(name LIKE ? ESCAPE '\\' OR EXISTS (SELECT 1 FROM tags WHERE recording_id = recordings.id AND tag LIKE ? ESCAPE '\\') \
+                 OR EXISTS (SELECT 1 FROM markers WHERE recording_id = recordings.id AND label LIKE ? ESCAPE '\\'))",
+            );
+            let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            params.extend(std::iter::repeat_n(Value::Text(format!("%{}%", escaped)), 3));
+        }
+
+        let mut clause = String::new();
+        if !conditions.is_empty() {
+            clause = format!("WHERE {}", conditions.join(" AND "));
+        }
+        let (column, descending) = match self.sort {
+            SortKey::Date => ("COALESCE(started_ms, modified_ms)", true),
+            SortKey::Duration => ("duration_secs", true),
+            SortKey::Size => ("size", true),
+            SortKey::Placement => ("placement", false),
+            SortKey::Name => ("name", false),
+        };
+        let direction = if descending != self.reverse { "DESC" } else { "ASC" };
+        // Unknown values (no duration, no placement) always go last
+        clause.push_str(&format!(" ORDER BY {} IS NULL, {} {}, path", column, column, direction));
+        if let Some(limit) = self.limit {
+            clause.push_str(&format!(" LIMIT {}", limit));
+        }
+        (clause, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{temp_dir, write_recording};
+    use crate::Library;
+    use chrono::TimeZone;
+    use recorder_core::markers::{Marker, MarkerList};
+    use recorder_core::{GameInfo, RecordingConfig, RecordingMetadata};
+
+    #[test]
+    fn test_filters_and_sorting() {
+        let dir = temp_dir("query");
+        let games = [("TFT-a.mp4", 3, 8, "14.19", "ranked"), ("TFT-b.mp4", 1, 2, "14.20", "ranked"), ("TFT-c.mp4", 20, 5, "14.20", "hyper roll")];
+        for (name, day, placement, patch, tag) in games {
+            let path = dir.join(name);
+            write_recording(&path);
+            let game = GameInfo { patch: Some(patch.into()), rank: None, placement: Some(placement) };
+            let mut metadata = RecordingMetadata::new(&RecordingConfig::default().with_tags(vec![tag.into()]).with_game(game), &path);
+            metadata.started_at = Local.with_ymd_and_hms(2024, 9, day, 20, 0, 0).unwrap();
+            metadata.save().unwrap();
+        }
+        MarkerList::new(MarkerList::sidecar_path(&dir.join("TFT-c.mp4"))).add(Marker { time: Duration::ZERO, label: "3-star Kobuko".into() }).unwrap();
+        // Still recording, 30 minutes in: only the metadata knows the duration
+        let live = dir.join("TFT-d.mp4");
+        std::fs::write(&live, b"").unwrap();
+        let mut metadata = RecordingMetadata::new(&RecordingConfig::default(), &live);
+        metadata.duration_secs = 1800.0;
+        metadata.save().unwrap();
+
+        let mut library = Library::open(&dir).unwrap();
+        library.scan().unwrap();
+        let names = |query: LibraryQuery| library.find(&query).unwrap().iter().map(|e| e.file_name().to_string()).collect::<Vec<_>>();
+
+        assert_eq!(names(LibraryQuery::default()), ["TFT-d.mp4", "TFT-c.mp4", "TFT-a.mp4", "TFT-b.mp4"]);
+        let september = |day| Some(Local.with_ymd_and_hms(2024, 9, day, 0, 0, 0).unwrap());
+        assert_eq!(names(LibraryQuery { since: september(2), until: september(10), ..Default::default() }), ["TFT-a.mp4"]);
+        assert_eq!(names(LibraryQuery { min_duration: Some(Duration::from_secs(60)), ..Default::default() }), ["TFT-d.mp4"]);
+        assert_eq!(names(LibraryQuery { tags: vec!["RANKED".into()], patch: Some("14.20".into()), ..Default::default() }), ["TFT-b.mp4"]);
+        assert_eq!(names(LibraryQuery { placement: Some((1, 4)), ..Default::default() }), ["TFT-b.mp4"]);
+        assert_eq!(names(LibraryQuery { text: Some("kobuko".into()), ..Default::default() }), ["TFT-c.mp4"]);
+        assert_eq!(names(LibraryQuery { text: Some("hyper".into()), ..Default::default() }), ["TFT-c.mp4"]);
+        assert!(names(LibraryQuery { text: Some("%".into()), ..Default::default() }).is_empty());
+
+        assert_eq!(names(LibraryQuery { sort: SortKey::Placement, ..Default::default() }), ["TFT-b.mp4", "TFT-c.mp4", "TFT-a.mp4", "TFT-d.mp4"]);
+        assert_eq!(names(LibraryQuery { sort: SortKey::Placement, reverse: true, limit: Some(2), ..Default::default() }), ["TFT-a.mp4", "TFT-c.mp4"]);
+        assert_eq!(names(LibraryQuery { sort: SortKey::Name, ..Default::default() }), ["TFT-a.mp4", "TFT-b.mp4", "TFT-c.mp4", "TFT-d.mp4"]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
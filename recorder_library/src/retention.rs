@@ -0,0 +1,205 @@
+// ABOUTME: Retention rules for the library (keep N days, cap total size, keep tagged recordings)
+// ABOUTME: Plans which recordings to delete, oldest first, and carries the plan out unless it's a dry run
+
+use crate::index::{Library, LibraryEntry};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Incomplete recordings touched this recently are assumed to still be recording.
+pub const IN_PROGRESS_GRACE: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Delete recordings older than this many days.
+    pub keep_days: Option<u32>,
+    /// Delete the oldest recordings while the library holds more than this.
+    pub max_bytes: Option<u64>,
+    /// Never delete recordings with a tag (such as `starred`).
+    pub keep_tagged: bool,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self { keep_days: None, max_bytes: None, keep_tagged: true }
+    }
+}
+
+impl RetentionPolicy {
+    /// Whether any rule would delete anything.
+    pub fn is_active(&self) -> bool {
+        self.keep_days.is_some() || self.max_bytes.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneReason {
+    /// Older than `keep_days`.
+    Age,
+    /// Deleted to bring the library under `max_bytes`.
+    Size,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Pruned {
+    pub entry: LibraryEntry,
+    pub reason: PruneReason,
+}
+
+/// What a prune deleted, or would delete on a dry run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PruneReport {
+    pub dry_run: bool,
+    /// Oldest first.
+    pub deleted: Vec<Pruned>,
+    pub freed_bytes: u64,
+    /// Size of the library before and after.
+    pub total_bytes: u64,
+    pub remaining_bytes: u64,
+    /// Recordings kept only because they're tagged or still recording.
+    pub protected: usize,
+}
+
+/// The recordings `policy` deletes at `now`, oldest first.
+pub fn plan(entries: &[LibraryEntry], policy: &RetentionPolicy, now: DateTime<Local>) -> PruneReport {
+    let total_bytes: u64 = entries.iter().map(|e| e.size).sum();
+    let in_progress = |entry: &LibraryEntry| !entry.complete && (now - entry.modified).to_std().unwrap_or_default() < IN_PROGRESS_GRACE;
+    let tagged = |entry: &LibraryEntry| policy.keep_tagged && !entry.tags.is_empty();
+
+    let mut oldest_first: Vec<&LibraryEntry> = entries.iter().collect();
+    oldest_first.sort_by_key(|e| e.date());
+    let mut reasons: Vec<Option<PruneReason>> = vec![None; oldest_first.len()];
+    let mut protected = 0;
+    if policy.is_active() {
+        for (entry, reason) in oldest_first.iter().zip(&mut reasons) {
+            if tagged(entry) || in_progress(entry) {
+                protected += 1;
+                continue;
+            }
+            let age = (now - entry.date()).to_std().unwrap_or_default();
+            if policy.keep_days.is_some_and(|days| age > Duration::from_secs(u64::from(days) * 24 * 60 * 60)) {
+                *reason = Some(PruneReason::Age);
+            }
+        }
+    }
+
+    let mut remaining = total_bytes - oldest_first.iter().zip(&reasons).filter(|(_, r)| r.is_some()).map(|(e, _)| e.size).sum::<u64>();
+    if let Some(max) = policy.max_bytes {
+        for (entry, reason) in oldest_first.iter().zip(&mut reasons) {
+            if remaining <= max {
+                break;
+            }
+            if reason.is_none() && !tagged(entry) && !in_progress(entry) {
+                *reason = Some(PruneReason::Size);
+                remaining -= entry.size;
+            }
+        }
+    }
+
+    let deleted: Vec<Pruned> = oldest_first
+        .into_iter()
+        .zip(reasons)
+        .filter_map(|(entry, reason)| Some(Pruned { entry: entry.clone(), reason: reason? }))
+        .collect();
+    PruneReport { dry_run: true, freed_bytes: total_bytes - remaining, deleted, total_bytes, remaining_bytes: remaining, protected }
+}
+
+impl Library {
+    /// Applies `policy` to the indexed recordings. With `dry_run` nothing is
+    /// deleted and the report says what would be.
+    pub fn prune(&mut self, policy: &RetentionPolicy, dry_run: bool) -> Result<PruneReport> {
+        let mut report = plan(&self.recordings()?, policy, Local::now());
+        report.dry_run = dry_run;
+        if !dry_run {
+            for pruned in &report.deleted {
+                self.delete(&pruned.entry.path)?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{temp_dir, write_recording};
+    use recorder_core::{GameInfo, RecordingConfig, RecordingMetadata};
+    use std::path::PathBuf;
+
+    fn entry(name: &str, days_old: i64, size: u64, tags: &[&str], now: DateTime<Local>) -> LibraryEntry {
+        LibraryEntry {
+            path: PathBuf::from(name),
+            size,
+            modified: now - chrono::Duration::days(days_old),
+            duration_secs: Some(1800.0),
+            video_codec: Some("h264".into()),
+            audio_codecs: Vec::new(),
+            width: None,
+            height: None,
+            fps: None,
+            started_at: Some(now - chrono::Duration::days(days_old)),
+            complete: true,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            markers: Vec::new(),
+            game: GameInfo::default(),
+        }
+    }
+
+    fn names(report: &PruneReport) -> Vec<(&str, PruneReason)> {
+        report.deleted.iter().map(|p| (p.entry.path.to_str().unwrap(), p.reason)).collect()
+    }
+
+    #[test]
+    fn test_plan_deletes_old_then_oldest_until_under_budget() {
+        let now = Local::now();
+        let mut live = entry("live.mp4", 0, 500, &[], now);
+        live.complete = false;
+        let entries = vec![
+            entry("new.mp4", 1, 100, &[], now),
+            entry("old-starred.mp4", 60, 100, &["starred"], now),
+            entry("old.mp4", 40, 100, &[], now),
+            entry("mid.mp4", 10, 100, &[], now),
+            entry("recent.mp4", 5, 100, &[], now),
+            live,
+        ];
+
+        let report = plan(&entries, &RetentionPolicy { keep_days: Some(30), ..Default::default() }, now);
+        assert_eq!(names(&report), [("old.mp4", PruneReason::Age)]);
+        assert_eq!((report.total_bytes, report.freed_bytes, report.remaining_bytes, report.protected), (1000, 100, 900, 2));
+
+        // Over budget: oldest unprotected go first, the live recording never
+        let policy = RetentionPolicy { keep_days: Some(30), max_bytes: Some(650), keep_tagged: true };
+        let report = plan(&entries, &policy, now);
+        assert_eq!(names(&report), [("old.mp4", PruneReason::Age), ("mid.mp4", PruneReason::Size), ("recent.mp4", PruneReason::Size), ("new.mp4", PruneReason::Size)]);
+        assert_eq!(report.remaining_bytes, 600);
+
+        let report = plan(&entries, &RetentionPolicy { keep_days: Some(30), max_bytes: None, keep_tagged: false }, now);
+        assert_eq!(names(&report), [("old-starred.mp4", PruneReason::Age), ("old.mp4", PruneReason::Age)]);
+        assert!(plan(&entries, &RetentionPolicy::default(), now).deleted.is_empty());
+    }
+
+    #[test]
+    fn test_dry_run_keeps_files() {
+        let dir = temp_dir("prune");
+        let recording = dir.join("TFT-old.mp4");
+        write_recording(&recording);
+        let mut metadata = RecordingMetadata::new(&RecordingConfig::default(), &recording);
+        metadata.started_at = Local::now() - chrono::Duration::days(90);
+        metadata.ended_at = Some(metadata.started_at);
+        metadata.save().unwrap();
+        let mut library = Library::open(&dir).unwrap();
+        library.scan().unwrap();
+
+        let policy = RetentionPolicy { keep_days: Some(30), ..Default::default() };
+        let report = library.prune(&policy, true).unwrap();
+        assert!(report.dry_run && report.deleted.len() == 1);
+        assert!(recording.exists());
+        let report = library.prune(&policy, false).unwrap();
+        assert!(!report.dry_run && report.deleted.len() == 1);
+        assert!(!recording.exists() && !RecordingMetadata::sidecar_path(&recording).exists());
+        assert!(library.recordings().unwrap().is_empty());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,105 @@
+// ABOUTME: Watches a recordings directory and re-indexes recordings that other programs change
+// ABOUTME: Filesystem events are batched on a worker thread holding its own library connection
+
+use crate::index::{recording_for, Library};
+use anyhow::Result;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+/// Quiet time after the last event before a batch is indexed.
+pub const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// Longest a batch waits, so files being recorded still get re-indexed.
+const MAX_BATCH_TIME: Duration = Duration::from_secs(2);
+
+/// Keeps the index of a directory current until dropped.
+pub struct LibraryWatcher {
+    _watcher: RecommendedWatcher,
+    changed: Receiver<PathBuf>,
+}
+
+impl LibraryWatcher {
+    /// Starts watching `root`. `on_change` is called from the worker thread
+    /// after each batch that changed the index, e.g. to wake up a UI.
+    pub fn start(root: impl AsRef<Path>, on_change: impl Fn() + Send + 'static) -> Result<Self> {
+        let root = root.as_ref();
+        let mut library = Library::open(root)?;
+        let (event_tx, event_rx) = mpsc::channel::<PathBuf>();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            for path in event.map(|e| e.paths).unwrap_or_default() {
+                if let Some(recording) = recording_for(&path) {
+                    let _ = event_tx.send(recording);
+                }
+            }
+        })?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+
+        let (changed_tx, changed) = mpsc::channel();
+        // Ends when the watcher, and with it the event sender, is dropped
+        std::thread::spawn(move || {
+            while let Ok(first) = event_rx.recv() {
+                let mut batch = BTreeSet::from([first]);
+                let deadline = Instant::now() + MAX_BATCH_TIME;
+                while let Ok(path) = event_rx.recv_timeout(SETTLE_TIME.min(deadline.saturating_duration_since(Instant::now()))) {
+                    batch.insert(path);
+                }
+                let mut any = false;
+                for recording in batch {
+                    match library.refresh(&recording) {
+                        Ok(true) => {
+                            any = true;
+                            let _ = changed_tx.send(recording);
+                        }
+                        Ok(false) => {}
+                        Err(e) => eprintln!("Failed to index {}: {:#}", recording.display(), e),
+                    }
+                }
+                if any {
+                    on_change();
+                }
+            }
+        });
+        Ok(Self { _watcher: watcher, changed })
+    }
+
+    /// Recordings re-indexed since the last call.
+    pub fn changes(&self) -> Vec<PathBuf> {
+        self.changed.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{temp_dir, write_recording};
+
+    #[test]
+    fn test_external_changes_are_indexed() {
+        let dir = temp_dir("watch");
+        let (wake_tx, wake_rx) = mpsc::channel();
+        let watcher = LibraryWatcher::start(&dir, move || {
+            let _ = wake_tx.send(());
+        })
+        .unwrap();
+
+        let recording = dir.join("TFT-4.mp4");
+        write_recording(&recording);
+        wake_rx.recv_timeout(Duration::from_secs(10)).expect("watcher never reported the new file");
+        assert!(watcher.changes().contains(&recording));
+        // Writing the file may take more than one batch; wait for the last
+        let library = Library::open(&dir).unwrap();
+        let indexed = |library: &Library| library.recordings().unwrap().first().map(|e| (e.path.clone(), e.complete));
+        while indexed(&library) != Some((recording.clone(), true)) {
+            wake_rx.recv_timeout(Duration::from_secs(10)).expect("watcher never indexed the finished file");
+        }
+
+        std::fs::remove_file(&recording).unwrap();
+        while indexed(&library).is_some() {
+            wake_rx.recv_timeout(Duration::from_secs(10)).expect("watcher never reported the deletion");
+        }
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,121 @@
+// ABOUTME: Upload settings: the S3-compatible endpoint, bucket, credentials, part size and bandwidth cap
+// ABOUTME: Read from the [upload] section of the settings file; keys may come from the AWS_* environment
+
+use crate::sigv4::Credentials;
+use anyhow::Result;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// S3 rejects smaller parts, except for the last one.
+pub const MIN_PART_MEGABYTES: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    /// Base URL of the store, e.g. `http://minio.local:9000`. Buckets are
+    /// addressed path-style (`<endpoint>/<bucket>/<key>`), which MinIO expects.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    /// Falls back to `AWS_ACCESS_KEY_ID`.
+    pub access_key: Option<String>,
+    /// Falls back to `AWS_SECRET_ACCESS_KEY`.
+    pub secret_key: Option<String>,
+    /// Prepended to each object key, which is otherwise the file's path
+    /// below the recordings directory.
+    pub prefix: String,
+    /// Files larger than this are sent as a multipart upload in parts of
+    /// this size.
+    pub part_size_mb: u64,
+    /// Cap on upload bandwidth in megabits per second; 0 means none.
+    pub max_mbps: f64,
+    /// Queue recordings as soon as they are finalized.
+    pub auto: bool,
+    /// Attempts before a file is marked failed and left for `retry`.
+    pub max_attempts: u32,
+    /// Limit for each request, connecting included.
+    pub timeout_secs: u64,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            access_key: None,
+            secret_key: None,
+            prefix: String::new(),
+            part_size_mb: 16,
+            max_mbps: 0.0,
+            auto: true,
+            max_attempts: 5,
+            timeout_secs: 60,
+        }
+    }
+}
+
+impl UploadConfig {
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            self.endpoint.starts_with("http://") || self.endpoint.starts_with("https://"),
+            "upload.endpoint must be an http:// or https:// URL, got {:?}",
+            self.endpoint
+        );
+        anyhow::ensure!(!self.bucket.is_empty() && !self.bucket.contains('/'), "upload.bucket must name a bucket");
+        anyhow::ensure!(
+            self.part_size_mb >= MIN_PART_MEGABYTES,
+            "upload.part_size_mb must be at least {} (S3's minimum part size)",
+            MIN_PART_MEGABYTES
+        );
+        anyhow::ensure!(self.max_mbps >= 0.0 && self.max_mbps.is_finite(), "upload.max_mbps can't be negative");
+        anyhow::ensure!(self.max_attempts > 0, "upload.max_attempts must be at least 1");
+        anyhow::ensure!(self.timeout_secs > 0, "upload.timeout_secs must be at least 1");
+        Ok(())
+    }
+
+    /// The configured keys, or the ones in the `AWS_*` environment.
+    pub fn credentials(&self) -> Result<Credentials> {
+        let key = |configured: &Option<String>, variable: &str| {
+            configured
+                .clone()
+                .or_else(|| std::env::var(variable).ok())
+                .ok_or_else(|| anyhow::anyhow!("No upload credentials: set upload.{} or {}", if variable.contains("SECRET") { "secret_key" } else { "access_key" }, variable))
+        };
+        Ok(Credentials { access_key: key(&self.access_key, "AWS_ACCESS_KEY_ID")?, secret_key: key(&self.secret_key, "AWS_SECRET_ACCESS_KEY")? })
+    }
+
+    pub fn part_size(&self) -> u64 {
+        self.part_size_mb * 1024 * 1024
+    }
+
+    /// The bandwidth cap in bytes per second, if there is one.
+    pub fn max_bytes_per_sec(&self) -> Option<f64> {
+        (self.max_mbps > 0.0).then(|| self.max_mbps * 1_000_000.0 / 8.0)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let config = UploadConfig { endpoint: "http://localhost:9000".into(), bucket: "scrims".into(), ..Default::default() };
+        config.validate().unwrap();
+        assert_eq!(config.max_bytes_per_sec(), None);
+        assert_eq!(UploadConfig { max_mbps: 8.0, ..config.clone() }.max_bytes_per_sec(), Some(1_000_000.0));
+
+        assert!(UploadConfig { endpoint: "minio:9000".into(), ..config.clone() }.validate().is_err());
+        assert!(UploadConfig { bucket: String::new(), ..config.clone() }.validate().is_err());
+        assert!(UploadConfig { part_size_mb: 4, ..config.clone() }.validate().is_err());
+        assert!(UploadConfig { max_mbps: -1.0, ..config.clone() }.validate().is_err());
+
+        let keyed = UploadConfig { access_key: Some("minio".into()), secret_key: Some("minio123".into()), ..config };
+        assert_eq!(keyed.credentials().unwrap().secret_key, "minio123");
+    }
+}
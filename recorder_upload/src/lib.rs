@@ -0,0 +1,169 @@
+// ABOUTME: Uploads finished recordings and their sidecars to S3-compatible storage such as MinIO
+// ABOUTME: A persisted queue makes multipart uploads resumable across restarts; bandwidth can be capped
+
+pub mod config;
+pub mod queue;
+pub mod s3;
+pub mod sigv4;
+pub mod throttle;
+pub mod uploader;
+
+pub use config::UploadConfig;
+pub use queue::{UploadItem, UploadQueue, UploadState, DEFAULT_QUEUE};
+pub use uploader::{enqueue_recording, object_key, RunStats, Uploader};
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("upload_{}_{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[derive(Default)]
+    struct StubState {
+        objects: HashMap<String, Vec<u8>>,
+        uploads: HashMap<String, BTreeMap<u32, Vec<u8>>>,
+        next_upload: u32,
+        /// Part numbers to answer with a 500 once.
+        fail_parts: Vec<u32>,
+        /// `METHOD /path?query` of each request.
+        requests: Vec<String>,
+    }
+
+    /// An in-process stand-in for MinIO serving bucket `scrims` with PutObject
+    /// and the multipart calls. Checks that requests are signed and that the
+    /// payload matches its `x-amz-content-sha256`.
+    pub(crate) struct StubS3 {
+        pub endpoint: String,
+        state: Arc<Mutex<StubState>>,
+    }
+
+    impl StubS3 {
+        pub fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let endpoint = format!("http://{}", listener.local_addr().unwrap());
+            let state = Arc::new(Mutex::new(StubState::default()));
+            let shared = state.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let state = shared.clone();
+                    std::thread::spawn(move || serve(stream, &state).ok());
+                }
+            });
+            Self { endpoint, state }
+        }
+
+        pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+            self.state.lock().unwrap().objects.get(key).cloned()
+        }
+
+        pub fn fail_part(&self, number: u32) {
+            self.state.lock().unwrap().fail_parts.push(number);
+        }
+
+        pub fn requests(&self) -> Vec<String> {
+            self.state.lock().unwrap().requests.clone()
+        }
+
+        pub fn clear_requests(&self) {
+            self.state.lock().unwrap().requests.clear();
+        }
+    }
+
+    fn serve(stream: TcpStream, state: &Mutex<StubState>) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or_default().to_string(), parts.next().unwrap_or_default().to_string());
+        let mut headers = HashMap::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            let Some((name, value)) = header.trim_end().split_once(':') else { break };
+            headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+        }
+        let length = headers.get("content-length").and_then(|length| length.parse().ok()).unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        let (status, extra, reply) = respond(&method, &target, &headers, body, &mut state.lock().unwrap());
+        let mut stream = stream;
+        write!(stream, "HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}", status, reply.len(), extra, reply)
+    }
+
+    fn respond(method: &str, target: &str, headers: &HashMap<String, String>, body: Vec<u8>, state: &mut StubState) -> (u16, String, String) {
+        state.requests.push(format!("{} {}", method, target));
+        let error = |status, code: &str| (status, String::new(), format!("<Error><Code>{}</Code><Message>stub</Message></Error>", code));
+        if !headers.get("authorization").is_some_and(|auth| auth.starts_with("AWS4-HMAC-SHA256 Credential=minio/")) {
+            return error(403, "AccessDenied");
+        }
+        if headers.get("x-amz-content-sha256") != Some(&crate::sigv4::sha256_hex(&body)) {
+            return error(400, "XAmzContentSHA256Mismatch");
+        }
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let Some(key) = path.strip_prefix("/scrims/") else {
+            return error(404, "NoSuchBucket");
+        };
+        let key = percent_decode(key);
+        let query: HashMap<&str, &str> = query.split('&').filter(|pair| !pair.is_empty()).map(|pair| pair.split_once('=').unwrap_or((pair, ""))).collect();
+
+        match (method, query.get("uploadId"), query.get("partNumber")) {
+            ("POST", None, _) if query.contains_key("uploads") => {
+                state.next_upload += 1;
+                let id = format!("upload-{}", state.next_upload);
+                state.uploads.insert(id.clone(), BTreeMap::new());
+                (200, String::new(), format!("<InitiateMultipartUploadResult><Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>", key, id))
+            }
+            ("PUT", Some(id), Some(number)) => {
+                let number: u32 = number.parse().unwrap();
+                if let Some(index) = state.fail_parts.iter().position(|&n| n == number) {
+                    state.fail_parts.remove(index);
+                    return error(500, "InternalError");
+                }
+                let Some(upload) = state.uploads.get_mut(*id) else { return error(404, "NoSuchUpload") };
+                upload.insert(number, body);
+                (200, format!("ETag: \"part-{}\"\r\n", number), String::new())
+            }
+            ("POST", Some(id), None) => {
+                let Some(upload) = state.uploads.remove(*id) else { return error(404, "NoSuchUpload") };
+                let request = String::from_utf8(body).unwrap();
+                let mut object = Vec::new();
+                for number in request.split("<PartNumber>").skip(1).map(|rest| rest.split('<').next().unwrap().parse::<u32>().unwrap()) {
+                    object.extend_from_slice(&upload[&number]);
+                }
+                state.objects.insert(key, object);
+                (200, String::new(), "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>".to_string())
+            }
+            ("PUT", None, None) => {
+                state.objects.insert(key, body);
+                (200, "ETag: \"object\"\r\n".to_string(), String::new())
+            }
+            _ => error(400, "NotImplemented"),
+        }
+    }
+
+    fn percent_decode(text: &str) -> String {
+        let bytes = text.as_bytes();
+        let mut decoded = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'%' && i + 2 < bytes.len() {
+                decoded.push(u8::from_str_radix(&text[i + 1..i + 3], 16).unwrap());
+                i += 3;
+            } else {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+        String::from_utf8(decoded).unwrap()
+    }
+}
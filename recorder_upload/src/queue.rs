@@ -0,0 +1,275 @@
+// ABOUTME: The upload queue, persisted as JSON so uploads resume where they stopped after a restart
+// ABOUTME: Changes run under an exclusive lock and rewrite the file atomically, so the daemon, CLI and GUI can share it
+
+use crate::s3::CompletedPart;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_QUEUE: &str = "~/.tft-recorder/uploads.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadState {
+    Queued,
+    /// Started and not finished; picked up first, resuming after the last
+    /// stored part.
+    Uploading,
+    Done,
+    /// Out of attempts; waits for `retry`.
+    Failed,
+}
+
+impl UploadState {
+    pub fn is_pending(self) -> bool {
+        matches!(self, UploadState::Queued | UploadState::Uploading)
+    }
+}
+
+/// A multipart upload in progress.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultipartProgress {
+    pub upload_id: String,
+    pub part_size: u64,
+    /// Parts stored so far, in order.
+    pub parts: Vec<CompletedPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadItem {
+    pub id: u64,
+    pub path: PathBuf,
+    pub key: String,
+    pub bytes: u64,
+    pub uploaded_bytes: u64,
+    pub state: UploadState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multipart: Option<MultipartProgress>,
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub added_at: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Local>>,
+}
+
+impl UploadItem {
+    /// Share of the file stored so far, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        if self.bytes == 0 {
+            return if self.state == UploadState::Done { 1.0 } else { 0.0 };
+        }
+        self.uploaded_bytes as f32 / self.bytes as f32
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UploadQueue {
+    next_id: u64,
+    items: Vec<UploadItem>,
+}
+
+impl UploadQueue {
+    /// Reads the queue at `path`; a missing file is an empty queue.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Malformed upload queue {}", path.display()))
+    }
+
+    /// Writes the queue to a temporary file renamed over `path`, so readers
+    /// never see it half-written. Only called under the lock of `update`.
+    fn save(&self, path: &Path) -> Result<()> {
+        let temp = sibling(path, &format!("{}.tmp", std::process::id()));
+        std::fs::write(&temp, serde_json::to_string_pretty(self)?).with_context(|| format!("Failed to write {}", temp.display()))?;
+        std::fs::rename(&temp, path).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Applies `change` to the queue stored at `path` and saves it, holding
+    /// the queue's lock throughout so changes from other processes (the
+    /// daemon's worker saves after every part) are neither lost nor lose ours.
+    pub fn update<T>(path: &Path, change: impl FnOnce(&mut UploadQueue) -> T) -> Result<T> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let _lock = QueueLock::acquire(&sibling(path, "lock"))?;
+        let mut queue = Self::load(path)?;
+        let result = change(&mut queue);
+        queue.save(path)?;
+        Ok(result)
+    }
+
+    pub fn items(&self) -> &[UploadItem] {
+        &self.items
+    }
+
+    pub fn get(&self, id: u64) -> Option<&UploadItem> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut UploadItem> {
+        self.items.iter_mut().find(|item| item.id == id)
+    }
+
+    /// Queues `path` as `key`, unless it is already waiting to be uploaded.
+    /// Returns the new item's ID.
+    pub fn add(&mut self, path: &Path, key: &str) -> Result<Option<u64>> {
+        if self.items.iter().any(|item| item.path == path && item.state.is_pending()) {
+            return Ok(None);
+        }
+        let bytes = std::fs::metadata(path).with_context(|| format!("Can't upload {}", path.display()))?.len();
+        self.next_id += 1;
+        self.items.push(UploadItem {
+            id: self.next_id,
+            path: path.to_path_buf(),
+            key: key.to_string(),
+            bytes,
+            uploaded_bytes: 0,
+            state: UploadState::Queued,
+            multipart: None,
+            attempts: 0,
+            error: None,
+            added_at: Local::now(),
+            finished_at: None,
+        });
+        Ok(Some(self.next_id))
+    }
+
+    /// IDs of the items to upload, interrupted ones first, then by age.
+    pub fn pending(&self) -> Vec<u64> {
+        let mut pending: Vec<&UploadItem> = self.items.iter().filter(|item| item.state.is_pending()).collect();
+        pending.sort_by_key(|item| (item.state != UploadState::Uploading, item.id));
+        pending.iter().map(|item| item.id).collect()
+    }
+
+    /// Queues failed items again with fresh attempts; returns how many.
+    pub fn retry_failed(&mut self) -> usize {
+        let failed = self.items.iter_mut().filter(|item| item.state == UploadState::Failed);
+        let mut count = 0;
+        for item in failed {
+            item.state = if item.multipart.is_some() { UploadState::Uploading } else { UploadState::Queued };
+            item.attempts = 0;
+            item.error = None;
+            count += 1;
+        }
+        count
+    }
+
+    /// Forgets uploaded items; returns how many.
+    pub fn clear_done(&mut self) -> usize {
+        let before = self.items.len();
+        self.items.retain(|item| item.state != UploadState::Done);
+        before - self.items.len()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<UploadItem> {
+        let index = self.items.iter().position(|item| item.id == id)?;
+        Some(self.items.remove(index))
+    }
+}
+
+/// `<path>.<suffix>`, next to the queue file.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", suffix));
+    path.with_file_name(name)
+}
+
+/// An exclusive `flock` on the queue's lock file, released when dropped.
+struct QueueLock {
+    _file: std::fs::File,
+}
+
+impl QueueLock {
+    fn acquire(path: &Path) -> Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        loop {
+            // SAFETY: the descriptor belongs to `file`, which outlives the call
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+                return Ok(Self { _file: file });
+            }
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(error).with_context(|| format!("Failed to lock {}", path.display()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_dir;
+
+    #[test]
+    fn test_queue_persists_and_orders() {
+        let dir = temp_dir("queue");
+        let file = dir.join("uploads.json");
+        let (first, second) = (dir.join("a.mp4"), dir.join("b.mp4"));
+        std::fs::write(&first, b"aaaa").unwrap();
+        std::fs::write(&second, b"bb").unwrap();
+
+        let ids = UploadQueue::update(&file, |queue| {
+            let a = queue.add(&first, "a.mp4").unwrap().unwrap();
+            let b = queue.add(&second, "b.mp4").unwrap().unwrap();
+            // Already waiting
+            assert_eq!(queue.add(&first, "a.mp4").unwrap(), None);
+            queue.get_mut(b).unwrap().state = UploadState::Uploading;
+            (a, b)
+        })
+        .unwrap();
+        assert!(UploadQueue::load(&dir.join("missing.json")).unwrap().items().is_empty());
+        assert!(UploadQueue::load(&file).unwrap().add(&dir.join("gone.mp4"), "gone.mp4").is_err());
+
+        let mut queue = UploadQueue::load(&file).unwrap();
+        assert_eq!(queue.get(ids.0).unwrap().bytes, 4);
+        // The interrupted upload resumes first
+        assert_eq!(queue.pending(), [ids.1, ids.0]);
+
+        queue.get_mut(ids.0).unwrap().state = UploadState::Done;
+        queue.get_mut(ids.1).unwrap().state = UploadState::Failed;
+        assert!(queue.pending().is_empty());
+        assert_eq!(queue.retry_failed(), 1);
+        assert_eq!(queue.get(ids.1).unwrap().state, UploadState::Queued);
+        assert_eq!(queue.clear_done(), 1);
+        // Uploaded files can be queued again
+        assert!(queue.add(&first, "a.mp4").unwrap().unwrap() > ids.1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_concurrent_updates_are_not_lost() {
+        let dir = temp_dir("queue_lock");
+        let file = dir.join("uploads.json");
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let (dir, file) = (dir.clone(), file.clone());
+                std::thread::spawn(move || {
+                    for n in 0..10 {
+                        let path = dir.join(format!("{}-{}.mp4", writer, n));
+                        std::fs::write(&path, b"x").unwrap();
+                        UploadQueue::update(&file, |queue| queue.add(&path, "x.mp4").unwrap()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let queue = UploadQueue::load(&file).unwrap();
+        assert_eq!(queue.items().len(), 80);
+        assert_eq!(queue.next_id, 80);
+        assert!(dir.join("uploads.json.lock").exists());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,170 @@
+// ABOUTME: Minimal S3 client for uploads: PutObject and the multipart upload calls, signed with SigV4
+// ABOUTME: Addresses buckets path-style so it works with MinIO and other S3-compatible stores
+
+use crate::config::UploadConfig;
+use crate::sigv4::{self, CanonicalRequest, Credentials};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Read;
+
+/// A part stored by `UploadPart`, needed again to complete the upload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompletedPart {
+    pub number: u32,
+    pub etag: String,
+}
+
+/// An error response from the store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Error {
+    pub status: u16,
+    /// The `<Code>` of the error document, e.g. `NoSuchUpload`.
+    pub code: String,
+    pub message: String,
+}
+
+impl fmt::Display for S3Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S3 error {} {}", self.status, self.code)?;
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for S3Error {}
+
+pub struct S3Client {
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    credentials: Credentials,
+    agent: ureq::Agent,
+}
+
+impl S3Client {
+    pub fn new(config: &UploadConfig) -> Result<Self> {
+        config.validate()?;
+        let endpoint = config.endpoint.trim_end_matches('/').to_string();
+        let host = endpoint.split_once("://").map(|(_, rest)| rest).unwrap_or(&endpoint);
+        let host = host.split('/').next().unwrap_or_default().to_string();
+        Ok(Self {
+            endpoint,
+            host,
+            bucket: config.bucket.clone(),
+            region: config.region.clone(),
+            credentials: config.credentials()?,
+            agent: ureq::AgentBuilder::new().timeout(config.timeout()).build(),
+        })
+    }
+
+    /// Stores `body` (`length` bytes hashing to `sha256`) as `key` in one
+    /// request, returning its ETag.
+    pub fn put_object(&self, key: &str, body: impl Read, length: u64, sha256: &str) -> Result<String> {
+        let response = self.send("PUT", key, &[], sha256, Some((body, length)))?;
+        etag(&response)
+    }
+
+    /// Starts a multipart upload of `key`, returning its upload ID.
+    pub fn create_multipart_upload(&self, key: &str) -> Result<String> {
+        let response = self.send::<&[u8]>("POST", key, &[("uploads", "")], sigv4::EMPTY_SHA256, None)?;
+        let body = response.into_string()?;
+        xml_value(&body, "UploadId").map(str::to_string).with_context(|| format!("No UploadId in the response: {}", body))
+    }
+
+    /// Stores part `number` (1-based) of an upload, returning its ETag.
+    pub fn upload_part(&self, key: &str, upload_id: &str, number: u32, body: impl Read, length: u64, sha256: &str) -> Result<String> {
+        let number = number.to_string();
+        let response = self.send("PUT", key, &[("partNumber", &number), ("uploadId", upload_id)], sha256, Some((body, length)))?;
+        etag(&response)
+    }
+
+    /// Joins the `parts` of an upload into the object.
+    pub fn complete_multipart_upload(&self, key: &str, upload_id: &str, parts: &[CompletedPart]) -> Result<()> {
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for part in parts {
+            body.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", part.number, xml_escape(&part.etag)));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let sha256 = sigv4::sha256_hex(body.as_bytes());
+        let response = self.send("POST", key, &[("uploadId", upload_id)], &sha256, Some((body.as_bytes(), body.len() as u64)))?;
+        // Errors during the join arrive with a 200 status
+        let status = response.status();
+        let text = response.into_string()?;
+        if text.contains("<Error>") {
+            return Err(error_from_body(status, &text).into());
+        }
+        Ok(())
+    }
+
+    fn send<R: Read>(&self, method: &str, key: &str, query: &[(&str, &str)], sha256: &str, body: Option<(R, u64)>) -> Result<ureq::Response> {
+        let path = format!("/{}/{}", sigv4::uri_encode(&self.bucket, true), sigv4::uri_encode(key, false));
+        let query: Vec<(String, String)> = query.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let now = chrono::Utc::now();
+        let headers = vec![
+            ("host".to_string(), self.host.clone()),
+            ("x-amz-content-sha256".to_string(), sha256.to_string()),
+            ("x-amz-date".to_string(), sigv4::amz_date(now)),
+        ];
+        let canonical = CanonicalRequest { method, path: &path, query: &query, headers: &headers, payload_sha256: sha256 };
+        let authorization = sigv4::authorization(&self.credentials, &self.region, &canonical, now);
+
+        let mut url = format!("{}{}", self.endpoint, path);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&sigv4::canonical_query(&query));
+        }
+        let mut request = self.agent.request(method, &url).set("Authorization", &authorization);
+        for (name, value) in headers.iter().filter(|(name, _)| name != "host") {
+            request = request.set(name, value);
+        }
+        let result = match body {
+            Some((reader, length)) => request.set("Content-Length", &length.to_string()).send(reader),
+            None => request.call(),
+        };
+        match result {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(status, response)) => Err(error_from_body(status, &response.into_string().unwrap_or_default()).into()),
+            Err(e) => Err(anyhow::Error::from(e).context(format!("{} {} failed", method, url))),
+        }
+    }
+}
+
+fn etag(response: &ureq::Response) -> Result<String> {
+    response.header("ETag").map(str::to_string).context("The store didn't return an ETag")
+}
+
+fn error_from_body(status: u16, body: &str) -> S3Error {
+    S3Error {
+        status,
+        code: xml_value(body, "Code").unwrap_or("Unknown").to_string(),
+        message: xml_value(body, "Message").unwrap_or_default().to_string(),
+    }
+}
+
+/// The text of the first `<tag>` element; enough for S3's flat responses.
+pub fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(&xml[start..end])
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xml_value() {
+        let body = "<Error><Code>NoSuchUpload</Code><Message>The upload does not exist</Message></Error>";
+        assert_eq!(xml_value(body, "Code"), Some("NoSuchUpload"));
+        assert_eq!(xml_value(body, "UploadId"), None);
+        assert_eq!(error_from_body(404, body).to_string(), "S3 error 404 NoSuchUpload: The upload does not exist");
+    }
+}
@@ -0,0 +1,83 @@
+// ABOUTME: Bandwidth cap for uploads: readers that sleep to keep the average rate under a limit
+// ABOUTME: The budget is shared by every request of an uploader, so the cap holds across parts and files
+
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Largest read passed on at once, keeping the rate smooth.
+const CHUNK: usize = 64 * 1024;
+
+/// After this long idle the budget starts over instead of allowing a burst.
+const IDLE_RESET: Duration = Duration::from_secs(1);
+
+pub struct Throttle {
+    bytes_per_sec: Option<f64>,
+    window: Mutex<(Instant, u64)>,
+}
+
+impl Throttle {
+    /// A limit of `None` never sleeps.
+    pub fn new(bytes_per_sec: Option<f64>) -> Self {
+        Self { bytes_per_sec, window: Mutex::new((Instant::now(), 0)) }
+    }
+
+    /// Accounts for `bytes` just sent, sleeping until they fit the rate.
+    pub fn consume(&self, bytes: usize) {
+        let Some(rate) = self.bytes_per_sec else {
+            return;
+        };
+        let wait = {
+            let mut window = self.window.lock().unwrap();
+            let (started, sent) = &mut *window;
+            let due = Duration::from_secs_f64(*sent as f64 / rate);
+            if started.elapsed() > due + IDLE_RESET {
+                *started = Instant::now();
+                *sent = 0;
+            }
+            *sent += bytes as u64;
+            Duration::from_secs_f64(*sent as f64 / rate).saturating_sub(started.elapsed())
+        };
+        std::thread::sleep(wait);
+    }
+
+    pub fn reader<R: Read>(&self, inner: R) -> ThrottledReader<'_, R> {
+        ThrottledReader { inner, throttle: self }
+    }
+}
+
+pub struct ThrottledReader<'a, R> {
+    inner: R,
+    throttle: &'a Throttle,
+}
+
+impl<R: Read> Read for ThrottledReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let limit = buf.len().min(CHUNK);
+        let read = self.inner.read(&mut buf[..limit])?;
+        self.throttle.consume(read);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttled_reads_keep_to_the_rate() {
+        let throttle = Throttle::new(Some(1_000_000.0));
+        let data = vec![7u8; 300_000];
+        let started = Instant::now();
+        let mut copied = Vec::new();
+        throttle.reader(data.as_slice()).read_to_end(&mut copied).unwrap();
+        assert_eq!(copied, data);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(250) && elapsed < Duration::from_secs(2), "{:?}", elapsed);
+
+        let unlimited = Throttle::new(None);
+        let started = Instant::now();
+        std::io::copy(&mut unlimited.reader(vec![0u8; 10_000_000].as_slice()), &mut std::io::sink()).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,318 @@
+// ABOUTME: Works through the upload queue: small files in one PutObject, large ones as multipart uploads
+// ABOUTME: Progress is saved after every part, so an interrupted upload resumes after the last stored part
+
+use crate::config::UploadConfig;
+use crate::queue::{MultipartProgress, UploadItem, UploadQueue, UploadState};
+use crate::s3::{CompletedPart, S3Client, S3Error};
+use crate::sigv4;
+use crate::throttle::Throttle;
+use anyhow::{Context, Result};
+use recorder_core::markers::MarkerList;
+use recorder_core::RecordingMetadata;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// What a pass over the queue did.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RunStats {
+    pub uploaded: usize,
+    pub failed: usize,
+    /// Stopped early; the rest resumes on the next pass.
+    pub interrupted: bool,
+}
+
+pub struct Uploader {
+    config: UploadConfig,
+    client: S3Client,
+    queue_file: PathBuf,
+    throttle: Throttle,
+}
+
+impl Uploader {
+    pub fn new(config: UploadConfig, queue_file: &Path) -> Result<Self> {
+        let client = S3Client::new(&config)?;
+        let throttle = Throttle::new(config.max_bytes_per_sec());
+        Ok(Self { config, client, queue_file: queue_file.to_path_buf(), throttle })
+    }
+
+    /// Uploads each pending item once, calling `progress` as it goes. A
+    /// failed item is retried on a later pass until it runs out of attempts.
+    /// Setting `stop` ends the pass after the part being sent.
+    pub fn run_pending(&self, stop: &AtomicBool, progress: &dyn Fn(&UploadItem)) -> Result<RunStats> {
+        let mut stats = RunStats::default();
+        for id in UploadQueue::load(&self.queue_file)?.pending() {
+            if stop.load(Ordering::SeqCst) {
+                stats.interrupted = true;
+                break;
+            }
+            let Ok(item) = self.save(id, |item| item.state = UploadState::Uploading) else {
+                continue;
+            };
+            progress(&item);
+            match self.upload(&item, stop, progress) {
+                Ok(true) => stats.uploaded += 1,
+                Ok(false) => {
+                    stats.interrupted = true;
+                    break;
+                }
+                Err(e) => {
+                    stats.failed += 1;
+                    let max_attempts = self.config.max_attempts;
+                    if let Ok(item) = self.save(id, |item| {
+                        item.attempts += 1;
+                        item.error = Some(format!("{:#}", e));
+                        if item.attempts >= max_attempts {
+                            item.state = UploadState::Failed;
+                        }
+                    }) {
+                        progress(&item);
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Sends `item`, returning `false` if `stop` interrupted it.
+    fn upload(&self, item: &UploadItem, stop: &AtomicBool, progress: &dyn Fn(&UploadItem)) -> Result<bool> {
+        let bytes = std::fs::metadata(&item.path).with_context(|| format!("Can't read {}", item.path.display()))?.len();
+        let part_size = self.config.part_size();
+        if bytes <= part_size {
+            let data = std::fs::read(&item.path)?;
+            self.client.put_object(&item.key, self.throttle.reader(data.as_slice()), bytes, &sigv4::sha256_hex(&data))?;
+            progress(&self.finish(item.id, bytes)?);
+            return Ok(true);
+        }
+
+        // Progress only carries over if the file and the part size are unchanged
+        let mut upload = match &item.multipart {
+            Some(upload) if item.bytes == bytes && upload.part_size == part_size => upload.clone(),
+            _ => {
+                let upload = MultipartProgress { upload_id: self.client.create_multipart_upload(&item.key)?, part_size, parts: Vec::new() };
+                let started = upload.clone();
+                self.save(item.id, move |item| {
+                    item.bytes = bytes;
+                    item.uploaded_bytes = 0;
+                    item.multipart = Some(started);
+                })?;
+                upload
+            }
+        };
+        let count = bytes.div_ceil(part_size) as u32;
+        let mut file = std::fs::File::open(&item.path)?;
+        let mut buffer = Vec::with_capacity(part_size as usize);
+        for number in upload.parts.len() as u32 + 1..=count {
+            if stop.load(Ordering::SeqCst) {
+                return Ok(false);
+            }
+            let offset = (number as u64 - 1) * part_size;
+            file.seek(SeekFrom::Start(offset))?;
+            buffer.clear();
+            (&mut file).take(part_size).read_to_end(&mut buffer)?;
+            let sha256 = sigv4::sha256_hex(&buffer);
+            let etag = self
+                .client
+                .upload_part(&item.key, &upload.upload_id, number, self.throttle.reader(buffer.as_slice()), buffer.len() as u64, &sha256)
+                .map_err(|e| self.forget_expired(item.id, e))?;
+            upload.parts.push(CompletedPart { number, etag });
+            let stored = upload.clone();
+            let uploaded = (offset + buffer.len() as u64).min(bytes);
+            progress(&self.save(item.id, move |item| {
+                item.multipart = Some(stored);
+                item.uploaded_bytes = uploaded;
+            })?);
+        }
+        self.client
+            .complete_multipart_upload(&item.key, &upload.upload_id, &upload.parts)
+            .map_err(|e| self.forget_expired(item.id, e))?;
+        progress(&self.finish(item.id, bytes)?);
+        Ok(true)
+    }
+
+    /// Drops the saved progress if the store no longer knows the upload
+    /// (aborted or expired), so the next attempt starts a new one.
+    fn forget_expired(&self, id: u64, error: anyhow::Error) -> anyhow::Error {
+        if error.downcast_ref::<S3Error>().is_some_and(|e| e.code == "NoSuchUpload") {
+            self.save(id, |item| {
+                item.multipart = None;
+                item.uploaded_bytes = 0;
+            })
+            .ok();
+            return error.context("The multipart upload is gone from the store; it will start over");
+        }
+        error
+    }
+
+    fn finish(&self, id: u64, bytes: u64) -> Result<UploadItem> {
+        self.save(id, |item| {
+            item.state = UploadState::Done;
+            item.bytes = bytes;
+            item.uploaded_bytes = bytes;
+            item.multipart = None;
+            item.error = None;
+            item.finished_at = Some(chrono::Local::now());
+        })
+    }
+
+    /// Applies `change` to item `id` in the stored queue, returning the
+    /// updated item. Fails if the item was removed meanwhile.
+    fn save(&self, id: u64, change: impl FnOnce(&mut UploadItem)) -> Result<UploadItem> {
+        UploadQueue::update(&self.queue_file, |queue| {
+            queue.get_mut(id).map(|item| {
+                change(item);
+                item.clone()
+            })
+        })?
+        .context("The upload was removed from the queue")
+    }
+}
+
+/// `prefix/<path below root>`, or `prefix/<file name>` for files outside
+/// `root`, always with `/` separators.
+pub fn object_key(prefix: &str, root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).ok().filter(|relative| !relative.as_os_str().is_empty());
+    let relative = relative.unwrap_or_else(|| Path::new(path.file_name().unwrap_or(path.as_os_str())));
+    let relative = relative.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        relative
+    } else {
+        format!("{}/{}", prefix, relative)
+    }
+}
+
+/// Queues `recording` (a file, segment or playlist) and the metadata and
+/// marker sidecars next to it. Returns the IDs of the new items.
+pub fn enqueue_recording(queue_file: &Path, config: &UploadConfig, root: &Path, recording: &Path) -> Result<Vec<u64>> {
+    let mut files = vec![recording.to_path_buf()];
+    files.extend([RecordingMetadata::sidecar_path(recording), MarkerList::sidecar_path(recording)].into_iter().filter(|sidecar| sidecar.exists()));
+    UploadQueue::update(queue_file, |queue| {
+        let mut added = Vec::new();
+        for file in &files {
+            if let Some(id) = queue.add(file, &object_key(&config.prefix, root, file))? {
+                added.push(id);
+            }
+        }
+        Ok(added)
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{temp_dir, StubS3};
+    use std::sync::Mutex;
+
+    fn config(stub: &StubS3) -> UploadConfig {
+        UploadConfig {
+            endpoint: stub.endpoint.clone(),
+            bucket: "scrims".into(),
+            access_key: Some("minio".into()),
+            secret_key: Some("minio123".into()),
+            prefix: "team".into(),
+            max_attempts: 2,
+            timeout_secs: 5,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_object_key() {
+        let root = Path::new("/rec");
+        assert_eq!(object_key("", root, Path::new("/rec/2024/05/TFT-1.mp4")), "2024/05/TFT-1.mp4");
+        assert_eq!(object_key("/team/", root, Path::new("/rec/TFT-1.json")), "team/TFT-1.json");
+        assert_eq!(object_key("team", root, Path::new("/elsewhere/clip.mp4")), "team/clip.mp4");
+    }
+
+    #[test]
+    fn test_recording_and_sidecars_are_uploaded() {
+        let stub = StubS3::start();
+        let dir = temp_dir("small");
+        let recording = dir.join("2024").join("TFT 1.mp4");
+        std::fs::create_dir_all(recording.parent().unwrap()).unwrap();
+        std::fs::write(&recording, vec![1u8; 1000]).unwrap();
+        std::fs::write(RecordingMetadata::sidecar_path(&recording), b"{}").unwrap();
+        let queue_file = dir.join("uploads.json");
+
+        let config = config(&stub);
+        assert_eq!(enqueue_recording(&queue_file, &config, &dir, &recording).unwrap().len(), 2);
+        // Queuing the same files again does nothing
+        assert!(enqueue_recording(&queue_file, &config, &dir, &recording).unwrap().is_empty());
+
+        let uploader = Uploader::new(config, &queue_file).unwrap();
+        let seen = Mutex::new(Vec::new());
+        let stats = uploader.run_pending(&AtomicBool::new(false), &|item| seen.lock().unwrap().push(item.state)).unwrap();
+        assert_eq!(stats, RunStats { uploaded: 2, failed: 0, interrupted: false });
+        assert_eq!(seen.into_inner().unwrap(), [UploadState::Uploading, UploadState::Done, UploadState::Uploading, UploadState::Done]);
+        assert_eq!(stub.object("team/2024/TFT 1.mp4").unwrap(), vec![1u8; 1000]);
+        assert_eq!(stub.object("team/2024/TFT 1.json").unwrap(), b"{}");
+        assert!(UploadQueue::load(&queue_file).unwrap().items().iter().all(|item| item.state == UploadState::Done && item.progress() == 1.0));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_multipart_upload_resumes_after_a_failure() {
+        let stub = StubS3::start();
+        let dir = temp_dir("multipart");
+        let recording = dir.join("TFT-2.mp4");
+        // 12 MB: parts of 5, 5 and 2 MB
+        let data: Vec<u8> = (0..12 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&recording, &data).unwrap();
+        let queue_file = dir.join("uploads.json");
+        let config = UploadConfig { part_size_mb: 5, ..config(&stub) };
+        let id = enqueue_recording(&queue_file, &config, &dir, &recording).unwrap()[0];
+
+        // Part 2 fails; parts already stored are kept
+        stub.fail_part(2);
+        let stats = Uploader::new(config.clone(), &queue_file).unwrap().run_pending(&AtomicBool::new(false), &|_| {}).unwrap();
+        assert_eq!(stats.failed, 1);
+        let item = UploadQueue::load(&queue_file).unwrap().get(id).cloned().unwrap();
+        assert_eq!((item.state, item.attempts, item.uploaded_bytes), (UploadState::Uploading, 1, 5 * 1024 * 1024));
+        assert!(item.error.unwrap().contains("500"));
+        assert_eq!(item.multipart.as_ref().unwrap().parts.len(), 1);
+
+        // A new uploader, as after a restart, carries on from part 2
+        stub.clear_requests();
+        let stats = Uploader::new(config, &queue_file).unwrap().run_pending(&AtomicBool::new(false), &|_| {}).unwrap();
+        assert_eq!(stats.uploaded, 1);
+        let parts: Vec<String> = stub.requests().into_iter().filter(|request| request.contains("partNumber")).collect();
+        assert_eq!(parts.len(), 2, "{:?}", parts);
+        assert!(parts[0].contains("partNumber=2"));
+        assert_eq!(stub.object("team/TFT-2.mp4").unwrap(), data);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_expired_upload_starts_over() {
+        let stub = StubS3::start();
+        let dir = temp_dir("expired");
+        let recording = dir.join("TFT-3.mp4");
+        std::fs::write(&recording, vec![9u8; 6 * 1024 * 1024]).unwrap();
+        let queue_file = dir.join("uploads.json");
+        let config = UploadConfig { part_size_mb: 5, max_attempts: 1, ..config(&stub) };
+        let id = enqueue_recording(&queue_file, &config, &dir, &recording).unwrap()[0];
+        UploadQueue::update(&queue_file, |queue| {
+            let item = queue.get_mut(id).unwrap();
+            item.state = UploadState::Uploading;
+            item.multipart = Some(MultipartProgress { upload_id: "aborted".into(), part_size: 5 * 1024 * 1024, parts: Vec::new() });
+        })
+        .unwrap();
+
+        let uploader = Uploader::new(config, &queue_file).unwrap();
+        uploader.run_pending(&AtomicBool::new(false), &|_| {}).unwrap();
+        let item = UploadQueue::load(&queue_file).unwrap().get(id).cloned().unwrap();
+        assert_eq!(item.state, UploadState::Failed);
+        assert!(item.multipart.is_none() && item.error.unwrap().contains("start over"));
+
+        UploadQueue::update(&queue_file, |queue| queue.retry_failed()).unwrap();
+        assert_eq!(uploader.run_pending(&AtomicBool::new(false), &|_| {}).unwrap().uploaded, 1);
+        assert_eq!(stub.object("team/TFT-3.mp4").unwrap().len(), 6 * 1024 * 1024);
+
+        // Stopping before the next item leaves it queued
+        std::fs::write(dir.join("TFT-4.mp4"), b"x").unwrap();
+        enqueue_recording(&queue_file, &UploadConfig::default(), &dir, &dir.join("TFT-4.mp4")).unwrap();
+        assert!(uploader.run_pending(&AtomicBool::new(true), &|_| {}).unwrap().interrupted);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}